@@ -0,0 +1,671 @@
+//! Spells and timed status effects. A spell order walks into range and plays
+//! the CastSpell animation; iscript's castspell opcode then pays the energy
+//! (techdata.dat) and launches the order's orders.dat weapon. The effect is
+//! applied where that weapon hits, or right away for spells without weapon.
+
+use ecs::{World, Entity, EntityData, DataHelper, ModifyData};
+
+use scformats::iscript::AnimationType;
+use scformats::unitsdata::{OrderType, WEAPON_NONE, UF_BUILDING, UF_ORGANIC,
+                           UF_USE_MEDIUM_OVERLAYS, UF_USE_LARGE_OVERLAYS};
+
+use gamedata::GameData;
+use fixedpoint::{Fixed, approx_distance};
+use orders::{Order, OrderTarget, UnitInfo, find_unit, unit_distance, chase, face, can_target};
+use combat::{is_dying, kill_unit, record_death, launch_weapon};
+use unit_ecs::{UnitComponents, UnitServices, UnitSystems, DatFiles, PlanningMap, SCUnitStep,
+               IScriptEntityAction, OverlayComponent, create_scimage};
+
+// techdata.dat
+const TECH_STIM_PACKS: usize = 0;
+
+// units.dat
+const UNIT_MARINE: usize = 0;
+const UNIT_FIREBAT: usize = 32;
+
+/// hit points a stim costs
+const STIM_COST: Fixed = 10 * 256;
+/// range of spells whose order has no weapon
+const DEFAULT_SPELL_RANGE: i32 = 8 * 32;
+/// order state while the CastSpell animation plays
+const CAST_STATE: u8 = 2;
+/// damage absorbed by a defensive matrix
+const DEFENSIVE_MATRIX_HP: Fixed = 250 * 256;
+/// damage per tick: 250 over the whole duration
+const IRRADIATE_DAMAGE: Fixed = 216;
+const IRRADIATE_RANGE: i32 = 32;
+/// damage per tick: 300 over the whole duration, never kills
+const PLAGUE_DAMAGE: Fixed = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatusEffect {
+    Stim,
+    Lockdown,
+    Stasis,
+    Irradiate,
+    Ensnare,
+    Plague,
+    DefensiveMatrix,
+    Maelstrom,
+}
+const STATUS_EFFECTS: usize = 8;
+
+impl StatusEffect {
+    /// in ticks
+    pub fn duration(self) -> u16 {
+        match self {
+            StatusEffect::Stim => 37 * 8,
+            StatusEffect::Lockdown | StatusEffect::Stasis => 131 * 8,
+            StatusEffect::Irradiate => 37 * 8,
+            StatusEffect::Ensnare | StatusEffect::Plague => 75 * 8,
+            StatusEffect::DefensiveMatrix => 168 * 8,
+            StatusEffect::Maelstrom => 22 * 8,
+        }
+    }
+
+    /// images.dat id of the small overlay, the medium and large ones follow
+    fn overlay_image(self) -> Option<usize> {
+        match self {
+            StatusEffect::Stim => None,
+            StatusEffect::Lockdown => Some(363),
+            StatusEffect::DefensiveMatrix => Some(367),
+            StatusEffect::Irradiate => Some(378),
+            StatusEffect::Ensnare => Some(381),
+            StatusEffect::Plague => Some(384),
+            StatusEffect::Stasis => Some(387),
+            StatusEffect::Maelstrom => Some(975),
+        }
+    }
+}
+
+/// the timed effects on a unit
+pub struct StatusComponent {
+    /// ticks left, indexed by StatusEffect
+    timers: [u16; STATUS_EFFECTS],
+    pub matrix_hp: Fixed,
+    pub overlays: Vec<(StatusEffect, Entity)>,
+    /// set by triggers
+    pub invincible: bool,
+}
+impl StatusComponent {
+    pub fn new() -> Self {
+        StatusComponent {
+            timers: [0; STATUS_EFFECTS],
+            matrix_hp: 0,
+            overlays: Vec::new(),
+            invincible: false,
+        }
+    }
+
+    pub fn has(&self, effect: StatusEffect) -> bool {
+        self.timers[effect as usize] > 0
+    }
+
+    /// starts an effect or restarts its timer
+    pub fn apply(&mut self, effect: StatusEffect) {
+        self.timers[effect as usize] = effect.duration();
+        if effect == StatusEffect::DefensiveMatrix {
+            self.matrix_hp = DEFENSIVE_MATRIX_HP;
+        }
+    }
+
+    /// counts down the timers, returns the effects that wore off
+    pub fn tick(&mut self) -> Vec<StatusEffect> {
+        let mut expired = Vec::new();
+        for (i, effect) in [StatusEffect::Stim,
+                            StatusEffect::Lockdown,
+                            StatusEffect::Stasis,
+                            StatusEffect::Irradiate,
+                            StatusEffect::Ensnare,
+                            StatusEffect::Plague,
+                            StatusEffect::DefensiveMatrix,
+                            StatusEffect::Maelstrom]
+            .iter()
+            .enumerate() {
+            if self.timers[i] == 0 {
+                continue;
+            }
+            self.timers[i] -= 1;
+            if self.timers[i] == 0 {
+                expired.push(*effect);
+            }
+        }
+        if !self.has(StatusEffect::DefensiveMatrix) {
+            self.matrix_hp = 0;
+        }
+        expired
+    }
+
+    /// lockdown, stasis and maelstrom stop the unit from doing anything
+    pub fn is_disabled(&self) -> bool {
+        self.has(StatusEffect::Lockdown) || self.has(StatusEffect::Stasis) ||
+        self.has(StatusEffect::Maelstrom)
+    }
+
+    pub fn is_invulnerable(&self) -> bool {
+        self.invincible || self.has(StatusEffect::Stasis)
+    }
+
+    /// top speed in percent
+    pub fn speed_percent(&self) -> Fixed {
+        let mut percent = 100;
+        if self.has(StatusEffect::Stim) {
+            percent = percent * 3 / 2;
+        }
+        if self.has(StatusEffect::Ensnare) {
+            percent /= 2;
+        }
+        percent
+    }
+
+    pub fn attack_cooldown(&self, cooldown: u8) -> u8 {
+        let mut cooldown = cooldown as u32;
+        if self.has(StatusEffect::Stim) {
+            cooldown /= 2;
+        }
+        if self.has(StatusEffect::Ensnare) {
+            cooldown = cooldown * 5 / 4;
+        }
+        cooldown.max(1).min(255) as u8
+    }
+
+    /// the defensive matrix takes damage first, returns what is left. A
+    /// broken matrix wears off on the next tick, so its overlay goes away.
+    pub fn absorb(&mut self, damage: Fixed) -> Fixed {
+        if !self.has(StatusEffect::DefensiveMatrix) {
+            return damage;
+        }
+        let absorbed = damage.min(self.matrix_hp);
+        self.matrix_hp -= absorbed;
+        if self.matrix_hp <= 0 {
+            self.timers[StatusEffect::DefensiveMatrix as usize] = 1;
+        }
+        damage - absorbed
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpellTarget {
+    Unit,
+    Position,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpellEffect {
+    Status(StatusEffect),
+    /// removes shields and energy
+    EmpShockwave,
+    /// the weapon deals normal damage, e.g. psionic storm
+    WeaponDamage,
+}
+
+/// energy cost and tech come from techdata.dat, weapon and range from the
+/// orders.dat weapon
+pub struct Spell {
+    pub order: OrderType,
+    pub tech: usize,
+    pub target: SpellTarget,
+    pub effect: SpellEffect,
+    /// area of effect around the hit, 0 for the target only
+    pub radius: i32,
+    /// units of the caster's player are spared
+    pub enemies_only: bool,
+}
+
+pub static SPELLS: [Spell; 9] = [Spell {
+                                     order: OrderType::CastLockdown,
+                                     tech: 1,
+                                     target: SpellTarget::Unit,
+                                     effect: SpellEffect::Status(StatusEffect::Lockdown),
+                                     radius: 0,
+                                     enemies_only: false,
+                                 },
+                                 Spell {
+                                     order: OrderType::CastEMPShockwave,
+                                     tech: 2,
+                                     target: SpellTarget::Position,
+                                     effect: SpellEffect::EmpShockwave,
+                                     radius: 64,
+                                     enemies_only: false,
+                                 },
+                                 Spell {
+                                     order: OrderType::CastDefensiveMatrix,
+                                     tech: 6,
+                                     target: SpellTarget::Unit,
+                                     effect: SpellEffect::Status(StatusEffect::DefensiveMatrix),
+                                     radius: 0,
+                                     enemies_only: false,
+                                 },
+                                 Spell {
+                                     order: OrderType::CastIrradiate,
+                                     tech: 7,
+                                     target: SpellTarget::Unit,
+                                     effect: SpellEffect::Status(StatusEffect::Irradiate),
+                                     radius: 0,
+                                     enemies_only: false,
+                                 },
+                                 Spell {
+                                     order: OrderType::CastPlague,
+                                     tech: 15,
+                                     target: SpellTarget::Position,
+                                     effect: SpellEffect::Status(StatusEffect::Plague),
+                                     radius: 64,
+                                     enemies_only: true,
+                                 },
+                                 Spell {
+                                     order: OrderType::CastEnsnare,
+                                     tech: 17,
+                                     target: SpellTarget::Position,
+                                     effect: SpellEffect::Status(StatusEffect::Ensnare),
+                                     radius: 64,
+                                     enemies_only: true,
+                                 },
+                                 Spell {
+                                     order: OrderType::CastPsionicStorm,
+                                     tech: 19,
+                                     target: SpellTarget::Position,
+                                     effect: SpellEffect::WeaponDamage,
+                                     radius: 0,
+                                     enemies_only: false,
+                                 },
+                                 Spell {
+                                     order: OrderType::CastStasisField,
+                                     tech: 22,
+                                     target: SpellTarget::Position,
+                                     effect: SpellEffect::Status(StatusEffect::Stasis),
+                                     radius: 48,
+                                     enemies_only: false,
+                                 },
+                                 Spell {
+                                     order: OrderType::CastMaelstrom,
+                                     tech: 31,
+                                     target: SpellTarget::Position,
+                                     effect: SpellEffect::Status(StatusEffect::Maelstrom),
+                                     radius: 48,
+                                     enemies_only: false,
+                                 }];
+
+pub fn spell_for_order(order_type: OrderType) -> Option<&'static Spell> {
+    SPELLS.iter().find(|s| s.order == order_type)
+}
+
+pub fn is_spell_order(order_type: OrderType) -> bool {
+    spell_for_order(order_type).is_some()
+}
+
+/// the spell whose effect a weapon applies when it hits
+pub fn spell_for_weapon(dat: &DatFiles, weapon: usize) -> Option<&'static Spell> {
+    SPELLS.iter().find(|s| {
+        s.effect != SpellEffect::WeaponDamage &&
+        dat.orders_dat.targeting[s.order as usize] as usize == weapon
+    })
+}
+
+fn spell_weapon(dat: &DatFiles, spell: &Spell) -> Option<usize> {
+    let weapon = dat.orders_dat.targeting[spell.order as usize];
+    if weapon == WEAPON_NONE {
+        None
+    } else {
+        Some(weapon as usize)
+    }
+}
+
+pub fn energy_cost(dat: &DatFiles, spell: &Spell) -> Fixed {
+    dat.techdata_dat.energy_required[spell.tech] as Fixed * 256
+}
+
+fn spell_range(dat: &DatFiles, spell: &Spell) -> i32 {
+    spell_weapon(dat, spell).map_or(DEFAULT_SPELL_RANGE,
+                                    |w| dat.weapons_dat.maximum_range[w] as i32)
+}
+
+pub fn is_disabled(e: EntityData<UnitComponents>,
+                   dh: &UnitComponents)
+                   -> bool {
+    dh.status.has(&e) && dh.status[e].is_disabled()
+}
+
+pub fn is_invulnerable(e: EntityData<UnitComponents>,
+                       dh: &UnitComponents)
+                       -> bool {
+    dh.status.has(&e) && dh.status[e].is_invulnerable()
+}
+
+/// top speed in percent
+pub fn speed_percent(e: EntityData<UnitComponents>,
+                     dh: &DataHelper<UnitComponents, UnitServices>)
+                     -> Fixed {
+    if dh.status.has(&e) {
+        dh.status[e].speed_percent()
+    } else {
+        100
+    }
+}
+
+pub fn attack_cooldown(e: EntityData<UnitComponents>,
+                       dh: &UnitComponents,
+                       cooldown: u8)
+                       -> u8 {
+    if dh.status.has(&e) {
+        dh.status[e].attack_cooldown(cooldown)
+    } else {
+        cooldown
+    }
+}
+
+/// states: 1 approaching, 2 casting (waiting for iscript's castspell)
+pub fn order_cast_spell(e: &EntityData<UnitComponents>,
+                        dh: &mut DataHelper<UnitComponents, UnitServices>,
+                        map: &PlanningMap,
+                        dat: &DatFiles,
+                        units: &[UnitInfo],
+                        me: &UnitInfo,
+                        order: Order) {
+    let spell = spell_for_order(order.order_type).unwrap();
+    if dh.scunit[*e].orders.state == CAST_STATE {
+        return;
+    }
+    let target = match order.target {
+        OrderTarget::Unit(target) if spell.target == SpellTarget::Unit => {
+            find_unit(units, target).map(|t| (t.x, t.y, unit_distance(&dat.units_dat, me, &t)))
+        }
+        OrderTarget::Position(x, y) if spell.target == SpellTarget::Position => {
+            Some((x, y, approx_distance(x - me.x, y - me.y)))
+        }
+        _ => None,
+    };
+    let (tx, ty, dist) = match target {
+        Some(target) => target,
+        None => {
+            SCUnitStep::halt(e, dh);
+            dh.scunit[*e].orders.finish();
+            return;
+        }
+    };
+    if !dh.services.has_tech(me.player, spell.tech) ||
+       dh.health[*e].energy < energy_cost(dat, spell) {
+        SCUnitStep::halt(e, dh);
+        dh.scunit[*e].orders.finish();
+        return;
+    }
+    if dist > spell_range(dat, spell) {
+        dh.scunit[*e].orders.state = 1;
+        chase(e, dh, map, tx, ty);
+        return;
+    }
+    SCUnitStep::halt(e, dh);
+    if face(e, dh, tx, ty, 16) {
+        dh.iscript_state[*e].next_animation = Some(AnimationType::CastSpell);
+        dh.scunit[*e].orders.state = CAST_STATE;
+    }
+}
+
+/// iscript's castspell opcode ran: pays the energy and launches the weapon
+pub fn cast_spell(world: &mut World<UnitSystems>, gd: &GameData, caster: Entity) {
+    let dat = world.data.services.dat();
+    let cast = world.with_entity_data(&caster, |e, data| {
+        if !data.scunit.has(&e) || is_dying(e, data) ||
+           data.scunit[e].orders.state != CAST_STATE {
+            return None;
+        }
+        let order = data.scunit[e].orders.current;
+        let spell = match spell_for_order(order.order_type) {
+            Some(spell) => spell,
+            None => return None,
+        };
+        data.scunit[e].orders.finish();
+        let cost = energy_cost(&dat, spell);
+        if data.health[e].energy < cost {
+            return None;
+        }
+        data.health[e].energy -= cost;
+        Some((spell,
+              order.target,
+              data.scimage[e].player_id,
+              data.iscript_state[e].map_pos_x,
+              data.iscript_state[e].map_pos_y))
+    });
+    let (spell, target, player, x, y) = match cast {
+        Some(Some(cast)) => cast,
+        _ => return,
+    };
+    let (target, tx, ty) = match target {
+        OrderTarget::Unit(t) => {
+            match world.with_entity_data(&t, |e, data| {
+                (data.iscript_state[e].map_pos_x, data.iscript_state[e].map_pos_y)
+            }) {
+                Some((tx, ty)) => (Some(t), tx, ty),
+                None => return,
+            }
+        }
+        OrderTarget::Position(tx, ty) => (None, tx as u16, ty as u16),
+        OrderTarget::None => return,
+    };
+    match spell_weapon(&dat, spell) {
+        Some(weapon) => {
+            // weapons aimed at a position appear there
+            let (sx, sy) = if target.is_some() { (x, y) } else { (tx, ty) };
+            launch_weapon(world, gd, weapon, Some(caster), target, sx, sy, player, 0);
+        }
+        None => apply_spell(world, gd, spell, target, tx as i32, ty as i32, player),
+    }
+}
+
+/// applies the effect of a spell at (x, y), or to `target` only
+pub fn apply_spell(world: &mut World<UnitSystems>,
+                   gd: &GameData,
+                   spell: &Spell,
+                   target: Option<Entity>,
+                   x: i32,
+                   y: i32,
+                   player: usize) {
+    let dat = world.data.services.dat();
+    let weapon = spell_weapon(&dat, spell);
+    let mut victims = Vec::<Entity>::new();
+    {
+        let dh = &world.data;
+        for e in world.entities().filter(aspect!(<UnitComponents> all: [scunit, status]
+                                                 none: [passenger, subunit]),
+                                         world) {
+            if is_dying(e, dh) || is_invulnerable(e, dh) {
+                continue;
+            }
+            let unit_id = dh.scunit[e].unit_id as usize;
+            let in_area = if spell.radius == 0 {
+                Some(**e) == target
+            } else {
+                approx_distance(dh.iscript_state[e].map_pos_x as i32 - x,
+                                dh.iscript_state[e].map_pos_y as i32 - y) <= spell.radius
+            };
+            if !in_area || (spell.enemies_only && dh.scimage[e].player_id == player) ||
+               weapon.map_or(false, |w| !can_target(&dat.weapons_dat, w, &dat.units_dat, unit_id)) {
+                continue;
+            }
+            if spell.effect != SpellEffect::Status(StatusEffect::Plague) &&
+               dat.units_dat.flags(unit_id).contains(UF_BUILDING) {
+                continue;
+            }
+            victims.push(**e);
+        }
+    }
+    for victim in victims {
+        match spell.effect {
+            SpellEffect::Status(effect) => add_status(world, gd, victim, effect),
+            SpellEffect::EmpShockwave => {
+                world.with_entity_data(&victim, |e, data| {
+                    data.health[e].shields = 0;
+                    data.health[e].energy = 0;
+                });
+            }
+            SpellEffect::WeaponDamage => {}
+        }
+    }
+}
+
+/// starts an effect on a unit and shows its overlay
+pub fn add_status(world: &mut World<UnitSystems>,
+                  gd: &GameData,
+                  unit: Entity,
+                  effect: StatusEffect) {
+    let info = world.with_entity_data(&unit, |e, data| {
+        data.status[e].apply(effect);
+        let has_overlay = data.status[e].overlays.iter().any(|&(o, _)| o == effect);
+        (has_overlay,
+         data.scunit[e].unit_id as usize,
+         data.iscript_state[e].map_pos_x,
+         data.iscript_state[e].map_pos_y,
+         data.scimage[e].player_id)
+    });
+    let (has_overlay, unit_id, x, y, player) = match info {
+        Some(info) => info,
+        None => return,
+    };
+    let image = match effect.overlay_image() {
+        Some(image) if !has_overlay => image,
+        _ => return,
+    };
+    let flags = gd.units_dat.flags(unit_id);
+    let size = if flags.contains(UF_USE_LARGE_OVERLAYS) {
+        2
+    } else if flags.contains(UF_USE_MEDIUM_OVERLAYS) {
+        1
+    } else {
+        0
+    };
+    let overlay = create_scimage(world, gd, image + size, x, y, Some(unit), player);
+    world.modify_entity(overlay,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        data.overlay.insert(&e, OverlayComponent {});
+    });
+    world.with_entity_data(&unit, |e, data| {
+        data.iscript_state[e].children.push(overlay);
+        data.status[e].overlays.push((effect, overlay));
+    });
+}
+
+/// stims a marine or firebat, costs hit points instead of energy
+pub fn use_stim_packs(world: &mut World<UnitSystems>, unit: Entity) -> bool {
+    let researched = match world.with_entity_data(&unit, |e, data| data.scimage[e].player_id) {
+        Some(player) => world.data.services.has_tech(player, TECH_STIM_PACKS),
+        None => return false,
+    };
+    world.with_entity_data(&unit, |e, data| {
+            let unit_id = data.scunit[e].unit_id as usize;
+            if (unit_id != UNIT_MARINE && unit_id != UNIT_FIREBAT) || is_dying(e, data) ||
+               is_disabled(e, data) || !researched || data.health[e].hit_points <= STIM_COST {
+                return false;
+            }
+            data.health[e].hit_points -= STIM_COST;
+            data.status[e].apply(StatusEffect::Stim);
+            true
+        })
+        .unwrap_or(false)
+}
+
+/// counts down the effects of a unit and deals their damage
+pub fn step_status(e: &EntityData<UnitComponents>,
+                   dh: &mut DataHelper<UnitComponents, UnitServices>,
+                   dat: &DatFiles,
+                   units: &[UnitInfo],
+                   actions: &mut Vec<IScriptEntityAction>) {
+    if !dh.status.has(e) {
+        return;
+    }
+    let expired = dh.status[*e].tick();
+    for effect in expired {
+        let (gone, kept): (Vec<_>, Vec<_>) =
+            dh.status[*e].overlays.drain(..).partition(|&(o, _)| o == effect);
+        dh.status[*e].overlays = kept;
+        for (_, overlay) in gone {
+            dh.iscript_state[*e].children.retain(|c| *c != overlay);
+            actions.push(IScriptEntityAction::RemoveEntity { entity: overlay });
+        }
+    }
+
+    if dh.status[*e].has(StatusEffect::Plague) && dh.health[*e].hit_points > 256 {
+        dh.health[*e].hit_points = (dh.health[*e].hit_points - PLAGUE_DAMAGE).max(256);
+    }
+    if dh.status[*e].has(StatusEffect::Irradiate) {
+        // hurts organic units close by, including itself
+        let me = match find_unit(units, ***e) {
+            Some(me) => me,
+            None => return,
+        };
+        for other in units {
+            if !dat.units_dat.flags(other.unit_id).contains(UF_ORGANIC) ||
+               unit_distance(&dat.units_dat, &me, other) > IRRADIATE_RANGE {
+                continue;
+            }
+            let death = dh.with_entity_data(&other.entity, |te, data| {
+                if is_dying(te, data) || is_invulnerable(te, data) {
+                    return None;
+                }
+                data.health[te].hit_points -= IRRADIATE_DAMAGE;
+                if data.health[te].is_dead() {
+                    kill_unit(te, data)
+                } else {
+                    None
+                }
+            });
+            record_death(&mut dh.services, death.and_then(|d| d));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use simulation::Simulation;
+    use unit_ecs::create_scunit;
+    use super::{StatusComponent, StatusEffect, add_status};
+
+    const UNIT_MARINE: usize = 0;
+
+    #[test]
+    fn status_timers() {
+        let mut status = StatusComponent::new();
+        status.apply(StatusEffect::Stim);
+        assert!(status.has(StatusEffect::Stim));
+        assert_eq!(status.attack_cooldown(22), 11);
+        assert_eq!(status.speed_percent(), 150);
+        for _ in 1..StatusEffect::Stim.duration() {
+            assert!(status.tick().is_empty());
+        }
+        assert_eq!(status.tick(), vec![StatusEffect::Stim]);
+        assert!(!status.has(StatusEffect::Stim));
+        assert_eq!(status.attack_cooldown(22), 22);
+
+        status.apply(StatusEffect::Stasis);
+        assert!(status.is_disabled());
+        assert!(status.is_invulnerable());
+    }
+
+    #[test]
+    fn defensive_matrix() {
+        let mut status = StatusComponent::new();
+        assert_eq!(status.absorb(1000), 1000);
+        status.apply(StatusEffect::DefensiveMatrix);
+        assert_eq!(status.absorb(200 * 256), 0);
+        assert_eq!(status.absorb(100 * 256), 50 * 256);
+        assert_eq!(status.absorb(10 * 256), 10 * 256);
+        assert_eq!(status.tick(), vec![StatusEffect::DefensiveMatrix]);
+        assert!(!status.has(StatusEffect::DefensiveMatrix));
+    }
+
+    #[test]
+    fn broken_matrix_loses_overlay() {
+        let mut sim = Simulation::from_files(&Path::new(&::scdata_path()), "test.scx").unwrap();
+        let marine = create_scunit(&mut sim.world, &sim.gd, UNIT_MARINE, 200, 200, 0);
+        add_status(&mut sim.world, &sim.gd, marine, StatusEffect::DefensiveMatrix);
+        let overlay = sim.world
+            .with_entity_data(&marine, |e, data| data.status[e].overlays[0].1)
+            .expect("marine is gone");
+        sim.world.with_entity_data(&marine, |e, data| data.status[e].absorb(300 * 256));
+        sim.run(2);
+        let overlays = sim.world
+            .with_entity_data(&marine, |e, data| data.status[e].overlays.len())
+            .expect("marine is gone");
+        assert_eq!(overlays, 0);
+        assert!(sim.world.with_entity_data(&overlay, |_, _| ()).is_none());
+    }
+}
@@ -0,0 +1,1604 @@
+//! Computer players. An AI player runs scripts from aiscript.bin and
+//! bwscript.bin in threads, like the original: the scripts request buildings,
+//! units and research, put together attack forces and say when to attack.
+//! Every few ticks a town manager does the rest: idle workers mine, supply and
+//! workers are made as needed, requests are started by priority as long as
+//! the money lasts and units near the towns fight off attackers.
+
+use std::mem;
+
+use ecs::{World, Entity};
+
+use scformats::aiscript::{AIScriptPos, AICommand, script_id, AI_GOTO,
+                          AI_NOTOWNS_JUMP, AI_WAIT, AI_START_TOWN, AI_START_AREATOWN, AI_EXPAND,
+                          AI_BUILD, AI_UPGRADE, AI_TECH, AI_WAIT_BUILD, AI_WAIT_BUILDSTART,
+                          AI_ATTACK_CLEAR, AI_ATTACK_ADD, AI_ATTACK_PREPARE, AI_ATTACK_DO,
+                          AI_DEFENSEBUILD_GG, AI_DEFENSEBUILD_AG, AI_DEFENSEBUILD_GA,
+                          AI_DEFENSEBUILD_AA, AI_DEFENSEUSE_GG, AI_DEFENSEUSE_AG,
+                          AI_DEFENSEUSE_GA, AI_DEFENSEUSE_AA, AI_DEFENSECLEAR_GG,
+                          AI_DEFENSECLEAR_AA, AI_SEND_SUICIDE, AI_STOP, AI_DEBUG, AI_FATAL_ERROR,
+                          AI_RANDOM_JUMP, AI_TIME_JUMP, AI_FARMS_NOTIMING, AI_FARMS_TIMING,
+                          AI_START_CAMPAIGN, AI_RACE_JUMP, AI_GROUNDMAP_JUMP, AI_WAIT_FORCE,
+                          AI_CALL, AI_RETURN, AI_PLAYER_NEED, AI_DO_MORPH, AI_WAIT_UPGRADES,
+                          AI_MULTIRUN, AI_TRAIN, AI_WAIT_TRAIN, AI_RESOURCES_JUMP,
+                          AI_ENEMYOWNS_JUMP, AI_ENEMYRESOURCES_JUMP, AI_IF_DIF, AI_EASY_ATTACK,
+                          AI_KILL_THREAD, AI_WAIT_FINISHATTACK, AI_QUICK_ATTACK,
+                          AI_SET_RANDOMSEED, AI_IF_OWNED, AI_CREATE_UNIT, AI_WAIT_SECURE,
+                          AI_CAPT_EXPAND, AI_BUILD_BUNKERS, AI_WAIT_BUNKERS, AI_PLAYER_ENEMY,
+                          AI_PLAYER_ALLY, AI_DEFAULT_MIN, AI_DEFAULTBUILD_OFF, AI_SWITCH_RESCUE,
+                          AI_MOVE_DT, AI_ENTER_BUNKER, AI_VALUE_AREA, AI_TRANSPORTS_OFF,
+                          AI_CHECK_TRANSPORTS, AI_NUKE_RATE, AI_MAX_FORCE, AI_CLEAR_COMBATDATA,
+                          AI_BUILD_TURRETS, AI_WAIT_TURRETS, AI_DEFAULT_BUILD, AI_HARASS_FACTOR,
+                          AI_REGION_SIZE, AI_GET_OLDPEONS, AI_PLACE_GUARD, AI_GUARD_RESOURCES,
+                          AI_EVAL_HARASS, AI_CREEP, AI_PANIC, AI_RUSH, AI_SCOUT_WITH,
+                          AI_DEFINE_MAX, AI_TARGET_EXPANSION, AI_SET_ATTACKS, AI_SET_GENCMD,
+                          AI_MAKE_PATROL, AI_GIVE_MONEY, AI_PREP_DOWN, AI_ENTER_TRANSPORT,
+                          AI_EXIT_TRANSPORT, AI_SHAREDVISION_ON, AI_SHAREDVISION_OFF,
+                          AI_NUKE_LOCATION, AI_HARASS_LOCATION, AI_IMPLODE, AI_GUARD_ALL,
+                          AI_KILLABLE, AI_JUNKYARD_DOG, AI_FAKE_NUKE, AI_DISRUPTION_WEB,
+                          AI_RECALL_LOCATION, AI_CREATE_NUKE, AI_NUKE_POS, AI_HELP_IFTROUBLE,
+                          AI_ALLIES_WATCH, AI_TRY_TOWNPOINT};
+use scformats::unitsdata::{OrderType, UnitsDat, UF_BUILDING, UF_WORKER, UF_FLYER,
+                           UF_RESOURCE_DEPOT, UF_RESOURCE_CONTAINER, UF_REQUIRES_PSI,
+                           UF_PRODUCES_UNITS, UF_TWO_UNITS_IN_EGG, UNIT_VESPENE_GEYSER,
+                           UNIT_START_LOCATION};
+
+use gamedata::GameData;
+use combat::is_dying;
+use construction::{BuildError, can_place, issue_build_order, is_under_construction, snap_to_grid,
+                   train, trained_at};
+use economy::{Race, ResourceType, race, MAX_SUPPLY};
+use groupmove::issue_group_order;
+use morph::{is_lifted, morph_shell, start_morph};
+use orders::{Order, OrderTarget, issue_order};
+use tech::{Research, start_research};
+use unit_ecs::{UnitComponents, UnitSystems, PlanningMap, create_scunit};
+
+/// game ticks between two AI updates
+const AI_INTERVAL: u32 = 8;
+/// commands a thread runs at most per update, in case a script loops
+const MAX_COMMANDS: usize = 64;
+const TICKS_PER_SECOND: u32 = 15;
+/// computers can be players 1-8
+const AI_PLAYERS: usize = 8;
+
+// MapData::owners of computer players
+const OWNER_COMPUTER_GAME: u8 = 1;
+const OWNER_COMPUTER: u8 = 5;
+
+/// buildings, workers and resources this close belong to a town
+const TOWN_RANGE: i32 = 12 * 32;
+/// enemies this close to a building are fought off
+const DEFENSE_RANGE: i32 = 10 * 32;
+/// mineral fields this close form one expansion site
+const CLUSTER_RANGE: i32 = 8 * 32;
+/// how far to look for building spots, in tiles
+const BUILD_SEARCH_RADIUS: i32 = 12;
+const WORKERS_PER_MINERAL: u32 = 2;
+const WORKERS_PER_REFINERY: u32 = 3;
+const MAX_TOWN_WORKERS: u32 = 24;
+/// free supply (in half units) at which more is made
+const SUPPLY_MARGIN: u32 = 4;
+const SUPPLY_MARGIN_TIMING: u32 = 16;
+/// attack_do leaves without the missing units after this many ticks
+const ATTACK_GATHER_TIMEOUT: u32 = 120 * TICKS_PER_SECOND;
+/// priority of train, do_morph and player_need requests
+const SCRIPT_PRIORITY: u8 = 80;
+/// difficulty if_dif compares against unless set
+const DEFAULT_DIFFICULTY: u32 = 1;
+/// give_money tops up minerals and gas below this
+const GIVE_MONEY_BELOW: u32 = 500;
+const GIVE_MONEY_AMOUNT: u32 = 2000;
+
+// units.dat
+const UNIT_SCV: usize = 7;
+const UNIT_LARVA: usize = 35;
+const UNIT_DRONE: usize = 41;
+const UNIT_OVERLORD: usize = 42;
+const UNIT_PROBE: usize = 64;
+const UNIT_SUPPLY_DEPOT: usize = 109;
+const UNIT_PYLON: usize = 156;
+
+/// per race (zerg, terran, protoss)
+const WORKERS: [usize; 3] = [UNIT_DRONE, UNIT_SCV, UNIT_PROBE];
+const SUPPLY_UNITS: [usize; 3] = [UNIT_OVERLORD, UNIT_SUPPLY_DEPOT, UNIT_PYLON];
+
+/// what the AI needs to know about a unit
+#[derive(Clone, Copy)]
+struct AIUnit {
+    entity: Entity,
+    unit_id: usize,
+    player: usize,
+    x: i32,
+    y: i32,
+    complete: bool,
+    idle: bool,
+    /// building placed by the current or a queued build order
+    building: Option<usize>,
+    /// training, researching or morphing
+    busy: bool,
+    training: Option<usize>,
+    research: Option<Research>,
+    morph_into: Option<usize>,
+    harvest_target: Option<Entity>,
+    /// resources left to mine
+    resource: Option<ResourceType>,
+    flying: bool,
+    lifted: bool,
+}
+impl AIUnit {
+    fn near(&self, x: i32, y: i32, range: i32) -> bool {
+        dist2(self.x, self.y, x, y) <= range as i64 * range as i64
+    }
+}
+
+fn dist2(ax: i32, ay: i32, bx: i32, by: i32) -> i64 {
+    let dx = (ax - bx) as i64;
+    let dy = (ay - by) as i64;
+    dx * dx + dy * dy
+}
+
+fn is_build_order(order_type: OrderType) -> bool {
+    match order_type {
+        OrderType::PlaceBuilding |
+        OrderType::PlaceProtossBuilding |
+        OrderType::DroneStartBuild => true,
+        _ => false,
+    }
+}
+
+/// units that fight: no workers, buildings, overlords or larvae
+fn is_military(units_dat: &UnitsDat, unit_id: usize) -> bool {
+    let flags = units_dat.flags(unit_id);
+    !flags.contains(UF_WORKER) && !flags.contains(UF_BUILDING) && unit_id != UNIT_LARVA &&
+    units_dat.supply_required[unit_id] > 0
+}
+
+// FIXME: alliances
+fn is_enemy(me: usize, player: usize) -> bool {
+    player < AI_PLAYERS && player != me
+}
+
+fn nearest<'a, I: Iterator<Item = &'a AIUnit>>(units: I, x: i32, y: i32) -> Option<&'a AIUnit> {
+    units.min_by_key(|u| dist2(u.x, u.y, x, y))
+}
+
+/// all living units, without turrets and passengers
+fn ai_units(world: &World<UnitSystems>) -> Vec<AIUnit> {
+    let dh = &world.data;
+    let dat = dh.services.dat();
+    world.entities()
+        .filter(aspect!(<UnitComponents> all: [scunit] none: [subunit, passenger]), world)
+        .filter(|e| !is_dying(*e, dh))
+        .map(|e| {
+            let unit_id = dh.scunit[e].unit_id as usize;
+            let orders = &dh.scunit[e].orders;
+            let building = Some(&orders.current)
+                .into_iter()
+                .chain(orders.queue.iter())
+                .find(|o| is_build_order(o.order_type))
+                .and_then(|o| o.unit_type)
+                .map(|u| u as usize);
+            let training = if dh.train_queue.has(&e) {
+                dh.train_queue[e].queue.first().map(|&u| u as usize)
+            } else {
+                None
+            };
+            let research = if dh.research.has(&e) {
+                dh.research[e].current
+            } else {
+                None
+            };
+            let morph_into = if dh.morph.has(&e) {
+                Some(dh.morph[e].into as usize)
+            } else {
+                None
+            };
+            let harvest_target = if dh.worker.has(&e) {
+                dh.worker[e].harvest_target
+            } else {
+                None
+            };
+            let resource = if dh.resource.has(&e) && dh.resource[e].amount > 0 {
+                Some(dh.resource[e].resource_type)
+            } else {
+                None
+            };
+            let lifted = is_lifted(e, dh);
+            AIUnit {
+                entity: **e,
+                unit_id: unit_id,
+                player: dh.scimage[e].player_id,
+                x: dh.iscript_state[e].map_pos_x as i32,
+                y: dh.iscript_state[e].map_pos_y as i32,
+                complete: !is_under_construction(e, dh),
+                idle: orders.is_idle(),
+                building: building,
+                busy: training.is_some() || research.is_some() || morph_into.is_some(),
+                training: training,
+                research: research,
+                morph_into: morph_into,
+                harvest_target: harvest_target,
+                resource: resource,
+                flying: dat.units_dat.flags(unit_id).contains(UF_FLYER) || lifted,
+                lifted: lifted,
+            }
+        })
+        .collect()
+}
+
+/// how many `unit_id` a player has, optionally only near (x, y); `started`
+/// also counts those still being built, trained or morphed
+fn count_units(units_dat: &UnitsDat,
+               units: &[AIUnit],
+               player: usize,
+               unit_id: usize,
+               near: Option<(i32, i32)>,
+               started: bool)
+               -> u32 {
+    let per_egg = if units_dat.flags(unit_id).contains(UF_TWO_UNITS_IN_EGG) {
+        2
+    } else {
+        1
+    };
+    let mut count = 0;
+    for u in units.iter().filter(|u| u.player == player) {
+        if let Some((x, y)) = near {
+            if !u.near(x, y, TOWN_RANGE) {
+                continue;
+            }
+        }
+        if u.unit_id == unit_id && (u.complete || started) {
+            count += 1;
+        }
+        if started {
+            if u.morph_into == Some(unit_id) {
+                count += per_egg;
+            }
+            if u.training == Some(unit_id) {
+                count += 1;
+            }
+            if u.building == Some(unit_id) {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+/// the race a player plays, from the map or its units
+fn player_race(world: &World<UnitSystems>,
+               units_dat: &UnitsDat,
+               units: &[AIUnit],
+               player: usize)
+               -> Race {
+    let side = match world.systems.scunit_stepping_sys.map {
+        Some(ref map) if player < map.scmap.data.sides.len() => map.scmap.data.sides[player],
+        _ => 0xff,
+    };
+    match side {
+        0 => Race::Zerg,
+        1 => Race::Terran,
+        2 => Race::Protoss,
+        _ => {
+            units.iter()
+                .filter(|u| u.player == player)
+                .filter_map(|u| race(units_dat, u.unit_id))
+                .next()
+                .unwrap_or(Race::Terran)
+        }
+    }
+}
+
+/// the first spot for `unit_id` in growing squares around (x, y), `stride`
+/// tiles apart
+fn search_spot(world: &World<UnitSystems>,
+               units_dat: &UnitsDat,
+               builder: Entity,
+               unit_id: usize,
+               x: i32,
+               y: i32,
+               stride: i32)
+               -> Option<(i32, i32)> {
+    for r in 0..BUILD_SEARCH_RADIUS / stride + 1 {
+        for dy in -r..r + 1 {
+            for dx in -r..r + 1 {
+                if dx.abs() != r && dy.abs() != r {
+                    continue;
+                }
+                let (sx, sy) =
+                    snap_to_grid(units_dat, unit_id, x + dx * stride * 32, y + dy * stride * 32);
+                if sx >= 0 && sy >= 0 && can_place(world, builder, unit_id, sx, sy).is_ok() {
+                    return Some((sx, sy));
+                }
+            }
+        }
+    }
+    None
+}
+
+#[derive(Clone)]
+struct Thread {
+    pos: AIScriptPos,
+    /// ticks left to wait
+    wait: u32,
+    /// return positions of call
+    stack: Vec<AIScriptPos>,
+    town: usize,
+    /// location the script was started at, 0 for none
+    location: u32,
+}
+
+struct Town {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Goal {
+    Unit(usize),
+    /// up to a level
+    Upgrade(usize, u8),
+    Tech(usize),
+}
+
+struct Request {
+    goal: Goal,
+    count: u32,
+    priority: u8,
+    town: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AttackState {
+    Idle,
+    /// ticks spent gathering
+    Preparing(u32),
+    Attacking,
+}
+
+/// why an AI script or one of its threads stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AIError {
+    /// the script isn't in aiscript.bin or bwscript.bin
+    MissingScript(u32),
+    /// no melee script for the player's race
+    NoMeleeScript,
+    /// the thread ran into something that isn't a command
+    BadCommand(AIScriptPos),
+    UnknownOpcode(u8, AIScriptPos),
+    /// the script ended itself with fatal_error
+    FatalError(AIScriptPos),
+}
+
+/// what a command does to its thread
+enum Step {
+    Next,
+    Jump(AIScriptPos),
+    /// go on with the next command after some ticks
+    Wait(u32),
+    /// run the command again next update
+    Block,
+    End,
+}
+
+struct AIPlayer {
+    player: usize,
+    race: Race,
+    threads: Vec<Thread>,
+    /// the first one is the main town
+    towns: Vec<Town>,
+    /// highest priority first
+    requests: Vec<Request>,
+    /// (unit, count) of the next attack
+    attack_force: Vec<(usize, u32)>,
+    attackers: Vec<Entity>,
+    attack: AttackState,
+    /// unit types defending against ground and air attackers, any if empty
+    defense_use: [Vec<usize>; 2],
+    /// (unit, count) built when attacked from the ground and the air
+    defense_build: [Vec<(usize, u32)>; 2],
+    /// 1 if the last attacker was in the air, None without one
+    threat: Option<usize>,
+    /// build supply ahead of time
+    farms_timing: bool,
+    /// (unit, count) the requests are limited to
+    max_counts: Vec<(usize, u32)>,
+    /// started once the main town is mined out
+    panic: Option<AIScriptPos>,
+    difficulty: u32,
+    /// threads that stopped with an error
+    errors: Vec<AIError>,
+    /// texts of debug commands
+    messages: Vec<String>,
+}
+impl AIPlayer {
+    fn new(player: usize, race: Race, difficulty: u32) -> Self {
+        AIPlayer {
+            player: player,
+            race: race,
+            threads: Vec::new(),
+            towns: Vec::new(),
+            requests: Vec::new(),
+            attack_force: Vec::new(),
+            attackers: Vec::new(),
+            attack: AttackState::Idle,
+            defense_use: [Vec::new(), Vec::new()],
+            defense_build: [Vec::new(), Vec::new()],
+            threat: None,
+            farms_timing: true,
+            max_counts: Vec::new(),
+            panic: None,
+            difficulty: difficulty,
+            errors: Vec::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, world: &mut World<UnitSystems>, gd: &GameData, ticks: u32) {
+        let units = ai_units(world);
+        self.run_threads(world, gd, &units, ticks);
+        let units = ai_units(world);
+        self.update_towns(&gd.units_dat, &units);
+        self.check_panic(&units);
+        self.gather(world, &gd.units_dat, &units);
+        self.defend(world, &gd.units_dat, &units);
+        self.command_attack(world, &gd.units_dat, &units);
+        self.produce(world, gd);
+    }
+
+    fn run_threads(&mut self,
+                   world: &mut World<UnitSystems>,
+                   gd: &GameData,
+                   units: &[AIUnit],
+                   ticks: u32) {
+        let threads = mem::replace(&mut self.threads, Vec::new());
+        let mut running = Vec::with_capacity(threads.len());
+        for mut thread in threads {
+            if self.run_thread(world, gd, units, &mut thread, ticks) {
+                running.push(thread);
+            }
+        }
+        // threads started by multirun and expand
+        running.extend(self.threads.drain(..));
+        self.threads = running;
+    }
+
+    /// runs a thread until it waits, false when it ended
+    fn run_thread(&mut self,
+                  world: &mut World<UnitSystems>,
+                  gd: &GameData,
+                  units: &[AIUnit],
+                  thread: &mut Thread,
+                  ticks: u32)
+                  -> bool {
+        if thread.wait > AI_INTERVAL {
+            thread.wait -= AI_INTERVAL;
+            return true;
+        }
+        thread.wait = 0;
+        for _ in 0..MAX_COMMANDS {
+            let cmd = match gd.ai_scripts.decode(thread.pos) {
+                Some(cmd) => cmd,
+                None => {
+                    self.errors.push(AIError::BadCommand(thread.pos));
+                    return false;
+                }
+            };
+            match self.execute(world, gd, units, thread, &cmd, ticks) {
+                Step::Next => thread.pos.offset = cmd.next,
+                Step::Jump(pos) => thread.pos = pos,
+                Step::Wait(wait) => {
+                    thread.pos.offset = cmd.next;
+                    thread.wait = wait;
+                    return true;
+                }
+                Step::Block => return true,
+                Step::End => return false,
+            }
+        }
+        true
+    }
+
+    fn execute(&mut self,
+               world: &mut World<UnitSystems>,
+               gd: &GameData,
+               units: &[AIUnit],
+               thread: &mut Thread,
+               cmd: &AICommand,
+               ticks: u32)
+               -> Step {
+        let me = self.player;
+        let dat = &gd.units_dat;
+        let a = &cmd.args;
+        let file = thread.pos.file;
+        let block = |i: usize| {
+            AIScriptPos {
+                file: file,
+                offset: cmd.args[i] as usize,
+            }
+        };
+        let jump_if = |cond: bool, i: usize| if cond {
+            Step::Jump(block(i))
+        } else {
+            Step::Next
+        };
+        let count = |unit_id: u32, started: bool| {
+            count_units(dat, units, me, unit_id as usize, None, started)
+        };
+        match cmd.opcode {
+            AI_GOTO => Step::Jump(block(0)),
+            AI_NOTOWNS_JUMP => jump_if(count(a[0], true) == 0, 1),
+            AI_WAIT => Step::Wait(a[0]),
+            AI_START_TOWN | AI_START_CAMPAIGN => {
+                thread.town = self.start_town(world, dat, units, 0);
+                Step::Next
+            }
+            AI_START_AREATOWN => {
+                thread.town = self.start_town(world, dat, units, thread.location);
+                Step::Next
+            }
+            AI_EXPAND => {
+                if self.towns.len() < a[0] as usize {
+                    if let Some((x, y)) = self.find_expansion(dat, units) {
+                        self.towns.push(Town { x: x, y: y });
+                        self.threads.push(Thread {
+                            pos: block(1),
+                            wait: 0,
+                            stack: Vec::new(),
+                            town: self.towns.len() - 1,
+                            location: thread.location,
+                        });
+                    }
+                }
+                Step::Next
+            }
+            AI_BUILD => {
+                self.request(Goal::Unit(a[1] as usize), a[0], a[2] as u8, thread.town);
+                Step::Next
+            }
+            AI_PLAYER_NEED | AI_DO_MORPH => {
+                self.request(Goal::Unit(a[1] as usize), a[0], SCRIPT_PRIORITY, thread.town);
+                Step::Next
+            }
+            AI_UPGRADE => {
+                self.request(Goal::Upgrade(a[1] as usize, a[0] as u8), 1, a[2] as u8, thread.town);
+                Step::Next
+            }
+            AI_TECH => {
+                self.request(Goal::Tech(a[0] as usize), 1, a[1] as u8, thread.town);
+                Step::Next
+            }
+            AI_WAIT_BUILD | AI_WAIT_FORCE | AI_WAIT_TRAIN => {
+                if count(a[1], false) >= a[0] {
+                    Step::Next
+                } else {
+                    Step::Block
+                }
+            }
+            AI_WAIT_BUILDSTART => {
+                if count(a[1], true) >= a[0] {
+                    Step::Next
+                } else {
+                    Step::Block
+                }
+            }
+            AI_TRAIN => {
+                self.request(Goal::Unit(a[1] as usize), a[0], SCRIPT_PRIORITY, thread.town);
+                if count(a[1], false) >= a[0] {
+                    Step::Next
+                } else {
+                    Step::Block
+                }
+            }
+            AI_WAIT_UPGRADES => {
+                if self.upgrades_pending(units) {
+                    Step::Block
+                } else {
+                    Step::Next
+                }
+            }
+            AI_ATTACK_CLEAR => {
+                self.attack_force.clear();
+                Step::Next
+            }
+            AI_ATTACK_ADD | AI_EASY_ATTACK => {
+                self.attack_force.push((a[1] as usize, a[0]));
+                Step::Next
+            }
+            AI_ATTACK_PREPARE => {
+                self.attack = AttackState::Preparing(0);
+                Step::Next
+            }
+            AI_ATTACK_DO => {
+                let attack = self.attack;
+                match attack {
+                    AttackState::Attacking => Step::Next,
+                    AttackState::Preparing(waited) if waited < ATTACK_GATHER_TIMEOUT &&
+                                                      !self.force_ready(units) => Step::Block,
+                    _ => {
+                        self.launch_attack(world, dat, units, false);
+                        Step::Next
+                    }
+                }
+            }
+            AI_QUICK_ATTACK => {
+                self.launch_attack(world, dat, units, false);
+                Step::Next
+            }
+            AI_SEND_SUICIDE => {
+                self.launch_attack(world, dat, units, true);
+                Step::Next
+            }
+            AI_WAIT_FINISHATTACK => {
+                if self.attack == AttackState::Attacking {
+                    Step::Block
+                } else {
+                    Step::Next
+                }
+            }
+            AI_DEFENSEBUILD_GG | AI_DEFENSEBUILD_AG => {
+                self.defense_build[0].push((a[1] as usize, a[0]));
+                Step::Next
+            }
+            AI_DEFENSEBUILD_GA | AI_DEFENSEBUILD_AA => {
+                self.defense_build[1].push((a[1] as usize, a[0]));
+                Step::Next
+            }
+            AI_DEFENSEUSE_GG | AI_DEFENSEUSE_AG => {
+                self.defense_use[0].push(a[1] as usize);
+                Step::Next
+            }
+            AI_DEFENSEUSE_GA | AI_DEFENSEUSE_AA => {
+                self.defense_use[1].push(a[1] as usize);
+                Step::Next
+            }
+            AI_DEFENSECLEAR_GG => {
+                self.defense_use[0].clear();
+                self.defense_build[0].clear();
+                Step::Next
+            }
+            AI_DEFENSECLEAR_AA => {
+                self.defense_use[1].clear();
+                self.defense_build[1].clear();
+                Step::Next
+            }
+            AI_FARMS_NOTIMING => {
+                self.farms_timing = false;
+                Step::Next
+            }
+            AI_FARMS_TIMING => {
+                self.farms_timing = true;
+                Step::Next
+            }
+            AI_CALL => {
+                thread.stack.push(AIScriptPos {
+                    file: file,
+                    offset: cmd.next,
+                });
+                Step::Jump(block(0))
+            }
+            AI_RETURN => {
+                match thread.stack.pop() {
+                    Some(pos) => Step::Jump(pos),
+                    None => Step::End,
+                }
+            }
+            AI_MULTIRUN => {
+                self.threads.push(Thread {
+                    pos: block(0),
+                    wait: 0,
+                    stack: Vec::new(),
+                    town: thread.town,
+                    location: thread.location,
+                });
+                Step::Next
+            }
+            AI_STOP | AI_KILL_THREAD => Step::End,
+            AI_RANDOM_JUMP => {
+                let roll = world.data.services.rng.rand_u8() as u32;
+                jump_if(roll < a[0], 1)
+            }
+            AI_TIME_JUMP => jump_if(ticks >= a[0] * 60 * TICKS_PER_SECOND, 1),
+            AI_RACE_JUMP => {
+                // FIXME: the original looks at the enemy it is going to attack
+                let enemy = units.iter()
+                    .filter(|u| is_enemy(me, u.player))
+                    .filter_map(|u| race(dat, u.unit_id))
+                    .next();
+                match enemy {
+                    Some(Race::Terran) => Step::Jump(block(0)),
+                    Some(Race::Zerg) => Step::Jump(block(1)),
+                    Some(Race::Protoss) => Step::Jump(block(2)),
+                    None => Step::Next,
+                }
+            }
+            AI_RESOURCES_JUMP => {
+                let state = &world.data.services.players[me];
+                jump_if(state.minerals >= a[0] && state.gas >= a[1], 2)
+            }
+            AI_ENEMYRESOURCES_JUMP => {
+                let players = &world.data.services.players;
+                let rich = (0..AI_PLAYERS).any(|p| {
+                    is_enemy(me, p) && units.iter().any(|u| u.player == p) &&
+                    players[p].minerals >= a[0] && players[p].gas >= a[1]
+                });
+                jump_if(rich, 2)
+            }
+            AI_ENEMYOWNS_JUMP => {
+                let owns = units.iter()
+                    .any(|u| is_enemy(me, u.player) && u.unit_id == a[0] as usize);
+                jump_if(owns, 1)
+            }
+            AI_IF_OWNED => jump_if(count(a[0], true) > 0, 1),
+            AI_IF_DIF => {
+                let cond = if a[0] == 0 {
+                    self.difficulty < a[1]
+                } else {
+                    self.difficulty > a[1]
+                };
+                jump_if(cond, 2)
+            }
+            // FIXME: assumes every expansion can be reached by ground
+            AI_GROUNDMAP_JUMP => Step::Jump(block(0)),
+            AI_DEBUG => {
+                self.messages.push(cmd.text.clone().unwrap_or_else(String::new));
+                Step::Jump(block(0))
+            }
+            AI_FATAL_ERROR => {
+                self.errors.push(AIError::FatalError(thread.pos));
+                Step::End
+            }
+            AI_SET_RANDOMSEED => {
+                world.data.services.rng.seed = a[0];
+                Step::Next
+            }
+            AI_CREATE_UNIT => {
+                create_scunit(world, gd, a[0] as usize, a[1] as u16, a[2] as u16, me);
+                Step::Next
+            }
+            AI_WAIT_SECURE => {
+                if self.threat.is_some() {
+                    Step::Block
+                } else {
+                    Step::Next
+                }
+            }
+            AI_DEFINE_MAX => {
+                let unit_id = a[1] as usize;
+                self.max_counts.retain(|&(u, _)| u != unit_id);
+                self.max_counts.push((unit_id, a[0]));
+                for r in self.requests.iter_mut().filter(|r| r.goal == Goal::Unit(unit_id)) {
+                    r.count = r.count.min(a[0]);
+                }
+                Step::Next
+            }
+            AI_GIVE_MONEY => {
+                let state = &mut world.data.services.players[me];
+                if state.minerals < GIVE_MONEY_BELOW {
+                    state.minerals += GIVE_MONEY_AMOUNT;
+                }
+                if state.gas < GIVE_MONEY_BELOW {
+                    state.gas += GIVE_MONEY_AMOUNT;
+                }
+                Step::Next
+            }
+            AI_PANIC => {
+                self.panic = Some(block(0));
+                Step::Next
+            }
+            // FIXME: what these look at (region sizes, harassment, enemy
+            // rushes, allied towns) isn't tracked, so they never jump
+            AI_REGION_SIZE | AI_EVAL_HARASS | AI_RUSH | AI_ALLIES_WATCH | AI_TRY_TOWNPOINT => {
+                Step::Next
+            }
+            // bunkers, turrets, transports, nukes, guards, scouting and the
+            // settings of the original's unit management aren't simulated
+            AI_CAPT_EXPAND | AI_BUILD_BUNKERS | AI_WAIT_BUNKERS | AI_PLAYER_ENEMY |
+            AI_PLAYER_ALLY | AI_DEFAULT_MIN | AI_DEFAULTBUILD_OFF | AI_SWITCH_RESCUE |
+            AI_MOVE_DT | AI_ENTER_BUNKER | AI_VALUE_AREA | AI_TRANSPORTS_OFF |
+            AI_CHECK_TRANSPORTS | AI_NUKE_RATE | AI_MAX_FORCE | AI_CLEAR_COMBATDATA |
+            AI_BUILD_TURRETS | AI_WAIT_TURRETS | AI_DEFAULT_BUILD | AI_HARASS_FACTOR |
+            AI_GET_OLDPEONS | AI_PLACE_GUARD | AI_GUARD_RESOURCES | AI_CREEP | AI_SCOUT_WITH |
+            AI_TARGET_EXPANSION | AI_SET_ATTACKS | AI_SET_GENCMD | AI_MAKE_PATROL |
+            AI_PREP_DOWN | AI_ENTER_TRANSPORT | AI_EXIT_TRANSPORT | AI_SHAREDVISION_ON |
+            AI_SHAREDVISION_OFF | AI_NUKE_LOCATION | AI_HARASS_LOCATION | AI_IMPLODE |
+            AI_GUARD_ALL | AI_KILLABLE | AI_JUNKYARD_DOG | AI_FAKE_NUKE | AI_DISRUPTION_WEB |
+            AI_RECALL_LOCATION | AI_CREATE_NUKE | AI_NUKE_POS | AI_HELP_IFTROUBLE => Step::Next,
+            opcode => {
+                self.errors.push(AIError::UnknownOpcode(opcode, thread.pos));
+                Step::End
+            }
+        }
+    }
+
+    /// starts the panic block once the main town is mined out
+    fn check_panic(&mut self, units: &[AIUnit]) {
+        let mined_out = match (self.panic, self.towns.first()) {
+            (Some(_), Some(town)) => {
+                !units.iter().any(|u| {
+                    u.resource == Some(ResourceType::Minerals) &&
+                    u.near(town.x, town.y, TOWN_RANGE)
+                })
+            }
+            _ => false,
+        };
+        if mined_out {
+            let pos = self.panic.take().unwrap();
+            self.threads.push(Thread {
+                pos: pos,
+                wait: 0,
+                stack: Vec::new(),
+                town: 0,
+                location: 0,
+            });
+        }
+    }
+
+    /// adds a request or raises the count and priority of an existing one
+    fn request(&mut self, goal: Goal, count: u32, priority: u8, town: usize) {
+        let count = match goal {
+            Goal::Unit(unit_id) => {
+                self.max_counts
+                    .iter()
+                    .find(|&&(u, _)| u == unit_id)
+                    .map_or(count, |&(_, max)| count.min(max))
+            }
+            _ => count,
+        };
+        let existing = self.requests.iter().position(|r| r.goal == goal && r.town == town);
+        match existing {
+            Some(i) => {
+                let r = &mut self.requests[i];
+                r.count = r.count.max(count);
+                r.priority = r.priority.max(priority);
+            }
+            None => {
+                self.requests.push(Request {
+                    goal: goal,
+                    count: count,
+                    priority: priority,
+                    town: town,
+                })
+            }
+        }
+        self.requests.sort_by(|a, b| b.priority.cmp(&a.priority));
+    }
+
+    /// is requested research still running
+    fn upgrades_pending(&self, units: &[AIUnit]) -> bool {
+        self.requests.iter().any(|r| match r.goal {
+            Goal::Unit(_) => false,
+            Goal::Upgrade(upgrade, _) => self.is_researching(units, Research::Upgrade(upgrade)),
+            Goal::Tech(tech) => self.is_researching(units, Research::Tech(tech)),
+        })
+    }
+
+    fn is_researching(&self, units: &[AIUnit], research: Research) -> bool {
+        units.iter().any(|u| u.player == self.player && u.research == Some(research))
+    }
+
+    /// where a town is, the main town or any own unit if it doesn't exist
+    fn town_pos(&self, town: usize, units: &[AIUnit]) -> Option<(i32, i32)> {
+        self.towns
+            .get(town)
+            .or(self.towns.first())
+            .map(|t| (t.x, t.y))
+            .or_else(|| units.iter().find(|u| u.player == self.player).map(|u| (u.x, u.y)))
+    }
+
+    /// the town at the first resource depot or start location, or at a
+    /// location if `location` isn't 0
+    fn start_town(&mut self,
+                  world: &World<UnitSystems>,
+                  units_dat: &UnitsDat,
+                  units: &[AIUnit],
+                  location: u32)
+                  -> usize {
+        let me = self.player;
+        let pos = world.data
+            .services
+            .locations
+            .center(location)
+            .or_else(|| {
+                units.iter()
+                    .find(|u| {
+                        u.player == me && units_dat.flags(u.unit_id).contains(UF_RESOURCE_DEPOT)
+                    })
+                    .map(|u| (u.x, u.y))
+            })
+            .or_else(|| {
+                units.iter()
+                    .find(|u| u.player == me && u.unit_id == UNIT_START_LOCATION as usize)
+                    .map(|u| (u.x, u.y))
+            })
+            .or_else(|| units.iter().find(|u| u.player == me).map(|u| (u.x, u.y)));
+        let (x, y) = match pos {
+            Some(pos) => pos,
+            None => return 0,
+        };
+        let range = TOWN_RANGE as i64 * TOWN_RANGE as i64;
+        if let Some(i) = self.towns.iter().position(|t| dist2(t.x, t.y, x, y) <= range) {
+            return i;
+        }
+        self.towns.push(Town { x: x, y: y });
+        self.towns.len() - 1
+    }
+
+    /// the center of the mineral cluster closest to the main town that
+    /// nobody has taken
+    fn find_expansion(&self, units_dat: &UnitsDat, units: &[AIUnit]) -> Option<(i32, i32)> {
+        let (mx, my) = match self.towns.first() {
+            Some(t) => (t.x, t.y),
+            None => return None,
+        };
+        let range = TOWN_RANGE as i64 * TOWN_RANGE as i64;
+        let minerals: Vec<&AIUnit> =
+            units.iter().filter(|u| u.resource == Some(ResourceType::Minerals)).collect();
+        let mut best = None;
+        for m in &minerals {
+            let cluster: Vec<&&AIUnit> =
+                minerals.iter().filter(|o| o.near(m.x, m.y, CLUSTER_RANGE)).collect();
+            let n = cluster.len() as i32;
+            let cx = cluster.iter().map(|o| o.x).sum::<i32>() / n;
+            let cy = cluster.iter().map(|o| o.y).sum::<i32>() / n;
+            let taken = self.towns.iter().any(|t| dist2(t.x, t.y, cx, cy) <= range) ||
+                        units.iter().any(|u| {
+                units_dat.flags(u.unit_id).contains(UF_RESOURCE_DEPOT) &&
+                u.near(cx, cy, TOWN_RANGE)
+            });
+            if taken {
+                continue;
+            }
+            let d = dist2(cx, cy, mx, my);
+            if best.map_or(true, |(bd, _, _)| d < bd) {
+                best = Some((d, cx, cy));
+            }
+        }
+        best.map(|(_, x, y)| (x, y))
+    }
+
+    /// towns move onto their resource depot once there is one
+    fn update_towns(&mut self, units_dat: &UnitsDat, units: &[AIUnit]) {
+        let me = self.player;
+        for town in &mut self.towns {
+            let depot = nearest(units.iter().filter(|u| {
+                                    u.player == me && u.complete &&
+                                    units_dat.flags(u.unit_id).contains(UF_RESOURCE_DEPOT) &&
+                                    u.near(town.x, town.y, TOWN_RANGE)
+                                }),
+                                town.x,
+                                town.y)
+                .map(|u| (u.x, u.y));
+            if let Some((x, y)) = depot {
+                town.x = x;
+                town.y = y;
+            }
+        }
+    }
+
+    /// sends idle workers to the minerals of their town and fills the
+    /// refineries
+    fn gather(&self, world: &mut World<UnitSystems>, units_dat: &UnitsDat, units: &[AIUnit]) {
+        let me = self.player;
+        let mut sent = Vec::<Entity>::new();
+        for town in &self.towns {
+            let has_depot = units.iter().any(|u| {
+                u.player == me && u.complete && !u.lifted &&
+                units_dat.flags(u.unit_id).contains(UF_RESOURCE_DEPOT) &&
+                u.near(town.x, town.y, TOWN_RANGE)
+            });
+            if !has_depot {
+                continue;
+            }
+            let minerals: Vec<&AIUnit> = units.iter()
+                .filter(|u| {
+                    u.resource == Some(ResourceType::Minerals) && u.near(town.x, town.y, TOWN_RANGE)
+                })
+                .collect();
+            let workers: Vec<&AIUnit> = units.iter()
+                .filter(|u| {
+                    u.player == me && u.complete && u.building.is_none() &&
+                    units_dat.flags(u.unit_id).contains(UF_WORKER) &&
+                    u.near(town.x, town.y, TOWN_RANGE)
+                })
+                .collect();
+            let refineries = units.iter().filter(|u| {
+                u.player == me && u.complete && u.resource == Some(ResourceType::Gas) &&
+                u.near(town.x, town.y, TOWN_RANGE)
+            });
+            for r in refineries {
+                let assigned = workers.iter()
+                    .filter(|w| w.harvest_target == Some(r.entity))
+                    .count() as u32;
+                if assigned >= WORKERS_PER_REFINERY {
+                    continue;
+                }
+                let worker = nearest(workers.iter().cloned().filter(|w| {
+                                         !sent.contains(&w.entity) &&
+                                         w.harvest_target.map_or(true, |t| {
+                                             minerals.iter().any(|m| m.entity == t)
+                                         })
+                                     }),
+                                     r.x,
+                                     r.y)
+                    .map(|w| w.entity);
+                if let Some(worker) = worker {
+                    let order = Order::new(OrderType::Harvest1, OrderTarget::Unit(r.entity));
+                    issue_order(world, worker, order, false);
+                    sent.push(worker);
+                }
+            }
+            for w in workers.iter().filter(|w| w.idle && !sent.contains(&w.entity)) {
+                let mineral = minerals.iter()
+                    .min_by_key(|m| {
+                        workers.iter().filter(|o| o.harvest_target == Some(m.entity)).count()
+                    })
+                    .map(|m| m.entity);
+                if let Some(mineral) = mineral {
+                    issue_order(world,
+                                w.entity,
+                                Order::new(OrderType::Harvest1, OrderTarget::Unit(mineral)),
+                                false);
+                }
+            }
+        }
+    }
+
+    /// idle units near an enemy that came close to a building attack it
+    fn defend(&mut self, world: &mut World<UnitSystems>, units_dat: &UnitsDat, units: &[AIUnit]) {
+        let me = self.player;
+        let buildings: Vec<&AIUnit> = units.iter()
+            .filter(|u| u.player == me && units_dat.flags(u.unit_id).contains(UF_BUILDING))
+            .collect();
+        let threat = units.iter()
+            .find(|u| {
+                is_enemy(me, u.player) && u.unit_id != UNIT_START_LOCATION as usize &&
+                buildings.iter().any(|b| b.near(u.x, u.y, DEFENSE_RANGE))
+            })
+            .cloned();
+        let threat = match threat {
+            Some(threat) => threat,
+            None => {
+                self.threat = None;
+                return;
+            }
+        };
+        let air = if threat.flying { 1 } else { 0 };
+        self.threat = Some(air);
+        let defenders: Vec<Entity> = units.iter()
+            .filter(|u| {
+                u.player == me && u.complete && u.idle && is_military(units_dat, u.unit_id) &&
+                !self.attackers.contains(&u.entity) &&
+                (self.defense_use[air].is_empty() ||
+                 self.defense_use[air].contains(&u.unit_id)) &&
+                u.near(threat.x, threat.y, 2 * TOWN_RANGE)
+            })
+            .map(|u| u.entity)
+            .collect();
+        if !defenders.is_empty() {
+            issue_group_order(world,
+                              &defenders,
+                              Order::new(OrderType::AttackMove,
+                                         OrderTarget::Position(threat.x, threat.y)),
+                              false);
+        }
+    }
+
+    fn force_ready(&self, units: &[AIUnit]) -> bool {
+        self.attack_force.iter().all(|&(unit_id, count)| {
+            let have = units.iter()
+                .filter(|u| u.unit_id == unit_id && self.attackers.contains(&u.entity))
+                .count() as u32;
+            have >= count
+        })
+    }
+
+    /// adds finished units of the wanted types to the attack force
+    fn gather_force(&mut self, units: &[AIUnit]) {
+        let me = self.player;
+        let force = self.attack_force.clone();
+        for (unit_id, count) in force {
+            let candidates: Vec<Entity> = {
+                let attackers = &self.attackers;
+                let have = units.iter()
+                    .filter(|u| u.unit_id == unit_id && attackers.contains(&u.entity))
+                    .count() as u32;
+                units.iter()
+                    .filter(|u| {
+                        u.player == me && u.complete && u.unit_id == unit_id &&
+                        !attackers.contains(&u.entity)
+                    })
+                    .take(count.saturating_sub(have) as usize)
+                    .map(|u| u.entity)
+                    .collect()
+            };
+            self.attackers.extend(candidates);
+        }
+    }
+
+    /// the closest enemy building to (x, y), or any enemy if there is none
+    fn attack_target(&self,
+                     units_dat: &UnitsDat,
+                     units: &[AIUnit],
+                     x: i32,
+                     y: i32)
+                     -> Option<(i32, i32)> {
+        let me = self.player;
+        let enemies = || {
+            units.iter().filter(move |u| {
+                is_enemy(me, u.player) && u.unit_id != UNIT_START_LOCATION as usize
+            })
+        };
+        nearest(enemies().filter(|u| units_dat.flags(u.unit_id).contains(UF_BUILDING)), x, y)
+            .or_else(|| nearest(enemies(), x, y))
+            .map(|u| (u.x, u.y))
+    }
+
+    /// sends the attack force, or with `all` every military unit, to the
+    /// closest enemy building
+    fn launch_attack(&mut self,
+                     world: &mut World<UnitSystems>,
+                     units_dat: &UnitsDat,
+                     units: &[AIUnit],
+                     all: bool) {
+        let me = self.player;
+        self.gather_force(units);
+        if all || self.attack_force.is_empty() {
+            let military: Vec<Entity> = units.iter()
+                .filter(|u| {
+                    u.player == me && u.complete && is_military(units_dat, u.unit_id) &&
+                    !self.attackers.contains(&u.entity)
+                })
+                .map(|u| u.entity)
+                .collect();
+            self.attackers.extend(military);
+        }
+        let from = match self.town_pos(0, units) {
+            Some(pos) => pos,
+            None => return,
+        };
+        let target = match self.attack_target(units_dat, units, from.0, from.1) {
+            Some(target) => target,
+            None => {
+                self.attack = AttackState::Idle;
+                return;
+            }
+        };
+        if self.attackers.is_empty() {
+            self.attack = AttackState::Idle;
+            return;
+        }
+        issue_group_order(world,
+                          &self.attackers,
+                          Order::new(OrderType::AttackMove,
+                                     OrderTarget::Position(target.0, target.1)),
+                          false);
+        self.attack = AttackState::Attacking;
+    }
+
+    /// gathers the attack force, keeps attackers going until they are dead
+    /// or there is nothing left to attack
+    fn command_attack(&mut self,
+                      world: &mut World<UnitSystems>,
+                      units_dat: &UnitsDat,
+                      units: &[AIUnit]) {
+        self.attackers.retain(|e| units.iter().any(|u| u.entity == *e));
+        let attack = self.attack;
+        match attack {
+            AttackState::Idle => {}
+            AttackState::Preparing(waited) => {
+                self.attack = AttackState::Preparing(waited + AI_INTERVAL);
+                self.gather_force(units);
+            }
+            AttackState::Attacking => {
+                if self.attackers.is_empty() {
+                    self.attack = AttackState::Idle;
+                    return;
+                }
+                let idle: Vec<AIUnit> = units.iter()
+                    .filter(|u| u.idle && self.attackers.contains(&u.entity))
+                    .cloned()
+                    .collect();
+                for u in idle {
+                    match self.attack_target(units_dat, units, u.x, u.y) {
+                        Some((x, y)) => {
+                            issue_order(world,
+                                        u.entity,
+                                        Order::new(OrderType::AttackMove,
+                                                   OrderTarget::Position(x, y)),
+                                        false);
+                        }
+                        None => {
+                            self.attackers.clear();
+                            self.attack = AttackState::Idle;
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// starts what is missing: supply, workers, the requests by priority,
+    /// defense and the attack force, until the money runs out
+    fn produce(&mut self, world: &mut World<UnitSystems>, gd: &GameData) {
+        let me = self.player;
+        let dat = &gd.units_dat;
+        let mut units = ai_units(world);
+        // (what, how many, town, only counted near)
+        let mut wanted = Vec::<(Goal, u32, usize, Option<(i32, i32)>)>::new();
+
+        let r = self.race as usize;
+        let (used, provided) = {
+            let supply = &world.data.services.players[me].supply;
+            (supply.used[r], supply.provided[r])
+        };
+        let farm = SUPPLY_UNITS[r];
+        let farms = count_units(dat, &units, me, farm, None, false);
+        let pending = count_units(dat, &units, me, farm, None, true) - farms;
+        let margin = if self.farms_timing {
+            SUPPLY_MARGIN_TIMING
+        } else {
+            SUPPLY_MARGIN
+        };
+        if provided < MAX_SUPPLY && pending == 0 && used + margin >= provided {
+            wanted.push((Goal::Unit(farm), farms + 1, 0, None));
+        }
+
+        for (i, town) in self.towns.iter().enumerate() {
+            let minerals = units.iter()
+                .filter(|u| {
+                    u.resource == Some(ResourceType::Minerals) && u.near(town.x, town.y, TOWN_RANGE)
+                })
+                .count() as u32;
+            let refineries = units.iter()
+                .filter(|u| {
+                    u.player == me && u.complete && u.resource == Some(ResourceType::Gas) &&
+                    u.near(town.x, town.y, TOWN_RANGE)
+                })
+                .count() as u32;
+            let workers = (WORKERS_PER_MINERAL * minerals + WORKERS_PER_REFINERY * refineries)
+                .min(MAX_TOWN_WORKERS);
+            wanted.push((Goal::Unit(WORKERS[r]), workers, i, Some((town.x, town.y))));
+        }
+
+        for req in &self.requests {
+            let near = match req.goal {
+                Goal::Unit(unit_id) if dat.flags(unit_id).contains(UF_BUILDING) => {
+                    self.towns.get(req.town).map(|t| (t.x, t.y))
+                }
+                _ => None,
+            };
+            wanted.push((req.goal, req.count, req.town, near));
+        }
+        if let Some(air) = self.threat {
+            for &(unit_id, count) in &self.defense_build[air] {
+                wanted.push((Goal::Unit(unit_id), count, 0, None));
+            }
+        }
+        if let AttackState::Preparing(_) = self.attack {
+            for &(unit_id, count) in &self.attack_force {
+                wanted.push((Goal::Unit(unit_id), count, 0, None));
+            }
+        }
+
+        for (goal, count, town, near) in wanted {
+            let result = match goal {
+                Goal::Unit(unit_id) => {
+                    if count_units(dat, &units, me, unit_id, near, true) >= count {
+                        continue;
+                    }
+                    self.make_unit(world, gd, &units, unit_id, town)
+                }
+                Goal::Upgrade(upgrade, level) => {
+                    let research = Research::Upgrade(upgrade);
+                    if world.data.services.upgrade_level(me, upgrade) >= level ||
+                       self.is_researching(&units, research) {
+                        continue;
+                    }
+                    self.research(world, dat, &units, research)
+                }
+                Goal::Tech(tech) => {
+                    let research = Research::Tech(tech);
+                    if world.data.services.has_tech(me, tech) ||
+                       self.is_researching(&units, research) {
+                        continue;
+                    }
+                    self.research(world, dat, &units, research)
+                }
+            };
+            match result {
+                Ok(()) => units = ai_units(world),
+                // save up instead of spending it on cheaper things
+                Err(BuildError::NotEnoughMinerals) |
+                Err(BuildError::NotEnoughGas) => break,
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// morphs, builds or trains one `unit_id` for a town
+    fn make_unit(&self,
+                 world: &mut World<UnitSystems>,
+                 gd: &GameData,
+                 units: &[AIUnit],
+                 unit_id: usize,
+                 town: usize)
+                 -> Result<(), BuildError> {
+        let me = self.player;
+        let dat = &gd.units_dat;
+        let (tx, ty) = match self.town_pos(town, units) {
+            Some(pos) => pos,
+            None => return Err(BuildError::NotAllowed),
+        };
+        let shell = nearest(units.iter().filter(|u| {
+                                u.player == me && u.complete && !u.busy &&
+                                morph_shell(dat, u.unit_id, unit_id).is_some()
+                            }),
+                            tx,
+                            ty)
+            .map(|u| u.entity);
+        if let Some(unit) = shell {
+            return start_morph(world, gd, unit, unit_id);
+        }
+
+        if dat.flags(unit_id).contains(UF_BUILDING) {
+            let worker = nearest(units.iter().filter(|u| {
+                                     u.player == me && u.complete && !u.busy &&
+                                     u.building.is_none() &&
+                                     dat.flags(u.unit_id).contains(UF_WORKER)
+                                 }),
+                                 tx,
+                                 ty)
+                .map(|u| u.entity);
+            let worker = match worker {
+                Some(worker) => worker,
+                None => return Err(BuildError::Busy),
+            };
+            return match self.build_spot(world, dat, units, worker, unit_id, tx, ty) {
+                Some((x, y)) => issue_build_order(world, worker, unit_id, x, y, false),
+                None => {
+                    // a new pylon makes room for protoss buildings
+                    let pylons = count_units(dat, units, me, UNIT_PYLON, None, false);
+                    if dat.flags(unit_id).contains(UF_REQUIRES_PSI) &&
+                       count_units(dat, units, me, UNIT_PYLON, None, true) == pylons {
+                        self.make_unit(world, gd, units, UNIT_PYLON, town)
+                    } else {
+                        Err(BuildError::Unbuildable)
+                    }
+                }
+            };
+        }
+
+        let building = nearest(units.iter().filter(|u| {
+                                   u.player == me && u.complete && !u.busy && !u.lifted &&
+                                   trained_at(unit_id) == Some(u.unit_id)
+                               }),
+                               tx,
+                               ty)
+            .map(|u| u.entity);
+        match building {
+            Some(building) => train(world, building, unit_id),
+            None => Err(BuildError::NotAllowed),
+        }
+    }
+
+    /// refineries go on a free geyser of the town, resource depots as close
+    /// to the town as allowed, other buildings behind it, away from the
+    /// minerals
+    fn build_spot(&self,
+                  world: &World<UnitSystems>,
+                  units_dat: &UnitsDat,
+                  units: &[AIUnit],
+                  worker: Entity,
+                  unit_id: usize,
+                  tx: i32,
+                  ty: i32)
+                  -> Option<(i32, i32)> {
+        let flags = units_dat.flags(unit_id);
+        if flags.contains(UF_RESOURCE_CONTAINER) {
+            let mut geysers: Vec<&AIUnit> = units.iter()
+                .filter(|u| {
+                    u.unit_id == UNIT_VESPENE_GEYSER as usize && u.near(tx, ty, TOWN_RANGE)
+                })
+                .collect();
+            geysers.sort_by_key(|u| dist2(u.x, u.y, tx, ty));
+            return geysers.iter()
+                .map(|u| (u.x, u.y))
+                .find(|&(x, y)| can_place(world, worker, unit_id, x, y).is_ok());
+        }
+        if flags.contains(UF_RESOURCE_DEPOT) {
+            return search_spot(world, units_dat, worker, unit_id, tx, ty, 1);
+        }
+        let minerals: Vec<&AIUnit> = units.iter()
+            .filter(|u| u.resource == Some(ResourceType::Minerals) && u.near(tx, ty, TOWN_RANGE))
+            .collect();
+        let (x, y) = if minerals.is_empty() {
+            (tx, ty)
+        } else {
+            let n = minerals.len() as i32;
+            let mx = minerals.iter().map(|u| u.x).sum::<i32>() / n;
+            let my = minerals.iter().map(|u| u.y).sum::<i32>() / n;
+            (tx + (tx - mx) / 2, ty + (ty - my) / 2)
+        };
+        search_spot(world, units_dat, worker, unit_id, x, y, 2)
+    }
+
+    /// starts `research` in the first building that takes it, preferring
+    /// those that don't train units
+    fn research(&self,
+                world: &mut World<UnitSystems>,
+                units_dat: &UnitsDat,
+                units: &[AIUnit],
+                research: Research)
+                -> Result<(), BuildError> {
+        let me = self.player;
+        let mut buildings: Vec<&AIUnit> = units.iter()
+            .filter(|u| {
+                u.player == me && u.complete && !u.busy && !u.lifted &&
+                units_dat.flags(u.unit_id).contains(UF_BUILDING)
+            })
+            .collect();
+        buildings.sort_by_key(|u| units_dat.flags(u.unit_id).contains(UF_PRODUCES_UNITS));
+        for b in buildings {
+            match start_research(world, b.entity, research) {
+                Err(BuildError::NotAllowed) |
+                Err(BuildError::Busy) => {}
+                result => return result,
+            }
+        }
+        Err(BuildError::NotAllowed)
+    }
+}
+
+/// the computer players
+pub struct AI {
+    players: Vec<AIPlayer>,
+    /// players that start their melee script on the next tick
+    melee: Vec<usize>,
+    ticks: u32,
+    /// what if_dif compares against
+    difficulty: u32,
+    /// (player, error) of scripts that couldn't start or stopped
+    pub errors: Vec<(usize, AIError)>,
+    /// (player, text) of debug commands
+    pub messages: Vec<(usize, String)>,
+}
+impl AI {
+    pub fn new(map: &PlanningMap) -> Self {
+        let data = &map.scmap.data;
+        // FIXME: the game type isn't known, maps without triggers are taken
+        // for melee maps
+        let melee = if data.triggers.is_empty() {
+            (0..AI_PLAYERS)
+                .filter(|&p| {
+                    data.owners[p] == OWNER_COMPUTER || data.owners[p] == OWNER_COMPUTER_GAME
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        AI {
+            players: Vec::new(),
+            melee: melee,
+            ticks: 0,
+            difficulty: DEFAULT_DIFFICULTY,
+            errors: Vec::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    /// sets the difficulty the scripts check with if_dif
+    pub fn set_difficulty(&mut self, difficulty: u32) {
+        self.difficulty = difficulty;
+        for player in &mut self.players {
+            player.difficulty = difficulty;
+        }
+    }
+
+    /// lets the computer play `player` with the melee script of its race
+    pub fn start_melee(&mut self, player: usize) {
+        if !self.melee.contains(&player) {
+            self.melee.push(player);
+        }
+    }
+
+    /// players run by the AI
+    pub fn players(&self) -> Vec<usize> {
+        self.players.iter().map(|p| p.player).collect()
+    }
+
+    fn player(&mut self,
+              world: &World<UnitSystems>,
+              units_dat: &UnitsDat,
+              units: &[AIUnit],
+              player: usize)
+              -> &mut AIPlayer {
+        let existing = self.players.iter().position(|p| p.player == player);
+        let i = match existing {
+            Some(i) => i,
+            None => {
+                let race = player_race(world, units_dat, units, player);
+                self.players.push(AIPlayer::new(player, race, self.difficulty));
+                self.players.len() - 1
+            }
+        };
+        &mut self.players[i]
+    }
+
+    fn start_script(&mut self,
+                    world: &World<UnitSystems>,
+                    gd: &GameData,
+                    units: &[AIUnit],
+                    player: usize,
+                    id: u32,
+                    location: u32) {
+        let entry = match gd.ai_scripts.find(id) {
+            Some(header) => header.entry,
+            None => {
+                self.errors.push((player, AIError::MissingScript(id)));
+                return;
+            }
+        };
+        self.player(world, &gd.units_dat, units, player).threads.push(Thread {
+            pos: entry,
+            wait: 0,
+            stack: Vec::new(),
+            town: 0,
+            location: location,
+        });
+    }
+
+    fn step(&mut self, world: &mut World<UnitSystems>, gd: &GameData) {
+        self.ticks += 1;
+        let requests = match world.data.services.triggers.as_mut() {
+            Some(triggers) => mem::replace(&mut triggers.ai_requests, Vec::new()),
+            None => Vec::new(),
+        };
+        if !requests.is_empty() || !self.melee.is_empty() {
+            let units = ai_units(world);
+            for player in mem::replace(&mut self.melee, Vec::new()) {
+                if !units.iter().any(|u| u.player == player) {
+                    continue;
+                }
+                let prefix = match player_race(world, &gd.units_dat, &units, player) {
+                    Race::Zerg => "Z",
+                    Race::Terran => "T",
+                    Race::Protoss => "P",
+                };
+                // Brood War scripts first
+                let script = ["MCx", "MCu"]
+                    .iter()
+                    .map(|s| script_id(&format!("{}{}", prefix, s)))
+                    .find(|&id| gd.ai_scripts.find(id).is_some());
+                match script {
+                    Some(id) => self.start_script(world, gd, &units, player, id, 0),
+                    None => self.errors.push((player, AIError::NoMeleeScript)),
+                }
+            }
+            for r in requests {
+                self.start_script(world, gd, &units, r.player, r.script, r.location);
+            }
+        }
+        if self.ticks % AI_INTERVAL != 0 {
+            return;
+        }
+        for player in &mut self.players {
+            player.update(world, gd, self.ticks);
+            let id = player.player;
+            self.errors.extend(player.errors.drain(..).map(|e| (id, e)));
+            self.messages.extend(player.messages.drain(..).map(|m| (id, m)));
+        }
+    }
+}
+
+/// runs the computer players for one game tick
+pub fn run_ai(world: &mut World<UnitSystems>, gd: &GameData) {
+    let mut ai = match world.data.services.ai.take() {
+        Some(ai) => ai,
+        None => return,
+    };
+    ai.step(world, gd);
+    world.data.services.ai = Some(ai);
+}
@@ -26,6 +26,8 @@ fn main() {
         "units" => gd.units_dat.print_entry(i),
         "weapons" => gd.weapons_dat.print_entry(i),
         "orders" => gd.orders_dat.print_entry(i),
+        "upgrades" => gd.upgrades_dat.print_entry(i),
+        "techdata" => gd.techdata_dat.print_entry(i),
         _ => { print_usage(&args); },
     }
 }
@@ -16,5 +16,7 @@ fn main() {
     let infile = &args[1];
     let outfile = &args[2];
     let gd = GameData::init(Path::new("/home/dm/.wine/drive_c/StarCraft/"));
-    gd.extract(infile, outfile);
+    if let Err(err) = gd.extract(infile, outfile) {
+        println!("can't extract {}: {:?}", infile, err);
+    }
 }
@@ -0,0 +1,83 @@
+use std::path::Path;
+use std::env;
+use std::process;
+use std::fs::File;
+use std::io::Write;
+
+extern crate scrust;
+use scrust::gamedata::GameData;
+use scrust::mapgen::{generate, MapGenParams};
+
+extern crate scformats;
+use scformats::terrain::TileSet;
+use scformats::stormlib::MPQArchive;
+
+fn print_usage(args: &[String]) {
+    println!("usage: {} [out.chk|out.scm] [tileset] [width] [height] [players] [seed]",
+             args[0]);
+}
+
+fn parse_tileset(name: &str) -> Option<TileSet> {
+    match name.to_lowercase().as_ref() {
+        "badlands" => Some(TileSet::Badlands),
+        "platform" | "spaceplatform" => Some(TileSet::SpacePlatform),
+        "install" | "installation" => Some(TileSet::Installation),
+        "ashworld" => Some(TileSet::Ashworld),
+        "jungle" => Some(TileSet::Jungle),
+        "desert" => Some(TileSet::Desert),
+        "ice" | "arctic" => Some(TileSet::Arctic),
+        "twilight" => Some(TileSet::Twilight),
+        _ => None,
+    }
+}
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    if args.len() < 2 {
+        print_usage(&args);
+        return;
+    }
+    let arg = |i: usize, default: &str| -> String {
+        if args.len() > i {
+            args[i].clone()
+        } else {
+            String::from(default)
+        }
+    };
+
+    let params = MapGenParams {
+        tileset: parse_tileset(&arg(2, "jungle")).expect("unknown tileset!"),
+        width: arg(3, "128").parse::<u16>().expect("width needs to be an integer!"),
+        height: arg(4, "128").parse::<u16>().expect("height needs to be an integer!"),
+        players: arg(5, "2").parse::<usize>().expect("players needs to be an integer!"),
+        seed: arg(6, "0").parse::<u32>().expect("seed needs to be an integer!"),
+    };
+
+    let gd = GameData::init(&Path::new(&scrust::scdata_path()));
+    let map = match generate(&gd, &params) {
+        Ok(map) => map,
+        Err(err) => {
+            println!("can't generate the map: {:?}", err);
+            process::exit(1);
+        }
+    };
+    println!("start locations: {:?}", map.start_locations);
+
+    let outfn = &args[1];
+    let mut chk = Vec::<u8>::new();
+    map.data.write_chk(&mut chk);
+    let written = if outfn.to_lowercase().ends_with(".chk") {
+        File::create(outfn)
+            .and_then(|mut outfile| outfile.write_all(&chk))
+            .map_err(|err| format!("{}", err))
+    } else {
+        MPQArchive::create(outfn, 16)
+            .and_then(|mpq| mpq.add_file("staredit\\scenario.chk", &chk))
+            .map_err(|err| format!("{:?}", err))
+    };
+    if let Err(err) = written {
+        println!("can't write {}: {}", outfn, err);
+        process::exit(1);
+    }
+    println!("wrote {}", outfn);
+}
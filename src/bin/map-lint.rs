@@ -0,0 +1,46 @@
+use std::path::Path;
+use std::env;
+use std::process;
+
+extern crate scrust;
+use scrust::gamedata::GameData;
+use scrust::maplint::{lint, Severity};
+
+extern crate scformats;
+use scformats::terrain::Map;
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    if args.len() < 2 {
+        println!("usage: {} [map]...", args[0]);
+        return;
+    }
+
+    let gd = GameData::init(&Path::new(&scrust::scdata_path()));
+    let mut errors = 0;
+    for mapfn in &args[1..] {
+        let map = match Map::read(&gd, mapfn) {
+            Ok(map) => map,
+            Err(err) => {
+                println!("{}: can't read the map: {:?}", mapfn, err);
+                errors += 1;
+                continue;
+            }
+        };
+        let messages = lint(&map, &gd.units_dat);
+        println!("{}: {} problems", mapfn, messages.len());
+        for msg in &messages {
+            let prefix = match msg.severity {
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            };
+            println!("  {}: {}", prefix, msg.message);
+            if msg.severity == Severity::Error {
+                errors += 1;
+            }
+        }
+    }
+    if errors > 0 {
+        process::exit(1);
+    }
+}
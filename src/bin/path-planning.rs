@@ -35,7 +35,13 @@ const MAP_RENDER_W: u16 = 20;
 const MAP_RENDER_H: u16 = 12;
 impl MapView {
     fn new(gd: &GameData, context: &mut GameContext, _: &mut GameState, mapfn: &str) -> Self {
-        let map = PlanningMap::new(Map::read(gd, mapfn));
+        let map = match Map::read(gd, mapfn) {
+            Ok(map) => PlanningMap::new(map),
+            Err(err) => {
+                println!("can't read {}: {:?}", mapfn, err);
+                ::std::process::exit(1);
+            }
+        };
         println!("map name: {}", map.scmap.name());
         println!("map desc: {}", map.scmap.description());
         context.screen.set_palette(&map.scmap.terrain_info.pal.to_sdl()).ok();
@@ -1,4 +1,3 @@
-use std::mem;
 use std::env;
 use std::collections::HashMap;
 
@@ -9,34 +8,55 @@ use scrust::gamedata::{GameData, GRPCache};
 use scrust::unit_ecs::PlanningMap;
 
 extern crate scformats;
-use scformats::unitsdata::WeaponBehavior;
 use scformats::terrain::Map;
 
 use scrust::LayerTrait;
 use scrust::ui::UiLayer;
 
-use scrust::iscriptsys::IScriptSteppingSys;
-
 #[macro_use]
 extern crate ecs;
 
 use ecs::World;
+use ecs::Entity;
 use ecs::DataHelper;
 use ecs::EntityData;
-use ecs::ModifyData;
 
 use scrust::unit_ecs::{UnitComponents, UnitSystems, UnitServices};
-use scrust::unit_ecs::IScriptEntityAction;
-use scrust::unit_ecs::{UnderlayComponent, OverlayComponent, SCWeaponComponent};
-use scrust::unit_ecs::{create_scimage, create_scsprite, create_scflingy, create_scunit};
-use scrust::unit_ecs::UnitCommand;
+use scrust::unit_ecs::create_map_unit;
+use scrust::simulation::{create_world, step_world};
+use scrust::orders::{Order, OrderTarget, issue_order};
+use scrust::groupmove::issue_group_order;
+use scrust::economy::can_harvest;
+use scrust::construction::set_rally;
+use scrust::cloak::is_hidden_from;
+use scrust::transport::can_enter;
+use scformats::unitsdata::OrderType;
 
 extern crate sdl2;
 use sdl2::pixels::Color;
 use sdl2::rect::Point;
+use sdl2::keyboard::Keycode;
+
+
+/// how cloaked and burrowed units look to the player
+#[derive(Clone, Copy, PartialEq)]
+enum Cloaking {
+    Distorted,
+    Hidden,
+}
 
-extern crate enum_primitive;
-use enum_primitive::FromPrimitive;
+fn cloaked_units(world: &World<UnitSystems>, player: usize) -> HashMap<Entity, Cloaking> {
+    let dh = &world.data;
+    let mut cloaked = HashMap::new();
+    for e in world.entities().filter(aspect!(<UnitComponents> all: [cloak]), world) {
+        if is_hidden_from(e, dh, player) {
+            cloaked.insert(**e, Cloaking::Hidden);
+        } else if dh.cloak[e].cloaked {
+            cloaked.insert(**e, Cloaking::Distorted);
+        }
+    }
+    cloaked
+}
 
 fn draw_scimage(e: EntityData<UnitComponents>,
                 dh: &DataHelper<UnitComponents, UnitServices>,
@@ -45,7 +65,19 @@ fn draw_scimage(e: EntityData<UnitComponents>,
                 gd: &GameData,
                 buffer: &mut [u8],
                 buffer_pitch: u32,
-                grp_cache: &GRPCache) {
+                grp_cache: &GRPCache,
+                cloaked: &HashMap<Entity, Cloaking>) {
+    // e.g. workers inside a refinery
+    if !dh.iscript_state[e].visible {
+        return;
+    }
+    // overlays look like the unit they belong to
+    let unit = dh.iscript_state[e].parent_entity.unwrap_or(**e);
+    let cloaking = cloaked.get(&unit).cloned();
+    if cloaking == Some(Cloaking::Hidden) ||
+       (cloaking.is_some() && !dh.scimage[e].draw_if_cloaked) {
+        return;
+    }
     // every entity is an scimage
     let scimg_comp = &dh.scimage[e];
     let grp = grp_cache.get_ro(scimg_comp.grp_id);
@@ -61,6 +93,17 @@ fn draw_scimage(e: EntityData<UnitComponents>,
     let x_center = cx + dh.iscript_state[e].rel_x as i32;
     let y_center = cy + dh.iscript_state[e].rel_y as i32;
 
+    if cloaking == Some(Cloaking::Distorted) {
+        scimg_comp.draw_cloaked(&grp.frames[fridx],
+                                grp.header.width as u32,
+                                grp.header.height as u32,
+                                draw_flipped,
+                                x_center,
+                                y_center,
+                                buffer,
+                                buffer_pitch);
+        return;
+    }
     scimg_comp.draw(&grp.frames[fridx],
                     grp.header.width as u32,
                     grp.header.height as u32,
@@ -72,6 +115,18 @@ fn draw_scimage(e: EntityData<UnitComponents>,
                     scimg_comp.reindexing_table(gd));
 }
 
+/// enemy units are only drawn where the player sees them, neutral ones
+/// (minerals, critters, ...) also in explored areas
+fn is_seen(e: EntityData<UnitComponents>,
+           dh: &DataHelper<UnitComponents, UnitServices>,
+           player: usize)
+           -> bool {
+    let (x, y) = (dh.iscript_state[e].map_pos_x as i32, dh.iscript_state[e].map_pos_y as i32);
+    let vision = &dh.services.vision;
+    dh.scimage[e].player_id == player || vision.is_visible_px(player, x, y) ||
+    (dh.scimage[e].player_id >= 8 && vision.is_explored_px(player, x, y))
+}
+
 struct UnitsLayer {
     // XXX distinguish high & low layer
     // sprites: Vec<SCSprite>,
@@ -81,15 +136,7 @@ struct UnitsLayer {
 }
 impl UnitsLayer {
     fn from_map(gd: &GameData, _: &mut GameContext, _: &mut GameState, map: Rc<PlanningMap>) -> Self {
-        let mut world = World::<UnitSystems>::new();
-        world.systems.iscript_stepping_sys.init(IScriptSteppingSys {
-            iscript_copy: gd.iscript.clone(),
-            images_dat: gd.images_dat.clone(),
-            weapons_dat: gd.weapons_dat.clone(),
-            lox_cache: gd.lox_cache.clone(),
-            iscript_entity_actions: Vec::<IScriptEntityAction>::new(),
-            interested: HashMap::new(),
-        });
+        let mut world = create_world(gd, Some(map.clone()));
 
         // create map units
         for mapunit in &map.scmap.data.units {
@@ -100,12 +147,9 @@ impl UnitsLayer {
             //                        mapunit.y,
             //                        mapunit.player_no as usize);
             // let _ = state.unit_instances.put(unit);
-            let _ = create_scunit(&mut world, gd, mapunit.unit_id as usize,
-                                  mapunit.x, mapunit.y, mapunit.player_no as usize);
+            let _ = create_map_unit(&mut world, gd, mapunit);
         }
 
-        world.systems.scunit_stepping_sys.map = Some(map);
-
         // let mut sprites = Vec::<SCSprite>::new();
         // for mapsprite in &map.data.sprites {
         //     let sprite = SCSprite::new(&context.gd, mapsprite.sprite_no, mapsprite.x, mapsprite.y);
@@ -118,7 +162,8 @@ impl UnitsLayer {
         }
     }
 
-    fn update(&mut self, gd: &GameData, _: &GameContext, _: &mut GameState) {
+    /// one game tick
+    fn tick(&mut self, gd: &GameData) {
         // for (_, u) in &mut state.unit_instances {
         //     let action = u.get_scimg_mut().step(&context.gd);
         //     match action {
@@ -135,121 +180,7 @@ impl UnitsLayer {
         //     }
         // }
 
-        // interpret iscript for units
-        self.world.update();
-        self.world.flush_queue();
-
-        // generate new images, units
-        let actions = mem::replace(&mut self.world
-                                       .systems
-                                       .iscript_stepping_sys
-                                       .inner
-                                       .as_mut()
-                                       .unwrap()
-                                       .iscript_entity_actions,
-                                   Vec::<IScriptEntityAction>::new());
-        for action in actions {
-            match action {
-                IScriptEntityAction::RemoveEntity { entity } => {
-                    self.world.remove_entity(entity);
-                }
-                IScriptEntityAction::CreateImageUnderlay { parent, image_id, rel_x, rel_y } => {
-                    let (par_mx, par_my, player_id) = self.world.with_entity_data(&parent, |e, data| {
-                        (data.iscript_state[e].map_pos_x, data.iscript_state[e].map_pos_y,
-                        data.scimage[e].player_id)
-                        }).expect("couldn't get parent map pos");
-                    let ent =
-                        create_scimage(&mut self.world, gd, image_id as usize, par_mx, par_my,
-                                       Some(parent), player_id);
-                   self.world.modify_entity(parent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.iscript_state[e].children.push(ent);
-                    });
-                    self.world.modify_entity(ent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.iscript_state[e].rel_x = rel_x;
-                        data.iscript_state[e].rel_y = rel_y;
-                        data.underlay.insert(&e, UnderlayComponent {});
-                    });
-
-                }
-                IScriptEntityAction::CreateImageOverlay { parent, image_id, rel_x, rel_y } => {
-                    let (par_mx, par_my, player_id) =
-                        self.world.with_entity_data(&parent, |e, data| {
-                            (data.iscript_state[e].map_pos_x, data.iscript_state[e].map_pos_y,
-                            data.scimage[e].player_id)
-                        }).expect("couldn't get parent map pos");
-                    let ent =
-                        create_scimage(&mut self.world, gd, image_id as usize, par_mx, par_my,
-                                       Some(parent), player_id);
-                    self.world.modify_entity(parent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.iscript_state[e].children.push(ent);
-                    });
-                    self.world.modify_entity(ent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.iscript_state[e].rel_x = rel_x;
-                        data.iscript_state[e].rel_y = rel_y;
-                        data.overlay.insert(&e, OverlayComponent {});
-                    });
-                }
-                IScriptEntityAction::CreateSpriteOverlay { sprite_id, x, y } => {
-                    let ent = create_scsprite(&mut self.world, gd, sprite_id as usize, x, y, None, 0);
-                    self.world.modify_entity(ent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.overlay.insert(&e, OverlayComponent {});
-                    });
-                }
-                IScriptEntityAction::CreateSpriteUnderlay { parent,
-                                                            sprite_id,
-                                                            x,
-                                                            y,
-                                                            use_parent_dir } => {
-                    let ent =
-                        create_scsprite(&mut self.world, gd, sprite_id as usize, x, y, parent, 0);
-                    let parent_dir = if use_parent_dir {
-                        self.world.with_entity_data(&parent.unwrap(), |e, data| {
-                            data.iscript_state[e].children.push(ent);
-                            data.iscript_state[e].direction
-                        })
-                    } else {
-                        None
-                    };
-                    self.world.modify_entity(ent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.underlay.insert(&e, UnderlayComponent {});
-                        if let Some(initial_dir) = parent_dir {
-                            data.iscript_state[e].direction = initial_dir;
-                        }
-                    });
-                }
-                IScriptEntityAction::CreateWeaponsFlingy { weapon_id, ../*rel_x, rel_y*/ } => {
-                    let ent = create_scflingy(&mut self.world,
-                                              gd,
-                                              gd.weapons_dat.graphics[weapon_id as usize] as usize,
-                                              // FIXME: use proper location
-                                              0,
-                                              0,
-                    0);
-
-                    let behavior = WeaponBehavior::from_u8(gd.weapons_dat.behavior[weapon_id as
-                                                            usize])
-                        .expect("could not get weapon behavior!");
-                    self.world.modify_entity(ent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.scweapon.insert(&e,
-                                             SCWeaponComponent {
-                                                 weapon_id: weapon_id,
-                                                 behavior: behavior,
-                                                 age: 0,
-                                             });
-                    });
-                }
-                // _ => {
-                //     println!("ignoring {:?} iscript create action", action);
-                // }
-            }
-        }
+        step_world(&mut self.world, gd);
     }
 
     fn generate_events(&mut self, _: &GameData, gc: &GameContext, state: &mut GameState) -> Vec<GameEvents> {
@@ -263,7 +194,8 @@ impl UnitsLayer {
             for e in self.world
                 .entities()
                 .filter(aspect!(<UnitComponents> all: [selectable]), &self.world) {
-                    if !dh.iscript_state[e].alive {
+                    if !dh.iscript_state[e].alive || !dh.iscript_state[e].visible ||
+                       (dh.cloak.has(&e) && is_hidden_from(e, dh, state.player)) {
                         continue;
                     }
 
@@ -297,21 +229,51 @@ impl UnitsLayer {
             events.push(GameEvents::SelectUnit(over_unit_instance.unwrap()));
         }
 
-        // commands for selected unit
-        if !state.selected_units.is_empty() {
-            if !self.cursor_over_unit && gc.events.now.mouse_right {
-                // move command
-                println!("moving to {}, {}", mouse_pos_map.x(), mouse_pos_map.y());
-                for e in &state.selected_units {
-                    self.world.with_entity_data(e, |e, data| {
-                        let cmd = UnitCommand::Move(mouse_pos_map.x(), mouse_pos_map.y());
-                        // overwrite old command
-                        if data.scunit[e].commands.is_empty() {
-                            data.scunit[e].commands.push(cmd);
+        // orders for selected units
+        if !state.selected_units.is_empty() && gc.events.now.mouse_right {
+            let queued = gc.events.now.is_key_pressed(&Keycode::LShift) ||
+                         gc.events.now.is_key_pressed(&Keycode::RShift);
+            let target_player = over_unit_instance.and_then(|t| {
+                self.world.with_entity_data(&t, |e, data| data.scimage[e].player_id)
+            });
+            // production buildings only remember where to send their units
+            let rally = match over_unit_instance {
+                Some(target) => OrderTarget::Unit(target),
+                None => OrderTarget::Position(mouse_pos_map.x(), mouse_pos_map.y()),
+            };
+            let selected: Vec<_> = state.selected_units
+                .iter()
+                .cloned()
+                .filter(|e| !set_rally(&mut self.world, *e, rally))
+                .collect();
+            match (over_unit_instance, target_player) {
+                (Some(target), Some(target_player)) => {
+                    for e in &selected {
+                        let player = self.world
+                            .with_entity_data(e, |e, data| data.scimage[e].player_id);
+                        let order = if can_harvest(&mut self.world, *e, target) {
+                            Order::new(OrderType::Harvest1, OrderTarget::Unit(target))
+                        } else if can_enter(&mut self.world, *e, target) {
+                            Order::new(OrderType::EnterTransport, OrderTarget::Unit(target))
+                        } else if can_enter(&mut self.world, target, *e) {
+                            Order::new(OrderType::PickupTransport, OrderTarget::Unit(target))
+                        } else if Some(target_player) != player && target_player < 8 {
+                            Order::new(OrderType::AttackUnit, OrderTarget::Unit(target))
                         } else {
-                            data.scunit[e].commands[0] = cmd;
-                        }
-                    });
+                            Order::new(OrderType::Follow, OrderTarget::Unit(target))
+                        };
+                        // units that can't execute it keep their orders
+                        issue_order(&mut self.world, *e, order, queued);
+                    }
+                }
+                _ => {
+                    // one path search for the whole selection
+                    let order = Order::new(OrderType::Move,
+                                           OrderTarget::Position(mouse_pos_map.x(),
+                                                                 mouse_pos_map.y()));
+                    if !selected.is_empty() {
+                        issue_group_order(&mut self.world, &selected, order, queued);
+                    }
                 }
             }
         }
@@ -328,16 +290,19 @@ impl UnitsLayer {
               buffer: &mut [u8],
               buffer_pitch: u32) {
             let dh = &self.world.data;
+            let alpha = state.clock.alpha();
+            let cloaked = cloaked_units(&self.world, state.player);
 
             for e in self.world
                 .entities()
                 .filter(aspect!(<UnitComponents> all: [underlay]), &self.world) {
-                if !dh.iscript_state[e].alive {
+                if !dh.iscript_state[e].alive || !is_seen(e, dh, state.player) {
                     continue;
                 }
-                let cx = dh.iscript_state[e].map_pos_x as i32 - map_x as i32;
-                let cy = dh.iscript_state[e].map_pos_y as i32 - map_y as i32;
-                draw_scimage(e, dh, cx, cy, gd, buffer, buffer_pitch, &*grp_cache);
+                let (px, py) = dh.iscript_state[e].interpolated_pos(alpha);
+                let cx = px - map_x as i32;
+                let cy = py - map_y as i32;
+                draw_scimage(e, dh, cx, cy, gd, buffer, buffer_pitch, &*grp_cache, &cloaked);
             }
 
             // NOTE order is random in this loop!
@@ -346,13 +311,14 @@ impl UnitsLayer {
                 .filter(aspect!(<UnitComponents> none: [underlay, overlay]),
                         &self.world) {
                 // TODO we should remove dead entities instead
-                if !dh.iscript_state[e].alive {
+                if !dh.iscript_state[e].alive || !is_seen(e, dh, state.player) {
                     continue;
                 }
                 assert!(!dh.underlay.has(&e) && !dh.overlay.has(&e));
 
-                let cx = dh.iscript_state[e].map_pos_x as i32 - map_x as i32;
-                let cy = dh.iscript_state[e].map_pos_y as i32 - map_y as i32;
+                let (px, py) = dh.iscript_state[e].interpolated_pos(alpha);
+                let cx = px - map_x as i32;
+                let cy = py - map_y as i32;
 
                 // draw path if available
                     if dh.scunit.has(&e) {
@@ -374,23 +340,25 @@ impl UnitsLayer {
                     if is_selected {
                         dh.selectable[e]
                             .draw_selection_circle(&*grp_cache, cx, cy, buffer, buffer_pitch);
-                    // dh.selectable[e].draw_healthbar(200, 230,
-                    //                                 buffer,
-                    //                                 buffer_pitch);
+                        if dh.health.has(&e) {
+                            dh.selectable[e]
+                                .draw_healthbar(&dh.health[e], cx, cy, buffer, buffer_pitch);
+                        }
                     }
                 }
-                draw_scimage(e, dh, cx, cy, gd, buffer, buffer_pitch, &*grp_cache);
+                draw_scimage(e, dh, cx, cy, gd, buffer, buffer_pitch, &*grp_cache, &cloaked);
             }
 
             for e in self.world
                 .entities()
                 .filter(aspect!(<UnitComponents> all: [overlay]), &self.world) {
-                if !dh.iscript_state[e].alive {
+                if !dh.iscript_state[e].alive || !is_seen(e, dh, state.player) {
                     continue;
                 }
-                let cx = dh.iscript_state[e].map_pos_x as i32 - map_x as i32;
-                let cy = dh.iscript_state[e].map_pos_y as i32 - map_y as i32;
-                draw_scimage(e, dh, cx, cy, gd, buffer, buffer_pitch, &*grp_cache);
+                let (px, py) = dh.iscript_state[e].interpolated_pos(alpha);
+                let cx = px - map_x as i32;
+                let cy = py - map_y as i32;
+                draw_scimage(e, dh, cx, cy, gd, buffer, buffer_pitch, &*grp_cache, &cloaked);
             }
     }
 }
@@ -402,12 +370,26 @@ struct MapView {
 
     units_layer: UnitsLayer,
     ui_layer: UiLayer,
+    /// frames until the minimap fog gets redrawn
+    minimap_fog_delay: u32,
+    /// debug overlay of the map's locations, toggled with L
+    show_locations: bool,
+    locations_key_down: bool,
 }
 const MAP_RENDER_W: u16 = 20;
 const MAP_RENDER_H: u16 = 12;
+const MINIMAP_FOG_INTERVAL: u32 = 24;
+/// white in all tileset palettes
+const LOCATION_COLOR: u8 = 255;
 impl MapView {
     fn new(gd: &GameData, context: &mut GameContext, state: &mut GameState, mapfn: &str) -> Self {
-        let map = Rc::new(PlanningMap::new(Map::read(gd, mapfn)));
+        let map = match Map::read(gd, mapfn) {
+            Ok(map) => Rc::new(PlanningMap::new(map)),
+            Err(err) => {
+                println!("can't read {}: {:?}", mapfn, err);
+                ::std::process::exit(1);
+            }
+        };
         println!("map name: {}", map.scmap.name());
         println!("map desc: {}", map.scmap.description());
         context.screen.set_palette(&map.scmap.terrain_info.pal.to_sdl()).ok();
@@ -418,13 +400,48 @@ impl MapView {
             map: map,
             units_layer: units_layer,
             ui_layer: ui_layer,
+            minimap_fog_delay: 0,
+            show_locations: false,
+            locations_key_down: false,
         }
     }
 }
 impl View for MapView {
     fn update(&mut self, gd: &GameData, context: &mut GameContext, state: &mut GameState) {
-        self.units_layer.update(gd, context, state);
         self.ui_layer.update(gd, context, state);
+        {
+            let player = &self.units_layer.world.data.services.players[state.player];
+            self.ui_layer.update_resources(gd,
+                                           context,
+                                           player.minerals,
+                                           player.gas,
+                                           player.supply.main_race());
+        }
+        if let Some(ref mut triggers) = self.units_layer.world.data.services.triggers {
+            // FIXME: show them in the ui
+            for (player, text) in triggers.messages.drain(..) {
+                if player == state.player {
+                    println!("{}", text);
+                }
+            }
+        }
+        let key_down = context.events.now.is_key_pressed(&Keycode::L);
+        if key_down && !self.locations_key_down {
+            self.show_locations = !self.show_locations;
+        }
+        self.locations_key_down = key_down;
+        if self.minimap_fog_delay == 0 {
+            self.ui_layer.update_fog(context,
+                                     &self.units_layer.world.data.services.vision,
+                                     state.player,
+                                     &gd.shadow_reindexing[..256]);
+            self.minimap_fog_delay = MINIMAP_FOG_INTERVAL;
+        } else {
+            self.minimap_fog_delay -= 1;
+        }
+    }
+    fn tick(&mut self, gd: &GameData, _: &mut GameState) {
+        self.units_layer.tick(gd);
     }
     fn render(&mut self, gd: &GameData, context: &mut GameContext, state: &GameState, _: f64) -> ViewAction {
         if context.events.now.quit // || context.events.now.key_escape == Some(true)
@@ -452,6 +469,21 @@ impl View for MapView {
 
                 self.units_layer.render(gd, state, map_x, map_y, grp_cache, buffer, screen_pitch);
 
+                // the darkening table is the same for every row
+                self.units_layer.world.data.services.vision.render_fog(state.player,
+                                                                       map_x,
+                                                                       map_y,
+                                                                       &gd.shadow_reindexing[..256],
+                                                                       buffer,
+                                                                       screen_pitch);
+
+                if self.show_locations {
+                    self.units_layer.world.data.services.locations.render(map_x,
+                                                                          map_y,
+                                                                          LOCATION_COLOR,
+                                                                          buffer,
+                                                                          screen_pitch);
+                }
             });
         }
 
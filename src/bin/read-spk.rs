@@ -3,8 +3,8 @@ use scformats::stormlib::MPQArchive;
 use scformats::spk::SPK;
 
 fn main() {
-    let mpq = MPQArchive::open("/home/dm/code/mysc/data/STARDAT.MPQ");
-    let mut file = mpq.open_file("parallax/star.spk");
+    let mpq = MPQArchive::open("/home/dm/code/mysc/data/STARDAT.MPQ").unwrap();
+    let mut file = mpq.open_file("parallax/star.spk").unwrap();
     let spk = SPK::read_spk(&mut file);
 
     println!("read {} layers, {} images",
@@ -0,0 +1,308 @@
+use std::path::Path;
+use std::env;
+use std::process;
+use std::fs::File;
+use std::io::Write;
+
+extern crate byteorder;
+use byteorder::{LittleEndian, WriteBytesExt};
+
+extern crate scrust;
+use scrust::gamedata::GameData;
+use scrust::render::render_buffer_with_solid_reindexing;
+
+extern crate scformats;
+use scformats::terrain::{Map, TileHeight};
+use scformats::pal::Palette;
+use scformats::unitsdata::UNIT_START_LOCATION;
+
+fn print_usage(args: &[String]) {
+    println!("usage: {} [map] [out.ppm|out.bmp] [options]", args[0]);
+    println!("  --scale N       shrink the image by factor N");
+    println!("  --units         draw the preplaced units");
+    println!("  --sprites       draw the doodad sprites");
+    println!("  --starts        mark the start locations");
+    println!("  --passability   hatch unwalkable terrain");
+    println!("  --elevation     hatch mid and high ground");
+}
+
+struct Options {
+    scale: usize,
+    units: bool,
+    sprites: bool,
+    starts: bool,
+    passability: bool,
+    elevation: bool,
+}
+
+fn closest_color(pal: &Palette, r: u8, g: u8, b: u8) -> u8 {
+    let mut best = 0;
+    let mut best_dist = i32::max_value();
+    for i in 0..256 {
+        let dr = pal.data[i * 3] as i32 - r as i32;
+        let dg = pal.data[i * 3 + 1] as i32 - g as i32;
+        let db = pal.data[i * 3 + 2] as i32 - b as i32;
+        let dist = dr * dr + dg * dg + db * db;
+        if dist < best_dist {
+            best = i;
+            best_dist = dist;
+        }
+    }
+    best as u8
+}
+
+/// draws the first frame of an image, centered at (x, y)
+fn draw_image(gd: &GameData,
+              image_id: usize,
+              player: usize,
+              x: i32,
+              y: i32,
+              buffer: &mut [u8],
+              pitch: u32) {
+    let grp_id = gd.images_dat.grp_id[image_id];
+    let mut grp_cache = gd.grp_cache.borrow_mut();
+    let grp = grp_cache.get(gd, grp_id);
+    let reindex = if player < 11 {
+        &gd.player_reindexing[player * 256..player * 256 + 256]
+    } else {
+        &gd.player_reindexing[0..256]
+    };
+    render_buffer_with_solid_reindexing(&grp.frames[0],
+                                        grp.header.width as u32,
+                                        grp.header.height as u32,
+                                        false,
+                                        x,
+                                        y,
+                                        buffer,
+                                        pitch,
+                                        reindex);
+}
+
+fn draw_rect(buffer: &mut [u8], pitch: usize, x0: i32, y0: i32, x1: i32, y1: i32, col: u8) {
+    let height = (buffer.len() / pitch) as i32;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let border = y < y0 + 2 || y >= y1 - 2 || x < x0 + 2 || x >= x1 - 2;
+            if border && x >= 0 && y >= 0 && x < pitch as i32 && y < height {
+                buffer[y as usize * pitch + x as usize] = col;
+            }
+        }
+    }
+}
+
+/// every other pixel of the matching minitiles gets the color
+fn hatch_minitiles<F>(map: &Map, buffer: &mut [u8], pitch: usize, col: u8, pred: F)
+    where F: Fn(i32, i32) -> bool
+{
+    let grid = &map.terrain_grid;
+    for my in 0..grid.minitile_height() {
+        for mx in 0..grid.minitile_width() {
+            if !pred(mx as i32, my as i32) {
+                continue;
+            }
+            for y in my * 8..my * 8 + 8 {
+                for x in mx * 8..mx * 8 + 8 {
+                    if (x + y) % 2 == 0 {
+                        buffer[y * pitch + x] = col;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn downscale(buffer: &[u8], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    let (out_w, out_h) = (width / scale, height / scale);
+    let mut res = Vec::<u8>::with_capacity(out_w * out_h);
+    for y in 0..out_h {
+        for x in 0..out_w {
+            res.push(buffer[y * scale * width + x * scale]);
+        }
+    }
+    res
+}
+
+fn write_ppm(filename: &str, buffer: &[u8], width: usize, height: usize, pal: &Palette) {
+    let mut outfile = File::create(filename).unwrap();
+    outfile.write_fmt(format_args!("P6\n{} {}\n255\n", width, height)).unwrap();
+    let mut data = Vec::<u8>::with_capacity(buffer.len() * 3);
+    for col in buffer {
+        let idx = *col as usize * 3;
+        data.extend(&pal.data[idx..idx + 3]);
+    }
+    outfile.write_all(&data).unwrap();
+}
+
+/// 8 bit paletted bmp
+fn write_bmp(filename: &str, buffer: &[u8], width: usize, height: usize, pal: &Palette) {
+    let row_size = (width + 3) / 4 * 4;
+    let data_offset = 14 + 40 + 256 * 4;
+    let mut out = Vec::<u8>::with_capacity(data_offset + row_size * height);
+    out.extend(b"BM");
+    out.write_u32::<LittleEndian>((data_offset + row_size * height) as u32).unwrap();
+    out.write_u32::<LittleEndian>(0).unwrap();
+    out.write_u32::<LittleEndian>(data_offset as u32).unwrap();
+    // BITMAPINFOHEADER
+    out.write_u32::<LittleEndian>(40).unwrap();
+    out.write_i32::<LittleEndian>(width as i32).unwrap();
+    out.write_i32::<LittleEndian>(height as i32).unwrap();
+    out.write_u16::<LittleEndian>(1).unwrap();
+    out.write_u16::<LittleEndian>(8).unwrap();
+    out.write_u32::<LittleEndian>(0).unwrap();
+    out.write_u32::<LittleEndian>((row_size * height) as u32).unwrap();
+    out.write_i32::<LittleEndian>(2835).unwrap();
+    out.write_i32::<LittleEndian>(2835).unwrap();
+    out.write_u32::<LittleEndian>(256).unwrap();
+    out.write_u32::<LittleEndian>(0).unwrap();
+    for i in 0..256 {
+        out.push(pal.data[i * 3 + 2]);
+        out.push(pal.data[i * 3 + 1]);
+        out.push(pal.data[i * 3]);
+        out.push(0);
+    }
+    // bottom up
+    for y in (0..height).rev() {
+        out.extend(&buffer[y * width..(y + 1) * width]);
+        for _ in width..row_size {
+            out.push(0);
+        }
+    }
+    File::create(filename).unwrap().write_all(&out).unwrap();
+}
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    if args.len() < 3 {
+        print_usage(&args);
+        return;
+    }
+    let mut opts = Options {
+        scale: 1,
+        units: false,
+        sprites: false,
+        starts: false,
+        passability: false,
+        elevation: false,
+    };
+    let mut i = 3;
+    while i < args.len() {
+        match args[i].as_ref() {
+            "--scale" => {
+                i += 1;
+                opts.scale = match args.get(i).and_then(|s| s.parse::<usize>().ok()) {
+                    Some(scale) if scale > 0 => scale,
+                    _ => {
+                        print_usage(&args);
+                        return;
+                    }
+                };
+            }
+            "--units" => opts.units = true,
+            "--sprites" => opts.sprites = true,
+            "--starts" => opts.starts = true,
+            "--passability" => opts.passability = true,
+            "--elevation" => opts.elevation = true,
+            _ => {
+                print_usage(&args);
+                return;
+            }
+        }
+        i += 1;
+    }
+
+    let gd = GameData::init(&Path::new(&scrust::scdata_path()));
+    let map = match Map::read(&gd, &args[1]) {
+        Ok(map) => map,
+        Err(err) => {
+            println!("can't read {}: {:?}", args[1], err);
+            process::exit(1);
+        }
+    };
+    let pal = &map.terrain_info.pal;
+    let width = map.data.width as usize * 32;
+    let height = map.data.height as usize * 32;
+    let mut buffer = vec![0 as u8; width * height];
+    map.render(0,
+               0,
+               map.data.width,
+               map.data.height,
+               &mut buffer,
+               width as u32);
+
+    if opts.elevation {
+        let mid_col = closest_color(pal, 255, 255, 0);
+        let high_col = closest_color(pal, 255, 0, 0);
+        hatch_minitiles(&map, &mut buffer, width, mid_col, |x, y| {
+            map.terrain_grid.tile_height(x, y) == TileHeight::Mid
+        });
+        hatch_minitiles(&map, &mut buffer, width, high_col, |x, y| {
+            map.terrain_grid.tile_height(x, y) == TileHeight::High
+        });
+    }
+    if opts.passability {
+        hatch_minitiles(&map, &mut buffer, width, 0, |x, y| !map.terrain_grid.is_walkable(x, y));
+    }
+
+    if opts.sprites {
+        for sprite in &map.data.sprites {
+            // bit 12: pure sprite, otherwise a unit id
+            let sprite_id = if sprite.flags & 0x1000 > 0 {
+                sprite.sprite_no as usize
+            } else {
+                let flingy_id = gd.units_dat.flingy_id[sprite.sprite_no as usize] as usize;
+                gd.flingy_dat.sprite_id[flingy_id] as usize
+            };
+            let image_id = gd.sprites_dat.image_id[sprite_id] as usize;
+            draw_image(&gd,
+                       image_id,
+                       sprite.player_no as usize,
+                       sprite.x as i32,
+                       sprite.y as i32,
+                       &mut buffer,
+                       width as u32);
+        }
+    }
+    if opts.units {
+        for unit in &map.data.units {
+            if unit.unit_id == UNIT_START_LOCATION {
+                continue;
+            }
+            let flingy_id = gd.units_dat.flingy_id[unit.unit_id as usize] as usize;
+            let sprite_id = gd.flingy_dat.sprite_id[flingy_id] as usize;
+            let image_id = gd.sprites_dat.image_id[sprite_id] as usize;
+            draw_image(&gd,
+                       image_id,
+                       unit.player_no as usize,
+                       unit.x as i32,
+                       unit.y as i32,
+                       &mut buffer,
+                       width as u32);
+        }
+    }
+    if opts.starts {
+        for unit in &map.data.units {
+            if unit.unit_id != UNIT_START_LOCATION {
+                continue;
+            }
+            let player = unit.player_no as usize % 12;
+            let col = gd.player_reindexing[player * 256 + 8];
+            // command center sized
+            let (x, y) = (unit.x as i32, unit.y as i32);
+            draw_rect(&mut buffer, width, x - 64, y - 48, x + 64, y + 48, col);
+        }
+    }
+
+    let (out_w, out_h) = (width / opts.scale, height / opts.scale);
+    let out_buf = if opts.scale > 1 {
+        downscale(&buffer, width, height, opts.scale)
+    } else {
+        buffer
+    };
+    let outfn = &args[2];
+    if outfn.to_lowercase().ends_with(".bmp") {
+        write_bmp(outfn, &out_buf, out_w, out_h, pal);
+    } else {
+        write_ppm(outfn, &out_buf, out_w, out_h, pal);
+    }
+    println!("wrote {} ({}x{})", outfn, out_w, out_h);
+}
@@ -0,0 +1,115 @@
+use std::path::Path;
+use std::env;
+use std::process;
+
+extern crate scrust;
+use scrust::simulation::Simulation;
+use scrust::orders::{Order, OrderTarget};
+
+extern crate scformats;
+use scformats::unitsdata::OrderType;
+
+fn print_usage(args: &[String]) {
+    println!("usage: {} [map] [ticks] [ai[:difficulty]] [[a][q]tick:unit:x:y ...]", args[0]);
+    println!("runs the simulation without a window and prints the resulting state hash,");
+    println!("every tick:unit:x:y orders a map unit to move at the given tick,");
+    println!("prefixing it with 'a' attack-moves instead, with 'q' it is queued,");
+    println!("with 'ai' the computer plays every player that has units, 'ai:n' sets the");
+    println!("difficulty its scripts check");
+}
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    if args.len() < 3 {
+        print_usage(&args);
+        return;
+    }
+    let ticks = args[2].parse::<u64>().expect("ticks needs to be an integer!");
+
+    let mut sim = match Simulation::from_files(&Path::new(&scrust::scdata_path()), &args[1]) {
+        Ok(sim) => sim,
+        Err(err) => {
+            println!("can't read {}: {:?}", args[1], err);
+            process::exit(1);
+        }
+    };
+    let ai = args.len() > 3 && (args[3] == "ai" || args[3].starts_with("ai:"));
+    if ai {
+        if args[3].len() > 3 {
+            let difficulty = match args[3][3..].parse::<u32>() {
+                Ok(difficulty) => difficulty,
+                Err(_) => {
+                    print_usage(&args);
+                    return;
+                }
+            };
+            if let Some(ai) = sim.world.data.services.ai.as_mut() {
+                ai.set_difficulty(difficulty);
+            }
+        }
+        let counts = sim.player_unit_counts();
+        for player in (0..8).filter(|&p| counts[p] > 0) {
+            sim.start_ai(player);
+        }
+    }
+    for cmd in &args[if ai { 4 } else { 3 }..] {
+        let (order_type, cmd) = if cmd.starts_with('a') {
+            (OrderType::AttackMove, &cmd[1..])
+        } else {
+            (OrderType::Move, &cmd[..])
+        };
+        let (queued, cmd) = if cmd.starts_with('q') {
+            (true, &cmd[1..])
+        } else {
+            (false, cmd)
+        };
+        let parts = cmd.split(':')
+            .map(|p| p.parse::<i32>().expect("commands look like tick:unit:x:y"))
+            .collect::<Vec<_>>();
+        if parts.len() != 4 {
+            print_usage(&args);
+            return;
+        }
+        let unit = parts[1] as usize;
+        if unit >= sim.unit_count() {
+            println!("map only has {} units", sim.unit_count());
+            return;
+        }
+        sim.schedule(parts[0] as u64,
+                     unit,
+                     Order::new(order_type, OrderTarget::Position(parts[2], parts[3])),
+                     queued);
+    }
+
+    sim.run(ticks);
+
+    for (i, unit) in sim.snapshot().iter().enumerate() {
+        match *unit {
+            Some(ref u) => {
+                println!("unit {} (id {}): ({}, {})",
+                         i,
+                         u.unit_id,
+                         u.exact_x as f32 / 256.,
+                         u.exact_y as f32 / 256.)
+            }
+            None => println!("unit {}: gone", i),
+        }
+    }
+    if ai {
+        for (player, count) in sim.player_unit_counts().iter().enumerate().take(8) {
+            println!("player {}: {} units", player + 1, count);
+        }
+        if let Some(ai) = sim.world.data.services.ai.as_ref() {
+            for &(player, ref text) in &ai.messages {
+                println!("ai of player {}: {}", player + 1, text);
+            }
+            for &(player, ref err) in &ai.errors {
+                println!("ai of player {} stopped: {:?}", player + 1, err);
+            }
+        }
+    }
+    if sim.rejected_commands > 0 {
+        println!("{} orders couldn't be executed", sim.rejected_commands);
+    }
+    println!("state hash after {} ticks: {:016x}", sim.tick_count, sim.state_hash());
+}
@@ -1,6 +1,3 @@
-use std::mem;
-use std::collections::HashMap;
-
 extern crate sdl2;
 use sdl2::pixels::Color;
 use sdl2::keyboard::Keycode;
@@ -9,13 +6,11 @@ use sdl2::rect::Rect;
 extern crate scrust;
 use scrust::gamedata::{GameData, GRPCache};
 use scrust::{GameContext, GameState, View, ViewAction};
-use scrust::iscriptsys::IScriptSteppingSys;
 
 extern crate scformats;
 use scformats::font::FontSize;
 use scformats::font::RenderText;
 use scformats::iscript::{IScript, AnimationType};
-use scformats::unitsdata::WeaponBehavior;
 
 #[macro_use]
 extern crate ecs;
@@ -26,13 +21,9 @@ use ecs::Entity;
 use ecs::EntityData;
 
 use scrust::unit_ecs::{UnitComponents, UnitSystems, UnitServices};
-use scrust::unit_ecs::IScriptEntityAction;
-use scrust::unit_ecs::{UnderlayComponent, OverlayComponent, SCWeaponComponent};
-use scrust::unit_ecs::{create_scimage, create_scsprite, create_scflingy, create_scunit};
-use ecs::ModifyData;
+use scrust::unit_ecs::create_scunit;
+use scrust::simulation::{create_world, step_world};
 
-extern crate enum_primitive;
-use enum_primitive::FromPrimitive;
 
 fn draw_scimage(e: EntityData<UnitComponents>,
                     dh: &DataHelper<UnitComponents, UnitServices>,
@@ -40,6 +31,10 @@ fn draw_scimage(e: EntityData<UnitComponents>,
                     buffer: &mut [u8],
                     buffer_pitch: u32,
                     grp_cache: &GRPCache) {
+    // e.g. workers inside a refinery
+    if !dh.iscript_state[e].visible {
+        return;
+    }
     // every entity is an scimage
     let scimg_comp = &dh.scimage[e];
     let grp = grp_cache.get_ro(scimg_comp.grp_id);
@@ -80,16 +75,7 @@ impl UnitsECSView {
         let pal = gd.install_pal.to_sdl();
         context.screen.set_palette(&pal).ok();
 
-        let mut world = World::<UnitSystems>::new();
-
-        world.systems.iscript_stepping_sys.init(IScriptSteppingSys {
-            iscript_copy: gd.iscript.clone(),
-            images_dat: gd.images_dat.clone(),
-            weapons_dat: gd.weapons_dat.clone(),
-            lox_cache: gd.lox_cache.clone(),
-            iscript_entity_actions: Vec::<IScriptEntityAction>::new(),
-            interested: HashMap::new(),
-        });
+        let mut world = create_world(gd, None);
 
         let args: Vec<String> = env::args().collect();
         let unit_id = if args.len() == 2 {
@@ -215,112 +201,6 @@ impl View for UnitsECSView {
                               AnimationType::Death);
         }
 
-        // interpret iscript for units
-        self.world.update();
-        self.world.flush_queue();
-
-        // generate new images, units
-        let actions = mem::replace(&mut self.world
-                                       .systems
-                                       .iscript_stepping_sys
-                                       .inner
-                                       .as_mut()
-                                       .unwrap()
-                                       .iscript_entity_actions,
-                                   Vec::<IScriptEntityAction>::new());
-        for action in actions {
-            match action {
-                IScriptEntityAction::RemoveEntity { entity } => {
-                    self.world.remove_entity(entity);
-                }
-                IScriptEntityAction::CreateImageUnderlay { parent, image_id, rel_x, rel_y } => {
-                    let ent =
-                        create_scimage(&mut self.world, gd, image_id as usize, 0, 0, Some(parent),
-                        0);
-                    self.world.modify_entity(parent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.iscript_state[e].children.push(ent);
-                    });
-                    self.world.modify_entity(ent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.iscript_state[e].rel_x = rel_x;
-                        data.iscript_state[e].rel_y = rel_y;
-                        data.underlay.insert(&e, UnderlayComponent {});
-                    });
-
-                }
-                IScriptEntityAction::CreateImageOverlay { parent, image_id, rel_x, rel_y } => {
-                    let ent =
-                        create_scimage(&mut self.world, gd, image_id as usize, 0, 0, Some(parent),0);
-                    self.world.modify_entity(parent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.iscript_state[e].children.push(ent);
-                    });
-                    self.world.modify_entity(ent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.iscript_state[e].rel_x = rel_x;
-                        data.iscript_state[e].rel_y = rel_y;
-                        data.overlay.insert(&e, OverlayComponent {});
-                    });
-                }
-                IScriptEntityAction::CreateSpriteOverlay { sprite_id, x, y } => {
-                    let ent = create_scsprite(&mut self.world, gd, sprite_id as usize, x, y, None, 0);
-                    self.world.modify_entity(ent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.overlay.insert(&e, OverlayComponent {});
-                    });
-                }
-                IScriptEntityAction::CreateSpriteUnderlay { parent,
-                                                            sprite_id,
-                                                            x,
-                                                            y,
-                                                            use_parent_dir } => {
-                    let ent =
-                        create_scsprite(&mut self.world, gd, sprite_id as usize, x, y, parent, 0);
-                    let parent_dir = if use_parent_dir {
-                        self.world.with_entity_data(&parent.unwrap(), |e, data| {
-                            data.iscript_state[e].children.push(ent);
-                            data.iscript_state[e].direction
-                        })
-                    } else {
-                        None
-                    };
-                    self.world.modify_entity(ent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.underlay.insert(&e, UnderlayComponent {});
-                        if let Some(initial_dir) = parent_dir {
-                            data.iscript_state[e].direction = initial_dir;
-                        }
-                    });
-                }
-                IScriptEntityAction::CreateWeaponsFlingy { weapon_id, ../*rel_x, rel_y*/ } => {
-                    let ent = create_scflingy(&mut self.world,
-                                              gd,
-                                              gd.weapons_dat.graphics[weapon_id as usize] as usize,
-                                              // FIXME: use proper location
-                                              0,
-                                              0,
-                                              0);
-
-                    let behavior = WeaponBehavior::from_u8(gd.weapons_dat.behavior[weapon_id as
-                                                            usize])
-                        .expect("could not get weapon behavior!");
-                    self.world.modify_entity(ent, |e: ModifyData<UnitComponents>,
-                                              data: &mut UnitComponents| {
-                        data.scweapon.insert(&e,
-                                             SCWeaponComponent {
-                                                 weapon_id: weapon_id,
-                                                 behavior: behavior,
-                                                 age: 0,
-                                             });
-                    });
-                }
-                // _ => {
-                //     println!("ignoring {:?} iscript create action", action);
-                // }
-            }
-        }
-
         let grp_cache = gd.grp_cache.borrow();
 
         let fnt = gd.font(FontSize::Font16);
@@ -360,11 +240,12 @@ impl View for UnitsECSView {
 
                 // draw selection circle if available
                 if dh.selectable.has(&e) {
-                    // dh.selectable[e].draw_healthbar(200, 230,
-                    //                                 buffer,
-                    //                                 buffer_pitch);
                     dh.selectable[e]
                         .draw_selection_circle(&*grp_cache, 200, 200, buffer, buffer_pitch);
+                    if dh.health.has(&e) {
+                        dh.selectable[e]
+                            .draw_healthbar(&dh.health[e], 200, 200, buffer, buffer_pitch);
+                    }
                 }
                 draw_scimage(e, dh, gd, buffer, buffer_pitch, &*grp_cache);
             }
@@ -382,6 +263,10 @@ impl View for UnitsECSView {
 
         ViewAction::None
     }
+
+    fn tick(&mut self, gd: &GameData, _: &mut GameState) {
+        step_world(&mut self.world, gd);
+    }
 }
 
 fn main() {
@@ -0,0 +1,255 @@
+//! Cloaked and burrowed units. They can't be seen or targeted by other players
+//! unless a detector of that player covers them. Ghosts and wraiths cloak with
+//! energy, observers and dark templar are always cloaked, zerg ground units
+//! burrow with the Burrow/Unburrow iscript animations.
+
+use ecs::{World, Entity, EntityData, DataHelper, ModifyData};
+
+use scformats::iscript::AnimationType;
+use scformats::terrain::MapUnit;
+use scformats::unitsdata::{OrderType, UnitsDat, UF_CLOAKABLE, UF_PERMANENT_CLOAK,
+                           UF_BURROWABLE};
+
+use fixedpoint::Fixed;
+use orders::{Order, OrderTarget};
+use unit_ecs::{UnitComponents, UnitServices, UnitSystems, DatFiles, SCUnitStep};
+
+// techdata.dat
+const TECH_CLOAKING_FIELD: usize = 9;
+const TECH_PERSONNEL_CLOAKING: usize = 10;
+const TECH_BURROWING: usize = 11;
+
+// units.dat
+const UNIT_GHOST: usize = 1;
+
+/// energy to start cloaking
+const CLOAK_COST: Fixed = 25 * 256;
+/// energy per tick while cloaked, a bit more than the regeneration
+const CLOAK_DRAIN: Fixed = 13;
+/// ticks until a unit is in or out of the ground
+const BURROW_TICKS: u8 = 12;
+/// players 8 and up don't detect anything
+const DETECTING_PLAYERS: usize = 8;
+
+// MapUnit::state_flags
+const MAP_CLOAKED: u16 = 0x1;
+const MAP_BURROWED: u16 = 0x2;
+
+pub struct CloakComponent {
+    pub cloaked: bool,
+    /// observers, dark templar: no energy needed, can't decloak
+    pub permanent: bool,
+    pub burrowed: bool,
+}
+impl CloakComponent {
+    pub fn new(units_dat: &UnitsDat, unit_id: usize) -> Self {
+        let permanent = units_dat.flags(unit_id).contains(UF_PERMANENT_CLOAK);
+        CloakComponent {
+            cloaked: permanent,
+            permanent: permanent,
+            burrowed: false,
+        }
+    }
+
+    /// invisible to players without detection
+    pub fn is_hidden(&self) -> bool {
+        self.cloaked || self.burrowed
+    }
+}
+
+/// the players that can't see a unit (one bit per player)
+pub fn hidden_from(e: EntityData<UnitComponents>,
+                   dh: &DataHelper<UnitComponents, UnitServices>)
+                   -> u16 {
+    if !dh.cloak.has(&e) || !dh.cloak[e].is_hidden() {
+        return 0;
+    }
+    let owner = dh.scimage[e].player_id;
+    let x = dh.iscript_state[e].map_pos_x as i32;
+    let y = dh.iscript_state[e].map_pos_y as i32;
+    let mut hidden = 0;
+    for player in 0..DETECTING_PLAYERS {
+        if player != owner && !dh.services.vision.is_detected_px(player, x, y) {
+            hidden |= 1 << player;
+        }
+    }
+    hidden
+}
+
+pub fn is_hidden_from(e: EntityData<UnitComponents>,
+                      dh: &DataHelper<UnitComponents, UnitServices>,
+                      player: usize)
+                      -> bool {
+    hidden_from(e, dh) & (1 << player) != 0
+}
+
+pub fn is_cloaked(e: EntityData<UnitComponents>,
+                  dh: &UnitComponents)
+                  -> bool {
+    dh.cloak.has(&e) && dh.cloak[e].cloaked
+}
+
+pub fn is_burrowed(e: EntityData<UnitComponents>,
+                   dh: &UnitComponents)
+                   -> bool {
+    dh.cloak.has(&e) && dh.cloak[e].burrowed
+}
+
+/// the tech a unit needs to cloak
+fn cloak_tech(unit_id: usize) -> usize {
+    if unit_id == UNIT_GHOST {
+        TECH_PERSONNEL_CLOAKING
+    } else {
+        TECH_CLOAKING_FIELD
+    }
+}
+
+/// burrowed units can only be told to unburrow
+pub fn accepts_order(e: EntityData<UnitComponents>,
+                     dh: &UnitComponents,
+                     order: &Order)
+                     -> bool {
+    !is_burrowed(e, dh) || order.order_type == OrderType::Unburrowing
+}
+
+/// orders the burrow and cloak commands translate to, None if the unit can't
+pub fn burrow_order(world: &mut World<UnitSystems>, unit: Entity) -> Option<Order> {
+    let dat = world.data.services.dat();
+    let researched = match world.with_entity_data(&unit, |e, data| data.scimage[e].player_id) {
+        Some(player) => world.data.services.has_tech(player, TECH_BURROWING),
+        None => return None,
+    };
+    world.with_entity_data(&unit, |e, data| {
+            let unit_id = data.scunit[e].unit_id as usize;
+            if data.cloak[e].burrowed ||
+               !dat.units_dat.flags(unit_id).contains(UF_BURROWABLE) || !researched {
+                None
+            } else {
+                Some(Order::new(OrderType::Burrowing, OrderTarget::None))
+            }
+        })
+        .and_then(|o| o)
+}
+
+pub fn cloak_order(world: &mut World<UnitSystems>, unit: Entity) -> Option<Order> {
+    let dat = world.data.services.dat();
+    let researched = match world.with_entity_data(&unit, |e, data| {
+        (data.scunit[e].unit_id as usize, data.scimage[e].player_id)
+    }) {
+        Some((unit_id, player)) => world.data.services.has_tech(player, cloak_tech(unit_id)),
+        None => return None,
+    };
+    world.with_entity_data(&unit, |e, data| {
+            let unit_id = data.scunit[e].unit_id as usize;
+            if data.cloak[e].cloaked || !dat.units_dat.flags(unit_id).contains(UF_CLOAKABLE) ||
+               !researched || data.health[e].energy < CLOAK_COST {
+                None
+            } else {
+                Some(Order::new(OrderType::Cloak, OrderTarget::None))
+            }
+        })
+        .and_then(|o| o)
+}
+
+/// Cloak, Decloak, Burrowing, Burrowed and Unburrowing
+pub fn is_cloak_order(order_type: OrderType) -> bool {
+    match order_type {
+        OrderType::Cloak | OrderType::Decloak | OrderType::Burrowing | OrderType::Burrowed |
+        OrderType::Unburrowing => true,
+        _ => false,
+    }
+}
+
+pub fn order_cloak(e: &EntityData<UnitComponents>,
+                   dh: &mut DataHelper<UnitComponents, UnitServices>,
+                   dat: &DatFiles,
+                   order: Order) {
+    let unit_id = dh.scunit[*e].unit_id as usize;
+    let state = dh.scunit[*e].orders.state;
+    match order.order_type {
+        OrderType::Cloak => {
+            let player = dh.scimage[*e].player_id;
+            if !dh.cloak[*e].cloaked && dh.health[*e].energy >= CLOAK_COST &&
+               dat.units_dat.flags(unit_id).contains(UF_CLOAKABLE) &&
+               dh.services.has_tech(player, cloak_tech(unit_id)) {
+                dh.health[*e].energy -= CLOAK_COST;
+                dh.cloak[*e].cloaked = true;
+            }
+            dh.scunit[*e].orders.finish();
+        }
+        OrderType::Decloak => {
+            if !dh.cloak[*e].permanent {
+                dh.cloak[*e].cloaked = false;
+            }
+            dh.scunit[*e].orders.finish();
+        }
+        OrderType::Burrowing => {
+            if state == 0 {
+                SCUnitStep::halt(e, dh);
+                dh.iscript_state[*e].next_animation = Some(AnimationType::Burrow);
+            }
+            if state < BURROW_TICKS {
+                dh.scunit[*e].orders.state += 1;
+                return;
+            }
+            dh.cloak[*e].burrowed = true;
+            dh.scunit[*e].orders.replace(Order::new(OrderType::Burrowed, OrderTarget::None));
+        }
+        OrderType::Burrowed => {
+            // stays in the ground until told to unburrow
+            dh.scunit[*e].orders.state = 1;
+            if dh.scunit[*e].orders.queue.front().map_or(false, |o| {
+                o.order_type == OrderType::Unburrowing
+            }) {
+                dh.scunit[*e].orders.finish();
+            }
+        }
+        OrderType::Unburrowing => {
+            if !dh.cloak[*e].burrowed {
+                dh.scunit[*e].orders.finish();
+                return;
+            }
+            if state == 0 {
+                dh.iscript_state[*e].next_animation = Some(AnimationType::Unburrow);
+            }
+            if state < BURROW_TICKS {
+                dh.scunit[*e].orders.state += 1;
+                return;
+            }
+            dh.cloak[*e].burrowed = false;
+            dh.scunit[*e].orders.finish();
+        }
+        _ => {}
+    }
+}
+
+/// cloaking costs energy every tick, without energy the unit decloaks
+pub fn step_cloak(e: &EntityData<UnitComponents>,
+                  dh: &mut DataHelper<UnitComponents, UnitServices>) {
+    if !dh.cloak.has(e) || !dh.cloak[*e].cloaked || dh.cloak[*e].permanent {
+        return;
+    }
+    if dh.health[*e].energy < CLOAK_DRAIN {
+        dh.cloak[*e].cloaked = false;
+    } else {
+        dh.health[*e].energy -= CLOAK_DRAIN;
+    }
+}
+
+/// applies the cloak and burrow state a map gives for a unit
+pub fn set_map_cloak(world: &mut World<UnitSystems>, entity: Entity, mapunit: &MapUnit) {
+    let dat = world.data.services.dat();
+    let flags = dat.units_dat.flags(mapunit.unit_id as usize);
+    world.modify_entity(entity,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        if mapunit.state_flags & MAP_CLOAKED != 0 && flags.contains(UF_CLOAKABLE) {
+            data.cloak[e].cloaked = true;
+        }
+        if mapunit.state_flags & MAP_BURROWED != 0 && flags.contains(UF_BURROWABLE) {
+            data.cloak[e].burrowed = true;
+            data.iscript_state[e].next_animation = Some(AnimationType::Burrow);
+            data.scunit[e].orders.replace(Order::new(OrderType::Burrowed, OrderTarget::None));
+        }
+    });
+}
+
@@ -0,0 +1,228 @@
+//! Collision of ground units: units are boxes given by the units.dat
+//! dimensions, overlapping ground units push each other apart, buildings don't
+//! move and block their tiles for path planning. Air units are exempt.
+
+use std::collections::HashMap;
+
+use scformats::unitsdata::{UnitsDat, UF_FLYER, UF_BUILDING, UF_SUBUNIT, UNIT_START_LOCATION};
+
+use fixedpoint::{Fixed, px_to_fixed};
+use orders::UnitInfo;
+
+/// maximum distance a unit gets pushed per tick
+const MAX_PUSH: Fixed = 2 * 256;
+
+/// size of the grid cells in pixels
+pub const GRID_CELL_SIZE: i32 = 64;
+
+/// axis aligned box in map pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Footprint {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+impl Footprint {
+    pub fn of_unit(units_dat: &UnitsDat, unit_id: usize, x: i32, y: i32) -> Self {
+        Footprint {
+            left: x - units_dat.unit_size_left[unit_id] as i32,
+            top: y - units_dat.unit_size_up[unit_id] as i32,
+            right: x + units_dat.unit_size_right[unit_id] as i32,
+            bottom: y + units_dat.unit_size_down[unit_id] as i32,
+        }
+    }
+
+    /// overlap along x and y, both positive if the boxes intersect
+    pub fn overlap(&self, other: &Footprint) -> (i32, i32) {
+        (self.right.min(other.right) - self.left.max(other.left),
+         self.bottom.min(other.bottom) - self.top.max(other.top))
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.left && x <= self.right && y >= self.top && y <= self.bottom
+    }
+}
+
+/// buckets of units by position, for neighbour queries
+pub struct SpatialGrid {
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+impl SpatialGrid {
+    pub fn new() -> Self {
+        SpatialGrid { cells: HashMap::new() }
+    }
+
+    fn cell(px: i32) -> i32 {
+        if px < 0 {
+            (px + 1) / GRID_CELL_SIZE - 1
+        } else {
+            px / GRID_CELL_SIZE
+        }
+    }
+
+    fn cell_range(fp: &Footprint) -> (i32, i32, i32, i32) {
+        (SpatialGrid::cell(fp.left),
+         SpatialGrid::cell(fp.top),
+         SpatialGrid::cell(fp.right),
+         SpatialGrid::cell(fp.bottom))
+    }
+
+    pub fn insert(&mut self, idx: usize, fp: &Footprint) {
+        let (x0, y0, x1, y1) = SpatialGrid::cell_range(fp);
+        for cy in y0..y1 + 1 {
+            for cx in x0..x1 + 1 {
+                self.cells.entry((cx, cy)).or_insert_with(Vec::new).push(idx);
+            }
+        }
+    }
+
+    /// indices of everything in the cells touched by `fp`, sorted
+    pub fn query(&self, fp: &Footprint) -> Vec<usize> {
+        let (x0, y0, x1, y1) = SpatialGrid::cell_range(fp);
+        let mut res = Vec::new();
+        for cy in y0..y1 + 1 {
+            for cx in x0..x1 + 1 {
+                if let Some(cell) = self.cells.get(&(cx, cy)) {
+                    res.extend_from_slice(cell);
+                }
+            }
+        }
+        res.sort();
+        res.dedup();
+        res
+    }
+}
+
+/// does the unit take part in ground collision
+pub fn collides(units_dat: &UnitsDat, unit_id: usize) -> bool {
+    let flags = units_dat.flags(unit_id);
+    !flags.contains(UF_FLYER) && !flags.contains(UF_SUBUNIT) &&
+    unit_id != UNIT_START_LOCATION as usize
+}
+
+pub fn is_building(units_dat: &UnitsDat, unit_id: usize) -> bool {
+    units_dat.flags(unit_id).contains(UF_BUILDING)
+}
+
+/// megatiles (by index) whose centers are covered by buildings
+pub fn building_tiles(units_dat: &UnitsDat,
+                      units: &[UnitInfo],
+                      map_width: usize,
+                      map_height: usize)
+                      -> Vec<usize> {
+    let mut tiles = Vec::new();
+    for u in units {
+        if !collides(units_dat, u.unit_id) || !is_building(units_dat, u.unit_id) || u.lifted {
+            continue;
+        }
+        let fp = Footprint::of_unit(units_dat, u.unit_id, u.x, u.y);
+        let tx0 = (fp.left / 32).max(0) as usize;
+        let ty0 = (fp.top / 32).max(0) as usize;
+        let tx1 = ((fp.right / 32).max(0) as usize).min(map_width - 1);
+        let ty1 = ((fp.bottom / 32).max(0) as usize).min(map_height - 1);
+        for ty in ty0..ty1 + 1 {
+            for tx in tx0..tx1 + 1 {
+                if fp.contains(tx as i32 * 32 + 16, ty as i32 * 32 + 16) {
+                    tiles.push(ty * map_width + tx);
+                }
+            }
+        }
+    }
+    tiles
+}
+
+fn sign(v: i32, tie: i32) -> i32 {
+    if v > 0 {
+        1
+    } else if v < 0 {
+        -1
+    } else {
+        tie
+    }
+}
+
+/// how far every unit has to be pushed (in 1/256 pixels) to resolve overlaps.
+/// `moving[i]` is true if unit i is currently walking; walking units push
+/// idle ones out of the way.
+pub fn separation(units_dat: &UnitsDat,
+                  units: &[UnitInfo],
+                  moving: &[bool])
+                  -> Vec<(Fixed, Fixed)> {
+    let mut pushes = vec![(0, 0); units.len()];
+    let mut grid = SpatialGrid::new();
+    let mut footprints = Vec::with_capacity(units.len());
+    for (i, u) in units.iter().enumerate() {
+        let fp = Footprint::of_unit(units_dat, u.unit_id, u.x, u.y);
+        if collides(units_dat, u.unit_id) && !u.lifted {
+            grid.insert(i, &fp);
+        }
+        footprints.push(fp);
+    }
+
+    for i in 0..units.len() {
+        if !collides(units_dat, units[i].unit_id) || units[i].lifted {
+            continue;
+        }
+        let building_i = is_building(units_dat, units[i].unit_id);
+        for j in grid.query(&footprints[i]) {
+            if j <= i {
+                continue;
+            }
+            let building_j = is_building(units_dat, units[j].unit_id);
+            if building_i && building_j {
+                continue;
+            }
+            let (ox, oy) = footprints[i].overlap(&footprints[j]);
+            if ox <= 0 || oy <= 0 {
+                continue;
+            }
+            // push apart along the axis with less overlap
+            let (px, py) = if ox < oy {
+                (px_to_fixed(ox * sign(units[i].x - units[j].x, -1)), 0)
+            } else {
+                (0, px_to_fixed(oy * sign(units[i].y - units[j].y, -1)))
+            };
+            // share of the push i gets, of 256
+            let share_i = if building_j {
+                256
+            } else if building_i {
+                0
+            } else if moving[i] && !moving[j] {
+                0
+            } else if moving[j] && !moving[i] {
+                256
+            } else {
+                128
+            };
+            pushes[i].0 += px * share_i / 256;
+            pushes[i].1 += py * share_i / 256;
+            pushes[j].0 -= px * (256 - share_i) / 256;
+            pushes[j].1 -= py * (256 - share_i) / 256;
+        }
+    }
+    for p in &mut pushes {
+        p.0 = p.0.max(-MAX_PUSH).min(MAX_PUSH);
+        p.1 = p.1.max(-MAX_PUSH).min(MAX_PUSH);
+    }
+    pushes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Footprint, SpatialGrid};
+
+    #[test]
+    fn grid_query() {
+        let mut grid = SpatialGrid::new();
+        let a = Footprint { left: 0, top: 0, right: 10, bottom: 10 };
+        let b = Footprint { left: 60, top: 60, right: 70, bottom: 70 };
+        let c = Footprint { left: 300, top: 300, right: 310, bottom: 310 };
+        grid.insert(0, &a);
+        grid.insert(1, &b);
+        grid.insert(2, &c);
+        assert_eq!(grid.query(&a), vec![0, 1]);
+        assert_eq!(grid.query(&c), vec![2]);
+        assert_eq!(a.overlap(&Footprint { left: 5, top: 8, right: 20, bottom: 20 }), (5, 2));
+    }
+}
@@ -0,0 +1,597 @@
+//! Weapons and damage. Weapon flingies move according to their weapons.dat
+//! behavior; damage is dealt when their iscript runs domissiledmg or
+//! dogrddamage, or when a melee unit runs attackmelee.
+
+use std::cmp;
+
+use ecs::{World, Entity, EntityData, DataHelper, EntityIter, ModifyData, System};
+use ecs::system::EntityProcess;
+use enum_primitive::FromPrimitive;
+
+use scformats::iscript::AnimationType;
+use scformats::unitsdata::{OrderType, WeaponBehavior, WeaponsDamageType, WeaponsExplosionType,
+                           UF_FLYER, UF_INVINCIBLE};
+
+use gamedata::GameData;
+use fixedpoint::{Fixed, DIRECTION_TABLE, approx_distance, direction_from_delta,
+                 direction_offset, dir256_to_dir32, px_to_fixed, fixed_to_px};
+use orders::can_target;
+use health::HealthComponent;
+use abilities::{spell_for_weapon, apply_spell, is_invulnerable};
+use unit_ecs::{UnitComponents, UnitServices, UnitSystems, DatFiles, SCWeaponComponent,
+               IScriptEntityAction, create_scflingy};
+
+/// Protoss Plasma Shields
+pub const UPGRADE_PLASMA_SHIELDS: usize = 15;
+/// e.g. mutalisk glaives hit up to 3 units
+const MAX_BOUNCES: u8 = 2;
+/// how far a bouncing weapon looks for the next target
+const BOUNCE_RANGE: i32 = 96;
+/// persisting weapons (psionic storm) hit every few ticks
+const PERSIST_DAMAGE_INTERVAL: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WeaponState {
+    Init,
+    Moving,
+    Persisting,
+    /// playing the death animation, which deals the damage
+    Dying,
+}
+
+/// damage multiplier (of 256) of a damage type vs. units.dat unit size
+fn size_factor(damage_type: WeaponsDamageType, unit_size: u8) -> i32 {
+    match (damage_type, unit_size) {
+        (WeaponsDamageType::Explosive, 1) => 128,
+        (WeaponsDamageType::Explosive, 2) => 192,
+        (WeaponsDamageType::Concussive, 2) => 128,
+        (WeaponsDamageType::Concussive, 3) => 64,
+        _ => 256,
+    }
+}
+
+/// damage of one hit in 1/256 hit points, including upgrades
+pub fn weapon_damage(dat: &DatFiles, services: &UnitServices, weapon: usize, player: usize) -> Fixed {
+    let level = services.upgrade_level(player, dat.weapons_dat.damage_upgrade[weapon] as usize);
+    let damage = dat.weapons_dat.damage_amount[weapon] as i32 +
+                 dat.weapons_dat.damage_bonus[weapon] as i32 * level as i32;
+    damage * dat.weapons_dat.damage_factor[weapon] as i32 * 256
+}
+
+/// deals `damage` to a unit, shields first. `armor_level` and `shield_level`
+/// are the upgrade levels of the target's owner. Returns true if the unit died.
+pub fn apply_damage(dat: &DatFiles,
+                    health: &mut HealthComponent,
+                    unit_id: usize,
+                    armor_level: u8,
+                    shield_level: u8,
+                    weapon: usize,
+                    damage: Fixed)
+                    -> bool {
+    if health.hit_points <= 0 || dat.units_dat.flags(unit_id).contains(UF_INVINCIBLE) {
+        return false;
+    }
+    let damage_type = WeaponsDamageType::from_u8(dat.weapons_dat.damage_type[weapon])
+        .unwrap_or(WeaponsDamageType::Normal);
+    if damage_type == WeaponsDamageType::Independent {
+        // half a hit point, regardless of armor and shields
+        health.hit_points -= 128;
+        return health.hit_points <= 0;
+    }
+
+    let mut damage = damage;
+    if health.shields > 0 {
+        let shield_armor = shield_level as Fixed * 256;
+        let absorbed = cmp::min(health.shields, cmp::max(damage - shield_armor, 0));
+        health.shields -= absorbed;
+        damage -= absorbed + shield_armor;
+        if damage <= 0 {
+            return false;
+        }
+    }
+    if damage_type != WeaponsDamageType::IgnoreArmor {
+        damage -= (dat.units_dat.armor[unit_id] as Fixed + armor_level as Fixed) * 256;
+    }
+    damage = damage * size_factor(damage_type, dat.units_dat.unit_size[unit_id]) / 256;
+    // at least half a hit point
+    health.hit_points -= cmp::max(damage, 128);
+    health.hit_points <= 0
+}
+
+/// starts the death animation of a unit, iscript removes it afterwards.
+/// returns player and unit id if the death counts, see `record_death`
+pub fn kill_unit(e: EntityData<UnitComponents>,
+                 dh: &mut UnitComponents)
+                 -> Option<(usize, usize)> {
+    // turrets die with their base and don't count
+    let death = if dh.scunit.has(&e) && !is_dying(e, dh) && !dh.subunit.has(&e) {
+        Some((dh.scimage[e].player_id, dh.scunit[e].unit_id as usize))
+    } else {
+        None
+    };
+    if dh.health.has(&e) {
+        dh.health[e].hit_points = 0;
+    }
+    if dh.scunit.has(&e) {
+        dh.scunit[e].orders.die();
+        dh.scunit[e].path = None;
+    }
+    dh.iscript_state[e].next_animation = Some(AnimationType::Death);
+    death
+}
+
+/// counts a death returned by `kill_unit`
+pub fn record_death(services: &mut UnitServices, death: Option<(usize, usize)>) {
+    if let Some((player, unit_id)) = death {
+        services.players[player].deaths[unit_id] += 1;
+    }
+}
+
+/// kills a unit outside of a system, e.g. from a trigger
+pub fn kill_entity(world: &mut World<UnitSystems>, ent: Entity) {
+    let death = world.with_entity_data(&ent, |e, data| kill_unit(e, data)).and_then(|d| d);
+    record_death(&mut world.data.services, death);
+}
+
+pub fn is_dying(e: EntityData<UnitComponents>,
+                dh: &UnitComponents)
+                -> bool {
+    !dh.iscript_state[e].alive ||
+    (dh.scunit.has(&e) && dh.scunit[e].orders.current.order_type == OrderType::Die)
+}
+
+/// distance from a point to the edge of a unit
+fn point_distance(dat: &DatFiles, unit_id: usize, ux: i32, uy: i32, px: i32, py: i32) -> i32 {
+    let dx = cmp::max(0,
+                      cmp::max(ux - dat.units_dat.unit_size_left[unit_id] as i32 - px,
+                               px - ux - dat.units_dat.unit_size_right[unit_id] as i32));
+    let dy = cmp::max(0,
+                      cmp::max(uy - dat.units_dat.unit_size_up[unit_id] as i32 - py,
+                               py - uy - dat.units_dat.unit_size_down[unit_id] as i32));
+    approx_distance(dx, dy)
+}
+
+/// spawns a weapon flingy, e.g. from iscript's attack opcodes. `bounce` counts
+/// how often a bouncing weapon already hit.
+pub fn launch_weapon(world: &mut World<UnitSystems>,
+                     gd: &GameData,
+                     weapon_id: usize,
+                     source: Option<Entity>,
+                     target: Option<Entity>,
+                     x: u16,
+                     y: u16,
+                     player_id: usize,
+                     bounce: u8)
+                     -> Entity {
+    let behavior = WeaponBehavior::from_u8(gd.weapons_dat.behavior[weapon_id])
+        .expect("could not get weapon behavior!");
+    let target_pos = target.and_then(|t| {
+            world.with_entity_data(&t, |e, data| {
+                (data.iscript_state[e].map_pos_x, data.iscript_state[e].map_pos_y)
+            })
+        })
+        .unwrap_or((x, y));
+    let (sx, sy) = match behavior {
+        WeaponBehavior::AppearOnTargetUnit |
+        WeaponBehavior::AppearOnTargetSite |
+        WeaponBehavior::AttackTarget3x3Area |
+        WeaponBehavior::PsionicStorm => target_pos,
+        _ => (x, y),
+    };
+    let direction = direction_from_delta(target_pos.0 as i32 - x as i32,
+                                         target_pos.1 as i32 - y as i32);
+
+    let ent = create_scflingy(world,
+                              gd,
+                              gd.weapons_dat.graphics[weapon_id] as usize,
+                              sx,
+                              sy,
+                              player_id);
+    world.modify_entity(ent,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        data.iscript_state[e].movement_direction = direction;
+        data.iscript_state[e].direction = dir256_to_dir32(direction);
+        data.scweapon.insert(&e,
+                             SCWeaponComponent {
+                                 weapon_id: weapon_id as u16,
+                                 behavior: behavior,
+                                 age: 0,
+                                 source: source,
+                                 player_id: player_id,
+                                 target: target,
+                                 target_pos: (target_pos.0 as i32, target_pos.1 as i32),
+                                 state: WeaponState::Init,
+                                 bounces_left: if behavior == WeaponBehavior::Bounce {
+                                     MAX_BOUNCES - bounce
+                                 } else {
+                                     0
+                                 },
+                                 damage_divisor: 3i32.pow(bounce as u32),
+                                 hit_units: Vec::new(),
+                             });
+    });
+    ent
+}
+
+/// what a hit needs to know about the weapon
+struct Hit {
+    weapon: usize,
+    player: usize,
+    attacker: Option<Entity>,
+    target: Option<Entity>,
+    /// None for melee attacks
+    behavior: Option<WeaponBehavior>,
+    pos: Option<(i32, i32)>,
+    divisor: i32,
+    bounces_left: u8,
+    hit_units: Vec<Entity>,
+}
+
+struct Victim {
+    entity: Entity,
+    unit_id: usize,
+    player: usize,
+    x: i32,
+    y: i32,
+}
+
+/// resolves the damage of a weapon flingy or melee attacker
+pub fn deal_damage(world: &mut World<UnitSystems>, gd: &GameData, source: Entity) {
+    let dat = world.data.services.dat();
+    let hit = world.with_entity_data(&source, |e, data| {
+        if data.scweapon.has(&e) {
+            let w = &data.scweapon[e];
+            Some(Hit {
+                weapon: w.weapon_id as usize,
+                player: w.player_id,
+                attacker: w.source,
+                target: w.target,
+                behavior: Some(w.behavior),
+                pos: Some((data.iscript_state[e].map_pos_x as i32,
+                           data.iscript_state[e].map_pos_y as i32)),
+                divisor: w.damage_divisor,
+                bounces_left: w.bounces_left,
+                hit_units: w.hit_units.clone(),
+            })
+        } else if data.scunit.has(&e) {
+            Some(Hit {
+                weapon: data.scunit[e].used_weapon,
+                player: data.scimage[e].player_id,
+                attacker: Some(**e),
+                target: data.scunit[e].attack_target,
+                behavior: None,
+                pos: None,
+                divisor: 1,
+                bounces_left: 0,
+                hit_units: Vec::new(),
+            })
+        } else {
+            None
+        }
+    });
+    let hit = match hit {
+        Some(Some(hit)) => hit,
+        _ => return,
+    };
+    if hit.weapon >= dat.weapons_dat.damage_amount.len() {
+        return;
+    }
+
+    let mut victims = Vec::<Victim>::new();
+    {
+        let dh = &world.data;
+        for e in world.entities().filter(aspect!(<UnitComponents> all: [scunit, health]
+                                                 none: [passenger, subunit]),
+                                         world) {
+            if is_dying(e, dh) || is_invulnerable(e, dh) {
+                continue;
+            }
+            victims.push(Victim {
+                entity: **e,
+                unit_id: dh.scunit[e].unit_id as usize,
+                player: dh.scimage[e].player_id,
+                x: dh.iscript_state[e].map_pos_x as i32,
+                y: dh.iscript_state[e].map_pos_y as i32,
+            });
+        }
+    }
+    let target = hit.target.and_then(|t| victims.iter().find(|v| v.entity == t));
+    let (ix, iy) = match (hit.pos, target) {
+        (Some(pos), _) => pos,
+        (None, Some(t)) => (t.x, t.y),
+        (None, None) => return,
+    };
+    // e.g. lockdown, ensnare: the spell's effect instead of damage
+    if let Some(spell) = spell_for_weapon(&dat, hit.weapon) {
+        apply_spell(world, gd, spell, hit.target, ix, iy, hit.player);
+        return;
+    }
+
+    let base = weapon_damage(&dat, &world.data.services, hit.weapon, hit.player) / hit.divisor;
+    let explosion = WeaponsExplosionType::from_u8(dat.weapons_dat.explosion_type[hit.weapon])
+        .unwrap_or(WeaponsExplosionType::Normal);
+    let splash = hit.behavior == Some(WeaponBehavior::PsionicStorm) ||
+                 hit.behavior == Some(WeaponBehavior::GoToMaxRange) ||
+                 match explosion {
+        WeaponsExplosionType::RadialSplash |
+        WeaponsExplosionType::EnemySplash |
+        WeaponsExplosionType::SplashAir |
+        WeaponsExplosionType::NuclearMissile => true,
+        _ => false,
+    };
+
+    let mut hits = Vec::<(Entity, Fixed)>::new();
+    if splash {
+        let inner = dat.weapons_dat.inner_splash_range[hit.weapon] as i32;
+        let medium = dat.weapons_dat.medium_splash_range[hit.weapon] as i32;
+        let outer = dat.weapons_dat.outer_splash_range[hit.weapon] as i32;
+        for v in &victims {
+            if Some(v.entity) == hit.attacker || hit.hit_units.contains(&v.entity) ||
+               !can_target(&dat.weapons_dat, hit.weapon, &dat.units_dat, v.unit_id) {
+                continue;
+            }
+            if explosion == WeaponsExplosionType::EnemySplash && v.player == hit.player {
+                continue;
+            }
+            if explosion == WeaponsExplosionType::SplashAir && Some(v.entity) != hit.target &&
+               !dat.units_dat.flags(v.unit_id).contains(UF_FLYER) {
+                continue;
+            }
+            let dist = point_distance(&dat, v.unit_id, v.x, v.y, ix, iy);
+            let damage = if hit.behavior == Some(WeaponBehavior::PsionicStorm) {
+                if dist <= outer { base } else { 0 }
+            } else if dist <= inner {
+                base
+            } else if dist <= medium {
+                base / 2
+            } else if dist <= outer {
+                base / 4
+            } else {
+                0
+            };
+            if damage > 0 {
+                hits.push((v.entity, damage));
+            }
+        }
+    } else if let Some(t) = target {
+        match explosion {
+            WeaponsExplosionType::None |
+            WeaponsExplosionType::Normal |
+            WeaponsExplosionType::YamatoGun => hits.push((t.entity, base)),
+            // spell explosions without an effect in SPELLS (parasite, broodlings,
+            // feedback, ...) only deal their weapon's damage, which is mostly none
+            _ => {
+                if base > 0 {
+                    hits.push((t.entity, base));
+                }
+            }
+        }
+    }
+
+    for &(victim, damage) in &hits {
+        let (unit_id, player) = match victims.iter().find(|v| v.entity == victim) {
+            Some(v) => (v.unit_id, v.player),
+            None => continue,
+        };
+        let armor_level = world.data
+            .services
+            .upgrade_level(player, dat.units_dat.armor_upgrade[unit_id] as usize);
+        let shield_level = world.data.services.upgrade_level(player, UPGRADE_PLASMA_SHIELDS);
+        let death = world.with_entity_data(&victim, |e, data| {
+                let damage = data.status[e].absorb(damage);
+                if damage <= 0 {
+                    return None;
+                }
+                let killed = apply_damage(&dat,
+                                          &mut data.health[e],
+                                          unit_id,
+                                          armor_level,
+                                          shield_level,
+                                          hit.weapon,
+                                          damage);
+                if killed {
+                    kill_unit(e, data)
+                } else {
+                    None
+                }
+            })
+            .and_then(|k| k);
+        record_death(&mut world.data.services, death);
+        if let Some((_, victim_id)) = death {
+            if let Some(attacker) = hit.attacker {
+                let killer = world.with_entity_data(&attacker, |e, data| if data.scunit.has(&e) {
+                        data.scunit[e].kill_count += 1;
+                        Some(data.scimage[e].player_id)
+                    } else {
+                        None
+                    })
+                    .and_then(|p| p);
+                if let Some(player) = killer {
+                    world.data.services.players[player].record_kill(&dat.units_dat, victim_id);
+                }
+            }
+        }
+    }
+
+    match hit.behavior {
+        Some(WeaponBehavior::GoToMaxRange) => {
+            world.with_entity_data(&source, |e, data| for &(victim, _) in &hits {
+                data.scweapon[e].hit_units.push(victim);
+            });
+        }
+        Some(WeaponBehavior::AttackAndSelfDestruct) => {
+            if let Some(attacker) = hit.attacker {
+                kill_entity(world, attacker);
+            }
+        }
+        Some(WeaponBehavior::Bounce) if hit.bounces_left > 0 => {
+            // jump to the closest other unit the weapon can hit
+            let mut next = None;
+            let mut best_dist = BOUNCE_RANGE + 1;
+            for v in &victims {
+                if Some(v.entity) == hit.target || v.player == hit.player || v.player >= 8 ||
+                   !can_target(&dat.weapons_dat, hit.weapon, &dat.units_dat, v.unit_id) {
+                    continue;
+                }
+                let dist = point_distance(&dat, v.unit_id, v.x, v.y, ix, iy);
+                if dist < best_dist {
+                    next = Some(v.entity);
+                    best_dist = dist;
+                }
+            }
+            if next.is_some() {
+                launch_weapon(world,
+                              gd,
+                              hit.weapon,
+                              hit.attacker,
+                              next,
+                              ix as u16,
+                              iy as u16,
+                              hit.player,
+                              MAX_BOUNCES - hit.bounces_left + 1);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// moves the weapon flingies
+pub struct WeaponStep {
+    /// damage to be dealt after the update
+    pub actions: Vec<IScriptEntityAction>,
+}
+impl System for WeaponStep {
+    type Components = UnitComponents;
+    type Services = UnitServices;
+}
+impl WeaponStep {
+    fn die(e: &EntityData<UnitComponents>, dh: &mut DataHelper<UnitComponents, UnitServices>) {
+        dh.scweapon[*e].state = WeaponState::Dying;
+        dh.iscript_state[*e].next_animation = Some(AnimationType::Death);
+    }
+
+    /// returns true when the target position was reached
+    fn fly(e: &EntityData<UnitComponents>,
+           dh: &mut DataHelper<UnitComponents, UnitServices>,
+           dat: &DatFiles)
+           -> bool {
+        let (tx, ty) = dh.scweapon[*e].target_pos;
+        let x = dh.iscript_state[*e].map_pos_x as i32;
+        let y = dh.iscript_state[*e].map_pos_y as i32;
+        let flingy_id = dh.scflingy[*e].flingy_id as usize;
+        let top_speed = dat.flingy_dat.top_speed[flingy_id] as Fixed;
+        let acceleration = dat.flingy_dat.acceleration[flingy_id] as Fixed;
+        let speed = cmp::min(dh.scflingy[*e].speed + acceleration, top_speed);
+        dh.scflingy[*e].speed = speed;
+
+        let dist = px_to_fixed(approx_distance(tx - x, ty - y));
+        if dist <= cmp::max(speed, px_to_fixed(4)) {
+            dh.iscript_state[*e].set_exact_pos(px_to_fixed(tx), px_to_fixed(ty));
+            return true;
+        }
+        let dir = direction_from_delta(tx - x, ty - y);
+        dh.iscript_state[*e].movement_direction = dir;
+        dh.iscript_state[*e].direction = dir256_to_dir32(dir);
+        if dh.scflingy[*e].is_iscript_controlled() {
+            // iscript's move opcodes do the moving
+            return false;
+        }
+        let (vx, vy) = DIRECTION_TABLE[dir as usize];
+        let nx = dh.iscript_state[*e].exact_x + ((vx * speed) >> 8);
+        let ny = dh.iscript_state[*e].exact_y + ((vy * speed) >> 8);
+        dh.iscript_state[*e].set_exact_pos(nx, ny);
+        false
+    }
+
+    fn step(&mut self,
+            e: &EntityData<UnitComponents>,
+            dh: &mut DataHelper<UnitComponents, UnitServices>,
+            dat: &DatFiles) {
+        let weapon = dh.scweapon[*e].weapon_id as usize;
+        let behavior = dh.scweapon[*e].behavior;
+        dh.scweapon[*e].age += 1;
+        let state = dh.scweapon[*e].state;
+        if state == WeaponState::Dying {
+            return;
+        }
+        if dh.scweapon[*e].age > dat.weapons_dat.remove_after[weapon] as usize {
+            WeaponStep::die(e, dh);
+            return;
+        }
+
+        // homing weapons follow their target
+        if let Some(target) = dh.scweapon[*e].target {
+            if behavior == WeaponBehavior::FlyToTarget2 || behavior == WeaponBehavior::Bounce {
+                if let Some(pos) = dh.with_entity_data(&target, |te, data| {
+                    if is_dying(te, data) {
+                        None
+                    } else {
+                        Some((data.iscript_state[te].map_pos_x as i32,
+                              data.iscript_state[te].map_pos_y as i32))
+                    }
+                }) {
+                    if let Some(pos) = pos {
+                        dh.scweapon[*e].target_pos = pos;
+                    }
+                }
+            }
+        }
+
+        match state {
+            WeaponState::Init => {
+                match behavior {
+                    WeaponBehavior::FlyToTarget |
+                    WeaponBehavior::FlyToTarget2 |
+                    WeaponBehavior::Bounce => {
+                        dh.scweapon[*e].state = WeaponState::Moving;
+                    }
+                    WeaponBehavior::GoToMaxRange => {
+                        let dir = dh.iscript_state[*e].movement_direction;
+                        let (ox, oy) = direction_offset(dir,
+                                                        dat.weapons_dat.maximum_range[weapon] as
+                                                        i32);
+                        let x = dh.iscript_state[*e].map_pos_x as i32;
+                        let y = dh.iscript_state[*e].map_pos_y as i32;
+                        dh.scweapon[*e].target_pos = (x + fixed_to_px(ox), y + fixed_to_px(oy));
+                        dh.scweapon[*e].state = WeaponState::Moving;
+                    }
+                    WeaponBehavior::PsionicStorm => {
+                        dh.scweapon[*e].state = WeaponState::Persisting;
+                    }
+                    _ => {
+                        // appears where it hits
+                        WeaponStep::die(e, dh);
+                    }
+                }
+            }
+            WeaponState::Moving => {
+                if behavior == WeaponBehavior::GoToMaxRange {
+                    // hits everything on its way
+                    self.actions.push(IScriptEntityAction::DealDamage { source: ***e });
+                }
+                if WeaponStep::fly(e, dh, dat) {
+                    WeaponStep::die(e, dh);
+                }
+            }
+            WeaponState::Persisting => {
+                if dh.scweapon[*e].age % PERSIST_DAMAGE_INTERVAL == 0 {
+                    self.actions.push(IScriptEntityAction::DealDamage { source: ***e });
+                }
+            }
+            WeaponState::Dying => {}
+        }
+    }
+}
+impl EntityProcess for WeaponStep {
+    fn process(&mut self,
+               en: EntityIter<UnitComponents>,
+               dh: &mut DataHelper<UnitComponents, UnitServices>) {
+        let dat = dh.services.dat();
+        for e in en {
+            if !dh.iscript_state[e].alive {
+                continue;
+            }
+            self.step(&e, dh, &dat);
+        }
+    }
+}
@@ -0,0 +1,873 @@
+//! Buildings: placement checks, construction and the train queues of
+//! production buildings. Terran SCVs stay with the building until it is done,
+//! Protoss probes only start the warp-in, Zerg drones turn into the building.
+
+use ecs::{World, Entity, EntityData, DataHelper, ModifyData};
+
+use scformats::iscript::AnimationType;
+use scformats::terrain::TerrainGrid;
+use scformats::unitsdata::{OrderType, UnitsDat, UF_BUILDING, UF_WORKER, UF_RESOURCE_DEPOT,
+                           UF_RESOURCE_CONTAINER, UF_REQUIRES_CREEP, UF_REQUIRES_PSI,
+                           UF_PRODUCES_UNITS, UNIT_MINERAL_FIELD_1, UNIT_MINERAL_FIELD_3,
+                           UNIT_VESPENE_GEYSER};
+
+use gamedata::GameData;
+use fixedpoint::{Fixed, approx_distance};
+use collision::{Footprint, collides};
+use combat::is_dying;
+use cloak::hidden_from;
+use morph::{is_lifted, is_morphing};
+use economy::{Race, race, can_harvest, place_refinery};
+use groupmove::formation_slots;
+use orders::{Order, OrderTarget, UnitInfo, find_unit, issue_order, chase};
+use simulation::remove_entity_rec;
+use unit_ecs::{UnitComponents, UnitServices, UnitSystems, DatFiles, PlanningMap, SCUnitStep,
+               IScriptEntityAction, OverlayComponent, create_scimage, create_scunit};
+
+pub const TRAIN_QUEUE_SIZE: usize = 5;
+/// buildings start with this share (in percent) of their hit points and shields
+const INITIAL_HP_PERCENT: Fixed = 10;
+/// distance (pixels) between a worker and the center of the site to start building
+const BUILD_RANGE: i32 = 32;
+/// psi field of a pylon, half axes of the ellipse in pixels
+const PSI_RANGE_X: i32 = 8 * 32;
+const PSI_RANGE_Y: i32 = 5 * 32;
+/// creep around zerg buildings, in pixels
+const CREEP_RANGE: i32 = 6 * 32;
+/// resource depots keep this distance (pixels) to minerals and geysers
+const RESOURCE_DISTANCE: i32 = 3 * 32;
+
+const UNIT_PYLON: usize = 156;
+const UNIT_HATCHERY: usize = 131;
+const UNIT_HIVE: usize = 133;
+const UNIT_CREEP_COLONY: usize = 143;
+const UNIT_SPORE_COLONY: usize = 144;
+const UNIT_SUNKEN_COLONY: usize = 146;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildError {
+    Unbuildable,
+    Occupied,
+    NeedsCreep,
+    OnCreep,
+    NeedsPsi,
+    TooCloseToResources,
+    NeedsGeyser,
+    NotEnoughMinerals,
+    NotEnoughGas,
+    NotEnoughSupply,
+    QueueFull,
+    /// the unit can't build, train or research this
+    NotAllowed,
+    /// already training or researching
+    Busy,
+    /// disabled by the map
+    Unavailable,
+    AlreadyResearched,
+    /// a building or add-on the player needs is missing
+    MissingRequirement,
+}
+
+/// (unit, building that trains it, buildings the player needs, add-on the
+/// building needs); zerg units morph instead
+static TRAINED_AT: [(usize, usize, &'static [usize], Option<usize>); 26] =
+    [(7, 106, &[], None), // scv, command center
+     (14, 108, &[], None), // nuclear missile, nuclear silo
+     (0, 111, &[], None), // marine, barracks
+     (32, 111, &[112], None), // firebat, academy
+     (34, 111, &[112], None), // medic, academy
+     (1, 111, &[112, 117], None), // ghost, academy and covert ops
+     (2, 113, &[], None), // vulture, factory
+     (5, 113, &[], Some(120)), // siege tank, machine shop
+     (3, 113, &[123], None), // goliath, armory
+     (8, 114, &[], None), // wraith, starport
+     (11, 114, &[], Some(115)), // dropship, control tower
+     (9, 114, &[116], Some(115)), // science vessel, science facility
+     (12, 114, &[118], Some(115)), // battlecruiser, physics lab
+     (58, 114, &[123], Some(115)), // valkyrie, armory
+     (64, 154, &[], None), // probe, nexus
+     (65, 160, &[], None), // zealot, gateway
+     (66, 160, &[164], None), // dragoon, cybernetics core
+     (67, 160, &[165], None), // high templar, templar archives
+     (61, 160, &[165], None), // dark templar, templar archives
+     (69, 155, &[], None), // shuttle, robotics facility
+     (83, 155, &[171], None), // reaver, robotics support bay
+     (84, 155, &[159], None), // observer, observatory
+     (70, 167, &[], None), // scout, stargate
+     (60, 167, &[], None), // corsair
+     (72, 167, &[169], None), // carrier, fleet beacon
+     (71, 167, &[170], None) /* arbiter, arbiter tribunal */];
+
+pub struct ConstructionComponent {
+    /// ticks built so far
+    pub progress: u16,
+    pub build_time: u16,
+    /// terran: the SCV working on it
+    pub builder: Option<Entity>,
+    /// units.dat construction image, shown instead of the building
+    pub overlay: Option<Entity>,
+    /// hit points and shields gained per tick
+    hp_step: Fixed,
+    shield_step: Fixed,
+}
+impl ConstructionComponent {
+    pub fn is_complete(&self) -> bool {
+        self.progress >= self.build_time
+    }
+}
+
+pub struct TrainQueueComponent {
+    /// unit ids, the first one is in production
+    pub queue: Vec<u16>,
+    /// ticks the first unit has been in production
+    pub progress: u16,
+    /// where trained units go
+    pub rally: OrderTarget,
+}
+
+pub fn is_under_construction(e: EntityData<UnitComponents>,
+                             dh: &UnitComponents)
+                             -> bool {
+    dh.construction.has(&e) && !dh.construction[e].is_complete()
+}
+
+/// top left tile and size in tiles of the placement box of a building
+/// `width` x `height` pixels large, centered at (x, y)
+pub fn placement_tiles(width: i32, height: i32, x: i32, y: i32) -> (i32, i32, i32, i32) {
+    let (w, h) = ((width + 31) / 32, (height + 31) / 32);
+    ((x - w * 16) >> 5, (y - h * 16) >> 5, w, h)
+}
+
+/// moves the center of a building so that its placement box is on the tile grid
+pub fn snap_to_grid(units_dat: &UnitsDat, unit_id: usize, x: i32, y: i32) -> (i32, i32) {
+    let (tx, ty, w, h) = placement_tiles(units_dat.star_edit_placement_box_width[unit_id] as i32,
+                                         units_dat.star_edit_elacement_box_height[unit_id] as i32,
+                                         x + 16,
+                                         y + 16);
+    (tx * 32 + w * 16, ty * 32 + h * 16)
+}
+
+/// is (dx, dy) inside the psi field of a pylon
+pub fn in_psi_field(dx: i32, dy: i32) -> bool {
+    let (rx, ry) = (PSI_RANGE_X as i64, PSI_RANGE_Y as i64);
+    let (dx, dy) = (dx as i64, dy as i64);
+    dx * dx * ry * ry + dy * dy * rx * rx <= rx * rx * ry * ry
+}
+
+fn spreads_creep(unit_id: usize) -> bool {
+    (unit_id >= UNIT_HATCHERY && unit_id <= UNIT_HIVE) || unit_id == UNIT_CREEP_COLONY ||
+    unit_id == UNIT_SPORE_COLONY || unit_id == UNIT_SUNKEN_COLONY
+}
+
+fn is_refinery(units_dat: &UnitsDat, unit_id: usize) -> bool {
+    units_dat.flags(unit_id).contains(UF_RESOURCE_CONTAINER) &&
+    unit_id != UNIT_VESPENE_GEYSER as usize &&
+    !(unit_id >= UNIT_MINERAL_FIELD_1 as usize && unit_id <= UNIT_MINERAL_FIELD_3 as usize)
+}
+
+/// checks whether `player` may place `unit_id` centered at (x, y).
+/// `units` are all units except the builder, `complete` the finished buildings
+/// that can provide psi or creep.
+pub fn check_placement(terrain: &TerrainGrid,
+                       units_dat: &UnitsDat,
+                       units: &[UnitInfo],
+                       complete: &[UnitInfo],
+                       unit_id: usize,
+                       x: i32,
+                       y: i32,
+                       player: usize)
+                       -> Result<(), BuildError> {
+    if is_refinery(units_dat, unit_id) {
+        // on top of an uncovered geyser
+        let geyser = units.iter().any(|u| {
+            u.unit_id == UNIT_VESPENE_GEYSER as usize && u.x == x && u.y == y
+        });
+        let covered = units.iter()
+            .any(|u| u.x == x && u.y == y && is_refinery(units_dat, u.unit_id));
+        return if geyser && !covered {
+            Ok(())
+        } else {
+            Err(BuildError::NeedsGeyser)
+        };
+    }
+
+    let (tx, ty, w, h) = placement_tiles(units_dat.star_edit_placement_box_width[unit_id] as i32,
+                                         units_dat.star_edit_elacement_box_height[unit_id] as i32,
+                                         x,
+                                         y);
+    let flags = units_dat.flags(unit_id);
+    let creep_from_buildings = |px: i32, py: i32| {
+        complete.iter().any(|u| {
+            spreads_creep(u.unit_id) && approx_distance(u.x - px, u.y - py) <= CREEP_RANGE
+        })
+    };
+    for cy in ty..ty + h {
+        for cx in tx..tx + w {
+            if !terrain.is_buildable(cx, cy) {
+                return Err(BuildError::Unbuildable);
+            }
+            let creep = terrain.has_creep(cx, cy) ||
+                        creep_from_buildings(cx * 32 + 16, cy * 32 + 16);
+            if flags.contains(UF_REQUIRES_CREEP) && !creep {
+                return Err(BuildError::NeedsCreep);
+            }
+            // only zerg buildings may stand on creep
+            if creep && race(units_dat, unit_id) != Some(Race::Zerg) {
+                return Err(BuildError::OnCreep);
+            }
+        }
+    }
+
+    let site = Footprint {
+        left: tx * 32,
+        top: ty * 32,
+        right: (tx + w) * 32 - 1,
+        bottom: (ty + h) * 32 - 1,
+    };
+    for u in units {
+        if !collides(units_dat, u.unit_id) || u.lifted {
+            continue;
+        }
+        let (ox, oy) = site.overlap(&Footprint::of_unit(units_dat, u.unit_id, u.x, u.y));
+        if ox > 0 && oy > 0 {
+            return Err(BuildError::Occupied);
+        }
+    }
+
+    if flags.contains(UF_RESOURCE_DEPOT) {
+        let around = Footprint {
+            left: site.left - RESOURCE_DISTANCE,
+            top: site.top - RESOURCE_DISTANCE,
+            right: site.right + RESOURCE_DISTANCE,
+            bottom: site.bottom + RESOURCE_DISTANCE,
+        };
+        let near_resources = units.iter().any(|u| {
+            let (ox, oy) = around.overlap(&Footprint::of_unit(units_dat, u.unit_id, u.x, u.y));
+            units_dat.flags(u.unit_id).contains(UF_RESOURCE_CONTAINER) && ox > 0 && oy > 0
+        });
+        if near_resources {
+            return Err(BuildError::TooCloseToResources);
+        }
+    }
+
+    if flags.contains(UF_REQUIRES_PSI) {
+        let powered = complete.iter().any(|u| {
+            u.unit_id == UNIT_PYLON && u.player == player && in_psi_field(x - u.x, y - u.y)
+        });
+        if !powered {
+            return Err(BuildError::NeedsPsi);
+        }
+    }
+    Ok(())
+}
+
+/// all living units, and the finished buildings among them
+fn world_units(world: &World<UnitSystems>) -> (Vec<UnitInfo>, Vec<UnitInfo>) {
+    let dh = &world.data;
+    let dat = dh.services.dat();
+    let mut units = Vec::new();
+    let mut complete = Vec::new();
+    for e in world.entities() {
+        if !dh.scunit.has(&e) || dh.passenger.has(&e) || is_dying(e, dh) {
+            continue;
+        }
+        let info = UnitInfo {
+            entity: **e,
+            unit_id: dh.scunit[e].unit_id as usize,
+            player: dh.scimage[e].player_id,
+            x: dh.iscript_state[e].map_pos_x as i32,
+            y: dh.iscript_state[e].map_pos_y as i32,
+            hidden_from: hidden_from(e, dh),
+            lifted: is_lifted(e, dh),
+        };
+        if dat.units_dat.flags(info.unit_id).contains(UF_BUILDING) &&
+           !is_under_construction(e, dh) {
+            complete.push(info);
+        }
+        units.push(info);
+    }
+    (units, complete)
+}
+
+fn check_costs(world: &World<UnitSystems>,
+               unit_id: usize,
+               player: usize)
+               -> Result<(), BuildError> {
+    let dat = world.data.services.dat();
+    let state = &world.data.services.players[player];
+    if state.minerals < dat.units_dat.mineral_cost[unit_id] as u32 {
+        Err(BuildError::NotEnoughMinerals)
+    } else if state.gas < dat.units_dat.vespene_cost[unit_id] as u32 {
+        Err(BuildError::NotEnoughGas)
+    } else {
+        Ok(())
+    }
+}
+
+fn pay(world: &mut World<UnitSystems>, unit_id: usize, player: usize, refund: bool) {
+    let dat = world.data.services.dat();
+    let state = &mut world.data.services.players[player];
+    let (minerals, gas) = (dat.units_dat.mineral_cost[unit_id] as u32,
+                           dat.units_dat.vespene_cost[unit_id] as u32);
+    if refund {
+        state.minerals += minerals;
+        state.gas += gas;
+    } else {
+        state.minerals -= minerals;
+        state.gas -= gas;
+    }
+}
+
+/// placement check for `builder`, with (x, y) already snapped to the grid
+pub fn can_place(world: &World<UnitSystems>,
+                 builder: Entity,
+                 unit_id: usize,
+                 x: i32,
+                 y: i32)
+                 -> Result<(), BuildError> {
+    let map = match world.systems.scunit_stepping_sys.map {
+        Some(ref map) => map.clone(),
+        None => return Err(BuildError::Unbuildable),
+    };
+    let (units, complete) = world_units(world);
+    let me = match find_unit(&units, builder) {
+        Some(me) => me,
+        None => return Err(BuildError::NotAllowed),
+    };
+    let others: Vec<UnitInfo> = units.into_iter().filter(|u| u.entity != builder).collect();
+    check_placement(&map.scmap.terrain_grid,
+                    &world.data.services.dat().units_dat,
+                    &others,
+                    &complete,
+                    unit_id,
+                    x,
+                    y,
+                    me.player)
+}
+
+/// sends a worker to build `unit_id` at (x, y). The costs are paid when the
+/// construction starts.
+pub fn issue_build_order(world: &mut World<UnitSystems>,
+                         builder: Entity,
+                         unit_id: usize,
+                         x: i32,
+                         y: i32,
+                         queued: bool)
+                         -> Result<(), BuildError> {
+    let dat = world.data.services.dat();
+    let info = world.with_entity_data(&builder, |e, data| {
+        (data.scunit[e].unit_id as usize, data.scimage[e].player_id)
+    });
+    let (builder_id, player) = match info {
+        Some(info) => info,
+        None => return Err(BuildError::NotAllowed),
+    };
+    let builder_race = race(&dat.units_dat, builder_id);
+    if !dat.units_dat.flags(builder_id).contains(UF_WORKER) ||
+       !dat.units_dat.flags(unit_id).contains(UF_BUILDING) ||
+       builder_race != race(&dat.units_dat, unit_id) {
+        return Err(BuildError::NotAllowed);
+    }
+    let order_type = match builder_race {
+        Some(Race::Terran) => OrderType::PlaceBuilding,
+        Some(Race::Protoss) => OrderType::PlaceProtossBuilding,
+        Some(Race::Zerg) => OrderType::DroneStartBuild,
+        None => return Err(BuildError::NotAllowed),
+    };
+    let (x, y) = snap_to_grid(&dat.units_dat, unit_id, x, y);
+    try!(check_costs(world, unit_id, player));
+    try!(can_place(world, builder, unit_id, x, y));
+    let order = Order::with_unit_type(order_type, unit_id as u16, OrderTarget::Position(x, y));
+    if issue_order(world, builder, order, queued) {
+        Ok(())
+    } else {
+        Err(BuildError::NotAllowed)
+    }
+}
+
+/// worker walking to a building site
+pub fn order_place_building(e: &EntityData<UnitComponents>,
+                            dh: &mut DataHelper<UnitComponents, UnitServices>,
+                            map: &PlanningMap,
+                            me: &UnitInfo,
+                            order: Order,
+                            actions: &mut Vec<IScriptEntityAction>) {
+    let (x, y, unit_id) = match (order.target, order.unit_type) {
+        (OrderTarget::Position(x, y), Some(unit_id)) => (x, y, unit_id),
+        _ => {
+            dh.scunit[*e].orders.finish();
+            return;
+        }
+    };
+    if dh.scunit[*e].orders.state != 0 {
+        // construction didn't start
+        dh.scunit[*e].orders.finish();
+        return;
+    }
+    if approx_distance(x - me.x, y - me.y) > BUILD_RANGE {
+        chase(e, dh, map, x, y);
+        return;
+    }
+    SCUnitStep::halt(e, dh);
+    actions.push(IScriptEntityAction::StartConstruction {
+        builder: me.entity,
+        unit_id: unit_id,
+        x: x as u16,
+        y: y as u16,
+    });
+    dh.scunit[*e].orders.state = 1;
+}
+
+/// terran SCV working on a building
+pub fn order_construct(e: &EntityData<UnitComponents>,
+                       dh: &mut DataHelper<UnitComponents, UnitServices>,
+                       units: &[UnitInfo],
+                       me: &UnitInfo,
+                       order: Order) {
+    let building = match order.target {
+        OrderTarget::Unit(target) => find_unit(units, target),
+        _ => None,
+    };
+    let building = match building {
+        Some(building) => building,
+        None => {
+            dh.iscript_state[*e].next_animation = Some(AnimationType::WorkingToIdle);
+            dh.scunit[*e].orders.finish();
+            return;
+        }
+    };
+    let done = dh.with_entity_data(&building.entity, |b, data| {
+            if !data.construction.has(&b) || data.construction[b].is_complete() {
+                return true;
+            }
+            // take over a building whose builder is gone
+            let builder = data.construction[b].builder;
+            if builder.map_or(true, |builder| find_unit(units, builder).is_none()) {
+                data.construction[b].builder = Some(me.entity);
+            }
+            false
+        })
+        .unwrap_or(true);
+    if done {
+        dh.iscript_state[*e].next_animation = Some(AnimationType::WorkingToIdle);
+        dh.scunit[*e].orders.finish();
+    } else {
+        dh.scunit[*e].orders.state = 1;
+    }
+}
+
+/// creates the building a worker arrived to build, fails if the site or the
+/// player's resources changed in the meantime
+pub fn start_construction(world: &mut World<UnitSystems>,
+                          gd: &GameData,
+                          builder: Entity,
+                          unit_id: usize,
+                          x: i32,
+                          y: i32)
+                          -> Result<Entity, BuildError> {
+    let info = world.with_entity_data(&builder, |e, data| {
+        (data.scimage[e].player_id, data.scunit[e].unit_id as usize)
+    });
+    let (player, builder_id) = match info {
+        Some(info) => info,
+        None => return Err(BuildError::NotAllowed),
+    };
+    let checked = check_costs(world, unit_id, player)
+        .and_then(|_| can_place(world, builder, unit_id, x, y));
+    if let Err(err) = checked {
+        world.with_entity_data(&builder, |e, data| data.scunit[e].orders.finish());
+        return Err(err);
+    }
+    pay(world, unit_id, player, false);
+
+    let building = if is_refinery(&gd.units_dat, unit_id) {
+        let (units, _) = world_units(world);
+        let geyser = units.iter()
+            .find(|u| u.unit_id == UNIT_VESPENE_GEYSER as usize && u.x == x && u.y == y)
+            .map(|u| u.entity);
+        match geyser.and_then(|g| place_refinery(world, gd, g, unit_id, player)) {
+            Some(refinery) => refinery,
+            None => {
+                pay(world, unit_id, player, true);
+                return Err(BuildError::NeedsGeyser);
+            }
+        }
+    } else {
+        create_scunit(world, gd, unit_id, x as u16, y as u16, player)
+    };
+
+    let build_time = gd.units_dat.build_time[unit_id].max(1);
+    let builder_race = race(&gd.units_dat, builder_id);
+    // protoss buildings warp in, the others show the construction image first
+    let construction_image = gd.units_dat.construction_animation[unit_id] as usize;
+    let overlay = if builder_race != Some(Race::Protoss) && construction_image != 0 {
+        let ent = create_scimage(world, gd, construction_image, x as u16, y as u16,
+                                 Some(building), player);
+        world.modify_entity(ent,
+                            |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+            data.overlay.insert(&e, OverlayComponent {});
+        });
+        Some(ent)
+    } else {
+        None
+    };
+    world.modify_entity(building,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        let health = &mut data.health[e];
+        health.hit_points = (health.max_hit_points * INITIAL_HP_PERCENT / 100).max(1);
+        health.shields = health.max_shields * INITIAL_HP_PERCENT / 100;
+        let hp_step = (health.max_hit_points - health.hit_points) / build_time as Fixed;
+        let shield_step = (health.max_shields - health.shields) / build_time as Fixed;
+        if let Some(overlay) = overlay {
+            data.iscript_state[e].children.push(overlay);
+            data.iscript_state[e].visible = false;
+        } else {
+            data.iscript_state[e].next_animation = Some(AnimationType::Warpin);
+        }
+        data.construction.insert(&e,
+                                 ConstructionComponent {
+                                     progress: 0,
+                                     build_time: build_time,
+                                     builder: None,
+                                     overlay: overlay,
+                                     hp_step: hp_step,
+                                     shield_step: shield_step,
+                                 });
+    });
+
+    match builder_race {
+        Some(Race::Terran) => {
+            world.with_entity_data(&builder, |e, data| {
+                data.scunit[e]
+                    .orders
+                    .replace(Order::new(OrderType::ConstructingBuilding,
+                                        OrderTarget::Unit(building)));
+            });
+            world.with_entity_data(&building,
+                                   |e, data| data.construction[e].builder = Some(builder));
+        }
+        Some(Race::Zerg) => remove_entity_rec(world, builder),
+        _ => {
+            world.with_entity_data(&builder, |e, data| data.scunit[e].orders.finish());
+        }
+    }
+    Ok(building)
+}
+
+/// advances the construction of a building by one tick
+pub fn step_construction(e: &EntityData<UnitComponents>,
+                         dh: &mut DataHelper<UnitComponents, UnitServices>,
+                         actions: &mut Vec<IScriptEntityAction>) {
+    if !dh.construction.has(e) || dh.construction[*e].is_complete() {
+        return;
+    }
+    // terran buildings only grow while an SCV is working on them
+    if let Some(builder) = dh.construction[*e].builder {
+        let working = dh.with_entity_data(&builder, |b, data| {
+                data.scunit.has(&b) &&
+                data.scunit[b].orders.current.order_type == OrderType::ConstructingBuilding
+            })
+            .unwrap_or(false);
+        if !working {
+            return;
+        }
+    }
+    let (hp_step, shield_step) = (dh.construction[*e].hp_step, dh.construction[*e].shield_step);
+    {
+        let health = &mut dh.health[*e];
+        health.hit_points = (health.hit_points + hp_step).min(health.max_hit_points);
+        health.shields = (health.shields + shield_step).min(health.max_shields);
+    }
+    dh.construction[*e].progress += 1;
+    let (progress, build_time) = (dh.construction[*e].progress, dh.construction[*e].build_time);
+    if progress == build_time - build_time / 10 {
+        dh.iscript_state[*e].next_animation = Some(AnimationType::AlmostBuilt);
+    }
+    if progress >= build_time {
+        if let Some(overlay) = dh.construction[*e].overlay.take() {
+            dh.iscript_state[*e].children.retain(|c| *c != overlay);
+            actions.push(IScriptEntityAction::RemoveEntity { entity: overlay });
+        }
+        dh.iscript_state[*e].visible = true;
+        dh.iscript_state[*e].next_animation = Some(AnimationType::Built);
+        let dat = dh.services.dat();
+        let (player, unit_id) = (dh.scimage[*e].player_id, dh.scunit[*e].unit_id as usize);
+        dh.services.players[player].record_built(&dat.units_dat, unit_id);
+    }
+}
+
+/// adds a train queue to production buildings
+pub fn init_unit_production(world: &mut World<UnitSystems>, gd: &GameData, entity: Entity) {
+    let unit_id = match world.with_entity_data(&entity, |e, data| data.scunit[e].unit_id) {
+        Some(unit_id) => unit_id as usize,
+        None => return,
+    };
+    if !gd.units_dat.flags(unit_id).contains(UF_PRODUCES_UNITS) {
+        return;
+    }
+    world.modify_entity(entity,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        data.train_queue.insert(&e,
+                                TrainQueueComponent {
+                                    queue: Vec::with_capacity(TRAIN_QUEUE_SIZE),
+                                    progress: 0,
+                                    rally: OrderTarget::None,
+                                });
+    });
+}
+
+/// the building that trains `unit_id`, None for units that aren't trained
+pub fn trained_at(unit_id: usize) -> Option<usize> {
+    TRAINED_AT.iter().find(|t| t.0 == unit_id).map(|t| t.1)
+}
+
+/// can a `building_id` train `unit_id`; `owns` tells if the player has a
+/// finished building, `has_addon` if the add-on is attached to this building
+pub fn check_train_requirements<F, G>(unit_id: usize,
+                                      building_id: usize,
+                                      owns: F,
+                                      has_addon: G)
+                                      -> Result<(), BuildError>
+    where F: Fn(usize) -> bool,
+          G: Fn(usize) -> bool
+{
+    let &(_, producer, buildings, addon) = match TRAINED_AT.iter().find(|t| t.0 == unit_id) {
+        Some(t) => t,
+        None => return Err(BuildError::NotAllowed),
+    };
+    if producer != building_id {
+        return Err(BuildError::NotAllowed);
+    }
+    if !buildings.iter().all(|&b| owns(b)) || !addon.map_or(true, |a| has_addon(a)) {
+        return Err(BuildError::MissingRequirement);
+    }
+    Ok(())
+}
+
+/// is a finished `addon` of the same player attached to the right of `building`
+fn has_addon(units_dat: &UnitsDat,
+             complete: &[UnitInfo],
+             building: &UnitInfo,
+             addon: usize)
+             -> bool {
+    let tiles = |u: &UnitInfo| {
+        placement_tiles(units_dat.star_edit_placement_box_width[u.unit_id] as i32,
+                        units_dat.star_edit_elacement_box_height[u.unit_id] as i32,
+                        u.x,
+                        u.y)
+    };
+    let (bx, by, bw, bh) = tiles(building);
+    complete.iter().any(|u| {
+        u.unit_id == addon && u.player == building.player && {
+            let (ax, ay, _, ah) = tiles(u);
+            ax == bx + bw && ay + ah == by + bh
+        }
+    })
+}
+
+/// queues a unit in a production building and pays for it
+pub fn train(world: &mut World<UnitSystems>,
+             building: Entity,
+             unit_id: usize)
+             -> Result<(), BuildError> {
+    let dat = world.data.services.dat();
+    let info = world.with_entity_data(&building, |e, data| {
+        if !data.train_queue.has(&e) || is_under_construction(e, data) || is_lifted(e, data) ||
+           is_morphing(e, data) {
+            None
+        } else {
+            Some((data.scunit[e].unit_id as usize,
+                  data.scimage[e].player_id,
+                  data.train_queue[e].queue.len()))
+        }
+    });
+    let (building_id, player, queued) = match info {
+        Some(Some(info)) => info,
+        _ => return Err(BuildError::NotAllowed),
+    };
+    {
+        let (_, complete) = world_units(world);
+        let me = match find_unit(&complete, building) {
+            Some(me) => me,
+            None => return Err(BuildError::NotAllowed),
+        };
+        let owns = |b| complete.iter().any(|u| u.player == player && u.unit_id == b);
+        try!(check_train_requirements(unit_id,
+                                      building_id,
+                                      owns,
+                                      |a| has_addon(&dat.units_dat, &complete, &me, a)));
+    }
+    if queued >= TRAIN_QUEUE_SIZE {
+        return Err(BuildError::QueueFull);
+    }
+    try!(check_costs(world, unit_id, player));
+    pay(world, unit_id, player, false);
+    world.with_entity_data(&building,
+                           |e, data| data.train_queue[e].queue.push(unit_id as u16));
+    Ok(())
+}
+
+/// removes a unit from the queue and refunds it
+pub fn cancel_training(world: &mut World<UnitSystems>, building: Entity, slot: usize) -> bool {
+    let cancelled = world.with_entity_data(&building, |e, data| {
+        if !data.train_queue.has(&e) || slot >= data.train_queue[e].queue.len() {
+            return None;
+        }
+        if slot == 0 {
+            data.train_queue[e].progress = 0;
+        }
+        Some((data.train_queue[e].queue.remove(slot) as usize, data.scimage[e].player_id))
+    });
+    match cancelled {
+        Some(Some((unit_id, player))) => {
+            pay(world, unit_id, player, true);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// sets where newly trained units go, false if the unit doesn't train any
+pub fn set_rally(world: &mut World<UnitSystems>, building: Entity, target: OrderTarget) -> bool {
+    world.with_entity_data(&building, |e, data| if data.train_queue.has(&e) {
+            data.train_queue[e].rally = target;
+            true
+        } else {
+            false
+        })
+        .unwrap_or(false)
+}
+
+/// supply of the unit currently in production, in half units
+pub fn training_supply(e: EntityData<UnitComponents>,
+                       dh: &DataHelper<UnitComponents, UnitServices>,
+                       units_dat: &UnitsDat)
+                       -> Option<(Race, u32)> {
+    if !dh.train_queue.has(&e) || dh.train_queue[e].progress == 0 {
+        return None;
+    }
+    let unit_id = dh.train_queue[e].queue[0] as usize;
+    race(units_dat, unit_id).map(|r| (r, units_dat.supply_required[unit_id] as u32))
+}
+
+/// advances the train queue of a building by one tick
+pub fn step_production(e: &EntityData<UnitComponents>,
+                       dh: &mut DataHelper<UnitComponents, UnitServices>,
+                       dat: &DatFiles,
+                       actions: &mut Vec<IScriptEntityAction>) {
+    if !dh.train_queue.has(e) || dh.train_queue[*e].queue.is_empty() ||
+       is_under_construction(*e, dh) {
+        return;
+    }
+    let unit_id = dh.train_queue[*e].queue[0] as usize;
+    if dh.train_queue[*e].progress == 0 {
+        // production only starts with enough free supply
+        let player = dh.scimage[*e].player_id;
+        if let Some(r) = race(&dat.units_dat, unit_id) {
+            let supply = &dh.services.players[player].supply;
+            let required = dat.units_dat.supply_required[unit_id] as u32;
+            if required > 0 && supply.used[r as usize] + required > supply.provided[r as usize] {
+                return;
+            }
+        }
+    }
+    dh.train_queue[*e].progress += 1;
+    if dh.train_queue[*e].progress >= dat.units_dat.build_time[unit_id] {
+        dh.train_queue[*e].queue.remove(0);
+        dh.train_queue[*e].progress = 0;
+        actions.push(IScriptEntityAction::SpawnUnit {
+            building: ***e,
+            unit_id: unit_id as u16,
+        });
+    }
+}
+
+/// creates a trained unit next to the building and sends it to the rally point
+pub fn spawn_trained_unit(world: &mut World<UnitSystems>,
+                          gd: &GameData,
+                          building: Entity,
+                          unit_id: usize)
+                          -> Option<Entity> {
+    let info = world.with_entity_data(&building, |e, data| {
+        (data.scunit[e].unit_id as usize,
+         data.scimage[e].player_id,
+         data.iscript_state[e].map_pos_x as i32,
+         data.iscript_state[e].map_pos_y as i32,
+         data.train_queue[e].rally)
+    });
+    let (building_id, player, x, y, rally) = match info {
+        Some(info) => info,
+        None => return None,
+    };
+    // below the building
+    let y = y + gd.units_dat.unit_size_down[building_id] as i32 +
+            gd.units_dat.unit_size_up[unit_id] as i32 + 1;
+    let (x, y) = match world.systems.scunit_stepping_sys.map {
+        Some(ref map) => {
+            let size = (gd.units_dat.unit_size_left[unit_id] +
+                        gd.units_dat.unit_size_right[unit_id]) as i32;
+            formation_slots(x, y, 1, size + 2, map)[0]
+        }
+        None => (x, y),
+    };
+    let unit = create_scunit(world, gd, unit_id, x as u16, y as u16, player);
+    world.data.services.players[player].record_built(&gd.units_dat, unit_id);
+    let order = match rally {
+        OrderTarget::None => None,
+        OrderTarget::Position(..) => Some(Order::new(OrderType::Move, rally)),
+        OrderTarget::Unit(target) => {
+            if can_harvest(world, unit, target) {
+                Some(Order::new(OrderType::Harvest1, rally))
+            } else {
+                Some(Order::new(OrderType::Follow, rally))
+            }
+        }
+    };
+    if let Some(order) = order {
+        issue_order(world, unit, order, false);
+    }
+    Some(unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{placement_tiles, in_psi_field, check_train_requirements, BuildError,
+                PSI_RANGE_X, PSI_RANGE_Y};
+
+    #[test]
+    fn placement_box() {
+        // 4x3 tiles, e.g. a command center
+        assert_eq!(placement_tiles(128, 96, 10 * 32, 5 * 32 + 16), (8, 4, 4, 3));
+        // odd sizes round up
+        assert_eq!(placement_tiles(48, 32, 3 * 32, 3 * 32 + 16), (2, 3, 2, 1));
+    }
+
+    #[test]
+    fn psi_field() {
+        assert!(in_psi_field(0, 0));
+        assert!(in_psi_field(PSI_RANGE_X, 0));
+        assert!(in_psi_field(0, -PSI_RANGE_Y));
+        assert!(!in_psi_field(0, PSI_RANGE_Y + 1));
+        assert!(!in_psi_field(PSI_RANGE_X, PSI_RANGE_Y));
+    }
+
+    #[test]
+    fn train_requirements() {
+        let nothing = |_| false;
+        // scv at a command center, not at a barracks
+        assert_eq!(check_train_requirements(7, 106, nothing, nothing), Ok(()));
+        assert_eq!(check_train_requirements(7, 111, nothing, nothing),
+                   Err(BuildError::NotAllowed));
+        // firebats need an academy
+        assert_eq!(check_train_requirements(32, 111, nothing, nothing),
+                   Err(BuildError::MissingRequirement));
+        assert_eq!(check_train_requirements(32, 111, |b| b == 112, nothing), Ok(()));
+        // siege tanks need a machine shop at this factory
+        assert_eq!(check_train_requirements(5, 113, |b| b == 120, nothing),
+                   Err(BuildError::MissingRequirement));
+        assert_eq!(check_train_requirements(5, 113, nothing, |a| a == 120), Ok(()));
+        // zerg units aren't trained
+        assert_eq!(check_train_requirements(37, 131, nothing, nothing),
+                   Err(BuildError::NotAllowed));
+    }
+}
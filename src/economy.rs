@@ -0,0 +1,630 @@
+//! Resources: mineral fields, vespene geysers covered by refineries, workers
+//! harvesting them and the minerals, gas and supply of every player.
+
+use ecs::{World, Entity, EntityData, DataHelper, ModifyData};
+
+use scformats::iscript::AnimationType;
+use scformats::terrain::MapUnit;
+use scformats::unitsdata::{OrderType, UnitsDat, UF_WORKER, UF_RESOURCE_DEPOT,
+                           UF_RESOURCE_CONTAINER, UNIT_MINERAL_FIELD_1, UNIT_MINERAL_FIELD_3,
+                           UNIT_VESPENE_GEYSER};
+
+use gamedata::GameData;
+use combat::{kill_unit, record_death};
+use orders::{Order, OrderTarget, UnitInfo, unit_distance, find_unit, face, chase};
+use unit_ecs::{UnitComponents, UnitServices, UnitSystems, DatFiles, PlanningMap, SCUnitStep,
+               OverlayComponent, create_scimage, create_scunit};
+
+pub const MINERALS_PER_TRIP: u32 = 8;
+pub const GAS_PER_TRIP: u32 = 8;
+/// from geysers that ran dry
+const DEPLETED_GAS_PER_TRIP: u32 = 2;
+/// minerals every player starts with
+pub const STARTING_MINERALS: u32 = 50;
+/// ticks a worker needs to mine minerals, and spends in a refinery
+const MINING_TICKS: u8 = 75;
+const GAS_TICKS: u8 = 37;
+/// maximum distance (in pixels) between the edges of a worker and a resource
+/// or depot to harvest or return cargo
+const HARVEST_RANGE: i32 = 10;
+/// workers look for other mineral fields within this range (pixels)
+const MINERAL_SEARCH_RANGE: i32 = 12 * 32;
+/// supply is counted in half units in units.dat, max 200
+pub const MAX_SUPPLY: u32 = 400;
+
+// power ups, their images are used for the carried resources
+const UNIT_MINERAL_CHUNK: usize = 216;
+const UNIT_PROTOSS_GAS_ORB: usize = 218;
+const UNIT_ZERG_GAS_SAC: usize = 220;
+const UNIT_TERRAN_GAS_TANK: usize = 222;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceType {
+    Minerals,
+    Gas,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Race {
+    Zerg,
+    Terran,
+    Protoss,
+}
+
+/// from the units.dat StarEdit group flags
+pub fn race(units_dat: &UnitsDat, unit_id: usize) -> Option<Race> {
+    let flags = units_dat.star_edit_group_flags[unit_id];
+    if flags & 0x1 != 0 {
+        Some(Race::Zerg)
+    } else if flags & 0x2 != 0 {
+        Some(Race::Terran)
+    } else if flags & 0x4 != 0 {
+        Some(Race::Protoss)
+    } else {
+        None
+    }
+}
+
+/// mineral fields, geysers and refineries
+pub struct ResourceComponent {
+    pub resource_type: ResourceType,
+    pub amount: u32,
+    /// worker currently mining it or inside the refinery
+    pub harvester: Option<Entity>,
+    /// geysers: the refinery built on top
+    pub covered_by: Option<Entity>,
+}
+
+pub struct WorkerComponent {
+    pub carrying: Option<(ResourceType, u32)>,
+    /// resource to go back to after returning the cargo
+    pub harvest_target: Option<Entity>,
+    /// ticks left mining / in the refinery
+    pub timer: u8,
+    /// hidden overlays showing the carried resources
+    pub mineral_overlay: Option<Entity>,
+    pub gas_overlay: Option<Entity>,
+}
+
+/// creates the hidden overlay showing a carried resource, positioned by the
+/// worker image's special overlay (LO*) data
+fn create_cargo_overlay(world: &mut World<UnitSystems>,
+                        gd: &GameData,
+                        worker: Entity,
+                        powerup_id: usize)
+                        -> Entity {
+    let flingy_id = gd.units_dat.flingy_id[powerup_id] as usize;
+    let sprite_id = gd.flingy_dat.sprite_id[flingy_id] as usize;
+    let image_id = gd.sprites_dat.image_id[sprite_id] as usize;
+    let (x, y, player_id, worker_image) = world.with_entity_data(&worker, |e, data| {
+            (data.iscript_state[e].map_pos_x,
+             data.iscript_state[e].map_pos_y,
+             data.scimage[e].player_id,
+             data.scimage[e].image_id as usize)
+        })
+        .expect("worker doesn't exist");
+    let lo_id = gd.images_dat.special_overlay[worker_image];
+    let (rel_x, rel_y) = if lo_id != 0 {
+        let c = gd.lox_cache.borrow();
+        c.get_ro(lo_id).frames[0].offsets.get(0).cloned().unwrap_or((0, 0))
+    } else {
+        (0, 0)
+    };
+
+    let ent = create_scimage(world, gd, image_id, x, y, Some(worker), player_id);
+    world.modify_entity(worker,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        data.iscript_state[e].children.push(ent);
+    });
+    world.modify_entity(ent,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        data.iscript_state[e].rel_x = rel_x;
+        data.iscript_state[e].rel_y = rel_y;
+        data.iscript_state[e].visible = false;
+        data.overlay.insert(&e, OverlayComponent {});
+    });
+    ent
+}
+
+/// adds resource and worker components where units.dat asks for them
+pub fn init_unit_economy(world: &mut World<UnitSystems>, gd: &GameData, entity: Entity) {
+    let unit_id = match world.with_entity_data(&entity, |e, data| data.scunit[e].unit_id) {
+        Some(unit_id) => unit_id as usize,
+        None => return,
+    };
+    let flags = gd.units_dat.flags(unit_id);
+    if flags.contains(UF_RESOURCE_CONTAINER) {
+        // geysers and the refineries on them hold gas
+        let resource_type = if unit_id >= UNIT_MINERAL_FIELD_1 as usize &&
+                               unit_id <= UNIT_MINERAL_FIELD_3 as usize {
+            ResourceType::Minerals
+        } else {
+            ResourceType::Gas
+        };
+        world.modify_entity(entity,
+                            |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+            data.resource.insert(&e,
+                                 ResourceComponent {
+                                     resource_type: resource_type,
+                                     amount: 0,
+                                     harvester: None,
+                                     covered_by: None,
+                                 });
+        });
+    }
+    if flags.contains(UF_WORKER) {
+        let gas = match race(&gd.units_dat, unit_id) {
+            Some(Race::Zerg) => UNIT_ZERG_GAS_SAC,
+            Some(Race::Protoss) => UNIT_PROTOSS_GAS_ORB,
+            _ => UNIT_TERRAN_GAS_TANK,
+        };
+        let mineral_overlay = create_cargo_overlay(world, gd, entity, UNIT_MINERAL_CHUNK);
+        let gas_overlay = create_cargo_overlay(world, gd, entity, gas);
+        world.modify_entity(entity,
+                            |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+            data.worker.insert(&e,
+                               WorkerComponent {
+                                   carrying: None,
+                                   harvest_target: None,
+                                   timer: 0,
+                                   mineral_overlay: Some(mineral_overlay),
+                                   gas_overlay: Some(gas_overlay),
+                               });
+        });
+    }
+}
+
+/// resources placed in a map
+pub fn set_map_resources(world: &mut World<UnitSystems>, entity: Entity, mapunit: &MapUnit) {
+    world.with_entity_data(&entity, |e, data| if data.resource.has(&e) {
+        data.resource[e].amount = mapunit.resource_amount;
+    });
+}
+
+/// builds a refinery (or assimilator, extractor) of `player` on a geyser,
+/// the refinery takes over the remaining gas
+pub fn place_refinery(world: &mut World<UnitSystems>,
+                      gd: &GameData,
+                      geyser: Entity,
+                      refinery_id: usize,
+                      player: usize)
+                      -> Option<Entity> {
+    let geyser_info = world.with_entity_data(&geyser, |e, data| {
+        if !data.resource.has(&e) || data.resource[e].covered_by.is_some() ||
+           data.scunit[e].unit_id != UNIT_VESPENE_GEYSER {
+            None
+        } else {
+            Some((data.iscript_state[e].map_pos_x,
+                  data.iscript_state[e].map_pos_y,
+                  data.resource[e].amount))
+        }
+    });
+    let (x, y, amount) = match geyser_info {
+        Some(Some(info)) => info,
+        _ => return None,
+    };
+    let refinery = create_scunit(world, gd, refinery_id, x, y, player);
+    world.with_entity_data(&refinery, |e, data| if data.resource.has(&e) {
+        data.resource[e].amount = amount;
+    });
+    world.with_entity_data(&geyser, |e, data| {
+        data.resource[e].covered_by = Some(refinery);
+        data.iscript_state[e].visible = false;
+    });
+    Some(refinery)
+}
+
+/// the selected unit would harvest `target` on a right click
+pub fn can_harvest(world: &mut World<UnitSystems>, worker: Entity, target: Entity) -> bool {
+    let worker_player = world.with_entity_data(&worker, |e, data| {
+        if data.worker.has(&e) {
+            Some(data.scimage[e].player_id)
+        } else {
+            None
+        }
+    });
+    let worker_player = match worker_player {
+        Some(Some(player)) => player,
+        _ => return false,
+    };
+    world.with_entity_data(&target, |e, data| {
+            if !data.resource.has(&e) {
+                return false;
+            }
+            match data.resource[e].resource_type {
+                ResourceType::Minerals => true,
+                // gas only from own refineries
+                ResourceType::Gas => {
+                    data.scunit[e].unit_id != UNIT_VESPENE_GEYSER &&
+                    data.scimage[e].player_id == worker_player
+                }
+            }
+        })
+        .unwrap_or(false)
+}
+
+fn set_overlay_visible(dh: &mut DataHelper<UnitComponents, UnitServices>,
+                       overlay: Option<Entity>,
+                       visible: bool) {
+    if let Some(overlay) = overlay {
+        dh.with_entity_data(&overlay, |e, data| {
+            data.iscript_state[e].visible = visible;
+        });
+    }
+}
+
+fn set_cargo(e: &EntityData<UnitComponents>,
+             dh: &mut DataHelper<UnitComponents, UnitServices>,
+             cargo: Option<(ResourceType, u32)>) {
+    dh.worker[*e].carrying = cargo;
+    let (minerals, gas) = (dh.worker[*e].mineral_overlay, dh.worker[*e].gas_overlay);
+    let resource_type = cargo.map(|c| c.0);
+    set_overlay_visible(dh, minerals, resource_type == Some(ResourceType::Minerals));
+    set_overlay_visible(dh, gas, resource_type == Some(ResourceType::Gas));
+}
+
+/// type and remaining amount of a resource, None if it's gone
+fn resource_state(dh: &mut DataHelper<UnitComponents, UnitServices>,
+                  target: Entity)
+                  -> Option<(ResourceType, u32)> {
+    dh.with_entity_data(&target, |e, data| if data.resource.has(&e) {
+            Some((data.resource[e].resource_type, data.resource[e].amount))
+        } else {
+            None
+        })
+        .and_then(|r| r)
+}
+
+/// claims the resource for the worker, false if another one is using it
+fn try_claim(dh: &mut DataHelper<UnitComponents, UnitServices>,
+             units: &[UnitInfo],
+             worker: Entity,
+             target: Entity)
+             -> bool {
+    dh.with_entity_data(&target, |e, data| {
+            let busy = match data.resource[e].harvester {
+                Some(h) => h != worker && find_unit(units, h).is_some(),
+                None => false,
+            };
+            if !busy {
+                data.resource[e].harvester = Some(worker);
+            }
+            !busy
+        })
+        .unwrap_or(false)
+}
+
+fn release(dh: &mut DataHelper<UnitComponents, UnitServices>, worker: Entity, target: Entity) {
+    dh.with_entity_data(&target, |e, data| if data.resource[e].harvester == Some(worker) {
+        data.resource[e].harvester = None;
+    });
+}
+
+/// takes up to `amount` from the resource, returns how much the worker carries
+fn take_resource(dh: &mut DataHelper<UnitComponents, UnitServices>,
+                 target: Entity,
+                 amount: u32)
+                 -> u32 {
+    dh.with_entity_data(&target, |e, data| {
+            let res = &mut data.resource[e];
+            if res.amount >= amount {
+                res.amount -= amount;
+                amount
+            } else if res.resource_type == ResourceType::Gas {
+                res.amount = 0;
+                DEPLETED_GAS_PER_TRIP
+            } else {
+                let rest = res.amount;
+                res.amount = 0;
+                rest
+            }
+        })
+        .unwrap_or(0)
+}
+
+/// nearest mineral field nobody is mining
+fn find_free_minerals(dh: &mut DataHelper<UnitComponents, UnitServices>,
+                      dat: &DatFiles,
+                      me: &UnitInfo,
+                      units: &[UnitInfo])
+                      -> Option<Entity> {
+    let mut best: Option<(i32, Entity)> = None;
+    for u in units {
+        let free = dh.with_entity_data(&u.entity, |e, data| {
+                data.resource.has(&e) &&
+                data.resource[e].resource_type == ResourceType::Minerals &&
+                data.resource[e].amount > 0 &&
+                data.resource[e].harvester.map_or(true, |h| find_unit(units, h).is_none())
+            })
+            .unwrap_or(false);
+        if !free {
+            continue;
+        }
+        let dist = unit_distance(&dat.units_dat, me, u);
+        if dist <= MINERAL_SEARCH_RANGE && best.map_or(true, |b| dist < b.0) {
+            best = Some((dist, u.entity));
+        }
+    }
+    best.map(|b| b.1)
+}
+
+/// nearest own resource depot
+fn find_depot(dat: &DatFiles, me: &UnitInfo, units: &[UnitInfo]) -> Option<UnitInfo> {
+    units.iter()
+        .filter(|u| {
+            u.player == me.player && dat.units_dat.flags(u.unit_id).contains(UF_RESOURCE_DEPOT)
+        })
+        .min_by_key(|u| unit_distance(&dat.units_dat, me, u))
+        .cloned()
+}
+
+fn next_order(e: &EntityData<UnitComponents>,
+              dh: &mut DataHelper<UnitComponents, UnitServices>,
+              order_type: OrderType,
+              target: Entity) {
+    dh.scunit[*e].orders.replace(Order::new(order_type, OrderTarget::Unit(target)));
+}
+
+/// harvest orders of workers, `order` targets the resource (or the depot when
+/// returning cargo)
+pub fn order_harvest(e: &EntityData<UnitComponents>,
+                     dh: &mut DataHelper<UnitComponents, UnitServices>,
+                     map: &PlanningMap,
+                     dat: &DatFiles,
+                     units: &[UnitInfo],
+                     me: &UnitInfo,
+                     order: Order) {
+    if !dh.worker.has(e) {
+        dh.scunit[*e].orders.finish();
+        return;
+    }
+    let target = match order.target {
+        OrderTarget::Unit(target) => Some(target),
+        _ => None,
+    };
+    match order.order_type {
+        OrderType::Harvest1 | OrderType::Harvest2 | OrderType::Harvest3 |
+        OrderType::Harvest4 => {
+            let target = match target {
+                Some(target) => target,
+                None => {
+                    dh.scunit[*e].orders.finish();
+                    return;
+                }
+            };
+            dh.worker[*e].harvest_target = Some(target);
+            match (dh.worker[*e].carrying, resource_state(dh, target)) {
+                (Some((ResourceType::Minerals, _)), _) => {
+                    next_order(e, dh, OrderType::ReturnMinerals, target)
+                }
+                (Some((ResourceType::Gas, _)), _) => {
+                    next_order(e, dh, OrderType::ReturnGas, target)
+                }
+                (None, Some((ResourceType::Minerals, _))) => {
+                    next_order(e, dh, OrderType::MoveToMinerals, target)
+                }
+                (None, Some((ResourceType::Gas, _))) => {
+                    // geysers need a refinery first
+                    let geyser = find_unit(units, target)
+                        .map_or(true, |t| t.unit_id == UNIT_VESPENE_GEYSER as usize);
+                    if geyser {
+                        dh.scunit[*e].orders.finish();
+                    } else {
+                        next_order(e, dh, OrderType::MoveToGas, target);
+                    }
+                }
+                (None, None) => dh.scunit[*e].orders.finish(),
+            }
+        }
+        OrderType::MoveToMinerals | OrderType::MoveToGas | OrderType::WaitForMinerals |
+        OrderType::WaitForGas => {
+            let gas = order.order_type == OrderType::MoveToGas ||
+                      order.order_type == OrderType::WaitForGas;
+            let resource = target.and_then(|t| find_unit(units, t));
+            let resource = match resource {
+                Some(r) => r,
+                None => {
+                    // mined out, try the next field
+                    let next = if gas {
+                        None
+                    } else {
+                        find_free_minerals(dh, dat, me, units)
+                    };
+                    match next {
+                        Some(next) => next_order(e, dh, OrderType::Harvest1, next),
+                        None => dh.scunit[*e].orders.finish(),
+                    }
+                    return;
+                }
+            };
+            if unit_distance(&dat.units_dat, me, &resource) > HARVEST_RANGE {
+                chase(e, dh, map, resource.x, resource.y);
+                return;
+            }
+            SCUnitStep::halt(e, dh);
+            if try_claim(dh, units, me.entity, resource.entity) {
+                let harvest = if gas {
+                    OrderType::HarvestGas
+                } else {
+                    OrderType::MiningMinerals
+                };
+                next_order(e, dh, harvest, resource.entity);
+            } else if gas {
+                next_order(e, dh, OrderType::WaitForGas, resource.entity);
+            } else {
+                match find_free_minerals(dh, dat, me, units) {
+                    Some(free) if free != resource.entity => {
+                        next_order(e, dh, OrderType::MoveToMinerals, free)
+                    }
+                    _ => next_order(e, dh, OrderType::WaitForMinerals, resource.entity),
+                }
+            }
+        }
+        OrderType::MiningMinerals | OrderType::HarvestGas => {
+            let gas = order.order_type == OrderType::HarvestGas;
+            let target = match target.and_then(|t| find_unit(units, t)) {
+                Some(t) => t,
+                None => {
+                    // destroyed while harvesting
+                    dh.iscript_state[*e].visible = true;
+                    let next = if gas {
+                        None
+                    } else {
+                        find_free_minerals(dh, dat, me, units)
+                    };
+                    match next {
+                        Some(next) => next_order(e, dh, OrderType::Harvest1, next),
+                        None => dh.scunit[*e].orders.finish(),
+                    }
+                    return;
+                }
+            };
+            if dh.scunit[*e].orders.state == 0 {
+                dh.worker[*e].timer = if gas { GAS_TICKS } else { MINING_TICKS };
+                if gas {
+                    // inside the refinery
+                    dh.iscript_state[*e].visible = false;
+                }
+                dh.scunit[*e].orders.state = 1;
+            }
+            if !gas {
+                face(e, dh, target.x, target.y, 0);
+            }
+            if dh.worker[*e].timer > 0 {
+                dh.worker[*e].timer -= 1;
+                return;
+            }
+            let (resource_type, per_trip) = if gas {
+                (ResourceType::Gas, GAS_PER_TRIP)
+            } else {
+                (ResourceType::Minerals, MINERALS_PER_TRIP)
+            };
+            let amount = take_resource(dh, target.entity, per_trip);
+            release(dh, me.entity, target.entity);
+            if gas {
+                dh.iscript_state[*e].visible = true;
+            } else {
+                dh.iscript_state[*e].next_animation = Some(AnimationType::WorkingToIdle);
+            }
+            if amount > 0 {
+                set_cargo(e, dh, Some((resource_type, amount)));
+            }
+            let ret = if gas {
+                OrderType::ReturnGas
+            } else {
+                OrderType::ReturnMinerals
+            };
+            next_order(e, dh, ret, target.entity);
+        }
+        OrderType::ReturnMinerals | OrderType::ReturnGas => {
+            let cargo = match dh.worker[*e].carrying {
+                Some(cargo) => cargo,
+                None => {
+                    dh.scunit[*e].orders.finish();
+                    return;
+                }
+            };
+            let depot = match find_depot(dat, me, units) {
+                Some(depot) => depot,
+                None => {
+                    SCUnitStep::halt(e, dh);
+                    dh.scunit[*e].orders.finish();
+                    return;
+                }
+            };
+            if unit_distance(&dat.units_dat, me, &depot) > HARVEST_RANGE {
+                chase(e, dh, map, depot.x, depot.y);
+                return;
+            }
+            SCUnitStep::halt(e, dh);
+            {
+                let player = &mut dh.services.players[me.player];
+                match cargo.0 {
+                    ResourceType::Minerals => player.minerals += cargo.1,
+                    ResourceType::Gas => player.gas += cargo.1,
+                }
+            }
+            set_cargo(e, dh, None);
+            match dh.worker[*e].harvest_target.and_then(|t| find_unit(units, t)) {
+                Some(resource) => next_order(e, dh, OrderType::Harvest1, resource.entity),
+                None if cargo.0 == ResourceType::Minerals => {
+                    match find_free_minerals(dh, dat, me, units) {
+                        Some(next) => next_order(e, dh, OrderType::Harvest1, next),
+                        None => dh.scunit[*e].orders.finish(),
+                    }
+                }
+                None => dh.scunit[*e].orders.finish(),
+            }
+        }
+        _ => dh.scunit[*e].orders.finish(),
+    }
+}
+
+/// is `order_type` handled by order_harvest
+pub fn is_harvest_order(order_type: OrderType) -> bool {
+    match order_type {
+        OrderType::Harvest1 | OrderType::Harvest2 | OrderType::Harvest3 |
+        OrderType::Harvest4 | OrderType::MoveToMinerals | OrderType::MoveToGas |
+        OrderType::WaitForMinerals | OrderType::WaitForGas | OrderType::MiningMinerals |
+        OrderType::HarvestGas | OrderType::ReturnMinerals | OrderType::ReturnGas => true,
+        _ => false,
+    }
+}
+
+/// mineral fields disappear once mined out
+pub fn remove_depleted(e: &EntityData<UnitComponents>,
+                       dh: &mut DataHelper<UnitComponents, UnitServices>) {
+    if dh.resource.has(e) && dh.resource[*e].resource_type == ResourceType::Minerals &&
+       dh.resource[*e].amount == 0 {
+        let death = kill_unit(*e, dh);
+        record_death(&mut dh.services, death);
+    }
+}
+
+/// supply used and provided per race, in half units
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Supply {
+    pub used: [u32; 3],
+    pub provided: [u32; 3],
+}
+impl Supply {
+    /// counts the supply of `player`'s units
+    pub fn count(units_dat: &UnitsDat, units: &[UnitInfo], player: usize) -> Self {
+        let mut supply = Supply::default();
+        for u in units.iter().filter(|u| u.player == player) {
+            let r = match race(units_dat, u.unit_id) {
+                Some(r) => r as usize,
+                None => continue,
+            };
+            supply.used[r] += units_dat.supply_required[u.unit_id] as u32;
+            supply.provided[r] += units_dat.supply_provided[u.unit_id] as u32;
+        }
+        for p in supply.provided.iter_mut() {
+            *p = (*p).min(MAX_SUPPLY);
+        }
+        supply
+    }
+
+    /// (used, provided) of the race the player mainly plays, in half units
+    pub fn main_race(&self) -> (u32, u32) {
+        let r = (0..3).max_by_key(|&r| (self.provided[r] + self.used[r], 3 - r)).unwrap();
+        (self.used[r], self.provided[r])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Supply, MAX_SUPPLY};
+
+    #[test]
+    fn main_race() {
+        let mut supply = Supply::default();
+        supply.used[1] = 8;
+        supply.provided[1] = 20;
+        supply.provided[2] = 18;
+        assert_eq!(supply.main_race(), (8, 20));
+        supply.provided[1] = MAX_SUPPLY;
+        assert_eq!(supply.main_race(), (8, MAX_SUPPLY));
+        assert_eq!(Supply::default().main_race(), (0, 0));
+    }
+}
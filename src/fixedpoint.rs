@@ -0,0 +1,229 @@
+//! Integer math for the simulation. Positions and speeds are 8.8 fixed point
+//! (1/256 pixel), directions are 0..256 clockwise starting north, like in the
+//! original game. Nothing in here may use floats, so that two runs with the
+//! same inputs end up in exactly the same state.
+
+/// 1/256 pixels
+pub type Fixed = i32;
+
+pub fn px_to_fixed(px: i32) -> Fixed {
+    px * 256
+}
+pub fn fixed_to_px(f: Fixed) -> i32 {
+    f >> 8
+}
+
+/// unit vectors (length 256) for the 256 directions
+pub static DIRECTION_TABLE: [(i32, i32); 256] = [
+    (0, -256), (6, -256), (13, -256), (19, -255),
+    (25, -255), (31, -254), (38, -253), (44, -252),
+    (50, -251), (56, -250), (62, -248), (68, -247),
+    (74, -245), (80, -243), (86, -241), (92, -239),
+    (98, -237), (104, -234), (109, -231), (115, -229),
+    (121, -226), (126, -223), (132, -220), (137, -216),
+    (142, -213), (147, -209), (152, -206), (157, -202),
+    (162, -198), (167, -194), (172, -190), (177, -185),
+    (181, -181), (185, -177), (190, -172), (194, -167),
+    (198, -162), (202, -157), (206, -152), (209, -147),
+    (213, -142), (216, -137), (220, -132), (223, -126),
+    (226, -121), (229, -115), (231, -109), (234, -104),
+    (237, -98), (239, -92), (241, -86), (243, -80),
+    (245, -74), (247, -68), (248, -62), (250, -56),
+    (251, -50), (252, -44), (253, -38), (254, -31),
+    (255, -25), (255, -19), (256, -13), (256, -6),
+    (256, 0), (256, 6), (256, 13), (255, 19),
+    (255, 25), (254, 31), (253, 38), (252, 44),
+    (251, 50), (250, 56), (248, 62), (247, 68),
+    (245, 74), (243, 80), (241, 86), (239, 92),
+    (237, 98), (234, 104), (231, 109), (229, 115),
+    (226, 121), (223, 126), (220, 132), (216, 137),
+    (213, 142), (209, 147), (206, 152), (202, 157),
+    (198, 162), (194, 167), (190, 172), (185, 177),
+    (181, 181), (177, 185), (172, 190), (167, 194),
+    (162, 198), (157, 202), (152, 206), (147, 209),
+    (142, 213), (137, 216), (132, 220), (126, 223),
+    (121, 226), (115, 229), (109, 231), (104, 234),
+    (98, 237), (92, 239), (86, 241), (80, 243),
+    (74, 245), (68, 247), (62, 248), (56, 250),
+    (50, 251), (44, 252), (38, 253), (31, 254),
+    (25, 255), (19, 255), (13, 256), (6, 256),
+    (0, 256), (-6, 256), (-13, 256), (-19, 255),
+    (-25, 255), (-31, 254), (-38, 253), (-44, 252),
+    (-50, 251), (-56, 250), (-62, 248), (-68, 247),
+    (-74, 245), (-80, 243), (-86, 241), (-92, 239),
+    (-98, 237), (-104, 234), (-109, 231), (-115, 229),
+    (-121, 226), (-126, 223), (-132, 220), (-137, 216),
+    (-142, 213), (-147, 209), (-152, 206), (-157, 202),
+    (-162, 198), (-167, 194), (-172, 190), (-177, 185),
+    (-181, 181), (-185, 177), (-190, 172), (-194, 167),
+    (-198, 162), (-202, 157), (-206, 152), (-209, 147),
+    (-213, 142), (-216, 137), (-220, 132), (-223, 126),
+    (-226, 121), (-229, 115), (-231, 109), (-234, 104),
+    (-237, 98), (-239, 92), (-241, 86), (-243, 80),
+    (-245, 74), (-247, 68), (-248, 62), (-250, 56),
+    (-251, 50), (-252, 44), (-253, 38), (-254, 31),
+    (-255, 25), (-255, 19), (-256, 13), (-256, 6),
+    (-256, 0), (-256, -6), (-256, -13), (-255, -19),
+    (-255, -25), (-254, -31), (-253, -38), (-252, -44),
+    (-251, -50), (-250, -56), (-248, -62), (-247, -68),
+    (-245, -74), (-243, -80), (-241, -86), (-239, -92),
+    (-237, -98), (-234, -104), (-231, -109), (-229, -115),
+    (-226, -121), (-223, -126), (-220, -132), (-216, -137),
+    (-213, -142), (-209, -147), (-206, -152), (-202, -157),
+    (-198, -162), (-194, -167), (-190, -172), (-185, -177),
+    (-181, -181), (-177, -185), (-172, -190), (-167, -194),
+    (-162, -198), (-157, -202), (-152, -206), (-147, -209),
+    (-142, -213), (-137, -216), (-132, -220), (-126, -223),
+    (-121, -226), (-115, -229), (-109, -231), (-104, -234),
+    (-98, -237), (-92, -239), (-86, -241), (-80, -243),
+    (-74, -245), (-68, -247), (-62, -248), (-56, -250),
+    (-50, -251), (-44, -252), (-38, -253), (-31, -254),
+    (-25, -255), (-19, -255), (-13, -256), (-6, -256),
+];
+
+/// vector of length `dist` pixels in direction `dir`, in fixed point
+pub fn direction_offset(dir: u8, dist: i32) -> (Fixed, Fixed) {
+    let (vx, vy) = DIRECTION_TABLE[dir as usize];
+    (vx * dist, vy * dist)
+}
+
+/// tan of the boundaries between the directions of one quadrant, 16.16 fixed point
+static TAN_TABLE: [i64; 64] = [
+    804, 2414, 4026, 5644, 7268, 8901, 10545, 12202,
+    13874, 15564, 17273, 19005, 20762, 22546, 24360, 26208,
+    28093, 30018, 31986, 34002, 36071, 38196, 40382, 42636,
+    44963, 47369, 49863, 52451, 55144, 57950, 60880, 63947,
+    67165, 70548, 74116, 77887, 81885, 86135, 90670, 95523,
+    100736, 106358, 112447, 119071, 126314, 134276, 143081, 152884,
+    163878, 176309, 190499, 206870, 225990, 248648, 275959, 309568,
+    351993, 407305, 482534, 590958, 761030, 1066730, 1779314, 5340086,
+];
+
+/// number of direction boundaries passed going `across` per `along`, i.e. the
+/// direction inside one quadrant
+fn quadrant_steps(along: i32, across: i32) -> u32 {
+    let mut steps = 0;
+    for t in TAN_TABLE.iter() {
+        if t * (along as i64) < (across as i64) * 65536 {
+            steps += 1;
+        }
+    }
+    steps
+}
+
+/// direction that points closest to (dx, dy)
+pub fn direction_from_delta(dx: i32, dy: i32) -> u8 {
+    let dir = if dx == 0 && dy == 0 {
+        0
+    } else if dx >= 0 && dy < 0 {
+        quadrant_steps(-dy, dx)
+    } else if dx > 0 && dy >= 0 {
+        64 + quadrant_steps(dx, dy)
+    } else if dx <= 0 && dy > 0 {
+        128 + quadrant_steps(dy, -dx)
+    } else {
+        192 + quadrant_steps(-dx, -dy)
+    };
+    (dir % 256) as u8
+}
+
+/// signed difference from `from` to `to`, positive is clockwise
+pub fn direction_diff(from: u8, to: u8) -> i32 {
+    to.wrapping_sub(from) as i8 as i32
+}
+
+/// turns from `current` towards `target` by at most `rate` directions
+pub fn turn_towards(current: u8, target: u8, rate: u8) -> u8 {
+    let diff = direction_diff(current, target);
+    if diff.abs() <= rate as i32 {
+        target
+    } else if diff > 0 {
+        current.wrapping_add(rate)
+    } else {
+        current.wrapping_sub(rate)
+    }
+}
+
+/// 256 directions to the 32 used for graphics and by iscript
+pub fn dir256_to_dir32(dir: u8) -> u8 {
+    (((dir as u16 + 4) / 8) % 32) as u8
+}
+pub fn dir32_to_dir256(dir: u8) -> u8 {
+    (dir % 32) * 8
+}
+
+/// the game's distance approximation, in the unit of dx/dy
+pub fn approx_distance(dx: i32, dy: i32) -> i32 {
+    let mut min = dx.abs();
+    let mut max = dy.abs();
+    if max < min {
+        ::std::mem::swap(&mut min, &mut max);
+    }
+    if min < (max >> 2) {
+        return max;
+    }
+    let min_calc = (3 * min) >> 3;
+    (min_calc >> 5) + min_calc + max - (max >> 4) - (max >> 6)
+}
+
+/// linear congruential generator used by the game
+#[derive(Default, Clone, Debug)]
+pub struct GameRng {
+    pub seed: u32,
+}
+impl GameRng {
+    pub fn new(seed: u32) -> Self {
+        GameRng { seed: seed }
+    }
+
+    /// 0..0x7fff
+    pub fn rand(&mut self) -> u16 {
+        self.seed = self.seed.wrapping_mul(0x015A4E35).wrapping_add(1);
+        ((self.seed >> 16) & 0x7fff) as u16
+    }
+
+    pub fn rand_u8(&mut self) -> u8 {
+        (self.rand() & 0xff) as u8
+    }
+
+    /// min..max, both inclusive
+    pub fn range(&mut self, min: u32, max: u32) -> u32 {
+        assert!(min <= max);
+        min + ((self.rand() as u32 * (max - min + 1)) >> 15)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directions() {
+        assert_eq!(direction_from_delta(0, -10), 0);
+        assert_eq!(direction_from_delta(10, 0), 64);
+        assert_eq!(direction_from_delta(0, 10), 128);
+        assert_eq!(direction_from_delta(-10, 0), 192);
+        assert_eq!(direction_from_delta(10, -10), 32);
+        for dir in 0..256 {
+            let (vx, vy) = DIRECTION_TABLE[dir];
+            assert_eq!(direction_from_delta(vx, vy) as usize, dir);
+        }
+
+        assert_eq!(turn_towards(250, 10, 8), 2);
+        assert_eq!(turn_towards(10, 250, 8), 2);
+        assert_eq!(turn_towards(10, 14, 8), 14);
+        assert_eq!(dir256_to_dir32(254), 0);
+        assert_eq!(dir256_to_dir32(dir32_to_dir256(17)), 17);
+    }
+
+    #[test]
+    fn rng_is_deterministic() {
+        let mut a = GameRng::new(1234);
+        let mut b = GameRng::new(1234);
+        for _ in 0..1000 {
+            let r = a.range(3, 9);
+            assert!(r >= 3 && r <= 9);
+            assert_eq!(r, b.range(3, 9));
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Simulation clock. The game logic runs in fixed ticks ("logic frames") whose
+//! length depends on the game speed, independent of how often we render.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GameSpeed {
+    Slowest,
+    Slower,
+    Slow,
+    Normal,
+    Fast,
+    Faster,
+    Fastest,
+}
+impl GameSpeed {
+    /// length of a tick in ms
+    pub fn tick_ms(&self) -> u32 {
+        match *self {
+            GameSpeed::Slowest => 167,
+            GameSpeed::Slower => 111,
+            GameSpeed::Slow => 83,
+            GameSpeed::Normal => 67,
+            GameSpeed::Fast => 56,
+            GameSpeed::Faster => 48,
+            GameSpeed::Fastest => 42,
+        }
+    }
+
+    pub fn faster(&self) -> GameSpeed {
+        match *self {
+            GameSpeed::Slowest => GameSpeed::Slower,
+            GameSpeed::Slower => GameSpeed::Slow,
+            GameSpeed::Slow => GameSpeed::Normal,
+            GameSpeed::Normal => GameSpeed::Fast,
+            GameSpeed::Fast => GameSpeed::Faster,
+            GameSpeed::Faster | GameSpeed::Fastest => GameSpeed::Fastest,
+        }
+    }
+    pub fn slower(&self) -> GameSpeed {
+        match *self {
+            GameSpeed::Slowest | GameSpeed::Slower => GameSpeed::Slowest,
+            GameSpeed::Slow => GameSpeed::Slower,
+            GameSpeed::Normal => GameSpeed::Slow,
+            GameSpeed::Fast => GameSpeed::Normal,
+            GameSpeed::Faster => GameSpeed::Fast,
+            GameSpeed::Fastest => GameSpeed::Faster,
+        }
+    }
+}
+
+/// if rendering falls behind by more than this, the simulation slows down
+/// instead of trying to catch up
+const MAX_TICKS_PER_FRAME: u32 = 8;
+
+pub struct GameClock {
+    speed: GameSpeed,
+    paused: bool,
+    /// real time not yet used up by ticks, in ms
+    accumulated_ms: u32,
+    steps_requested: u32,
+    /// number of ticks since the start
+    pub tick_count: u64,
+    /// ticks dropped because rendering fell behind
+    pub skipped_ticks: u64,
+}
+impl GameClock {
+    pub fn new(speed: GameSpeed) -> Self {
+        GameClock {
+            speed: speed,
+            paused: false,
+            accumulated_ms: 0,
+            steps_requested: 0,
+            tick_count: 0,
+            skipped_ticks: 0,
+        }
+    }
+
+    /// lets `elapsed_ms` of real time pass, returns the number of ticks to run
+    pub fn advance(&mut self, elapsed_ms: u32) -> u32 {
+        if self.paused {
+            let steps = self.steps_requested;
+            self.steps_requested = 0;
+            self.tick_count += steps as u64;
+            return steps;
+        }
+        let tick_ms = self.speed.tick_ms();
+        self.accumulated_ms += elapsed_ms;
+        let mut ticks = self.accumulated_ms / tick_ms;
+        self.accumulated_ms %= tick_ms;
+        if ticks > MAX_TICKS_PER_FRAME {
+            self.skipped_ticks += (ticks - MAX_TICKS_PER_FRAME) as u64;
+            ticks = MAX_TICKS_PER_FRAME;
+        }
+        self.tick_count += ticks as u64;
+        ticks
+    }
+
+    /// how far the current time is between the last and the next tick, 0..256
+    pub fn alpha(&self) -> u32 {
+        if self.paused {
+            return 0;
+        }
+        self.accumulated_ms * 256 / self.speed.tick_ms()
+    }
+
+    pub fn speed(&self) -> GameSpeed {
+        self.speed
+    }
+    pub fn set_speed(&mut self, speed: GameSpeed) {
+        self.speed = speed;
+        self.accumulated_ms = self.accumulated_ms.min(speed.tick_ms() - 1);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.accumulated_ms = 0;
+        self.steps_requested = 0;
+    }
+    pub fn toggle_pause(&mut self) {
+        let paused = !self.paused;
+        self.set_paused(paused);
+    }
+
+    /// runs a single tick on the next advance(), only while paused
+    pub fn step(&mut self) {
+        if self.paused {
+            self.steps_requested += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GameClock, GameSpeed};
+
+    #[test]
+    fn ticks_from_elapsed_time() {
+        let mut clock = GameClock::new(GameSpeed::Normal);
+        assert_eq!(clock.advance(30), 0);
+        assert_eq!(clock.advance(40), 1);
+        // 3 ms left over from before
+        assert_eq!(clock.advance(134), 2);
+        assert_eq!(clock.tick_count, 3);
+        assert_eq!(clock.alpha(), 3 * 256 / 67);
+    }
+
+    #[test]
+    fn speed_changes() {
+        let mut clock = GameClock::new(GameSpeed::Fastest);
+        assert_eq!(clock.advance(100), 2);
+        // the 16 ms left over count towards the slower tick
+        clock.set_speed(GameSpeed::Slowest);
+        assert_eq!(clock.advance(150), 0);
+        assert_eq!(clock.advance(1), 1);
+        // a shorter tick doesn't run more than one tick for the rest
+        assert_eq!(clock.advance(166), 0);
+        clock.set_speed(GameSpeed::Fastest);
+        assert_eq!(clock.advance(1), 1);
+        assert_eq!(clock.tick_count, 4);
+
+        assert_eq!(GameSpeed::Fastest.faster(), GameSpeed::Fastest);
+        assert_eq!(GameSpeed::Slowest.slower(), GameSpeed::Slowest);
+        assert_eq!(GameSpeed::Normal.faster().slower(), GameSpeed::Normal);
+    }
+
+    #[test]
+    fn falling_behind_skips_ticks() {
+        let mut clock = GameClock::new(GameSpeed::Normal);
+        assert_eq!(clock.advance(20 * 67), 8);
+        assert_eq!(clock.skipped_ticks, 12);
+        // the skipped ticks are not caught up later
+        assert_eq!(clock.advance(66), 0);
+        assert_eq!(clock.tick_count, 8);
+        assert_eq!(clock.skipped_ticks, 12);
+    }
+
+    #[test]
+    fn pause_and_step() {
+        let mut clock = GameClock::new(GameSpeed::Normal);
+        clock.step();
+        assert_eq!(clock.advance(50), 0);
+        clock.toggle_pause();
+        assert!(clock.is_paused());
+        assert_eq!(clock.advance(1000), 0);
+        assert_eq!(clock.alpha(), 0);
+        clock.step();
+        clock.step();
+        assert_eq!(clock.advance(0), 2);
+        assert_eq!(clock.advance(0), 0);
+        assert_eq!(clock.tick_count, 2);
+        // time from before the pause is dropped
+        clock.toggle_pause();
+        assert_eq!(clock.advance(20), 0);
+        assert_eq!(clock.advance(47), 1);
+    }
+}
@@ -3,17 +3,20 @@ use std::collections::HashMap;
 use std::path::Path;
 
 use std::cell::RefCell;
+use std::io::Read;
 
-use scformats::stormlib::{MPQArchive, MPQArchiveFile};
+use scformats::stormlib::{MPQArchive, MPQArchiveFile, MPQError};
 use scformats::font::{Font, FontSize};
 use scformats::pcx::PCX;
 use scformats::tbl::read_tbl;
 use scformats::pal::Palette;
 use scformats::iscript::IScript;
+use scformats::aiscript::AIScripts;
 use scformats::grp::GRP;
 use scformats::lox::LOX;
 
-use scformats::unitsdata::{ImagesDat, UnitsDat, SpritesDat, FlingyDat, WeaponsDat, OrdersDat};
+use scformats::unitsdata::{ImagesDat, UnitsDat, SpritesDat, FlingyDat, WeaponsDat, OrdersDat,
+                           UpgradesDat, TechDataDat};
 
 use Video;
 use smacker::SMK;
@@ -98,10 +101,13 @@ pub struct GameData {
 
     pub weapons_dat: WeaponsDat,
     pub orders_dat: OrdersDat,
+    pub upgrades_dat: UpgradesDat,
+    pub techdata_dat: TechDataDat,
 
     pub install_pal: Palette,
 
     pub iscript: IScript,
+    pub ai_scripts: AIScripts,
 
     pub grp_cache: RefCell<GRPCache>,
     pub lox_cache: Rc<RefCell<LOXCache>>,
@@ -128,7 +134,8 @@ impl GameData {
         for filename in &data_filenames {
             let combined = data_path.join(filename);
             if combined.exists() {
-                archives.push(MPQArchive::open(combined.to_str().unwrap()));
+                archives.push(MPQArchive::open(combined.to_str().unwrap())
+                    .expect("can't open a data archive"));
             }
         }
 
@@ -148,12 +155,22 @@ impl GameData {
             .unwrap());
         let orders_dat = OrdersDat::read(&mut GameData::open_(&archives, "arr/orders.dat")
             .unwrap());
+        let upgrades_dat = UpgradesDat::read(&mut GameData::open_(&archives, "arr/upgrades.dat")
+            .unwrap());
+        let techdata_dat = TechDataDat::read(&mut GameData::open_(&archives, "arr/techdata.dat")
+            .unwrap());
 
         let install_pal = Palette::read_wpe(&mut GameData::open_(&archives, "tileset/install.wpe")
             .unwrap());
 
         let iscript = IScript::read(&mut GameData::open_(&archives, "scripts/iscript.bin")
             .unwrap());
+        // bwscript.bin only comes with Brood War
+        let mut bwscript = GameData::open_(&archives, "scripts/bwscript.bin");
+        let ai_scripts =
+            AIScripts::read(&mut GameData::open_(&archives, "scripts/aiscript.bin").unwrap(),
+                            bwscript.as_mut().map(|f| f as &mut Read))
+                .expect("can't read the AI scripts");
 
         // FIXME depends on tileset
         let ofire_reindexing =
@@ -229,8 +246,11 @@ impl GameData {
             flingy_dat: flingy_dat,
             weapons_dat: weapons_dat,
             orders_dat: orders_dat,
+            upgrades_dat: upgrades_dat,
+            techdata_dat: techdata_dat,
 
             iscript: iscript,
+            ai_scripts: ai_scripts,
             ofire_reindexing: ofire_reindexing,
             bfire_reindexing: bfire_reindexing,
             gfire_reindexing: gfire_reindexing,
@@ -279,8 +299,7 @@ impl GameData {
         for mpq in archives {
             if mpq.has_file(filename) {
                 // println!("found {} in {}", filename, mpq.filename);
-                let res: MPQArchiveFile = mpq.open_file(filename);
-                return Some(res);
+                return mpq.open_file(filename).ok();
             }
         }
         None
@@ -290,14 +309,14 @@ impl GameData {
         &self.fonts[size as usize]
     }
 
-    pub fn extract(&self, in_fn: &str, out_fn: &str) {
+    pub fn extract(&self, in_fn: &str, out_fn: &str) -> Result<(), MPQError> {
         for mpq in &self.mpq_archives {
             if mpq.has_file(in_fn) {
                 // println!("found {} in {}", filename, mpq.filename);
-                mpq.extract(in_fn, out_fn);
-                return;
+                return mpq.extract(in_fn, out_fn);
             }
         }
+        Err(MPQError::OpenFile(in_fn.to_string()))
     }
 }
 
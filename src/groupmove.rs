@@ -0,0 +1,206 @@
+//! Move orders for several selected units at once. Compact groups keep their
+//! formation, spread out groups gather around the goal. The group shares a
+//! single path search, units only plan their own path if they are too far away
+//! from the shared one.
+
+use ecs::{Entity, World};
+
+use scformats::unitsdata::{OrderType, UF_FLYER};
+
+use fixedpoint::approx_distance;
+use orders::{Order, OrderTarget, issue_order};
+use unit_ecs::{UnitSystems, PlanningMap, Path};
+
+/// units within this distance (pixels) of the group center move in formation
+pub const COMPACT_GROUP_RADIUS: i32 = 128;
+/// units further away from the shared path plan their own one
+const JOIN_PATH_RANGE: i32 = 96;
+
+struct Member {
+    entity: Entity,
+    x: i32,
+    y: i32,
+    size: i32,
+    flyer: bool,
+}
+
+fn is_group_move(order: &Order) -> bool {
+    match (order.order_type, order.target) {
+        (OrderType::Move, OrderTarget::Position(..)) |
+        (OrderType::AttackMove, OrderTarget::Position(..)) |
+        (OrderType::Patrol, OrderTarget::Position(..)) => true,
+        _ => false,
+    }
+}
+
+/// free spots around (gx, gy), nearest first, `spacing` pixels apart
+pub fn formation_slots(gx: i32,
+                       gy: i32,
+                       count: usize,
+                       spacing: i32,
+                       map: &PlanningMap)
+                       -> Vec<(i32, i32)> {
+    let mut slots = Vec::with_capacity(count);
+    let mut ring: i32 = 0;
+    // rings of a square spiral, bounded in case the goal is mostly unwalkable
+    while slots.len() < count && ring < 16 {
+        for dy in -ring..ring + 1 {
+            for dx in -ring..ring + 1 {
+                if dx.abs() != ring && dy.abs() != ring {
+                    continue;
+                }
+                let (x, y) = (gx + dx * spacing, gy + dy * spacing);
+                if slots.len() < count && map.is_walkable(x, y) {
+                    slots.push((x, y));
+                }
+            }
+        }
+        ring += 1;
+    }
+    while slots.len() < count {
+        slots.push((gx, gy));
+    }
+    slots
+}
+
+/// the shared path, joined at the waypoint nearest to (x, y) and ending at
+/// (tx, ty). Waypoints are shifted by (dx, dy) where that is walkable.
+fn join_path(shared: &Path,
+             x: i32,
+             y: i32,
+             tx: i32,
+             ty: i32,
+             dx: i32,
+             dy: i32,
+             map: &PlanningMap)
+             -> Option<Path> {
+    if shared.path.is_empty() {
+        return None;
+    }
+    let shifted: Vec<::Point> = shared.path
+        .iter()
+        .map(|p| if map.is_walkable(p.x() + dx, p.y() + dy) {
+            ::Point::new(p.x() + dx, p.y() + dy)
+        } else {
+            *p
+        })
+        .collect();
+    // path is reversed, the start is at the end
+    let mut nearest = shifted.len() - 1;
+    let mut nearest_dist = i32::max_value();
+    for (i, p) in shifted.iter().enumerate() {
+        let dist = approx_distance(p.x() - x, p.y() - y);
+        if dist < nearest_dist {
+            nearest = i;
+            nearest_dist = dist;
+        }
+    }
+    if nearest_dist > JOIN_PATH_RANGE {
+        return None;
+    }
+    let mut path: Vec<::Point> = shifted[..nearest + 1].to_vec();
+    path[0] = ::Point::new(tx, ty);
+    Some(Path { path: path })
+}
+
+/// gives a move order to all `entities`, returns how many accepted it
+pub fn issue_group_order(world: &mut World<UnitSystems>,
+                         entities: &[Entity],
+                         order: Order,
+                         queued: bool)
+                         -> usize {
+    let map = world.systems.scunit_stepping_sys.map.clone();
+    let (gx, gy) = match order.target {
+        OrderTarget::Position(x, y) => (x, y),
+        _ => (0, 0),
+    };
+    if entities.len() < 2 || !is_group_move(&order) || map.is_none() {
+        return entities.iter()
+            .filter(|e| issue_order(world, **e, order, queued))
+            .count();
+    }
+    let map = map.unwrap();
+
+    let dat = world.data.services.dat();
+    let mut members = Vec::with_capacity(entities.len());
+    for entity in entities {
+        let member = world.with_entity_data(entity, |e, data| {
+            if !data.scunit.has(&e) {
+                return None;
+            }
+            let unit_id = data.scunit[e].unit_id as usize;
+            Some(Member {
+                entity: *entity,
+                x: data.iscript_state[e].map_pos_x as i32,
+                y: data.iscript_state[e].map_pos_y as i32,
+                size: (dat.units_dat.unit_size_left[unit_id] +
+                       dat.units_dat.unit_size_right[unit_id]) as i32,
+                flyer: dat.units_dat.flags(unit_id).contains(UF_FLYER),
+            })
+        });
+        if let Some(Some(member)) = member {
+            members.push(member);
+        }
+    }
+    if members.is_empty() {
+        return 0;
+    }
+
+    let n = members.len() as i32;
+    let cx = members.iter().map(|m| m.x).sum::<i32>() / n;
+    let cy = members.iter().map(|m| m.y).sum::<i32>() / n;
+    let compact = members.iter()
+        .all(|m| approx_distance(m.x - cx, m.y - cy) <= COMPACT_GROUP_RADIUS);
+
+    // the goal of every member
+    let targets: Vec<(i32, i32)> = if compact {
+        members.iter()
+            .map(|m| {
+                let (tx, ty) = (gx + m.x - cx, gy + m.y - cy);
+                if m.flyer || map.is_walkable(tx, ty) {
+                    (tx, ty)
+                } else {
+                    (gx, gy)
+                }
+            })
+            .collect()
+    } else {
+        let spacing = members.iter().map(|m| m.size).max().unwrap_or(0) + 2;
+        let slots = formation_slots(gx, gy, members.len(), spacing, &map);
+        // nearest units take the slots closest to the goal
+        let mut by_dist: Vec<usize> = (0..members.len()).collect();
+        by_dist.sort_by_key(|&i| (approx_distance(members[i].x - gx, members[i].y - gy), i));
+        let mut targets = vec![(gx, gy); members.len()];
+        for (slot, &i) in slots.iter().zip(by_dist.iter()) {
+            targets[i] = *slot;
+        }
+        targets
+    };
+
+    // queued orders start later from somewhere else, they plan on their own
+    let shared = if queued {
+        None
+    } else {
+        let leader = members.iter()
+            .min_by_key(|m| approx_distance(m.x - cx, m.y - cy))
+            .unwrap();
+        Some(Path::plan(leader.x, leader.y, gx, gy, &map))
+    };
+
+    let mut accepted = 0;
+    for (m, &(tx, ty)) in members.iter().zip(targets.iter()) {
+        let member_order = Order::new(order.order_type, OrderTarget::Position(tx, ty));
+        if !issue_order(world, m.entity, member_order, queued) {
+            continue;
+        }
+        accepted += 1;
+        let mut path = shared.as_ref().and_then(|shared| {
+            let (dx, dy) = if compact { (m.x - cx, m.y - cy) } else { (0, 0) };
+            join_path(shared, m.x, m.y, tx, ty, dx, dy, &map)
+        });
+        world.with_entity_data(&m.entity, |e, data| {
+            data.scunit[e].planned_path = path.take();
+        });
+    }
+    accepted
+}
@@ -0,0 +1,202 @@
+//! Hit points, shields and energy of units. Like in units.dat, all values are
+//! in 1/256 points.
+
+use scformats::unitsdata::{UnitsDat, UF_REGENERATE, UF_SPELLCASTER};
+use fixedpoint::Fixed;
+
+/// regeneration per tick
+const HP_REGENERATION: Fixed = 4;
+const SHIELD_REGENERATION: Fixed = 7;
+const ENERGY_REGENERATION: Fixed = 8;
+
+/// maximum energy of spellcasters without energy upgrade
+pub const DEFAULT_MAX_ENERGY: Fixed = 200 * 256;
+/// energy of newly created spellcasters
+const INITIAL_ENERGY: Fixed = 50 * 256;
+
+// MapUnit::changeable_props
+const MAP_HP_VALID: u16 = 0x2;
+const MAP_SHIELDS_VALID: u16 = 0x4;
+const MAP_ENERGY_VALID: u16 = 0x8;
+
+pub struct HealthComponent {
+    pub hit_points: Fixed,
+    pub max_hit_points: Fixed,
+    pub shields: Fixed,
+    pub max_shields: Fixed,
+    pub energy: Fixed,
+    /// 0 for units without energy
+    pub max_energy: Fixed,
+    /// zerg units regenerate hit points
+    pub regenerates: bool,
+}
+impl HealthComponent {
+    pub fn new(units_dat: &UnitsDat, unit_id: usize) -> Self {
+        let flags = units_dat.flags(unit_id);
+        let max_shields = if units_dat.shield_enable[unit_id] != 0 {
+            units_dat.shield_amount[unit_id] as Fixed * 256
+        } else {
+            0
+        };
+        let max_energy = if flags.contains(UF_SPELLCASTER) {
+            DEFAULT_MAX_ENERGY
+        } else {
+            0
+        };
+        HealthComponent {
+            hit_points: units_dat.hit_points[unit_id] as Fixed,
+            max_hit_points: units_dat.hit_points[unit_id] as Fixed,
+            shields: max_shields,
+            max_shields: max_shields,
+            energy: INITIAL_ENERGY.min(max_energy),
+            max_energy: max_energy,
+            regenerates: flags.contains(UF_REGENERATE),
+        }
+    }
+
+    /// applies the percentages (1-100) a map gives for a unit
+    pub fn set_map_percentages(&mut self, changeable_props: u16, hp: u8, shields: u8, energy: u8) {
+        if changeable_props & MAP_HP_VALID != 0 {
+            self.hit_points = (self.max_hit_points * hp as Fixed / 100).max(1);
+        }
+        if changeable_props & MAP_SHIELDS_VALID != 0 {
+            self.shields = self.max_shields * shields as Fixed / 100;
+        }
+        if changeable_props & MAP_ENERGY_VALID != 0 {
+            self.energy = self.max_energy * energy as Fixed / 100;
+        }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.hit_points <= 0
+    }
+
+    /// called once per tick
+    pub fn regenerate(&mut self) {
+        if self.is_dead() {
+            return;
+        }
+        if self.regenerates {
+            self.hit_points = (self.hit_points + HP_REGENERATION).min(self.max_hit_points);
+        }
+        self.shields = (self.shields + SHIELD_REGENERATION).min(self.max_shields);
+        self.energy = (self.energy + ENERGY_REGENERATION).min(self.max_energy);
+    }
+
+    /// e.g. by a shield battery, returns how much was recharged
+    pub fn recharge_shields(&mut self, amount: Fixed) -> Fixed {
+        let recharged = amount.min(self.max_shields - self.shields).max(0);
+        self.shields += recharged;
+        recharged
+    }
+
+    /// fraction of a bar (of `boxes` boxes) to fill
+    fn filled_boxes(value: Fixed, max: Fixed, boxes: u32) -> u32 {
+        if max <= 0 || value <= 0 {
+            return 0;
+        }
+        // a damaged unit never shows a full bar, an alive one at least one box
+        let filled = (value as i64 * boxes as i64 / max as i64) as u32;
+        filled.max(1).min(boxes)
+    }
+
+    pub fn hp_boxes(&self, boxes: u32) -> u32 {
+        HealthComponent::filled_boxes(self.hit_points, self.max_hit_points, boxes)
+    }
+    pub fn shield_boxes(&self, boxes: u32) -> u32 {
+        HealthComponent::filled_boxes(self.shields, self.max_shields, boxes)
+    }
+    pub fn energy_boxes(&self, boxes: u32) -> u32 {
+        HealthComponent::filled_boxes(self.energy, self.max_energy, boxes)
+    }
+
+    /// takes over the hit point and shield fractions and the energy of `old`,
+    /// e.g. when a unit morphs
+    pub fn keep_fractions(&mut self, old: &HealthComponent) {
+        if old.max_hit_points > 0 {
+            self.hit_points = ((self.max_hit_points as i64 * old.hit_points as i64 /
+                                old.max_hit_points as i64) as Fixed)
+                .max(1);
+        }
+        if old.max_shields > 0 {
+            self.shields = (self.max_shields as i64 * old.shields as i64 /
+                            old.max_shields as i64) as Fixed;
+        }
+        self.energy = old.energy.min(self.max_energy);
+    }
+
+    /// hit points in percent of the maximum
+    pub fn hp_percent(&self) -> i32 {
+        if self.max_hit_points <= 0 {
+            return 100;
+        }
+        (self.hit_points as i64 * 100 / self.max_hit_points as i64) as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HealthComponent;
+
+    fn marine() -> HealthComponent {
+        HealthComponent {
+            hit_points: 40 * 256,
+            max_hit_points: 40 * 256,
+            shields: 0,
+            max_shields: 0,
+            energy: 0,
+            max_energy: 0,
+            regenerates: false,
+        }
+    }
+
+    #[test]
+    fn bars() {
+        let mut h = marine();
+        assert_eq!(h.hp_boxes(8), 8);
+        h.hit_points = 1;
+        assert_eq!(h.hp_boxes(8), 1);
+        h.hit_points = 20 * 256;
+        assert_eq!(h.hp_boxes(8), 4);
+        assert_eq!(h.hp_percent(), 50);
+        assert_eq!(h.shield_boxes(8), 0);
+    }
+
+    #[test]
+    fn map_percentages() {
+        let mut h = marine();
+        h.set_map_percentages(0x2, 25, 100, 100);
+        assert_eq!(h.hit_points, 10 * 256);
+        h.set_map_percentages(0, 100, 100, 100);
+        assert_eq!(h.hit_points, 10 * 256);
+    }
+
+    #[test]
+    fn morph_fractions() {
+        let mut old = marine();
+        old.hit_points = 10 * 256;
+        let mut h = marine();
+        h.max_hit_points = 200 * 256;
+        h.max_shields = 20 * 256;
+        h.shields = h.max_shields;
+        h.keep_fractions(&old);
+        assert_eq!(h.hit_points, 50 * 256);
+        // no shields before: keep the full ones
+        assert_eq!(h.shields, 20 * 256);
+        assert_eq!(h.energy, 0);
+    }
+
+    #[test]
+    fn regeneration() {
+        let mut h = marine();
+        h.regenerates = true;
+        h.hit_points = 10 * 256;
+        for _ in 0..64 {
+            h.regenerate();
+        }
+        assert_eq!(h.hit_points, 11 * 256);
+        h.hit_points = 0;
+        h.regenerate();
+        assert!(h.is_dead());
+    }
+}
@@ -1,9 +1,9 @@
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::BuildHasherDefault;
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use rand::Rng;
-
 use ecs::DataHelper;
 use ecs::system::{System};
 use ecs::EntityIter;
@@ -13,24 +13,14 @@ use ecs::IndexedEntity;
 
 use enum_primitive::FromPrimitive;
 
-use scformats::unitsdata::{ImagesDat, WeaponsDat};
+use scformats::unitsdata::{ImagesDat, WeaponsDat, WEAPON_NONE, UF_FLYER};
 use scformats::iscript::IScript;
 use scformats::iscript::{OpCode, AnimationType};
 use ::gamedata::LOXCache;
 use ::unit_ecs::{IScriptEntityAction, UnitComponents, UnitServices};
-
-use std::f32;
-
-pub fn angle_to_discrete32(angle: f32) -> u8 {
-    let pi = f32::consts::PI;
-    // x+24 % 32 ~ x + 90°
-    (((angle+pi)*32. / (2.*pi)).round() + 24.) as u8 % 32
-}
-
-pub fn discrete32_to_angle(discrete_angle: u8) -> f32 {
-    let pi = f32::consts::PI;
-    (((discrete_angle + 8) % 32) as f32 / 32.) * (2.*pi) - pi
-}
+use ::fixedpoint::{direction_offset, dir32_to_dir256, fixed_to_px};
+use ::morph::is_lifted;
+use ::subunit::is_subunit;
 
 macro_rules! var_read {
     (u8, $gd: ident, $file:expr) => ($file.read_u8($gd));
@@ -103,7 +93,10 @@ pub struct IScriptSteppingSys {
 
     pub iscript_entity_actions: Vec<IScriptEntityAction>,
 
-    pub interested: HashMap<Entity, IndexedEntity<UnitComponents>>,
+    /// fixed hasher, so that entities are always processed in the same order
+    pub interested: HashMap<Entity,
+                            IndexedEntity<UnitComponents>,
+                            BuildHasherDefault<DefaultHasher>>,
 }
 impl System for IScriptSteppingSys {
     type Components = UnitComponents;
@@ -118,6 +111,56 @@ impl System for IScriptSteppingSys {
     }
 }
 impl IScriptSteppingSys {
+    fn target_is_air(&self,
+                     e: EntityData<UnitComponents>,
+                     dh: &mut DataHelper<UnitComponents, UnitServices>)
+                     -> bool {
+        let dat = dh.services.dat();
+        match dh.scunit[e].attack_target {
+            Some(target) => {
+                dh.with_entity_data(&target, |te, data| {
+                        data.scunit.has(&te) &&
+                        dat.units_dat
+                            .flags(data.scunit[te].unit_id as usize)
+                            .contains(UF_FLYER)
+                    })
+                    .unwrap_or(false)
+            }
+            None => false,
+        }
+    }
+
+    /// creates the weapon flingy in front of the unit
+    fn launch_weapon(&self,
+                     e: EntityData<UnitComponents>,
+                     dh: &mut DataHelper<UnitComponents, UnitServices>,
+                     weapon_id: usize)
+                     -> Option<IScriptEntityAction> {
+        if weapon_id >= WEAPON_NONE as usize {
+            return None;
+        }
+        dh.scunit[e].used_weapon = weapon_id;
+        let forward_offset = self.weapons_dat.forward_offset[weapon_id] as i32 +
+                             dh.scunit[e].weapon_shift_proj as i32;
+        dh.scunit[e].weapon_shift_proj = 0;
+        let upward_offset = self.weapons_dat.upward_offset[weapon_id] as i32;
+
+        // FIXME: use movement_direction (?)
+        let (rel_x, rel_y) = direction_offset(dir32_to_dir256(dh.iscript_state[e].direction),
+                                              forward_offset);
+        let x = dh.iscript_state[e].map_pos_x as i32 + fixed_to_px(rel_x);
+        let y = dh.iscript_state[e].map_pos_y as i32 + fixed_to_px(rel_y) - upward_offset;
+
+        Some(IScriptEntityAction::CreateWeaponsFlingy {
+            weapon_id: weapon_id as u16,
+            source: **e,
+            target: dh.scunit[e].attack_target,
+            x: x.max(0).min(65535) as u16,
+            y: y.max(0).min(65535) as u16,
+            player_id: dh.scimage[e].player_id,
+        })
+    }
+
     // FIXME: make sure there can be only one overlay/underlay type per parent instance
     fn interpret_iscript(&self,
                          cpy: &IScript,
@@ -133,7 +176,10 @@ impl IScriptSteppingSys {
                 let children = dh.iscript_state[e].children.clone();
                 for c in children {
                     dh.with_entity_data(&c, |ent, data| {
-                        data.iscript_state[ent].next_animation = Some(a);
+                        // turrets animate on their own
+                        if !is_subunit(ent, data) {
+                            data.iscript_state[ent].next_animation = Some(a);
+                        }
                     });
                 }
             },
@@ -144,7 +190,7 @@ impl IScriptSteppingSys {
         if !dh.iscript_state[e].alive || dh.iscript_state[e].paused {
             return None;
         }
-        // FIXME: waiting is actually counted in ticks, not in frames
+        // waiting is counted in game ticks, we are called once per tick
         if dh.iscript_state[e].waiting_ticks_left > 0 {
             dh.iscript_state[e].waiting_ticks_left -= 1;
             return None;
@@ -172,6 +218,7 @@ impl IScriptSteppingSys {
                 for si in soundids {
                     println!("sound: {}", si);
                 }
+                return Some(IScriptEntityAction::DealDamage { source: **e });
             }
 
             fixed_parameters:
@@ -331,7 +378,7 @@ impl IScriptSteppingSys {
             dh.iscript_state[e].waiting_ticks_left += ticks as usize;
         },
         OpCode::WaitRand => (minticks: u8, maxticks: u8) {
-            let r = ::rand::thread_rng().gen_range(minticks, maxticks+1);
+            let r = dh.services.rng.range(minticks as u32, maxticks as u32);
             dh.iscript_state[e].waiting_ticks_left += r as usize;
         },
         OpCode::SigOrder => (signal: u8) {
@@ -343,7 +390,7 @@ impl IScriptSteppingSys {
             dh.iscript_state[e].pos = target as usize;
         },
         OpCode::RandCondJmp => (val: u8, target: u16) {
-            let r = ::rand::random::<u8>();
+            let r = dh.services.rng.rand_u8();
             if r < val {
                 dh.iscript_state[e].pos = target as usize;
             }
@@ -359,7 +406,7 @@ impl IScriptSteppingSys {
             dh.iscript_state[e].pos = pos;
         },
         OpCode::TurnRand => (units: u8) {
-            if ::rand::thread_rng().gen_range(0, 100) < 50 {
+            if dh.services.rng.range(0, 99) < 50 {
                 dh.iscript_state[e].turn_cwise(units);
             } else {
                 dh.iscript_state[e].turn_ccwise(units);
@@ -384,11 +431,10 @@ impl IScriptSteppingSys {
         OpCode::Move => (dist: u8) {
             dh.iscript_state[e].move_forward(dist);
             let children = dh.iscript_state[e].children.clone();
-            let (mx, my) = (dh.iscript_state[e].map_pos_x, dh.iscript_state[e].map_pos_y);
+            let (ex, ey) = (dh.iscript_state[e].exact_x, dh.iscript_state[e].exact_y);
             for c in children {
                 dh.with_entity_data(&c, |ent, data| {
-                    data.iscript_state[ent].map_pos_x = mx;
-                    data.iscript_state[ent].map_pos_y = my;
+                    data.iscript_state[ent].set_exact_pos(ex, ey);
                 });
             }
         },
@@ -405,8 +451,17 @@ impl IScriptSteppingSys {
         },
         OpCode::PwrupCondJmp => (offset: u16) {
             // Jumps to a code block if the current unit is a powerup and it is currently picked up.
-            // FIXME
-            println!("pwrupcondjmp: {}", offset);
+            // carried powerups are overlays of their carrier
+            if dh.iscript_state[e].parent_entity.is_some() {
+                dh.iscript_state[e].pos = offset as usize;
+            }
+        },
+
+        OpCode::LiftOffCondJmp => (offset: u16) {
+            // Jumps to a code block when the current unit is a building that is lifted off.
+            if is_lifted(e, dh) {
+                dh.iscript_state[e].pos = offset as usize;
+            }
         },
 
         OpCode::NoBrkCodeStart => () {
@@ -439,39 +494,31 @@ impl IScriptSteppingSys {
             // attackwith <ground = 1, air = 2>
             assert!(dh.scunit.has(&e));
             assert!((weapon == 1) || (weapon == 2));
-            dh.scunit[e].used_weapon = match weapon {
-                1 => {
-                    println!("firing ground weapon");
-                    dh.scunit[e].ground_weapon_id
-                },
-                2 => {
-                    println!("firing air weapon");
-                     dh.scunit[e].air_weapon_id
-                },
-                _ => { unreachable!(); },
+            let weapon_id = if weapon == 2 {
+                dh.scunit[e].air_weapon_id
+            } else {
+                dh.scunit[e].ground_weapon_id
             };
-            let forward_offset = self.weapons_dat.forward_offset[dh.scunit[e].used_weapon] +
-                dh.scunit[e].weapon_shift_proj;
-            dh.scunit[e].weapon_shift_proj = 0;
-            let upward_offset = self.weapons_dat.upward_offset[dh.scunit[e].used_weapon];
-
-            // FIXME: use movement_angle (?)
-            let dir_f32 = discrete32_to_angle(dh.iscript_state[e].direction);
-            let rel_x_f32 = dir_f32.cos() * (forward_offset as f32);
-            let rel_y_f32 = dir_f32.sin() * (forward_offset as f32);
-
-            let rel_x = rel_x_f32 as isize;
-            let rel_y = (rel_y_f32 as isize) + (upward_offset as isize);
-            println!("rel: {}, {}", rel_x, rel_y);
-
-            return Some(IScriptEntityAction::CreateWeaponsFlingy {
-                weapon_id: dh.scunit[e].ground_weapon_id as u16,
-                // FIXME: calc from offsets
-                rel_x: rel_x,
-                rel_y: rel_y,
-            });
-
-        // FIXME
+            return self.launch_weapon(e, dh, weapon_id);
+        },
+        OpCode::Attack => () {
+            // Attack with the ground or air weapon, depending on the target.
+            assert!(dh.scunit.has(&e));
+            let weapon_id = if self.target_is_air(e, dh) {
+                dh.scunit[e].air_weapon_id
+            } else {
+                dh.scunit[e].ground_weapon_id
+            };
+            return self.launch_weapon(e, dh, weapon_id);
+        },
+        OpCode::UseWeapon => (weapon_id: u8) {
+            // Attacks the target with a weapons.dat entry
+            assert!(dh.scunit.has(&e));
+            return self.launch_weapon(e, dh, weapon_id as usize);
+        },
+        OpCode::CastSpell => () {
+            // Casts the spell of the unit's current order.
+            return Some(IScriptEntityAction::CastSpell { caster: **e });
         },
         OpCode::GotoRepeatAttk => () {
         // Signals to StarCraft that after this point, when the unit's cooldown time
@@ -488,6 +535,10 @@ impl IScriptSteppingSys {
         },
         OpCode::DoMissileDmg => () {
             // Causes the damage of a weapon flingy to be applied according to its weapons.dat entry.
+            return Some(IScriptEntityAction::DealDamage { source: **e });
+        },
+        OpCode::DoGrdDamage => () {
+            return Some(IScriptEntityAction::DealDamage { source: **e });
         },
         OpCode::TrgtRangeCondJmp => (dist: u16, file_offset: u16) {
             // Jumps to a block depending on the distance to the target.
@@ -516,6 +567,9 @@ impl Process for IScriptSteppingSys {
     fn process(&mut self,
                dh: &mut DataHelper<UnitComponents, UnitServices>) {
         let cpy = &self.iscript_copy;
+        for e in EntityIter::Map(self.interested.values()) {
+            dh.iscript_state[e].store_last_pos();
+        }
         let iter = EntityIter::Map(self.interested.values());
         for e in iter {
             let create_action = self.interpret_iscript(&cpy, e, dh);
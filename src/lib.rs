@@ -2,6 +2,7 @@ extern crate byteorder;
 extern crate libc;
 extern crate enum_primitive;
 extern crate num;
+#[cfg(feature = "sdl")]
 extern crate sdl2;
 extern crate rand;
 extern crate config;
@@ -19,25 +20,58 @@ use ecs::Entity;
 
 extern crate bresenham;
 
+#[cfg(feature = "sdl")]
 #[macro_use]
 pub mod events;
 
 pub mod gamedata;
 #[macro_use]
 pub mod utils;
+#[cfg(feature = "sdl")]
 pub mod ui;
 pub mod render;
 pub mod unit_ecs;
 pub mod iscriptsys;
-
+pub mod orders;
+pub mod combat;
+pub mod health;
+pub mod collision;
+pub mod groupmove;
+pub mod vision;
+pub mod economy;
+pub mod construction;
+pub mod tech;
+pub mod abilities;
+pub mod cloak;
+pub mod transport;
+pub mod morph;
+pub mod subunit;
+pub mod triggers;
+pub mod locations;
+pub mod ai;
+pub mod fixedpoint;
+pub mod gameclock;
+pub mod simulation;
+pub mod mapgen;
+pub mod maplint;
+
+#[cfg(feature = "sdl")]
 use std::path::Path;
+#[cfg(feature = "sdl")]
 use sdl2::render::Renderer;
+#[cfg(feature = "sdl")]
 use sdl2::pixels::PixelFormatEnum;
+#[cfg(feature = "sdl")]
 use sdl2::surface::Surface;
+#[cfg(feature = "sdl")]
 use gamedata::GameData;
+#[cfg(feature = "sdl")]
+use gameclock::{GameClock, GameSpeed};
 
+#[cfg(feature = "sdl")]
 use std::collections::HashSet;
 
+#[cfg(feature = "sdl")]
 struct_events! (
     mouse: {
         mouse_left: Left,
@@ -48,6 +82,26 @@ struct_events! (
     }
 );
 
+/// stands in for sdl2's Point in paths when building without SDL
+#[cfg(not(feature = "sdl"))]
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+pub struct Point {
+    x: i32,
+    y: i32,
+}
+#[cfg(not(feature = "sdl"))]
+impl Point {
+    pub fn new(x: i32, y: i32) -> Point {
+        Point { x: x, y: y }
+    }
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+}
+
 // FIXME: use same structure for GRPs?
 pub struct Video {
     pub pal: Palette,
@@ -109,13 +163,20 @@ pub enum GameEvents {
     SelectUnit(Entity),
 }
 
+#[cfg(feature = "sdl")]
 pub struct GameState {
     // pub unit_instances: Stash<SCUnit>,
     pub selected_units: Vec<Entity>,
 
     pub game_events: Vec<GameEvents>,
     pub map_pos: Point,
+    /// player whose view is shown
+    pub player: usize,
+
+    /// drives View::tick
+    pub clock: GameClock,
 }
+#[cfg(feature = "sdl")]
 impl GameState {
     fn new() -> Self {
         GameState {
@@ -123,11 +184,14 @@ impl GameState {
             selected_units: Vec::<Entity>::new(),
             game_events: Vec::<GameEvents>::new(),
             map_pos: Point::new(0, 0),
+            player: 0,
+            clock: GameClock::new(GameSpeed::Fastest),
         }
     }
 }
 
 
+#[cfg(feature = "sdl")]
 pub trait LayerTrait {
     fn render(&self, renderer: &mut Renderer);
     fn update(&mut self, gd: &GameData, gc: &mut GameContext, state: &mut GameState);
@@ -138,11 +202,13 @@ pub trait LayerTrait {
 }
 
 
+#[cfg(feature = "sdl")]
 pub struct GameContext<'window> {
     pub events: Events,
     pub renderer: Renderer<'window>,
     pub screen: Surface<'window>,
 }
+#[cfg(feature = "sdl")]
 impl<'window> GameContext<'window> {
     fn new(//gd: GameData,
            events: Events,
@@ -162,15 +228,21 @@ impl<'window> GameContext<'window> {
     }
 }
 
+#[cfg(feature = "sdl")]
 pub enum ViewAction {
     None,
     Quit,
     ChangeView(Box<View>),
 }
 
+#[cfg(feature = "sdl")]
 pub trait View {
+    /// called once per rendered frame, e.g. for input handling
     fn update(&mut self, _: &GameData, _: &mut GameContext, _: &mut GameState) {}
 
+    /// advances the simulation by one game tick
+    fn tick(&mut self, _: &GameData, _: &mut GameState) {}
+
     /// renders the current view into context.screen
     fn render(&mut self, gd: &GameData, context: &mut GameContext, state: &GameState, elapsed: f64) -> ViewAction;
 
@@ -198,6 +270,16 @@ pub trait View {
 
 
 
+/// StarCraft data directory, as configured in settings.toml
+pub fn scdata_path() -> String {
+    let mut c = config::Config::new();
+    c.merge(config::File::new("settings", config::FileFormat::Toml).required(false)).unwrap();
+    let scdata_path = c.get_str("scdata_path").expect("no StarCraft data path given!");
+    println!("loading SC data from path: {:?}", scdata_path);
+    scdata_path
+}
+
+#[cfg(feature = "sdl")]
 pub fn spawn<F>(title: &str, init: F)
     where F: Fn(&GameData, &mut GameContext, &mut GameState) -> Box<View>
 {
@@ -212,10 +294,7 @@ pub fn spawn<F>(title: &str, init: F)
 
     let mut timer = sdl_context.timer().unwrap();
 
-    let mut c = config::Config::new();
-    c.merge(config::File::new("settings", config::FileFormat::Toml).required(false)).unwrap();
-    let scdata_path = c.get_str("scdata_path").expect("no StarCraft data path given!");
-    println!("loading SC data from path: {:?}", scdata_path);
+    let scdata_path = scdata_path();
 
     let gd = GameData::init(&Path::new(&scdata_path));
 
@@ -256,7 +335,27 @@ pub fn spawn<F>(title: &str, init: F)
         }
 
         context.events.pump(&mut context.renderer);
+
+        // game speed controls
+        if context.events.now.is_key_pressed(&Keycode::Pause) {
+            state.clock.toggle_pause();
+        } else if context.events.now.is_key_pressed(&Keycode::Period) {
+            state.clock.step();
+        } else if context.events.now.is_key_pressed(&Keycode::KpPlus) {
+            let speed = state.clock.speed().faster();
+            state.clock.set_speed(speed);
+            println!("game speed: {:?}", speed);
+        } else if context.events.now.is_key_pressed(&Keycode::KpMinus) {
+            let speed = state.clock.speed().slower();
+            state.clock.set_speed(speed);
+            println!("game speed: {:?}", speed);
+        }
+
         current_view.update(&gd, &mut context, &mut state);
+        let ticks = state.clock.advance(dt);
+        for _ in 0..ticks {
+            current_view.tick(&gd, &mut state);
+        }
 
         current_view.generate_layer_events(&gd, &mut context, &mut state);
         current_view.process_layer_events(&mut context, &mut state);
@@ -0,0 +1,256 @@
+//! Map locations (MRGN): named rectangles triggers use to find, create and
+//! move units. A unit is in a location if its center is inside the rectangle
+//! and the location doesn't exclude the unit's elevation: ground units are at
+//! the height of the terrain below them, flyers are "air" at that height.
+//! Triggers can move a location, so they live in the unit services.
+
+use ecs::{World, Entity, EntityData, DataHelper};
+
+use scformats::terrain::{Map, MapLocation, TileHeight};
+use scformats::unitsdata::UF_FLYER;
+
+use combat::is_dying;
+use construction::is_under_construction;
+use morph::is_lifted;
+use unit_ecs::{UnitComponents, UnitServices, UnitSystems};
+
+/// location 64 is "Anywhere", the whole map
+pub const LOCATION_ANYWHERE: u32 = 64;
+/// Brood War maps have 255 locations, original ones 64
+const LOCATION_COUNT: usize = 255;
+
+// MRGN elevation flags, a set bit excludes the elevation
+const ELEVATION_LOW_GROUND: u16 = 0x01;
+const ELEVATION_AIR_SHIFT: u16 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    /// in pixels, right and bottom are exclusive
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    /// string index of the name, 0 for none
+    pub string: u16,
+    pub elevation_flags: u16,
+}
+impl Location {
+    fn from_mrgn(l: &MapLocation) -> Self {
+        // inverted locations are treated like normal ones
+        Location {
+            left: l.left.min(l.right) as i32,
+            top: l.top.min(l.bottom) as i32,
+            right: l.left.max(l.right) as i32,
+            bottom: l.top.max(l.bottom) as i32,
+            string: l.string,
+            elevation_flags: l.elevation_flags,
+        }
+    }
+
+    /// unused MRGN entries are all zero
+    pub fn is_used(&self) -> bool {
+        self.right > self.left || self.bottom > self.top || self.string != 0
+    }
+
+    pub fn contains_point(&self, x: i32, y: i32) -> bool {
+        x >= self.left && x < self.right && y >= self.top && y < self.bottom
+    }
+
+    pub fn allows_elevation(&self, height: TileHeight, flying: bool) -> bool {
+        let level = match height {
+            TileHeight::Low => 0,
+            TileHeight::Mid => 1,
+            TileHeight::High => 2,
+        };
+        let mut bit = ELEVATION_LOW_GROUND << level;
+        if flying {
+            bit <<= ELEVATION_AIR_SHIFT;
+        }
+        self.elevation_flags & bit == 0
+    }
+
+    pub fn center(&self) -> (i32, i32) {
+        ((self.left + self.right) / 2, (self.top + self.bottom) / 2)
+    }
+}
+
+#[derive(Default)]
+pub struct Locations {
+    /// location n is at index n - 1
+    locations: Vec<Location>,
+    /// map size in pixels
+    width: i32,
+    height: i32,
+}
+impl Locations {
+    pub fn new(map: &Map) -> Self {
+        let width = map.data.width as i32 * 32;
+        let height = map.data.height as i32 * 32;
+        let mut locations: Vec<Location> =
+            map.data.locations.iter().take(LOCATION_COUNT).map(Location::from_mrgn).collect();
+        let empty = Location {
+            left: 0,
+            top: 0,
+            right: 0,
+            bottom: 0,
+            string: 0,
+            elevation_flags: 0,
+        };
+        while locations.len() < LOCATION_COUNT {
+            locations.push(empty);
+        }
+        // "Anywhere" is the whole map, whatever MRGN says
+        let anywhere = &mut locations[LOCATION_ANYWHERE as usize - 1];
+        anywhere.left = 0;
+        anywhere.top = 0;
+        anywhere.right = width;
+        anywhere.bottom = height;
+        anywhere.elevation_flags = 0;
+        Locations {
+            locations: locations,
+            width: width,
+            height: height,
+        }
+    }
+
+    /// location `id` (1-based, like in triggers)
+    pub fn get(&self, id: u32) -> Option<&Location> {
+        if id == 0 {
+            return None;
+        }
+        self.locations.get(id as usize - 1)
+    }
+
+    /// (id, location) of all locations the map uses
+    pub fn used(&self) -> Vec<(u32, &Location)> {
+        self.locations
+            .iter()
+            .enumerate()
+            .filter(|&(_, l)| l.is_used())
+            .map(|(i, l)| (i as u32 + 1, l))
+            .collect()
+    }
+
+    pub fn center(&self, id: u32) -> Option<(i32, i32)> {
+        self.get(id).map(|l| l.center())
+    }
+
+    pub fn contains(&self, id: u32, x: i32, y: i32, height: TileHeight, flying: bool) -> bool {
+        match self.get(id) {
+            Some(l) => l.contains_point(x, y) && l.allows_elevation(height, flying),
+            None => false,
+        }
+    }
+
+    /// centers location `id` on (x, y), keeping its size and the whole
+    /// rectangle on the map
+    pub fn center_on(&mut self, id: u32, x: i32, y: i32) {
+        if id == 0 || id == LOCATION_ANYWHERE || id as usize > self.locations.len() {
+            return;
+        }
+        let (map_w, map_h) = (self.width, self.height);
+        let l = &mut self.locations[id as usize - 1];
+        let w = l.right - l.left;
+        let h = l.bottom - l.top;
+        let left = (x - w / 2).min(map_w - w).max(0);
+        let top = (y - h / 2).min(map_h - h).max(0);
+        l.left = left;
+        l.top = top;
+        l.right = left + w;
+        l.bottom = top + h;
+    }
+
+    /// draws the outlines of all used locations but "Anywhere"
+    pub fn render(&self, map_x: u16, map_y: u16, color: u8, buffer: &mut [u8], buffer_pitch: u32) {
+        let pitch = buffer_pitch as i32;
+        let rows = (buffer.len() / buffer_pitch as usize) as i32;
+        for (id, l) in self.used() {
+            if id == LOCATION_ANYWHERE {
+                continue;
+            }
+            let x0 = l.left - map_x as i32;
+            let y0 = l.top - map_y as i32;
+            let x1 = l.right - map_x as i32 - 1;
+            let y1 = l.bottom - map_y as i32 - 1;
+            for y in y0.max(0)..(y1 + 1).min(rows) {
+                for x in x0.max(0)..(x1 + 1).min(pitch) {
+                    if x == x0 || x == x1 || y == y0 || y == y1 {
+                        buffer[(y * pitch + x) as usize] = color;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// the terrain height below a unit and whether it is in the air
+pub fn unit_elevation(e: EntityData<UnitComponents>,
+                      dh: &DataHelper<UnitComponents, UnitServices>,
+                      map: &Map)
+                      -> (TileHeight, bool) {
+    let state = &dh.iscript_state[e];
+    let height = map.terrain_grid
+        .tile_height_px(state.map_pos_x as i32, state.map_pos_y as i32);
+    let unit_id = dh.scunit[e].unit_id as usize;
+    let flying = dh.services.dat().units_dat.flags(unit_id).contains(UF_FLYER) ||
+                 is_lifted(e, dh);
+    (height, flying)
+}
+
+/// finished, living units in location `id`, optionally only those of
+/// `player` and of type `unit_id`
+pub fn units_in(world: &World<UnitSystems>,
+                id: u32,
+                player: Option<usize>,
+                unit_id: Option<usize>)
+                -> Vec<Entity> {
+    let map = match world.systems.scunit_stepping_sys.map {
+        Some(ref map) => map.clone(),
+        None => return Vec::new(),
+    };
+    let dh = &world.data;
+    let locations = &dh.services.locations;
+    world.entities()
+        .filter(aspect!(<UnitComponents> all: [scunit] none: [subunit, passenger]), world)
+        .filter(|e| {
+            !is_dying(*e, dh) && !is_under_construction(*e, dh) &&
+            player.map_or(true, |p| dh.scimage[*e].player_id == p) &&
+            unit_id.map_or(true, |u| dh.scunit[*e].unit_id as usize == u)
+        })
+        .filter(|e| {
+            let state = &dh.iscript_state[*e];
+            let (height, flying) = unit_elevation(*e, dh, &map.scmap);
+            locations.contains(id,
+                               state.map_pos_x as i32,
+                               state.map_pos_y as i32,
+                               height,
+                               flying)
+        })
+        .map(|e| **e)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Location;
+    use scformats::terrain::TileHeight;
+
+    #[test]
+    fn elevations() {
+        // no high ground, no air at all
+        let l = Location {
+            left: 0,
+            top: 0,
+            right: 64,
+            bottom: 64,
+            string: 0,
+            elevation_flags: 0x04 | 0x08 | 0x10 | 0x20,
+        };
+        assert!(l.allows_elevation(TileHeight::Low, false));
+        assert!(l.allows_elevation(TileHeight::Mid, false));
+        assert!(!l.allows_elevation(TileHeight::High, false));
+        assert!(!l.allows_elevation(TileHeight::Low, true));
+        assert!(l.contains_point(0, 63));
+        assert!(!l.contains_point(64, 10));
+    }
+}
@@ -0,0 +1,551 @@
+//! Procedural generation of melee maps, mostly for bulk testing of the AI and
+//! pathfinding.
+//!
+//! The terrain is built from low and high ground diamonds (see
+//! `scformats::isom`), every start location sits on a high ground plateau
+//! with a ramp facing the map center. The map is point symmetric, so the
+//! number of players has to be even.
+
+use std::f32;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use scformats::terrain::{GameDataTrait, MapData, MapUnit, TerrainInfo, TerrainGrid, TileSet,
+                         TileHeight, vf4_tile_height, VF4_WALKABLE, CV5_UNBUILDABLE};
+use scformats::isom::{IsomMap, IsomRect, IsomTerrain};
+use scformats::unitsdata::{UNIT_MINERAL_FIELD_1, UNIT_VESPENE_GEYSER, UNIT_START_LOCATION};
+
+const NEUTRAL_PLAYER: u8 = 11;
+/// map size limits in tiles, StarCraft maps are at most 256x256
+const MIN_SIZE: u16 = 8;
+const MAX_SIZE: u16 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapGenError {
+    /// symmetric maps need 2, 4, 6 or 8 players
+    PlayerCount,
+    /// the width has to be a multiple of 4, the height a multiple of 2, both
+    /// between MIN_SIZE and MAX_SIZE
+    MapSize,
+    /// the tileset has no buildable low or high ground
+    GroundTypes,
+    /// the start locations can't reach each other even on low ground
+    NotConnected,
+}
+
+pub struct MapGenParams {
+    pub tileset: TileSet,
+    /// in tiles
+    pub width: u16,
+    pub height: u16,
+    pub players: usize,
+    pub seed: u32,
+}
+
+pub struct GeneratedMap {
+    pub data: MapData,
+    /// center of the start locations, in pixels, they can all reach each
+    /// other
+    pub start_locations: Vec<(u16, u16)>,
+}
+
+struct GroundTypes {
+    low: u16,
+    high: u16,
+}
+
+/// finds buildable low and high ground among the terrain types of a tileset
+fn ground_types(ti: &TerrainInfo, terrain: &IsomTerrain) -> Option<GroundTypes> {
+    let mut low = None;
+    let mut high = None;
+    let mut mid = None;
+    for &t in &terrain.terrain_types {
+        let (group, _) = terrain.groups_for(&IsomRect::solid(t)).unwrap();
+        let flags = ti.minitile_flags(group << 4);
+        let walkable = flags.iter().all(|f| f & VF4_WALKABLE > 0);
+        let buildable = ti.buildability(group << 4) & CV5_UNBUILDABLE == 0;
+        if !walkable || !buildable {
+            continue;
+        }
+        let height = flags.iter().map(|f| vf4_tile_height(*f)).max().unwrap();
+        let slot = match height {
+            TileHeight::Low => &mut low,
+            TileHeight::Mid => &mut mid,
+            TileHeight::High => &mut high,
+        };
+        if slot.is_none() {
+            *slot = Some(t);
+        }
+    }
+    match (low, high.or(mid)) {
+        (Some(low), Some(high)) => {
+            Some(GroundTypes {
+                low: low,
+                high: high,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// number of walkable minitiles of a tile pair
+fn walkable_minitiles(ti: &TerrainInfo, groups: (u16, u16)) -> usize {
+    ti.minitile_flags(groups.0 << 4)
+        .iter()
+        .chain(ti.minitile_flags(groups.1 << 4).iter())
+        .filter(|f| *f & VF4_WALKABLE > 0)
+        .count()
+}
+
+fn dist(x1: f32, y1: f32, x2: f32, y2: f32) -> f32 {
+    ((x1 - x2) * (x1 - x2) + (y1 - y2) * (y1 - y2)).sqrt()
+}
+
+/// center of an isom rectangle in pixels
+fn rect_center(x: usize, y: usize) -> (f32, f32) {
+    ((x * 64 + 32) as f32, (y * 32 + 16) as f32)
+}
+
+fn map_unit(unit_id: u16, x: u16, y: u16, player_no: u8, resources: u32) -> MapUnit {
+    MapUnit {
+        instance_id: 0,
+        x: x,
+        y: y,
+        unit_id: unit_id,
+        rel_type: 0,
+        special_prop_flags: 0,
+        // owner, hp, shields, energy (and resources) are valid
+        changeable_props: if resources > 0 { 0x1f } else { 0x0f },
+        player_no: player_no,
+        hit_points: 100,
+        shield_points: 100,
+        energy_points: 100,
+        resource_amount: resources,
+        units_in_hangar: 0,
+        state_flags: 0,
+        _unused: 0,
+        rel_instance_id: 0,
+    }
+}
+
+/// Tile occupation of units, to keep resources from overlapping.
+struct Footprints {
+    width: usize,
+    height: usize,
+    used: Vec<bool>,
+}
+impl Footprints {
+    fn new(width: usize, height: usize) -> Footprints {
+        Footprints {
+            width: width,
+            height: height,
+            used: vec![false; width * height],
+        }
+    }
+
+    /// tries to place a footprint with its top left tile at (tx, ty), it has
+    /// to be on the map and fit into its mirrored copy
+    fn place(&mut self, tx: i32, ty: i32, w: usize, h: usize) -> bool {
+        if tx < 0 || ty < 0 || tx as usize + w > self.width || ty as usize + h > self.height {
+            return false;
+        }
+        let (tx, ty) = (tx as usize, ty as usize);
+        let (mx, my) = (self.width - tx - w, self.height - ty - h);
+        for &(ox, oy) in &[(tx, ty), (mx, my)] {
+            for y in oy..oy + h {
+                for x in ox..ox + w {
+                    if self.used[y * self.width + x] {
+                        return false;
+                    }
+                }
+            }
+        }
+        for &(ox, oy) in &[(tx, ty), (mx, my)] {
+            for y in oy..oy + h {
+                for x in ox..ox + w {
+                    self.used[y * self.width + x] = true;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// true if all points can be reached from the first one at minitile
+/// resolution
+fn is_connected(grid: &TerrainGrid, points: &[(u16, u16)]) -> bool {
+    let w = grid.minitile_width();
+    let h = grid.minitile_height();
+    let (sx, sy) = (points[0].0 as i32 >> 3, points[0].1 as i32 >> 3);
+    if !grid.is_walkable(sx, sy) {
+        return false;
+    }
+    let mut reached = vec![false; w * h];
+    let mut open = vec![(sx, sy)];
+    reached[sy as usize * w + sx as usize] = true;
+    while let Some((x, y)) = open.pop() {
+        for &(dx, dy) in &[(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let (nx, ny) = (x + dx, y + dy);
+            if grid.is_walkable(nx, ny) && !reached[ny as usize * w + nx as usize] {
+                reached[ny as usize * w + nx as usize] = true;
+                open.push((nx, ny));
+            }
+        }
+    }
+    points.iter().all(|&(x, y)| reached[(y as usize >> 3) * w + (x as usize >> 3)])
+}
+
+/// the point symmetric counterpart of an isom rectangle
+fn mirror_rect(x: usize, y: usize, isom: &IsomMap) -> (usize, usize) {
+    (isom.width - 1 - x, isom.height - 1 - y)
+}
+
+/// distance of (x, y) to the line segment between (x1, y1) and (x2, y2)
+fn dist_to_segment(x: f32, y: f32, x1: f32, y1: f32, x2: f32, y2: f32) -> f32 {
+    let len = dist(x1, y1, x2, y2);
+    if len == 0. {
+        return dist(x, y, x1, y1);
+    }
+    let f = (((x - x1) * (x2 - x1) + (y - y1) * (y2 - y1)) / (len * len)).max(0.).min(1.);
+    dist(x, y, x1 + (x2 - x1) * f, y1 + (y2 - y1) * f)
+}
+
+/// lowers the diamonds within `radius` pixels of the way from each start
+/// location to the map center
+fn carve_corridors(isom: &mut IsomMap, start_locations: &[(u16, u16)], radius: f32, low: u16) {
+    let (center_x, center_y) = (((isom.width - 1) * 64) as f32 / 2.,
+                                ((isom.height - 1) * 32) as f32 / 2.);
+    for y in 0..isom.height {
+        for x in 0..isom.width {
+            if !isom.is_diamond(x, y) {
+                continue;
+            }
+            let (cx, cy) = rect_center(x, y);
+            let in_corridor = start_locations.iter().any(|&(sx, sy)| {
+                dist_to_segment(cx, cy, sx as f32, sy as f32, center_x, center_y) < radius
+            });
+            if in_corridor {
+                isom.set_diamond(x, y, low);
+            }
+        }
+    }
+}
+
+/// derives the tiles, combinations without tiles become high ground.
+/// Ramps: on the way from each plateau to the center, use the most walkable
+/// variant of the first cliff.
+fn derive_tiles(isom: &mut IsomMap,
+                terrain: &IsomTerrain,
+                ti: &TerrainInfo,
+                ground: &GroundTypes,
+                start_locations: &[(u16, u16)],
+                w: usize,
+                h: usize)
+                -> Vec<u16> {
+    let (center_x, center_y) = ((w * 32) as f32 / 2., (h * 32) as f32 / 2.);
+    let mut mtxm = vec![0 as u16; w * h];
+    for y in 0..isom.height {
+        for x in 0..isom.width {
+            if !isom.update_mtxm(terrain, x, y, &mut mtxm, w, h) {
+                isom.rects[y * isom.width + x] = IsomRect::solid(ground.high);
+                isom.update_mtxm(terrain, x, y, &mut mtxm, w, h);
+            }
+        }
+    }
+
+    for i in 0..start_locations.len() / 2 {
+        let (sx, sy) = (start_locations[i].0 as f32, start_locations[i].1 as f32);
+        let steps = (dist(sx, sy, center_x, center_y) / 16.) as usize;
+        for step in 0..steps {
+            let f = step as f32 / steps as f32;
+            let (px, py) = (sx + (center_x - sx) * f, sy + (center_y - sy) * f);
+            let (x, y) = (px as usize / 64, py as usize / 32);
+            let rect = isom.rects[y * isom.width + x];
+            let (left, top, right, bottom) = rect.edges();
+            if left == top && top == right && right == bottom {
+                continue;
+            }
+            let ramp = terrain.all_groups_for(&rect)
+                .into_iter()
+                .max_by_key(|g| walkable_minitiles(ti, *g));
+            if let Some(groups) = ramp {
+                let (mx, my) = mirror_rect(x, y, isom);
+                let mirrored = terrain.all_groups_for(&isom.rects[my * isom.width + mx])
+                    .into_iter()
+                    .max_by_key(|g| walkable_minitiles(ti, *g))
+                    .unwrap_or(groups);
+                IsomMap::set_tiles(x, y, groups, &mut mtxm, w, h);
+                IsomMap::set_tiles(mx, my, mirrored, &mut mtxm, w, h);
+            }
+            break;
+        }
+    }
+    mtxm
+}
+
+pub fn generate(gd: &GameDataTrait, params: &MapGenParams) -> Result<GeneratedMap, MapGenError> {
+    if params.players < 2 || params.players > 8 || params.players % 2 != 0 {
+        return Err(MapGenError::PlayerCount);
+    }
+    if params.width % 4 != 0 || params.height % 2 != 0 || params.width < MIN_SIZE ||
+       params.height < MIN_SIZE || params.width > MAX_SIZE || params.height > MAX_SIZE {
+        return Err(MapGenError::MapSize);
+    }
+    let mut rng = XorShiftRng::from_seed([params.seed, params.seed ^ 0x9e3779b9, 0x2545f491, 1]);
+
+    let ti = TerrainInfo::read(gd, params.tileset);
+    let terrain = IsomTerrain::new(&ti);
+    let ground = try!(ground_types(&ti, &terrain).ok_or(MapGenError::GroundTypes));
+
+    let w = params.width as usize;
+    let h = params.height as usize;
+    let px_w = (w * 32) as f32;
+    let px_h = (h * 32) as f32;
+    let (center_x, center_y) = (px_w / 2., px_h / 2.);
+    let mut isom = IsomMap::new(params.width, params.height, ground.low);
+
+    // start locations on an ellipse around the center, the second half
+    // mirrors the first one
+    let mut start_locations = Vec::<(u16, u16)>::new();
+    let base_angle = rng.gen_range(0., f32::consts::PI);
+    for i in 0..params.players / 2 {
+        let angle = base_angle + (i as f32) * 2. * f32::consts::PI / (params.players as f32) +
+                    rng.gen_range(-0.2, 0.2);
+        // snap to the tile grid, start locations are 4x3 tiles and have to
+        // stay on (tiny) maps
+        let tx = (((center_x + angle.cos() * px_w * 0.36) / 32.) as usize)
+            .saturating_sub(2)
+            .min(w.saturating_sub(4));
+        let ty = (((center_y + angle.sin() * px_h * 0.36) / 32.) as usize)
+            .saturating_sub(1)
+            .min(h.saturating_sub(3));
+        start_locations.push(((tx * 32 + 64) as u16, (ty * 32 + 48) as u16));
+    }
+    for i in 0..params.players / 2 {
+        let (x, y) = start_locations[i];
+        start_locations.push(((w * 32) as u16 - x, (h * 32) as u16 - y));
+    }
+
+    // high ground: plateaus around the start locations and some hills
+    let mut high = vec![false; isom.width * isom.height];
+    let plateau_radius = 12. * 32.;
+    let mut hills = Vec::<(f32, f32, f32)>::new();
+    for _ in 0..(w * h) / 2048 {
+        hills.push((rng.gen_range(0., px_w),
+                    rng.gen_range(0., px_h),
+                    rng.gen_range(4., 8.) * 32.));
+    }
+    for y in 0..isom.height {
+        for x in 0..isom.width {
+            if !isom.is_diamond(x, y) {
+                continue;
+            }
+            let (cx, cy) = rect_center(x, y);
+            let near_start = start_locations.iter()
+                .any(|&(sx, sy)| dist(cx, cy, sx as f32, sy as f32) < plateau_radius);
+            // keep hills away from the bases
+            let on_hill = hills.iter().any(|&(hx, hy, r)| dist(cx, cy, hx, hy) < r) &&
+                          start_locations.iter().all(|&(sx, sy)| {
+                dist(cx, cy, sx as f32, sy as f32) > plateau_radius + 4. * 32.
+            });
+            if near_start || on_hill {
+                high[y * isom.width + x] = true;
+            }
+        }
+    }
+    for y in 0..isom.height {
+        for x in 0..isom.width {
+            if isom.is_diamond(x, y) {
+                let (mx, my) = mirror_rect(x, y, &isom);
+                let is_high = high[y * isom.width + x] || high[my * isom.width + mx];
+                if is_high {
+                    isom.set_diamond(x, y, ground.high);
+                    isom.set_diamond(mx, my, ground.high);
+                }
+            }
+        }
+    }
+
+    // start locations that can't reach each other get corridors of low
+    // ground to the center, wider ones until it works. Once the corridors
+    // cover the whole map it is all low ground, which only fails to connect
+    // if the tileset's low ground doesn't.
+    let mut data = MapData::new(params.tileset, params.width, params.height);
+    let mut corridor = 0.;
+    loop {
+        let mut carved = isom.clone();
+        if corridor > 0. {
+            carve_corridors(&mut carved, &start_locations, corridor, ground.low);
+        }
+        data.mtxm = derive_tiles(&mut carved, &terrain, &ti, &ground, &start_locations, w, h);
+        data.isom = carved.to_raw();
+        let grid = TerrainGrid::new(&data, &ti);
+        if is_connected(&grid, &start_locations) {
+            break;
+        }
+        if corridor > px_w + px_h {
+            return Err(MapGenError::NotConnected);
+        }
+        corridor += if corridor > 0. { 64. } else { 96. };
+    }
+
+    for i in 0..12 {
+        if i < params.players {
+            // open human slot, user selectable race
+            data.owners[i] = 6;
+            data.sides[i] = 5;
+        } else {
+            data.owners[i] = 0;
+            data.sides[i] = 7;
+        }
+    }
+    data.sides[NEUTRAL_PLAYER as usize] = 4;
+    data.strings.push(format!("Generated {}", params.seed));
+    data.strings.push(format!("{}x{}, {} players, seed {}",
+                              params.width,
+                              params.height,
+                              params.players,
+                              params.seed));
+    data.scenario_name_str_idx = 1;
+    data.scenario_desc_str_idx = 2;
+
+    // units, resources of the first half are mirrored
+    let mut footprints = Footprints::new(w, h);
+    let mut units = Vec::<MapUnit>::new();
+    for (i, &(sx, sy)) in start_locations.iter().enumerate() {
+        if i < params.players / 2 {
+            footprints.place(sx as i32 / 32 - 2, sy as i32 / 32 - 1, 4, 3);
+        }
+        units.push(map_unit(UNIT_START_LOCATION, sx, sy, i as u8, 0));
+    }
+    for i in 0..params.players / 2 {
+        let (sx, sy) = (start_locations[i].0 as f32, start_locations[i].1 as f32);
+        // resources are behind the base, seen from the center
+        let away = (sy - center_y).atan2(sx - center_x);
+        let mut resources = Vec::<(u16, u16, u16, u32)>::new();
+        for k in 0..8 {
+            let angle = away + (k as f32 - 3.5) * 0.22;
+            for r in 7..10 {
+                let tx = ((sx + angle.cos() * (r * 32) as f32) / 32.) as i32 - 1;
+                let ty = ((sy + angle.sin() * (r * 32) as f32) / 32.) as i32;
+                if footprints.place(tx, ty, 2, 1) {
+                    resources.push((UNIT_MINERAL_FIELD_1 + (k % 3) as u16,
+                                    (tx * 32 + 32) as u16,
+                                    (ty * 32 + 16) as u16,
+                                    1500));
+                    break;
+                }
+            }
+        }
+        let angle = away + 1.4;
+        for r in 7..11 {
+            let tx = ((sx + angle.cos() * (r * 32) as f32) / 32.) as i32 - 2;
+            let ty = ((sy + angle.sin() * (r * 32) as f32) / 32.) as i32 - 1;
+            if footprints.place(tx, ty, 4, 2) {
+                resources.push((UNIT_VESPENE_GEYSER,
+                                (tx * 32 + 64) as u16,
+                                (ty * 32 + 32) as u16,
+                                5000));
+                break;
+            }
+        }
+        for (unit_id, x, y, amount) in resources {
+            units.push(map_unit(unit_id, x, y, NEUTRAL_PLAYER, amount));
+            units.push(map_unit(unit_id,
+                                (w * 32) as u16 - x,
+                                (h * 32) as u16 - y,
+                                NEUTRAL_PLAYER,
+                                amount));
+        }
+    }
+    for (i, unit) in units.iter_mut().enumerate() {
+        unit.instance_id = i as u32;
+    }
+    data.units = units;
+
+    Ok(GeneratedMap {
+        data: data,
+        start_locations: start_locations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::Path;
+    use gamedata::GameData;
+    use scformats::terrain::{GameDataTrait, TerrainGrid, TerrainInfo, TileSet};
+    use scformats::isom::IsomMap;
+    use super::{generate, is_connected, carve_corridors, MapGenParams, MapGenError};
+
+    struct NoData;
+    impl GameDataTrait for NoData {
+        fn open(&self, _: &str) -> Option<Cursor<Vec<u8>>> {
+            None
+        }
+    }
+
+    fn params(width: u16, height: u16, players: usize, seed: u32) -> MapGenParams {
+        MapGenParams {
+            tileset: TileSet::Jungle,
+            width: width,
+            height: height,
+            players: players,
+            seed: seed,
+        }
+    }
+
+    #[test]
+    fn invalid_params() {
+        // checked before any game data is needed
+        assert_eq!(generate(&NoData, &params(64, 64, 3, 0)).err(),
+                   Some(MapGenError::PlayerCount));
+        assert_eq!(generate(&NoData, &params(64, 64, 10, 0)).err(),
+                   Some(MapGenError::PlayerCount));
+        assert_eq!(generate(&NoData, &params(66, 64, 2, 0)).err(),
+                   Some(MapGenError::MapSize));
+        assert_eq!(generate(&NoData, &params(4, 4, 2, 0)).err(),
+                   Some(MapGenError::MapSize));
+        assert_eq!(generate(&NoData, &params(512, 64, 2, 0)).err(),
+                   Some(MapGenError::MapSize));
+    }
+
+    #[test]
+    fn wide_corridors_flatten_the_map() {
+        let (low, high) = (2, 4);
+        let mut isom = IsomMap::new(64, 64, high);
+        // on the diamonds (4, 10) and (27, 53)
+        let start_locations = [(288, 336), (64 * 32 - 288, 64 * 32 - 336)];
+        carve_corridors(&mut isom, &start_locations, 96., low);
+        // the way to the center is low, the corners stay high
+        assert_eq!(isom.diamond(4, 10), Some(low));
+        assert_eq!(isom.diamond(27, 53), Some(low));
+        assert_eq!(isom.diamond(16, 32), Some(low));
+        assert_eq!(isom.diamond(32, 0), Some(high));
+        carve_corridors(&mut isom, &start_locations, (64 * 32 * 2) as f32, low);
+        for y in 0..isom.height {
+            for x in 0..isom.width {
+                assert!(!isom.is_diamond(x, y) || isom.diamond(x, y) == Some(low));
+            }
+        }
+    }
+
+    #[test]
+    fn start_locations_are_connected() {
+        let gd = GameData::init(&Path::new(&::scdata_path()));
+        let ti = TerrainInfo::read(&gd, TileSet::Jungle);
+        // tiny maps are mostly plateau, the larger ones have hills in the way
+        for &(width, height, players) in &[(8, 8, 2), (64, 64, 2), (96, 64, 4), (128, 128, 8)] {
+            for seed in 0..8 {
+                let map = generate(&gd, &params(width, height, players, seed)).unwrap();
+                let grid = TerrainGrid::new(&map.data, &ti);
+                assert!(is_connected(&grid, &map.start_locations),
+                        "{}x{}, {} players, seed {}",
+                        width,
+                        height,
+                        players,
+                        seed);
+            }
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Checks maps for problems that would otherwise show up as panics or odd
+//! behavior at runtime.
+
+use scformats::terrain::Map;
+use scformats::unitsdata::{UnitsDat, UNIT_START_LOCATION, UF_BUILDING, UF_FLYER,
+                           UF_RESOURCE_CONTAINER, UF_SUBUNIT};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug)]
+pub struct LintMessage {
+    pub severity: Severity,
+    pub message: String,
+}
+
+// OWNR values
+const OWNER_INACTIVE: u8 = 0;
+const OWNER_COMPUTER_GAME: u8 = 1;
+const OWNER_HUMAN: u8 = 2;
+const OWNER_COMPUTER: u8 = 5;
+const OWNER_OPEN: u8 = 6;
+const OWNER_CLOSED: u8 = 8;
+
+struct Linter {
+    messages: Vec<LintMessage>,
+}
+impl Linter {
+    fn warn(&mut self, message: String) {
+        self.messages.push(LintMessage {
+            severity: Severity::Warning,
+            message: message,
+        });
+    }
+    fn error(&mut self, message: String) {
+        self.messages.push(LintMessage {
+            severity: Severity::Error,
+            message: message,
+        });
+    }
+}
+
+/// placement box of a unit in pixels: left, top, right, bottom
+fn unit_box(units_dat: &UnitsDat, unit_id: usize, x: i32, y: i32) -> (i32, i32, i32, i32) {
+    let w = units_dat.star_edit_placement_box_width[unit_id] as i32;
+    let h = units_dat.star_edit_elacement_box_height[unit_id] as i32;
+    (x - w / 2, y - h / 2, x - w / 2 + w, y - h / 2 + h)
+}
+
+fn check_sections(map: &Map, l: &mut Linter) {
+    for problem in &map.data.problems {
+        l.error(problem.clone());
+    }
+    for section in &map.data.unknown_sections {
+        l.warn(format!("unknown section {:?}", section));
+    }
+}
+
+fn check_tiles(map: &Map, l: &mut Linter) {
+    let w = map.data.width as usize;
+    let ti = &map.terrain_info;
+    let mut invalid = 0;
+    for (i, tile) in map.data.mtxm.iter().enumerate() {
+        if !ti.is_valid_tile(*tile) {
+            if invalid < 10 {
+                l.error(format!("tile ({}, {}): id {:#x} out of range ({} tile groups, {} \
+                                 doodads, {} megatiles)",
+                                i % w,
+                                i / w,
+                                tile,
+                                ti.cv5_count(),
+                                ti.doodad_count(),
+                                ti.megatile_count()));
+            }
+            invalid += 1;
+        }
+    }
+    if invalid > 10 {
+        l.error(format!("{} more invalid tiles", invalid - 10));
+    }
+}
+
+fn check_strings(map: &Map, l: &mut Linter) {
+    let string_count = map.data.strings.len();
+    let name_idx = map.data.scenario_name_str_idx;
+    if name_idx > string_count {
+        l.error(format!("scenario name refers to string {} of {}", name_idx, string_count));
+    }
+    let desc_idx = map.data.scenario_desc_str_idx;
+    if desc_idx > string_count {
+        l.error(format!("scenario description refers to string {} of {}",
+                        desc_idx,
+                        string_count));
+    }
+    for (i, trigger) in map.data.triggers.iter().enumerate() {
+        for action in trigger.used_actions() {
+            for &(what, idx) in &[("string", action.string), ("wav", action.wav_string)] {
+                if idx as usize > string_count {
+                    l.error(format!("trigger {}, action {}: {} refers to string {} of {}",
+                                    i,
+                                    action.action,
+                                    what,
+                                    idx,
+                                    string_count));
+                }
+            }
+        }
+    }
+}
+
+fn check_units(map: &Map, units_dat: &UnitsDat, l: &mut Linter) {
+    let px_w = map.data.width as i32 * 32;
+    let px_h = map.data.height as i32 * 32;
+    let grid = &map.terrain_grid;
+    let mut buildings = Vec::<(usize, (i32, i32, i32, i32))>::new();
+
+    for (i, unit) in map.data.units.iter().enumerate() {
+        let unit_id = unit.unit_id as usize;
+        if unit_id >= units_dat.flingy_id.len() {
+            l.error(format!("unit {}: invalid unit id {}", i, unit_id));
+            continue;
+        }
+        let (x, y) = (unit.x as i32, unit.y as i32);
+        if x >= px_w || y >= px_h {
+            l.error(format!("unit {} (id {}) at ({}, {}) is off the map", i, unit_id, x, y));
+            continue;
+        }
+
+        let player = unit.player_no as usize;
+        if player < 8 {
+            let owner = map.data.owners[player];
+            if owner == OWNER_INACTIVE || owner == OWNER_CLOSED {
+                l.warn(format!("unit {} (id {}) belongs to inactive player {}",
+                               i,
+                               unit_id,
+                               player + 1));
+            }
+        } else if player != 11 {
+            l.warn(format!("unit {} (id {}) belongs to player {}", i, unit_id, player + 1));
+        }
+
+        if unit.unit_id == UNIT_START_LOCATION {
+            continue;
+        }
+        let flags = units_dat.flags(unit_id);
+        if flags.contains(UF_FLYER) || flags.contains(UF_SUBUNIT) {
+            continue;
+        }
+        let bx = unit_box(units_dat, unit_id, x, y);
+        if flags.contains(UF_BUILDING) {
+            // resources are placed on unbuildable terrain all the time
+            if !flags.contains(UF_RESOURCE_CONTAINER) {
+                let mut buildable = true;
+                for ty in bx.1 / 32..(bx.3 + 31) / 32 {
+                    for tx in bx.0 / 32..(bx.2 + 31) / 32 {
+                        buildable = buildable && grid.is_buildable(tx, ty);
+                    }
+                }
+                if !buildable {
+                    l.warn(format!("building {} (id {}) at ({}, {}) is on unbuildable \
+                                    terrain",
+                                   i,
+                                   unit_id,
+                                   x,
+                                   y));
+                }
+            }
+            buildings.push((i, bx));
+        } else if !grid.is_walkable_px(x, y) {
+            l.warn(format!("unit {} (id {}) at ({}, {}) is on unwalkable terrain",
+                           i,
+                           unit_id,
+                           x,
+                           y));
+        }
+    }
+
+    for a in 0..buildings.len() {
+        for b in a + 1..buildings.len() {
+            let (ia, ba) = buildings[a];
+            let (ib, bb) = buildings[b];
+            if ba.0 < bb.2 && bb.0 < ba.2 && ba.1 < bb.3 && bb.1 < ba.3 {
+                l.warn(format!("buildings {} and {} overlap", ia, ib));
+            }
+        }
+    }
+}
+
+fn check_start_locations(map: &Map, l: &mut Linter) {
+    let starts = map.data
+        .units
+        .iter()
+        .filter(|u| u.unit_id == UNIT_START_LOCATION)
+        .map(|u| u.player_no as usize)
+        .collect::<Vec<_>>();
+    if starts.is_empty() {
+        l.error(String::from("map has no start locations"));
+        return;
+    }
+    for player in 0..8 {
+        let owner = map.data.owners[player];
+        let playing = owner == OWNER_COMPUTER_GAME || owner == OWNER_HUMAN ||
+                      owner == OWNER_COMPUTER || owner == OWNER_OPEN;
+        if playing && !starts.contains(&player) {
+            l.error(format!("player {} has no start location", player + 1));
+        }
+    }
+}
+
+pub fn lint(map: &Map, units_dat: &UnitsDat) -> Vec<LintMessage> {
+    let mut l = Linter { messages: Vec::new() };
+    check_sections(map, &mut l);
+    check_tiles(map, &mut l);
+    check_strings(map, &mut l);
+    check_units(map, units_dat, &mut l);
+    check_start_locations(map, &mut l);
+    l.messages
+}
@@ -0,0 +1,642 @@
+//! Units turning into other units: siege tanks switching modes, zerg larvae,
+//! units and buildings morphing through eggs and cocoons, and templar merging
+//! into archons. The entity stays the same, so selections and orders pointing
+//! at it keep working, only its unit id and graphics change. Also terran
+//! buildings lifting off and landing.
+
+use ecs::{World, Entity, EntityData, DataHelper, ModifyData};
+use enum_primitive::FromPrimitive;
+
+use scformats::iscript::AnimationType;
+use scformats::unitsdata::{OrderType, UnitsDat, UF_BUILDING, UF_FLYING_BUILDING,
+                           UF_TWO_UNITS_IN_EGG};
+
+use gamedata::GameData;
+use fixedpoint::px_to_fixed;
+use combat::is_dying;
+use economy::{Race, race, init_unit_economy};
+use construction::{BuildError, is_under_construction, snap_to_grid, can_place,
+                   init_unit_production};
+use tech::init_unit_research;
+use transport::init_unit_transport;
+use cloak::CloakComponent;
+use health::HealthComponent;
+use orders::{Order, OrderTarget, UnitInfo, UnitOrders, find_unit, unit_distance, chase};
+use simulation::remove_entity_rec;
+use subunit::init_subunit;
+use unit_ecs::{UnitComponents, UnitServices, UnitSystems, DatFiles, PlanningMap, SCUnitStep,
+               IScriptEntityAction, IScriptStateElement, SCImageComponent, SCSpriteComponent,
+               SelectableComponent, SCFlingyComponent, OverlayComponent, create_scunit,
+               create_scimage};
+
+// units.dat
+const UNIT_SIEGE_TANK: usize = 5;
+const UNIT_SIEGE_TANK_SIEGED: usize = 30;
+const UNIT_LARVA: usize = 35;
+const UNIT_EGG: usize = 36;
+const UNIT_HYDRALISK: usize = 38;
+const UNIT_MUTALISK: usize = 43;
+const UNIT_GUARDIAN: usize = 44;
+const UNIT_COCOON: usize = 59;
+const UNIT_DARK_TEMPLAR: usize = 61;
+const UNIT_DEVOURER: usize = 62;
+const UNIT_DARK_ARCHON: usize = 63;
+const UNIT_HIGH_TEMPLAR: usize = 67;
+const UNIT_ARCHON: usize = 68;
+const UNIT_LURKER_EGG: usize = 97;
+const UNIT_LURKER: usize = 103;
+const UNIT_HATCHERY: usize = 131;
+const UNIT_LAIR: usize = 132;
+const UNIT_HIVE: usize = 133;
+const UNIT_GREATER_SPIRE: usize = 137;
+const UNIT_SPIRE: usize = 141;
+const UNIT_CREEP_COLONY: usize = 143;
+const UNIT_SPORE_COLONY: usize = 144;
+const UNIT_SUNKEN_COLONY: usize = 146;
+
+// techdata.dat
+const TECH_SIEGE_MODE: usize = 5;
+const TECH_LURKER_ASPECT: usize = 32;
+
+/// images.dat: the first dust cloud of a lifting off or landing building, one
+/// image per offset of the building's LO* file
+const IMAGE_LIFT_OFF_DUST: usize = 355;
+const IMAGE_LANDING_DUST: usize = 350;
+
+/// ticks a siege tank can't move while unsieging
+const UNSIEGE_TICKS: u8 = 24;
+/// ticks between two larvae
+const LARVA_TIME: u16 = 342;
+const MAX_LARVAE: usize = 3;
+/// templar merge when they are this close
+const MERGE_RANGE: i32 = 8;
+
+/// (unit, what it can morph into, what it is in the meantime). Buildings stay
+/// themselves while morphing.
+static MORPHS: [(usize, usize, usize); 8] =
+    [(UNIT_MUTALISK, UNIT_GUARDIAN, UNIT_COCOON),
+     (UNIT_MUTALISK, UNIT_DEVOURER, UNIT_COCOON),
+     (UNIT_HYDRALISK, UNIT_LURKER, UNIT_LURKER_EGG),
+     (UNIT_HATCHERY, UNIT_LAIR, UNIT_HATCHERY),
+     (UNIT_LAIR, UNIT_HIVE, UNIT_LAIR),
+     (UNIT_SPIRE, UNIT_GREATER_SPIRE, UNIT_SPIRE),
+     (UNIT_CREEP_COLONY, UNIT_SUNKEN_COLONY, UNIT_CREEP_COLONY),
+     (UNIT_CREEP_COLONY, UNIT_SPORE_COLONY, UNIT_CREEP_COLONY)];
+
+/// a unit on its way to become another one
+pub struct MorphComponent {
+    pub into: u16,
+    /// ticks morphed so far
+    pub progress: u16,
+    pub morph_time: u16,
+}
+
+/// terran buildings that can fly
+pub struct LiftComponent {
+    pub lifted: bool,
+}
+
+/// hatcheries, lairs and hives
+pub struct LarvaComponent {
+    pub larvae: Vec<Entity>,
+    /// ticks since the last larva
+    pub timer: u16,
+}
+
+/// the unit a unit is while morphing into `into`, None if it can't morph into it
+pub fn morph_shell(units_dat: &UnitsDat, from: usize, into: usize) -> Option<usize> {
+    for &(f, i, shell) in MORPHS.iter() {
+        if f == from && i == into {
+            return Some(shell);
+        }
+    }
+    // larvae turn into the other zerg units
+    let into_morph = MORPHS.iter().any(|&(_, i, shell)| i == into || shell == into);
+    if from == UNIT_LARVA && !into_morph && into != UNIT_LARVA && into != UNIT_EGG &&
+       race(units_dat, into) == Some(Race::Zerg) &&
+       !units_dat.flags(into).contains(UF_BUILDING) {
+        Some(UNIT_EGG)
+    } else {
+        None
+    }
+}
+
+fn is_hatchery(unit_id: usize) -> bool {
+    unit_id == UNIT_HATCHERY || unit_id == UNIT_LAIR || unit_id == UNIT_HIVE
+}
+
+/// adds lift-off to terran buildings and larvae to hatcheries
+pub fn init_unit_morph(world: &mut World<UnitSystems>, gd: &GameData, entity: Entity) {
+    let unit_id = match world.with_entity_data(&entity, |e, data| data.scunit[e].unit_id) {
+        Some(unit_id) => unit_id as usize,
+        None => return,
+    };
+    world.modify_entity(entity,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        if gd.units_dat.flags(unit_id).contains(UF_FLYING_BUILDING) && !data.lift.has(&e) {
+            data.lift.insert(&e, LiftComponent { lifted: false });
+        }
+        if is_hatchery(unit_id) && !data.larva.has(&e) {
+            data.larva.insert(&e,
+                              LarvaComponent {
+                                  larvae: Vec::new(),
+                                  timer: 0,
+                              });
+        }
+    });
+}
+
+pub fn is_lifted(e: EntityData<UnitComponents>,
+                 dh: &UnitComponents)
+                 -> bool {
+    dh.lift.has(&e) && dh.lift[e].lifted
+}
+
+pub fn is_morphing(e: EntityData<UnitComponents>,
+                   dh: &UnitComponents)
+                   -> bool {
+    dh.morph.has(&e)
+}
+
+/// turns a unit into another unit type in place. Hit points and shields keep
+/// their fraction, the orders start over.
+pub fn transform(world: &mut World<UnitSystems>, gd: &GameData, entity: Entity, unit_id: usize) {
+    let children = match world.with_entity_data(&entity, |e, data| {
+        data.iscript_state[e].children.clone()
+    }) {
+        Some(children) => children,
+        None => return,
+    };
+    // shadows and overlays of the old graphics, the new ones create their own
+    for c in children {
+        remove_entity_rec(world, c);
+    }
+    let flingy_id = gd.units_dat.flingy_id[unit_id] as usize;
+    let sprite_id = gd.flingy_dat.sprite_id[flingy_id] as usize;
+    let image_id = gd.sprites_dat.image_id[sprite_id] as usize;
+    let iscript_id = gd.images_dat.iscript_id[image_id];
+    let idle_order = OrderType::from_u8(gd.units_dat.human_ai_idle[unit_id])
+        .unwrap_or(OrderType::Nothing);
+    let flags = gd.units_dat.flags(unit_id);
+    world.modify_entity(entity,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        // parts of the old unit type, the init functions below add the ones
+        // of the new type. Hatcheries keep their larvae when morphing.
+        data.worker.remove(&e);
+        data.resource.remove(&e);
+        data.train_queue.remove(&e);
+        data.research.remove(&e);
+        data.transport.remove(&e);
+        if !flags.contains(UF_FLYING_BUILDING) {
+            data.lift.remove(&e);
+        }
+        if !is_hatchery(unit_id) {
+            data.larva.remove(&e);
+        }
+
+        let mut iscript_state = {
+            let old = &data.iscript_state[e];
+            let mut state = IScriptStateElement::new(&gd.iscript,
+                                                     iscript_id,
+                                                     old.map_pos_x,
+                                                     old.map_pos_y,
+                                                     None);
+            state.set_exact_pos(old.exact_x, old.exact_y);
+            state.direction = old.direction;
+            state.movement_direction = old.movement_direction;
+            state
+        };
+        iscript_state.store_last_pos();
+        data.iscript_state.insert(&e, iscript_state);
+
+        let mut scimage = SCImageComponent::new(gd, image_id as u16);
+        scimage.player_id = data.scimage[e].player_id;
+        data.scimage.insert(&e, scimage);
+        data.scsprite.insert(&e, SCSpriteComponent { sprite_id: sprite_id as u16 });
+        match SelectableComponent::for_sprite(gd, sprite_id) {
+            Some(selectable) => {
+                data.selectable.insert(&e, selectable);
+            }
+            None => {
+                data.selectable.remove(&e);
+            }
+        }
+        data.scflingy.insert(&e, SCFlingyComponent::new(gd, flingy_id));
+
+        {
+            let unit = &mut data.scunit[e];
+            unit.unit_id = unit_id as u16;
+            unit.ground_weapon_id = gd.units_dat.ground_weapon[unit_id] as usize;
+            unit.air_weapon_id = gd.units_dat.air_weapon[unit_id] as usize;
+            unit.used_weapon = unit.ground_weapon_id;
+            unit.weapon_cooldown = 0;
+            unit.attack_target = None;
+            unit.path = None;
+            unit.planned_path = None;
+            unit.orders = UnitOrders::new(idle_order);
+            unit.subunit = None;
+        }
+        let mut health = HealthComponent::new(&gd.units_dat, unit_id);
+        health.keep_fractions(&data.health[e]);
+        data.health.insert(&e, health);
+        data.cloak.insert(&e, CloakComponent::new(&gd.units_dat, unit_id));
+        data.morph.remove(&e);
+    });
+    init_unit_economy(world, gd, entity);
+    init_unit_production(world, gd, entity);
+    init_unit_research(world, gd, entity);
+    init_unit_transport(world, gd, entity);
+    init_unit_morph(world, gd, entity);
+    init_subunit(world, gd, entity);
+}
+
+/// starts morphing a zerg unit or building and pays for it
+pub fn start_morph(world: &mut World<UnitSystems>,
+                   gd: &GameData,
+                   unit: Entity,
+                   into: usize)
+                   -> Result<(), BuildError> {
+    let info = world.with_entity_data(&unit, |e, data| {
+        if is_dying(e, data) || is_morphing(e, data) || is_under_construction(e, data) ||
+           data.train_queue.has(&e) && !data.train_queue[e].queue.is_empty() ||
+           data.research.has(&e) && data.research[e].current.is_some() {
+            None
+        } else {
+            Some((data.scunit[e].unit_id as usize, data.scimage[e].player_id))
+        }
+    });
+    let (unit_id, player) = match info {
+        Some(Some(info)) => info,
+        _ => return Err(BuildError::Busy),
+    };
+    if into >= gd.units_dat.flingy_id.len() {
+        return Err(BuildError::NotAllowed);
+    }
+    let shell = match morph_shell(&gd.units_dat, unit_id, into) {
+        Some(shell) => shell,
+        None => return Err(BuildError::NotAllowed),
+    };
+    if into == UNIT_LURKER && !world.data.services.has_tech(player, TECH_LURKER_ASPECT) {
+        return Err(BuildError::Unavailable);
+    }
+    let (minerals, gas) = (gd.units_dat.mineral_cost[into] as u32,
+                           gd.units_dat.vespene_cost[into] as u32);
+    {
+        let state = &mut world.data.services.players[player];
+        if state.minerals < minerals {
+            return Err(BuildError::NotEnoughMinerals);
+        }
+        if state.gas < gas {
+            return Err(BuildError::NotEnoughGas);
+        }
+        let required = morph_supply(&gd.units_dat, unit_id, into);
+        if required > 0 && state.supply.used[Race::Zerg as usize] + required >
+                           state.supply.provided[Race::Zerg as usize] {
+            return Err(BuildError::NotEnoughSupply);
+        }
+        state.minerals -= minerals;
+        state.gas -= gas;
+    }
+    if shell != unit_id {
+        transform(world, gd, unit, shell);
+    }
+    world.modify_entity(unit,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        data.morph.insert(&e,
+                          MorphComponent {
+                              into: into as u16,
+                              progress: 0,
+                              morph_time: gd.units_dat.build_time[into].max(1),
+                          });
+    });
+    Ok(())
+}
+
+/// additional supply the morphed unit needs, in half units
+fn morph_supply(units_dat: &UnitsDat, from: usize, into: usize) -> u32 {
+    (units_dat.supply_required[into] as u32).saturating_sub(units_dat.supply_required[from] as u32)
+}
+
+/// supply an egg or cocoon already counts for the unit it becomes
+pub fn morphing_supply(e: EntityData<UnitComponents>,
+                       dh: &DataHelper<UnitComponents, UnitServices>,
+                       units_dat: &UnitsDat)
+                       -> Option<(Race, u32)> {
+    if !dh.morph.has(&e) {
+        return None;
+    }
+    let into = dh.morph[e].into as usize;
+    let supply = morph_supply(units_dat, dh.scunit[e].unit_id as usize, into);
+    race(units_dat, into).map(|r| (r, supply))
+}
+
+/// advances a morph by one tick
+pub fn step_morph(e: &EntityData<UnitComponents>,
+                  dh: &mut DataHelper<UnitComponents, UnitServices>,
+                  actions: &mut Vec<IScriptEntityAction>) {
+    if !dh.morph.has(e) || dh.morph[*e].progress >= dh.morph[*e].morph_time {
+        return;
+    }
+    dh.morph[*e].progress += 1;
+    if dh.morph[*e].progress >= dh.morph[*e].morph_time {
+        actions.push(IScriptEntityAction::Morph {
+            unit: ***e,
+            unit_id: dh.morph[*e].into,
+        });
+    }
+}
+
+/// the end of a morph, eggs of e.g. zerglings hatch two units
+pub fn finish_morph(world: &mut World<UnitSystems>, gd: &GameData, unit: Entity, into: usize) {
+    let info = world.with_entity_data(&unit, |e, data| {
+        (data.scunit[e].unit_id as usize,
+         data.scimage[e].player_id,
+         data.iscript_state[e].map_pos_x,
+         data.iscript_state[e].map_pos_y,
+         is_morphing(e, data))
+    });
+    let (shell, player, x, y, morphing) = match info {
+        Some(info) => info,
+        None => return,
+    };
+    transform(world, gd, unit, into);
+    // mode switches like sieging aren't new units
+    if !morphing {
+        return;
+    }
+    world.data.services.players[player].record_built(&gd.units_dat, into);
+    if shell == UNIT_EGG && gd.units_dat.flags(into).contains(UF_TWO_UNITS_IN_EGG) {
+        let width = gd.units_dat.unit_size_right[into] + gd.units_dat.unit_size_left[into];
+        create_scunit(world, gd, into, x + width, y, player);
+        world.data.services.players[player].record_built(&gd.units_dat, into);
+    }
+}
+
+/// Sieging, Unsieging, BuildingLiftOff, BuildingLand, ArchonWarp and
+/// DarkArchonMeld
+pub fn is_morph_order(order_type: OrderType) -> bool {
+    match order_type {
+        OrderType::Sieging | OrderType::Unsieging | OrderType::BuildingLiftOff |
+        OrderType::BuildingLand | OrderType::ArchonWarp | OrderType::DarkArchonMeld => true,
+        _ => false,
+    }
+}
+
+pub fn order_morph(e: &EntityData<UnitComponents>,
+                   dh: &mut DataHelper<UnitComponents, UnitServices>,
+                   map: &PlanningMap,
+                   dat: &DatFiles,
+                   units: &[UnitInfo],
+                   me: &UnitInfo,
+                   order: Order,
+                   actions: &mut Vec<IScriptEntityAction>) {
+    let player = me.player;
+    let state = dh.scunit[*e].orders.state;
+    match (order.order_type, order.target) {
+        (OrderType::Sieging, _) => {
+            SCUnitStep::halt(e, dh);
+            // the siege mode graphics start with the sieging animation
+            if me.unit_id == UNIT_SIEGE_TANK && dh.services.has_tech(player, TECH_SIEGE_MODE) {
+                actions.push(IScriptEntityAction::Morph {
+                    unit: me.entity,
+                    unit_id: UNIT_SIEGE_TANK_SIEGED as u16,
+                });
+            }
+            dh.scunit[*e].orders.finish();
+        }
+        (OrderType::Unsieging, _) => {
+            if me.unit_id != UNIT_SIEGE_TANK_SIEGED {
+                dh.scunit[*e].orders.finish();
+                return;
+            }
+            if state == 0 {
+                dh.iscript_state[*e].next_animation = Some(AnimationType::SpecialState1);
+            }
+            if state < UNSIEGE_TICKS {
+                dh.scunit[*e].orders.state += 1;
+                return;
+            }
+            actions.push(IScriptEntityAction::Morph {
+                unit: me.entity,
+                unit_id: UNIT_SIEGE_TANK as u16,
+            });
+            dh.scunit[*e].orders.finish();
+        }
+        (OrderType::BuildingLiftOff, _) => {
+            let busy = dh.train_queue.has(e) && !dh.train_queue[*e].queue.is_empty() ||
+                       dh.research.has(e) && dh.research[*e].current.is_some();
+            if dh.lift.has(e) && !dh.lift[*e].lifted && !busy && !is_under_construction(*e, dh) {
+                actions.push(IScriptEntityAction::LiftOff { building: me.entity });
+            }
+            dh.scunit[*e].orders.finish();
+        }
+        (OrderType::BuildingLand, OrderTarget::Position(x, y)) => {
+            if !is_lifted(*e, dh) {
+                dh.scunit[*e].orders.finish();
+                return;
+            }
+            let (x, y) = snap_to_grid(&dat.units_dat, me.unit_id, x, y);
+            if state == 0 {
+                SCUnitStep::move_to(e, dh, map, x, y);
+                dh.scunit[*e].orders.state = 1;
+            }
+            if !SCUnitStep::follow_path(e, dh) {
+                actions.push(IScriptEntityAction::Land {
+                    building: me.entity,
+                    x: x as u16,
+                    y: y as u16,
+                });
+                dh.scunit[*e].orders.finish();
+            }
+        }
+        (OrderType::ArchonWarp, OrderTarget::Unit(target)) |
+        (OrderType::DarkArchonMeld, OrderTarget::Unit(target)) => {
+            let (templar, archon) = if order.order_type == OrderType::ArchonWarp {
+                (UNIT_HIGH_TEMPLAR, UNIT_ARCHON)
+            } else {
+                (UNIT_DARK_TEMPLAR, UNIT_DARK_ARCHON)
+            };
+            let partner = match find_unit(units, target) {
+                Some(partner) if me.unit_id == templar && partner.unit_id == templar &&
+                                 partner.player == player &&
+                                 partner.entity != me.entity => partner,
+                _ => {
+                    SCUnitStep::halt(e, dh);
+                    dh.scunit[*e].orders.finish();
+                    return;
+                }
+            };
+            dh.scunit[*e].orders.state = 1;
+            if unit_distance(&dat.units_dat, me, &partner) > MERGE_RANGE {
+                chase(e, dh, map, partner.x, partner.y);
+                return;
+            }
+            SCUnitStep::halt(e, dh);
+            actions.push(IScriptEntityAction::Merge {
+                unit: me.entity,
+                partner: partner.entity,
+                unit_id: archon as u16,
+            });
+        }
+        _ => dh.scunit[*e].orders.finish(),
+    }
+}
+
+/// creates the dust clouds of a lifting off or landing building
+fn create_dust(world: &mut World<UnitSystems>, gd: &GameData, building: Entity, landing: bool) {
+    let info = world.with_entity_data(&building, |e, data| {
+        (data.scimage[e].image_id as usize,
+         data.scimage[e].player_id,
+         data.iscript_state[e].map_pos_x,
+         data.iscript_state[e].map_pos_y)
+    });
+    let (image_id, player, x, y) = match info {
+        Some(info) => info,
+        None => return,
+    };
+    let (lo_id, first_dust) = if landing {
+        (gd.images_dat.landing_dust_overlay[image_id], IMAGE_LANDING_DUST)
+    } else {
+        (gd.images_dat.lift_off_overlay[image_id], IMAGE_LIFT_OFF_DUST)
+    };
+    if lo_id == 0 {
+        return;
+    }
+    let offsets = {
+        let c = gd.lox_cache.borrow();
+        c.get_ro(lo_id).frames.get(0).map_or(Vec::new(), |f| f.offsets.clone())
+    };
+    for (i, &(rel_x, rel_y)) in offsets.iter().enumerate() {
+        let dust = create_scimage(world, gd, first_dust + i, x, y, Some(building), player);
+        world.modify_entity(dust,
+                            |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+            data.iscript_state[e].rel_x = rel_x;
+            data.iscript_state[e].rel_y = rel_y;
+            data.overlay.insert(&e, OverlayComponent {});
+        });
+        world.with_entity_data(&building, |e, data| data.iscript_state[e].children.push(dust));
+    }
+}
+
+pub fn lift_off(world: &mut World<UnitSystems>, gd: &GameData, building: Entity) {
+    let lifted = world.with_entity_data(&building, |e, data| {
+        if !data.lift.has(&e) || data.lift[e].lifted {
+            return false;
+        }
+        data.lift[e].lifted = true;
+        data.iscript_state[e].next_animation = Some(AnimationType::LiftOff);
+        true
+    });
+    if lifted == Some(true) {
+        create_dust(world, gd, building, false);
+    }
+}
+
+/// lands a building at (x, y), returns false if it doesn't fit there
+pub fn land(world: &mut World<UnitSystems>,
+            gd: &GameData,
+            building: Entity,
+            x: i32,
+            y: i32)
+            -> bool {
+    let unit_id = match world.with_entity_data(&building, |e, data| {
+        if is_lifted(e, data) {
+            Some(data.scunit[e].unit_id as usize)
+        } else {
+            None
+        }
+    }) {
+        Some(Some(unit_id)) => unit_id,
+        _ => return false,
+    };
+    if let Err(err) = can_place(world, building, unit_id, x, y) {
+        println!("can't land {} at {}, {}: {:?}", unit_id, x, y, err);
+        return false;
+    }
+    world.with_entity_data(&building, |e, data| {
+        data.lift[e].lifted = false;
+        data.iscript_state[e].next_animation = Some(AnimationType::Landing);
+    });
+    SCUnitStep::move_unit(world, building, px_to_fixed(x), px_to_fixed(y));
+    create_dust(world, gd, building, true);
+    true
+}
+
+/// two templar become one archon
+pub fn merge(world: &mut World<UnitSystems>,
+             gd: &GameData,
+             unit: Entity,
+             partner: Entity,
+             unit_id: usize) {
+    let pos = |world: &mut World<UnitSystems>, entity: Entity| {
+        world.with_entity_data(&entity, |e, data| if is_dying(e, data) {
+                None
+            } else {
+                Some((data.iscript_state[e].exact_x, data.iscript_state[e].exact_y))
+            })
+            .and_then(|p| p)
+    };
+    // both templar ask to merge, the first one wins
+    let (ux, uy) = match pos(world, unit) {
+        Some(p) => p,
+        None => return,
+    };
+    let (px, py) = match pos(world, partner) {
+        Some(p) => p,
+        None => return,
+    };
+    remove_entity_rec(world, partner);
+    world.with_entity_data(&unit, |e, data| {
+        data.iscript_state[e].set_exact_pos((ux + px) / 2, (uy + py) / 2);
+    });
+    transform(world, gd, unit, unit_id);
+}
+
+/// hatcheries grow larvae until there are three of them
+pub fn step_larva(e: &EntityData<UnitComponents>,
+                  dh: &mut DataHelper<UnitComponents, UnitServices>,
+                  actions: &mut Vec<IScriptEntityAction>) {
+    if !dh.larva.has(e) || is_under_construction(*e, dh) {
+        return;
+    }
+    let larvae = dh.larva[*e].larvae.clone();
+    let alive: Vec<Entity> = larvae.into_iter()
+        .filter(|l| {
+            dh.with_entity_data(l, |le, data| {
+                    data.scunit.has(&le) && data.scunit[le].unit_id as usize == UNIT_LARVA &&
+                    !is_dying(le, data)
+                })
+                .unwrap_or(false)
+        })
+        .collect();
+    let count = alive.len();
+    dh.larva[*e].larvae = alive;
+    if count >= MAX_LARVAE {
+        dh.larva[*e].timer = 0;
+        return;
+    }
+    dh.larva[*e].timer += 1;
+    if dh.larva[*e].timer >= LARVA_TIME {
+        dh.larva[*e].timer = 0;
+        actions.push(IScriptEntityAction::SpawnLarva { hatchery: ***e });
+    }
+}
+
+/// creates a larva below a hatchery
+pub fn spawn_larva(world: &mut World<UnitSystems>, gd: &GameData, hatchery: Entity) {
+    let info = world.with_entity_data(&hatchery, |e, data| {
+        (data.scunit[e].unit_id as usize,
+         data.scimage[e].player_id,
+         data.iscript_state[e].map_pos_x,
+         data.iscript_state[e].map_pos_y,
+         data.larva[e].larvae.len() as u16)
+    });
+    let (unit_id, player, x, y, count) = match info {
+        Some(info) => info,
+        None => return,
+    };
+    let x = (x + count * 16).saturating_sub(16);
+    let y = y + gd.units_dat.unit_size_down[unit_id];
+    let larva = create_scunit(world, gd, UNIT_LARVA, x, y, player);
+    world.with_entity_data(&hatchery, |e, data| data.larva[e].larvae.push(larva));
+}
@@ -0,0 +1,692 @@
+//! Unit orders, as defined in orders.dat. Every unit has a current order and
+//! up to 16 queued ones (shift-click). The state machines of the orders run
+//! once per tick from SCUnitStep.
+
+use std::collections::VecDeque;
+
+use ecs::{World, Entity, EntityData, DataHelper};
+use enum_primitive::FromPrimitive;
+
+use scformats::iscript::AnimationType;
+use scformats::unitsdata::{OrderType, UnitsDat, WeaponsDat, WEAPON_NONE, UNIT_START_LOCATION,
+                           UF_FLYER, UF_BUILDING, UF_ORGANIC, UF_MECHANICAL, UF_ROBOTIC,
+                           UF_INVINCIBLE, UF_SUBUNIT, WT_AIR, WT_GROUND, WT_MECHANICAL, WT_ORGANIC,
+                           WT_NON_BUILDING, WT_NON_ROBOTIC, WT_ORGANIC_OR_MECHANICAL};
+
+use fixedpoint::{approx_distance, direction_from_delta, direction_diff, turn_towards,
+                 dir256_to_dir32};
+use combat::is_dying;
+use economy::{order_harvest, is_harvest_order};
+use construction::{order_place_building, order_construct};
+use abilities::{order_cast_spell, is_spell_order, attack_cooldown};
+use cloak::{order_cloak, is_cloak_order, accepts_order};
+use transport::{order_transport, is_transport_order};
+use morph::{order_morph, is_morph_order};
+use unit_ecs::{UnitComponents, UnitServices, UnitSystems, DatFiles, PlanningMap, SCUnitStep,
+               IScriptEntityAction};
+
+pub const MAX_QUEUED_ORDERS: usize = 16;
+
+/// units of the neutral player never attack
+const NEUTRAL_PLAYER: usize = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderTarget {
+    None,
+    Position(i32, i32),
+    Unit(Entity),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Order {
+    pub order_type: OrderType,
+    pub target: OrderTarget,
+    /// building or unit to create, e.g. for build orders
+    pub unit_type: Option<u16>,
+}
+impl Order {
+    pub fn new(order_type: OrderType, target: OrderTarget) -> Self {
+        Order {
+            order_type: order_type,
+            target: target,
+            unit_type: None,
+        }
+    }
+
+    pub fn with_unit_type(order_type: OrderType, unit_type: u16, target: OrderTarget) -> Self {
+        Order {
+            order_type: order_type,
+            target: target,
+            unit_type: Some(unit_type),
+        }
+    }
+}
+
+pub struct UnitOrders {
+    pub current: Order,
+    pub queue: VecDeque<Order>,
+    /// progress of the current order's state machine, 0 when it was just started
+    pub state: u8,
+    /// order the unit falls back to when there is nothing else to do
+    pub idle_order: OrderType,
+    /// patrol: position the unit came from
+    pub patrol_origin: (i32, i32),
+}
+impl UnitOrders {
+    pub fn new(idle_order: OrderType) -> Self {
+        UnitOrders {
+            current: Order::new(idle_order, OrderTarget::None),
+            queue: VecDeque::new(),
+            state: 0,
+            idle_order: idle_order,
+            patrol_origin: (0, 0),
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.current.order_type == self.idle_order
+    }
+
+    fn start(&mut self, order: Order) {
+        self.current = order;
+        self.state = 0;
+    }
+
+    /// the current order is done, continue with the next one
+    pub fn finish(&mut self) {
+        let next = match self.queue.pop_front() {
+            Some(order) => order,
+            None => Order::new(self.idle_order, OrderTarget::None),
+        };
+        self.start(next);
+    }
+
+    /// drops all orders, the death animation is playing
+    pub fn die(&mut self) {
+        self.queue.clear();
+        self.start(Order::new(OrderType::Die, OrderTarget::None));
+    }
+
+    /// continues with `order` instead of the current one
+    pub fn replace(&mut self, order: Order) {
+        self.start(order);
+    }
+
+    /// runs `order` now, the current one continues afterwards
+    pub fn interrupt_with(&mut self, order: Order) {
+        let current = self.current;
+        self.queue.push_front(current);
+        self.start(order);
+    }
+
+    /// returns false if the order was dropped
+    pub fn issue(&mut self, dat: &DatFiles, order: Order, queued: bool) -> bool {
+        let new_idx = order.order_type as usize;
+        let cur_idx = self.current.order_type as usize;
+        if queued && !self.is_idle() {
+            if dat.orders_dat.can_be_queued[new_idx] == 0 ||
+               self.queue.len() >= MAX_QUEUED_ORDERS {
+                return false;
+            }
+            self.queue.push_back(order);
+        } else if dat.orders_dat.can_be_interrupted[cur_idx] == 0 &&
+                  self.current.order_type != OrderType::Die {
+            // runs as soon as the current order is done
+            self.queue.clear();
+            self.queue.push_back(order);
+        } else if self.current.order_type == OrderType::Die {
+            return false;
+        } else {
+            self.queue.clear();
+            self.start(order);
+        }
+        true
+    }
+}
+
+/// what the order system needs to know about the other units
+#[derive(Clone, Copy, Debug)]
+pub struct UnitInfo {
+    pub entity: Entity,
+    pub unit_id: usize,
+    pub player: usize,
+    pub x: i32,
+    pub y: i32,
+    /// players that can't see the unit because it is cloaked or burrowed
+    pub hidden_from: u16,
+    /// a terran building in the air
+    pub lifted: bool,
+}
+
+/// distance between the edges of two units
+pub fn unit_distance(units_dat: &UnitsDat, a: &UnitInfo, b: &UnitInfo) -> i32 {
+    let a_left = a.x - units_dat.unit_size_left[a.unit_id] as i32;
+    let a_right = a.x + units_dat.unit_size_right[a.unit_id] as i32;
+    let a_top = a.y - units_dat.unit_size_up[a.unit_id] as i32;
+    let a_bottom = a.y + units_dat.unit_size_down[a.unit_id] as i32;
+    let b_left = b.x - units_dat.unit_size_left[b.unit_id] as i32;
+    let b_right = b.x + units_dat.unit_size_right[b.unit_id] as i32;
+    let b_top = b.y - units_dat.unit_size_up[b.unit_id] as i32;
+    let b_bottom = b.y + units_dat.unit_size_down[b.unit_id] as i32;
+    let dx = (b_left - a_right).max(a_left - b_right).max(0);
+    let dy = (b_top - a_bottom).max(a_top - b_bottom).max(0);
+    approx_distance(dx, dy)
+}
+
+/// weapon `attacker_id` would use against `target_id`, if any
+pub fn attack_weapon(units_dat: &UnitsDat,
+                     weapons_dat: &WeaponsDat,
+                     attacker_id: usize,
+                     target_id: usize)
+                     -> Option<usize> {
+    let target_flags = units_dat.flags(target_id);
+    let air = target_flags.contains(UF_FLYER);
+    let weapon = if air {
+        units_dat.air_weapon[attacker_id]
+    } else {
+        units_dat.ground_weapon[attacker_id]
+    };
+    if weapon == WEAPON_NONE {
+        return None;
+    }
+    let weapon = weapon as usize;
+    if can_target(weapons_dat, weapon, units_dat, target_id) {
+        Some(weapon)
+    } else {
+        None
+    }
+}
+
+/// checks the weapons.dat target flags
+pub fn can_target(weapons_dat: &WeaponsDat,
+                  weapon: usize,
+                  units_dat: &UnitsDat,
+                  target_id: usize)
+                  -> bool {
+    let flags = weapons_dat.target_flags(weapon);
+    let target = units_dat.flags(target_id);
+    let organic = target.contains(UF_ORGANIC);
+    let mechanical = target.contains(UF_MECHANICAL);
+    if target.contains(UF_FLYER) {
+        if !flags.contains(WT_AIR) {
+            return false;
+        }
+    } else if !flags.contains(WT_GROUND) {
+        return false;
+    }
+    !(flags.contains(WT_MECHANICAL) && !mechanical) &&
+    !(flags.contains(WT_ORGANIC) && !organic) &&
+    !(flags.contains(WT_NON_BUILDING) && target.contains(UF_BUILDING)) &&
+    !(flags.contains(WT_NON_ROBOTIC) && target.contains(UF_ROBOTIC)) &&
+    !(flags.contains(WT_ORGANIC_OR_MECHANICAL) && !organic && !mechanical)
+}
+
+/// false for cloaked or burrowed units that aren't detected
+pub fn can_see(me: &UnitInfo, other: &UnitInfo) -> bool {
+    other.hidden_from & (1 << me.player) == 0
+}
+
+pub fn is_enemy(units_dat: &UnitsDat, me: &UnitInfo, other: &UnitInfo) -> bool {
+    // FIXME: alliances
+    me.player < 8 && other.player < 8 && me.player != other.player && can_see(me, other) &&
+    other.unit_id != UNIT_START_LOCATION as usize &&
+    !units_dat.flags(other.unit_id).contains(UF_INVINCIBLE) &&
+    !units_dat.flags(other.unit_id).contains(UF_SUBUNIT)
+}
+
+/// closest enemy within `range` pixels that can be attacked
+fn find_target(dat: &DatFiles, me: &UnitInfo, units: &[UnitInfo], range: i32) -> Option<UnitInfo> {
+    let mut best = None;
+    let mut best_dist = range + 1;
+    for other in units {
+        if !is_enemy(&dat.units_dat, me, other) ||
+           attack_weapon(&dat.units_dat, &dat.weapons_dat, me.unit_id, other.unit_id).is_none() {
+            continue;
+        }
+        let dist = unit_distance(&dat.units_dat, me, other);
+        if dist < best_dist {
+            best = Some(*other);
+            best_dist = dist;
+        }
+    }
+    best
+}
+
+pub fn find_unit(units: &[UnitInfo], entity: Entity) -> Option<UnitInfo> {
+    units.iter().find(|u| u.entity == entity).cloned()
+}
+
+fn acquisition_range(dat: &DatFiles, unit_id: usize) -> i32 {
+    dat.units_dat.target_acquisition_range[unit_id] as i32 * 32
+}
+
+/// gives an order to a unit (i.e. a player command), returns false if the
+/// unit can't execute it
+pub fn issue_order(world: &mut World<UnitSystems>,
+                   entity: Entity,
+                   order: Order,
+                   queued: bool)
+                   -> bool {
+    let dat = world.data.services.dat();
+    if let OrderTarget::Unit(target) = order.target {
+        let target_id = world.with_entity_data(&target, |e, data| {
+            if data.scunit.has(&e) && !is_dying(e, data) {
+                Some(data.scunit[e].unit_id as usize)
+            } else {
+                None
+            }
+        });
+        let target_id = match target_id {
+            Some(Some(target_id)) => target_id,
+            _ => return false,
+        };
+        let order_idx = order.order_type as usize;
+        let weapon = dat.orders_dat.targeting[order_idx];
+        if dat.orders_dat.use_weapon_targeting[order_idx] != 0 && weapon != WEAPON_NONE &&
+           !can_target(&dat.weapons_dat, weapon as usize, &dat.units_dat, target_id) {
+            return false;
+        }
+    }
+    world.with_entity_data(&entity, |e, data| {
+            if !data.scunit.has(&e) || is_dying(e, data) || !accepts_order(e, data, &order) {
+                return false;
+            }
+            data.scunit[e].orders.issue(&dat, order, queued)
+        })
+        .unwrap_or(false)
+}
+
+/// turns towards (tx, ty), returns true when facing it within `angle`
+pub fn face(e: &EntityData<UnitComponents>,
+        dh: &mut DataHelper<UnitComponents, UnitServices>,
+        tx: i32,
+        ty: i32,
+        angle: u8)
+        -> bool {
+    let x = dh.iscript_state[*e].map_pos_x as i32;
+    let y = dh.iscript_state[*e].map_pos_y as i32;
+    let target_dir = direction_from_delta(tx - x, ty - y);
+    let facing = turn_towards(dh.iscript_state[*e].movement_direction,
+                              target_dir,
+                              dh.scflingy[*e].turn_rate);
+    dh.iscript_state[*e].movement_direction = facing;
+    dh.iscript_state[*e].direction = dir256_to_dir32(facing);
+    direction_diff(facing, target_dir).abs() <= angle as i32
+}
+
+/// shoots at a target in range if the weapon is ready
+fn fire_at(e: &EntityData<UnitComponents>,
+           dh: &mut DataHelper<UnitComponents, UnitServices>,
+           dat: &DatFiles,
+           target: &UnitInfo,
+           weapon: usize) {
+    // the turret shoots instead
+    if dh.scunit[*e].subunit.is_some() {
+        return;
+    }
+    let angle = dat.weapons_dat.attack_angle[weapon];
+    if !face(e, dh, target.x, target.y, angle) || dh.scunit[*e].weapon_cooldown > 0 {
+        return;
+    }
+    let air = dat.units_dat.flags(target.unit_id).contains(UF_FLYER);
+    // the first shot plays the init animation, the following ones repeat
+    let anim = match (air, dh.scunit[*e].orders.state >= 3) {
+        (false, false) => AnimationType::GndAttkInit,
+        (false, true) => AnimationType::GndAttkRpt,
+        (true, false) => AnimationType::AirAttkInit,
+        (true, true) => AnimationType::AirAttkRpt,
+    };
+    dh.iscript_state[*e].next_animation = Some(anim);
+    dh.scunit[*e].used_weapon = weapon;
+    dh.scunit[*e].attack_target = Some(target.entity);
+    dh.scunit[*e].weapon_cooldown = attack_cooldown(*e, dh, dat.weapons_dat.cooldown[weapon]);
+    dh.scunit[*e].orders.state = 3;
+}
+
+fn stop_attacking(e: &EntityData<UnitComponents>,
+                  dh: &mut DataHelper<UnitComponents, UnitServices>) {
+    if dh.scunit[*e].orders.state >= 3 {
+        let anim = if dh.scunit[*e].used_weapon == dh.scunit[*e].air_weapon_id {
+            AnimationType::AirAttkToIdle
+        } else {
+            AnimationType::GndAttkToIdle
+        };
+        dh.iscript_state[*e].next_animation = Some(anim);
+    }
+}
+
+/// keeps moving towards (tx, ty), replanning if it moved away from the path goal
+pub fn chase(e: &EntityData<UnitComponents>,
+         dh: &mut DataHelper<UnitComponents, UnitServices>,
+         map: &PlanningMap,
+         tx: i32,
+         ty: i32) {
+    let replan = match dh.scunit[*e].path {
+        Some(ref p) => {
+            match p.goal() {
+                Some(goal) => approx_distance(goal.x() - tx, goal.y() - ty) > 32,
+                None => true,
+            }
+        }
+        None => true,
+    };
+    if replan {
+        SCUnitStep::move_to(e, dh, map, tx, ty);
+    }
+    SCUnitStep::follow_path(e, dh);
+}
+
+fn order_move(e: &EntityData<UnitComponents>,
+              dh: &mut DataHelper<UnitComponents, UnitServices>,
+              map: &PlanningMap,
+              units: &[UnitInfo],
+              order: Order) {
+    if dh.scunit[*e].orders.state == 0 {
+        let goal = match order.target {
+            OrderTarget::Position(x, y) => Some((x, y)),
+            OrderTarget::Unit(target) => find_unit(units, target).map(|u| (u.x, u.y)),
+            OrderTarget::None => None,
+        };
+        match goal {
+            Some((x, y)) => SCUnitStep::move_to(e, dh, map, x, y),
+            None => {
+                dh.scunit[*e].orders.finish();
+                return;
+            }
+        }
+        dh.scunit[*e].orders.state = 1;
+    }
+    if !SCUnitStep::follow_path(e, dh) {
+        dh.scunit[*e].orders.finish();
+    }
+}
+
+fn order_follow(e: &EntityData<UnitComponents>,
+                dh: &mut DataHelper<UnitComponents, UnitServices>,
+                map: &PlanningMap,
+                units: &[UnitInfo],
+                me: &UnitInfo,
+                order: Order) {
+    dh.scunit[*e].orders.state = 1;
+    let target = match order.target {
+        OrderTarget::Unit(target) => find_unit(units, target),
+        _ => None,
+    };
+    match target {
+        Some(target) => {
+            if approx_distance(target.x - me.x, target.y - me.y) > 48 {
+                chase(e, dh, map, target.x, target.y);
+            } else {
+                SCUnitStep::halt(e, dh);
+            }
+        }
+        None => {
+            SCUnitStep::halt(e, dh);
+            dh.scunit[*e].orders.finish();
+        }
+    }
+}
+
+fn order_patrol(e: &EntityData<UnitComponents>,
+                dh: &mut DataHelper<UnitComponents, UnitServices>,
+                map: &PlanningMap,
+                dat: &DatFiles,
+                units: &[UnitInfo],
+                me: &UnitInfo,
+                order: Order) {
+    let (tx, ty) = match order.target {
+        OrderTarget::Position(x, y) => (x, y),
+        _ => {
+            dh.scunit[*e].orders.finish();
+            return;
+        }
+    };
+    if dh.scunit[*e].orders.state == 0 {
+        dh.scunit[*e].orders.patrol_origin = (me.x, me.y);
+        SCUnitStep::move_to(e, dh, map, tx, ty);
+        dh.scunit[*e].orders.state = 1;
+    }
+    if let Some(target) = find_target(dat, me, units, acquisition_range(dat, me.unit_id)) {
+        dh.scunit[*e].orders.interrupt_with(Order::new(OrderType::AttackUnit,
+                                                       OrderTarget::Unit(target.entity)));
+        return;
+    }
+    if !SCUnitStep::follow_path(e, dh) {
+        // turn around
+        let origin = dh.scunit[*e].orders.patrol_origin;
+        dh.scunit[*e].orders.patrol_origin = (tx, ty);
+        dh.scunit[*e].orders.current.target = OrderTarget::Position(origin.0, origin.1);
+        SCUnitStep::move_to(e, dh, map, origin.0, origin.1);
+    }
+}
+
+fn order_attack_move(e: &EntityData<UnitComponents>,
+                     dh: &mut DataHelper<UnitComponents, UnitServices>,
+                     map: &PlanningMap,
+                     dat: &DatFiles,
+                     units: &[UnitInfo],
+                     me: &UnitInfo,
+                     order: Order) {
+    let (tx, ty) = match order.target {
+        OrderTarget::Position(x, y) => (x, y),
+        _ => {
+            dh.scunit[*e].orders.finish();
+            return;
+        }
+    };
+    if dh.scunit[*e].orders.state == 0 {
+        SCUnitStep::move_to(e, dh, map, tx, ty);
+        dh.scunit[*e].orders.state = 1;
+    }
+    if let Some(target) = find_target(dat, me, units, acquisition_range(dat, me.unit_id)) {
+        dh.scunit[*e].orders.interrupt_with(Order::new(OrderType::AttackUnit,
+                                                       OrderTarget::Unit(target.entity)));
+        return;
+    }
+    if !SCUnitStep::follow_path(e, dh) {
+        dh.scunit[*e].orders.finish();
+    }
+}
+
+/// states: 1 approaching, 2 in range, 3 attacking
+fn order_attack_unit(e: &EntityData<UnitComponents>,
+                     dh: &mut DataHelper<UnitComponents, UnitServices>,
+                     map: &PlanningMap,
+                     dat: &DatFiles,
+                     units: &[UnitInfo],
+                     me: &UnitInfo,
+                     order: Order) {
+    if dh.scunit[*e].orders.state == 0 {
+        dh.scunit[*e].orders.state = 1;
+    }
+    let target = match order.target {
+        OrderTarget::Unit(target) => find_unit(units, target),
+        _ => None,
+    };
+    let (target, weapon) = match target {
+        Some(target) if can_see(me, &target) => {
+            match attack_weapon(&dat.units_dat, &dat.weapons_dat, me.unit_id, target.unit_id) {
+                Some(weapon) => (target, weapon),
+                None => {
+                    stop_attacking(e, dh);
+                    dh.scunit[*e].orders.finish();
+                    return;
+                }
+            }
+        }
+        _ => {
+            // target is dead or can't be seen anymore
+            stop_attacking(e, dh);
+            SCUnitStep::halt(e, dh);
+            dh.scunit[*e].orders.finish();
+            return;
+        }
+    };
+
+    let dist = unit_distance(&dat.units_dat, me, &target);
+    let max_range = dat.weapons_dat.maximum_range[weapon] as i32;
+    if dist < dat.weapons_dat.minimum_range[weapon] as i32 {
+        // e.g. sieged tanks, find something else
+        stop_attacking(e, dh);
+        dh.scunit[*e].orders.finish();
+    } else if dist > max_range {
+        stop_attacking(e, dh);
+        dh.scunit[*e].orders.state = 1;
+        if !dat.units_dat.flags(me.unit_id).contains(UF_SUBUNIT) {
+            chase(e, dh, map, target.x, target.y);
+        } else if order.order_type == OrderType::TurretAttack {
+            // the base moves into range, keep aiming
+            face(e, dh, target.x, target.y, 0);
+        } else {
+            dh.scunit[*e].orders.finish();
+        }
+    } else {
+        if dh.scunit[*e].orders.state == 1 {
+            SCUnitStep::halt(e, dh);
+            dh.scunit[*e].orders.state = 2;
+        }
+        fire_at(e, dh, dat, &target, weapon);
+    }
+}
+
+/// attacks enemies in weapon range without moving
+fn order_hold(e: &EntityData<UnitComponents>,
+              dh: &mut DataHelper<UnitComponents, UnitServices>,
+              dat: &DatFiles,
+              units: &[UnitInfo],
+              me: &UnitInfo) {
+    if dh.scunit[*e].orders.state == 0 {
+        SCUnitStep::halt(e, dh);
+        dh.scunit[*e].orders.state = 1;
+    }
+    let mut target = None;
+    for other in units {
+        if !is_enemy(&dat.units_dat, me, other) {
+            continue;
+        }
+        if let Some(weapon) = attack_weapon(&dat.units_dat,
+                                            &dat.weapons_dat,
+                                            me.unit_id,
+                                            other.unit_id) {
+            if unit_distance(&dat.units_dat, me, other) <=
+               dat.weapons_dat.maximum_range[weapon] as i32 {
+                target = Some((*other, weapon));
+                break;
+            }
+        }
+    }
+    match target {
+        Some((target, weapon)) => fire_at(e, dh, dat, &target, weapon),
+        None => {
+            stop_attacking(e, dh);
+            dh.scunit[*e].orders.state = 1;
+        }
+    }
+}
+
+/// idle, but attacks enemies that come close
+fn order_guard(e: &EntityData<UnitComponents>,
+               dh: &mut DataHelper<UnitComponents, UnitServices>,
+               dat: &DatFiles,
+               units: &[UnitInfo],
+               me: &UnitInfo) {
+    if dh.scunit[*e].orders.state == 0 {
+        SCUnitStep::halt(e, dh);
+        dh.scunit[*e].orders.state = 1;
+    }
+    if !dh.scunit[*e].orders.queue.is_empty() {
+        dh.scunit[*e].orders.finish();
+        return;
+    }
+    if me.player == NEUTRAL_PLAYER {
+        return;
+    }
+    if let Some(target) = find_target(dat, me, units, acquisition_range(dat, me.unit_id)) {
+        dh.scunit[*e].orders.start(Order::new(OrderType::AttackUnit,
+                                              OrderTarget::Unit(target.entity)));
+    }
+}
+
+/// plays the orders.dat animation of a newly started order
+fn start_order(e: &EntityData<UnitComponents>,
+               dh: &mut DataHelper<UnitComponents, UnitServices>,
+               dat: &DatFiles,
+               order: Order) {
+    let anim = dat.orders_dat.animation[order.order_type as usize];
+    if anim == AnimationType::Init as u8 {
+        return;
+    }
+    if let Some(anim) = AnimationType::from_u8(anim) {
+        dh.iscript_state[*e].next_animation = Some(anim);
+    }
+}
+
+/// runs the state machine of the unit's current order for one tick
+pub fn process_orders(e: &EntityData<UnitComponents>,
+                      dh: &mut DataHelper<UnitComponents, UnitServices>,
+                      map: &PlanningMap,
+                      dat: &DatFiles,
+                      units: &[UnitInfo],
+                      actions: &mut Vec<IScriptEntityAction>) {
+    let me = match find_unit(units, ***e) {
+        Some(me) => me,
+        None => return,
+    };
+    if dh.scunit[*e].weapon_cooldown > 0 {
+        dh.scunit[*e].weapon_cooldown -= 1;
+    }
+
+    let order = dh.scunit[*e].orders.current;
+    if dh.scunit[*e].orders.state == 0 {
+        start_order(e, dh, dat, order);
+    }
+    match order.order_type {
+        OrderType::Die => {}
+        OrderType::Stop | OrderType::ReaverStop | OrderType::CarrierStop => {
+            SCUnitStep::halt(e, dh);
+            dh.scunit[*e].orders.finish();
+        }
+        OrderType::Move | OrderType::ReaverCarrierMove => order_move(e, dh, map, units, order),
+        OrderType::Follow => order_follow(e, dh, map, units, &me, order),
+        OrderType::Patrol => order_patrol(e, dh, map, dat, units, &me, order),
+        OrderType::AttackMove => order_attack_move(e, dh, map, dat, units, &me, order),
+        OrderType::AttackUnit | OrderType::Attack1 | OrderType::Attack2 |
+        OrderType::TowerAttack | OrderType::TurretAttack => {
+            order_attack_unit(e, dh, map, dat, units, &me, order)
+        }
+        OrderType::HoldPosition | OrderType::CarrierHoldPosition |
+        OrderType::ReaverHoldPosition | OrderType::QueenHoldPosition |
+        OrderType::SuicideHoldPosition | OrderType::MedicHoldPosition => {
+            order_hold(e, dh, dat, units, &me)
+        }
+        OrderType::Guard | OrderType::PlayerGuard | OrderType::TowerGuard |
+        OrderType::TurretGuard | OrderType::BunkerGuard => order_guard(e, dh, dat, units, &me),
+        harvest if is_harvest_order(harvest) => order_harvest(e, dh, map, dat, units, &me, order),
+        OrderType::PlaceBuilding | OrderType::PlaceProtossBuilding |
+        OrderType::DroneStartBuild => order_place_building(e, dh, map, &me, order, actions),
+        OrderType::ConstructingBuilding => order_construct(e, dh, units, &me, order),
+        spell if is_spell_order(spell) => order_cast_spell(e, dh, map, dat, units, &me, order),
+        cloak if is_cloak_order(cloak) => order_cloak(e, dh, dat, order),
+        transport if is_transport_order(transport) => {
+            order_transport(e, dh, map, dat, units, &me, order, actions)
+        }
+        morph if is_morph_order(morph) => order_morph(e, dh, map, dat, units, &me, order, actions),
+        OrderType::Nothing => {
+            if !dh.scunit[*e].orders.queue.is_empty() {
+                dh.scunit[*e].orders.finish();
+            }
+        }
+        _ => {
+            if dh.scunit[*e].orders.is_idle() {
+                // e.g. critters, just stay idle
+                if !dh.scunit[*e].orders.queue.is_empty() {
+                    dh.scunit[*e].orders.finish();
+                }
+            } else {
+                // FIXME: orders without a state machine yet act like Stop
+                SCUnitStep::halt(e, dh);
+                dh.scunit[*e].orders.finish();
+            }
+        }
+    }
+}
@@ -143,3 +143,11 @@ render_function!(render_buffer_with_solid_reindexing,
                          *ob = *reindex.get_unchecked(col as usize - 1);
                      }
                  }; reindex: &[u8]);
+// cloaked units: the background shows through, shifted a few pixels
+render_function!(render_buffer_distortion, |col: u8, buffer: &mut [u8], outpos: usize| {
+    if col > 0 {
+        let src = (outpos + 1 + (col as usize & 3)).min(buffer.len() - 1);
+        let shifted = *buffer.get_unchecked(src);
+        *buffer.get_unchecked_mut(outpos) = shifted;
+    }
+};);
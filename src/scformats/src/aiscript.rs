@@ -0,0 +1,421 @@
+use std::io;
+use std::io::Read;
+
+use byteorder::{LittleEndian, ByteOrder};
+
+// AI scripts, scripts\aiscript.bin and scripts\bwscript.bin.
+// Both files start with a u32 offset to a table of 16 byte script headers:
+// u32: Script ID, four characters, e.g. "TMCx"
+// u32: Offset of the script in the file. 0 in aiscript.bin for scripts that
+// are in bwscript.bin.
+// u32: stat_txt.tbl string of the script name (1 based)
+// u32: Flags
+// Bit 0 - The script needs a location
+// Bit 1 - Hidden in StarEdit
+// Bit 2 - Brood War only
+// The scripts are byte code, one opcode byte followed by its parameters.
+// Jump targets ("blocks") are u16 offsets into the same file.
+
+pub const AI_FLAG_NEEDS_LOCATION: u32 = 0x1;
+pub const AI_FLAG_HIDDEN: u32 = 0x2;
+pub const AI_FLAG_BROODWAR: u32 = 0x4;
+
+const HEADER_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIScriptFile {
+    AIScript,
+    BWScript,
+}
+
+/// a position in one of the script files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AIScriptPos {
+    pub file: AIScriptFile,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct AIScriptHeader {
+    pub id: u32,
+    pub entry: AIScriptPos,
+    pub string_idx: u32,
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIParam {
+    Byte,
+    Word,
+    DWord,
+    /// u16 jump target
+    Block,
+    /// u16 units.dat id
+    Unit,
+    /// u16 upgrades.dat id
+    Upgrade,
+    /// u16 techdata.dat id
+    Tech,
+    /// zero terminated string
+    Text,
+}
+
+/// a decoded opcode with its parameters; strings aren't in `args`
+#[derive(Debug, Clone)]
+pub struct AICommand {
+    pub opcode: u8,
+    pub args: Vec<u32>,
+    pub text: Option<String>,
+    /// position of the next command
+    pub next: usize,
+}
+
+pub const AI_GOTO: u8 = 0x00;
+pub const AI_NOTOWNS_JUMP: u8 = 0x01;
+pub const AI_WAIT: u8 = 0x02;
+pub const AI_START_TOWN: u8 = 0x03;
+pub const AI_START_AREATOWN: u8 = 0x04;
+pub const AI_EXPAND: u8 = 0x05;
+pub const AI_BUILD: u8 = 0x06;
+pub const AI_UPGRADE: u8 = 0x07;
+pub const AI_TECH: u8 = 0x08;
+pub const AI_WAIT_BUILD: u8 = 0x09;
+pub const AI_WAIT_BUILDSTART: u8 = 0x0a;
+pub const AI_ATTACK_CLEAR: u8 = 0x0b;
+pub const AI_ATTACK_ADD: u8 = 0x0c;
+pub const AI_ATTACK_PREPARE: u8 = 0x0d;
+pub const AI_ATTACK_DO: u8 = 0x0e;
+pub const AI_WAIT_SECURE: u8 = 0x0f;
+pub const AI_CAPT_EXPAND: u8 = 0x10;
+pub const AI_BUILD_BUNKERS: u8 = 0x11;
+pub const AI_WAIT_BUNKERS: u8 = 0x12;
+pub const AI_DEFENSEBUILD_GG: u8 = 0x13;
+pub const AI_DEFENSEBUILD_AG: u8 = 0x14;
+pub const AI_DEFENSEBUILD_GA: u8 = 0x15;
+pub const AI_DEFENSEBUILD_AA: u8 = 0x16;
+pub const AI_DEFENSEUSE_GG: u8 = 0x17;
+pub const AI_DEFENSEUSE_AG: u8 = 0x18;
+pub const AI_DEFENSEUSE_GA: u8 = 0x19;
+pub const AI_DEFENSEUSE_AA: u8 = 0x1a;
+pub const AI_DEFENSECLEAR_GG: u8 = 0x1b;
+pub const AI_DEFENSECLEAR_AA: u8 = 0x1c;
+pub const AI_SEND_SUICIDE: u8 = 0x1d;
+pub const AI_PLAYER_ENEMY: u8 = 0x1e;
+pub const AI_PLAYER_ALLY: u8 = 0x1f;
+pub const AI_DEFAULT_MIN: u8 = 0x20;
+pub const AI_DEFAULTBUILD_OFF: u8 = 0x21;
+pub const AI_STOP: u8 = 0x22;
+pub const AI_SWITCH_RESCUE: u8 = 0x23;
+pub const AI_MOVE_DT: u8 = 0x24;
+pub const AI_DEBUG: u8 = 0x25;
+pub const AI_FATAL_ERROR: u8 = 0x26;
+pub const AI_ENTER_BUNKER: u8 = 0x27;
+pub const AI_VALUE_AREA: u8 = 0x28;
+pub const AI_TRANSPORTS_OFF: u8 = 0x29;
+pub const AI_CHECK_TRANSPORTS: u8 = 0x2a;
+pub const AI_NUKE_RATE: u8 = 0x2b;
+pub const AI_MAX_FORCE: u8 = 0x2c;
+pub const AI_CLEAR_COMBATDATA: u8 = 0x2d;
+pub const AI_RANDOM_JUMP: u8 = 0x2e;
+pub const AI_TIME_JUMP: u8 = 0x2f;
+pub const AI_FARMS_NOTIMING: u8 = 0x30;
+pub const AI_FARMS_TIMING: u8 = 0x31;
+pub const AI_BUILD_TURRETS: u8 = 0x32;
+pub const AI_WAIT_TURRETS: u8 = 0x33;
+pub const AI_DEFAULT_BUILD: u8 = 0x34;
+pub const AI_HARASS_FACTOR: u8 = 0x35;
+pub const AI_START_CAMPAIGN: u8 = 0x36;
+pub const AI_RACE_JUMP: u8 = 0x37;
+pub const AI_REGION_SIZE: u8 = 0x38;
+pub const AI_GET_OLDPEONS: u8 = 0x39;
+pub const AI_GROUNDMAP_JUMP: u8 = 0x3a;
+pub const AI_PLACE_GUARD: u8 = 0x3b;
+pub const AI_WAIT_FORCE: u8 = 0x3c;
+pub const AI_GUARD_RESOURCES: u8 = 0x3d;
+pub const AI_CALL: u8 = 0x3e;
+pub const AI_RETURN: u8 = 0x3f;
+pub const AI_EVAL_HARASS: u8 = 0x40;
+pub const AI_CREEP: u8 = 0x41;
+pub const AI_PANIC: u8 = 0x42;
+pub const AI_PLAYER_NEED: u8 = 0x43;
+pub const AI_DO_MORPH: u8 = 0x44;
+pub const AI_WAIT_UPGRADES: u8 = 0x45;
+pub const AI_MULTIRUN: u8 = 0x46;
+pub const AI_RUSH: u8 = 0x47;
+pub const AI_SCOUT_WITH: u8 = 0x48;
+pub const AI_DEFINE_MAX: u8 = 0x49;
+pub const AI_TRAIN: u8 = 0x4a;
+pub const AI_TARGET_EXPANSION: u8 = 0x4b;
+pub const AI_WAIT_TRAIN: u8 = 0x4c;
+pub const AI_SET_ATTACKS: u8 = 0x4d;
+pub const AI_SET_GENCMD: u8 = 0x4e;
+pub const AI_MAKE_PATROL: u8 = 0x4f;
+pub const AI_GIVE_MONEY: u8 = 0x50;
+pub const AI_PREP_DOWN: u8 = 0x51;
+pub const AI_RESOURCES_JUMP: u8 = 0x52;
+pub const AI_ENTER_TRANSPORT: u8 = 0x53;
+pub const AI_EXIT_TRANSPORT: u8 = 0x54;
+pub const AI_SHAREDVISION_ON: u8 = 0x55;
+pub const AI_SHAREDVISION_OFF: u8 = 0x56;
+pub const AI_NUKE_LOCATION: u8 = 0x57;
+pub const AI_HARASS_LOCATION: u8 = 0x58;
+pub const AI_IMPLODE: u8 = 0x59;
+pub const AI_GUARD_ALL: u8 = 0x5a;
+pub const AI_ENEMYOWNS_JUMP: u8 = 0x5b;
+pub const AI_ENEMYRESOURCES_JUMP: u8 = 0x5c;
+pub const AI_IF_DIF: u8 = 0x5d;
+pub const AI_EASY_ATTACK: u8 = 0x5e;
+pub const AI_KILL_THREAD: u8 = 0x5f;
+pub const AI_KILLABLE: u8 = 0x60;
+pub const AI_WAIT_FINISHATTACK: u8 = 0x61;
+pub const AI_QUICK_ATTACK: u8 = 0x62;
+pub const AI_JUNKYARD_DOG: u8 = 0x63;
+pub const AI_FAKE_NUKE: u8 = 0x64;
+pub const AI_DISRUPTION_WEB: u8 = 0x65;
+pub const AI_RECALL_LOCATION: u8 = 0x66;
+pub const AI_SET_RANDOMSEED: u8 = 0x67;
+pub const AI_IF_OWNED: u8 = 0x68;
+pub const AI_CREATE_NUKE: u8 = 0x69;
+pub const AI_CREATE_UNIT: u8 = 0x6a;
+pub const AI_NUKE_POS: u8 = 0x6b;
+pub const AI_HELP_IFTROUBLE: u8 = 0x6c;
+pub const AI_ALLIES_WATCH: u8 = 0x6d;
+pub const AI_TRY_TOWNPOINT: u8 = 0x6e;
+
+use self::AIParam::*;
+
+/// name and parameters of every opcode
+pub const AI_OPCODES: [(&'static str, &'static [AIParam]); 111] =
+    [("goto", &[Block]),
+     ("notowns_jump", &[Unit, Block]),
+     ("wait", &[Word]),
+     ("start_town", &[]),
+     ("start_areatown", &[]),
+     ("expand", &[Byte, Block]),
+     ("build", &[Byte, Unit, Byte]),
+     ("upgrade", &[Byte, Upgrade, Byte]),
+     ("tech", &[Tech, Byte]),
+     ("wait_build", &[Byte, Unit]),
+     ("wait_buildstart", &[Byte, Unit]),
+     ("attack_clear", &[]),
+     ("attack_add", &[Byte, Unit]),
+     ("attack_prepare", &[]),
+     ("attack_do", &[]),
+     ("wait_secure", &[]),
+     ("capt_expand", &[]),
+     ("build_bunkers", &[]),
+     ("wait_bunkers", &[]),
+     ("defensebuild_gg", &[Byte, Unit]),
+     ("defensebuild_ag", &[Byte, Unit]),
+     ("defensebuild_ga", &[Byte, Unit]),
+     ("defensebuild_aa", &[Byte, Unit]),
+     ("defenseuse_gg", &[Byte, Unit]),
+     ("defenseuse_ag", &[Byte, Unit]),
+     ("defenseuse_ga", &[Byte, Unit]),
+     ("defenseuse_aa", &[Byte, Unit]),
+     ("defenseclear_gg", &[]),
+     ("defenseclear_aa", &[]),
+     ("send_suicide", &[Byte]),
+     ("player_enemy", &[]),
+     ("player_ally", &[]),
+     ("default_min", &[Byte]),
+     ("defaultbuild_off", &[]),
+     ("stop", &[]),
+     ("switch_rescue", &[]),
+     ("move_dt", &[]),
+     ("debug", &[Block, Text]),
+     ("fatal_error", &[]),
+     ("enter_bunker", &[]),
+     ("value_area", &[]),
+     ("transports_off", &[]),
+     ("check_transports", &[]),
+     ("nuke_rate", &[Byte]),
+     ("max_force", &[Word]),
+     ("clear_combatdata", &[]),
+     ("random_jump", &[Byte, Block]),
+     ("time_jump", &[Byte, Block]),
+     ("farms_notiming", &[]),
+     ("farms_timing", &[]),
+     ("build_turrets", &[]),
+     ("wait_turrets", &[]),
+     ("default_build", &[]),
+     ("harass_factor", &[Word]),
+     ("start_campaign", &[]),
+     ("race_jump", &[Block, Block, Block]),
+     ("region_size", &[Byte, Block]),
+     ("get_oldpeons", &[Byte]),
+     ("groundmap_jump", &[Block]),
+     ("place_guard", &[Unit, Byte]),
+     ("wait_force", &[Byte, Unit]),
+     ("guard_resources", &[Unit]),
+     ("call", &[Block]),
+     ("return", &[]),
+     ("eval_harass", &[Block]),
+     ("creep", &[Byte]),
+     ("panic", &[Block]),
+     ("player_need", &[Byte, Unit]),
+     ("do_morph", &[Byte, Unit]),
+     ("wait_upgrades", &[]),
+     ("multirun", &[Block]),
+     ("rush", &[Byte, Block]),
+     ("scout_with", &[Unit]),
+     ("define_max", &[Byte, Unit]),
+     ("train", &[Byte, Unit]),
+     ("target_expansion", &[]),
+     ("wait_train", &[Byte, Unit]),
+     ("set_attacks", &[Byte]),
+     ("set_gencmd", &[]),
+     ("make_patrol", &[]),
+     ("give_money", &[]),
+     ("prep_down", &[Byte, Byte, Unit]),
+     ("resources_jump", &[Word, Word, Block]),
+     ("enter_transport", &[]),
+     ("exit_transport", &[]),
+     ("sharedvision_on", &[Byte]),
+     ("sharedvision_off", &[Byte]),
+     ("nuke_location", &[]),
+     ("harass_location", &[]),
+     ("implode", &[]),
+     ("guard_all", &[]),
+     ("enemyowns_jump", &[Unit, Block]),
+     ("enemyresources_jump", &[Word, Word, Block]),
+     ("if_dif", &[Byte, Byte, Block]),
+     ("easy_attack", &[Byte, Unit]),
+     ("kill_thread", &[]),
+     ("killable", &[]),
+     ("wait_finishattack", &[]),
+     ("quick_attack", &[]),
+     ("junkyard_dog", &[]),
+     ("fake_nuke", &[]),
+     ("disruption_web", &[]),
+     ("recall_location", &[]),
+     ("set_randomseed", &[DWord]),
+     ("if_owned", &[Unit, Block]),
+     ("create_nuke", &[]),
+     ("create_unit", &[Unit, Word, Word]),
+     ("nuke_pos", &[Word, Word]),
+     ("help_iftrouble", &[]),
+     ("allies_watch", &[Byte, Block]),
+     ("try_townpoint", &[Byte, Block])];
+
+/// "TMCx" -> 0x78434d54, the way script ids are stored
+pub fn script_id(name: &str) -> u32 {
+    name.bytes().take(4).enumerate().fold(0, |id, (i, b)| id | (b as u32) << (8 * i))
+}
+
+pub fn script_name(id: u32) -> String {
+    (0..4).map(|i| ((id >> (8 * i)) & 0xff) as u8 as char).collect()
+}
+
+pub struct AIScripts {
+    aiscript: Vec<u8>,
+    bwscript: Vec<u8>,
+    pub headers: Vec<AIScriptHeader>,
+}
+impl AIScripts {
+    /// bwscript.bin is missing without Brood War
+    pub fn read(aiscript: &mut Read, bwscript: Option<&mut Read>) -> io::Result<AIScripts> {
+        let mut ai_data = Vec::<u8>::new();
+        try!(aiscript.read_to_end(&mut ai_data));
+        let mut bw_data = Vec::<u8>::new();
+        if let Some(bwscript) = bwscript {
+            try!(bwscript.read_to_end(&mut bw_data));
+        }
+        let mut headers = AIScripts::read_headers(&ai_data, AIScriptFile::AIScript);
+        headers.extend(AIScripts::read_headers(&bw_data, AIScriptFile::BWScript));
+        Ok(AIScripts {
+            aiscript: ai_data,
+            bwscript: bw_data,
+            headers: headers,
+        })
+    }
+
+    fn read_headers(data: &[u8], file: AIScriptFile) -> Vec<AIScriptHeader> {
+        let mut headers = Vec::<AIScriptHeader>::new();
+        if data.len() < 4 {
+            return headers;
+        }
+        let mut pos = LittleEndian::read_u32(data) as usize;
+        while pos + HEADER_SIZE <= data.len() {
+            let id = LittleEndian::read_u32(&data[pos..]);
+            let offset = LittleEndian::read_u32(&data[pos + 4..]) as usize;
+            pos += HEADER_SIZE;
+            if id == 0 {
+                break;
+            }
+            // listed in aiscript.bin, but the code is in bwscript.bin
+            if offset == 0 {
+                continue;
+            }
+            headers.push(AIScriptHeader {
+                id: id,
+                entry: AIScriptPos {
+                    file: file,
+                    offset: offset,
+                },
+                string_idx: LittleEndian::read_u32(&data[pos - 8..]),
+                flags: LittleEndian::read_u32(&data[pos - 4..]),
+            });
+        }
+        headers
+    }
+
+    pub fn find(&self, id: u32) -> Option<&AIScriptHeader> {
+        self.headers.iter().find(|h| h.id == id)
+    }
+
+    fn data(&self, file: AIScriptFile) -> &[u8] {
+        match file {
+            AIScriptFile::AIScript => &self.aiscript,
+            AIScriptFile::BWScript => &self.bwscript,
+        }
+    }
+
+    /// the command at `pos`, None for unknown opcodes and truncated scripts
+    pub fn decode(&self, pos: AIScriptPos) -> Option<AICommand> {
+        let data = self.data(pos.file);
+        let mut next = pos.offset;
+        if next >= data.len() {
+            return None;
+        }
+        let opcode = data[next];
+        next += 1;
+        let params = match AI_OPCODES.get(opcode as usize) {
+            Some(&(_, params)) => params,
+            None => return None,
+        };
+        let mut args = Vec::with_capacity(params.len());
+        let mut text = None;
+        for param in params {
+            let size = match *param {
+                Byte => 1,
+                DWord => 4,
+                Text => 0,
+                _ => 2,
+            };
+            if next + size > data.len() {
+                return None;
+            }
+            match *param {
+                Byte => args.push(data[next] as u32),
+                DWord => args.push(LittleEndian::read_u32(&data[next..])),
+                Text => {
+                    let len = match data[next..].iter().position(|&b| b == 0) {
+                        Some(len) => len,
+                        None => return None,
+                    };
+                    text = Some(data[next..next + len].iter().map(|&b| b as char).collect());
+                    next += len + 1;
+                }
+                _ => args.push(LittleEndian::read_u16(&data[next..]) as u32),
+            }
+            next += size;
+        }
+        Some(AICommand {
+            opcode: opcode,
+            args: args,
+            text: text,
+            next: next,
+        })
+    }
+}
@@ -121,7 +121,9 @@ impl Font {
 
 // render into 8bit screen buffer
 
+#[cfg(feature = "sdl")]
 extern crate sdl2;
+#[cfg(feature = "sdl")]
 use self::sdl2::rect::Rect;
 
 #[derive(Clone)]
@@ -137,11 +139,13 @@ pub enum VerticalAlignment {
     Bottom
 }
 
+#[cfg(feature = "sdl")]
 pub struct TextLayout {
     width: u32,
     height: u32,
 }
 
+#[cfg(feature = "sdl")]
 pub trait RenderText {
     fn layout(&self, text: &str) -> TextLayout;
 
@@ -162,6 +166,7 @@ pub trait RenderText {
                       trg_pitch: u32,
                       trg_rect: &Rect);
     }
+#[cfg(feature = "sdl")]
 impl RenderText for Font {
     fn layout(&self, text: &str) -> TextLayout {
         let mut w = 0;
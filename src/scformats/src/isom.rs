@@ -0,0 +1,304 @@
+//! Isometric terrain, as stored in the ISOM section of a map.
+//!
+//! The map is covered by isom rectangles of 64x32 pixels (two tiles), with
+//! one extra column and row at the right and bottom border. Rectangles at
+//! (x, y) with x + y even are the diamonds of the isometric grid, filled
+//! with a single terrain type. The other rectangles are transitions: each of
+//! their sides takes the terrain type of the diamond next to it.
+//!
+//! Tiles are derived from the rectangles by looking up the tile group with
+//! matching CV5 edges, see `IsomTerrain`. This is a model of what the editor
+//! does, not a copy of its algorithm, it assumes that
+//!
+//! - the upper 12 bits of an ISOM side are the terrain type, the same value
+//!   the CV5 groups use for their edges (the lower 4 bits are editor flags),
+//! - CV5 groups come in pairs for the left and right half of a rectangle,
+//!   the terrain types are the groups with all four edges equal,
+//! - the first pair with matching edges is as good as any other. The editor
+//!   picks among all of them, so cliffs and ramps or flat variants with the
+//!   same edges can differ from a stock map even if the model is right.
+//!
+//! Doodads are placed on top of the terrain and are not part of it.
+
+use std::collections::HashMap;
+
+use ::terrain::{MapData, TerrainInfo};
+
+/// One entry of the ISOM section. Each side holds the terrain type in the
+/// upper 12 bits, the lower 4 bits are editor flags.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IsomRect {
+    pub left: u16,
+    pub top: u16,
+    pub right: u16,
+    pub bottom: u16,
+}
+impl IsomRect {
+    pub fn solid(terrain: u16) -> IsomRect {
+        let val = terrain << 4;
+        IsomRect {
+            left: val,
+            top: val,
+            right: val,
+            bottom: val,
+        }
+    }
+
+    /// terrain types of the sides, without editor flags
+    pub fn edges(&self) -> (u16, u16, u16, u16) {
+        (self.left >> 4, self.top >> 4, self.right >> 4, self.bottom >> 4)
+    }
+}
+
+/// Terrain types and transitions of a tileset, taken from the CV5 edges.
+pub struct IsomTerrain {
+    /// terrain types, i.e. the edge types of tile groups with all edges equal
+    pub terrain_types: Vec<u16>,
+    // (left, top, right, bottom) edges -> first groups of the tile pairs
+    groups: HashMap<(u16, u16, u16, u16), Vec<u16>>,
+}
+impl IsomTerrain {
+    pub fn new(ti: &TerrainInfo) -> IsomTerrain {
+        let edges = ti.cv5_groups()
+            .iter()
+            .map(|g| (g.left_edge, g.top_edge, g.right_edge, g.bottom_edge))
+            .collect::<Vec<_>>();
+        IsomTerrain::from_edges(&edges)
+    }
+
+    /// builds the lookup from the edges of all tile groups, in CV5 order
+    pub fn from_edges(edges: &[(u16, u16, u16, u16)]) -> IsomTerrain {
+        let mut terrain_types = Vec::<u16>::new();
+        let mut groups = HashMap::new();
+        // groups come in pairs, the left and right half of an isom rectangle
+        let mut group = 0;
+        while group + 1 < edges.len() {
+            let e = edges[group];
+            group += 2;
+            // null tiles
+            if e == (0, 0, 0, 0) {
+                continue;
+            }
+            groups.entry(e).or_insert_with(Vec::new).push((group - 2) as u16);
+            if e.0 == e.1 && e.1 == e.2 && e.2 == e.3 && !terrain_types.contains(&e.0) {
+                terrain_types.push(e.0);
+            }
+        }
+        IsomTerrain {
+            terrain_types: terrain_types,
+            groups: groups,
+        }
+    }
+
+    pub fn is_terrain_type(&self, terrain: u16) -> bool {
+        self.terrain_types.contains(&terrain)
+    }
+
+    /// tile groups for the left and right half of a rectangle, None if the
+    /// tileset has no tiles for this combination of terrain types
+    pub fn groups_for(&self, rect: &IsomRect) -> Option<(u16, u16)> {
+        self.groups.get(&rect.edges()).map(|g| (g[0], g[0] + 1))
+    }
+
+    /// all tile pairs fitting a rectangle, e.g. cliffs and ramps
+    pub fn all_groups_for(&self, rect: &IsomRect) -> Vec<(u16, u16)> {
+        match self.groups.get(&rect.edges()) {
+            Some(g) => g.iter().map(|g| (*g, *g + 1)).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct IsomMap {
+    /// size in isom rectangles, map width / 2 + 1
+    pub width: usize,
+    /// map height + 1
+    pub height: usize,
+    pub rects: Vec<IsomRect>,
+}
+impl IsomMap {
+    /// map (in tiles) filled with a single terrain type
+    pub fn new(map_width: u16, map_height: u16, terrain: u16) -> IsomMap {
+        let width = map_width as usize / 2 + 1;
+        let height = map_height as usize + 1;
+        IsomMap {
+            width: width,
+            height: height,
+            rects: vec![IsomRect::solid(terrain); width * height],
+        }
+    }
+
+    /// None if the map has no (or a too small) ISOM section
+    pub fn from_mapdata(mapdata: &MapData) -> Option<IsomMap> {
+        let width = mapdata.width as usize / 2 + 1;
+        let height = mapdata.height as usize + 1;
+        if mapdata.isom.len() < width * height * 4 {
+            return None;
+        }
+        let rects = mapdata.isom
+            .chunks(4)
+            .take(width * height)
+            .map(|c| {
+                IsomRect {
+                    left: c[0],
+                    top: c[1],
+                    right: c[2],
+                    bottom: c[3],
+                }
+            })
+            .collect::<Vec<_>>();
+        Some(IsomMap {
+            width: width,
+            height: height,
+            rects: rects,
+        })
+    }
+
+    /// contents of the ISOM section
+    pub fn to_raw(&self) -> Vec<u16> {
+        let mut res = Vec::<u16>::with_capacity(self.rects.len() * 4);
+        for r in &self.rects {
+            res.push(r.left);
+            res.push(r.top);
+            res.push(r.right);
+            res.push(r.bottom);
+        }
+        res
+    }
+
+    pub fn rect(&self, x: usize, y: usize) -> Option<&IsomRect> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(&self.rects[y * self.width + x])
+    }
+
+    pub fn is_diamond(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && (x + y) % 2 == 0
+    }
+
+    /// terrain type of the diamond at (x, y)
+    pub fn diamond(&self, x: usize, y: usize) -> Option<u16> {
+        if !self.is_diamond(x, y) {
+            return None;
+        }
+        self.rect(x, y).map(|r| r.left >> 4)
+    }
+
+    /// sets the terrain type of a diamond and the sides of the surrounding
+    /// transitions. Returns the rectangles that have changed.
+    pub fn set_diamond(&mut self, x: usize, y: usize, terrain: u16) -> Vec<(usize, usize)> {
+        let mut changed = Vec::<(usize, usize)>::new();
+        if !self.is_diamond(x, y) {
+            return changed;
+        }
+        let val = terrain << 4;
+        let w = self.width;
+        self.rects[y * w + x] = IsomRect::solid(terrain);
+        changed.push((x, y));
+        if x > 0 {
+            self.rects[y * w + x - 1].right = val;
+            changed.push((x - 1, y));
+        }
+        if x + 1 < self.width {
+            self.rects[y * w + x + 1].left = val;
+            changed.push((x + 1, y));
+        }
+        if y > 0 {
+            self.rects[(y - 1) * w + x].bottom = val;
+            changed.push((x, y - 1));
+        }
+        if y + 1 < self.height {
+            self.rects[(y + 1) * w + x].top = val;
+            changed.push((x, y + 1));
+        }
+        changed
+    }
+
+    /// writes the tiles of one rectangle into a MTXM grid. A tile keeps its
+    /// variant if the group does not change. Returns false if there are no
+    /// tiles for the rectangle, the MTXM is left alone then.
+    pub fn update_mtxm(&self,
+                       terrain: &IsomTerrain,
+                       x: usize,
+                       y: usize,
+                       mtxm: &mut [u16],
+                       map_width: usize,
+                       map_height: usize)
+                       -> bool {
+        match self.rect(x, y).and_then(|r| terrain.groups_for(r)) {
+            Some(groups) => {
+                IsomMap::set_tiles(x, y, groups, mtxm, map_width, map_height);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// writes the given tile groups for the rectangle at (x, y)
+    pub fn set_tiles(x: usize,
+                     y: usize,
+                     groups: (u16, u16),
+                     mtxm: &mut [u16],
+                     map_width: usize,
+                     map_height: usize) {
+        if y >= map_height {
+            return;
+        }
+        for &(tx, group) in &[(2 * x, groups.0), (2 * x + 1, groups.1)] {
+            if tx >= map_width {
+                continue;
+            }
+            let idx = y * map_width + tx;
+            if mtxm[idx] >> 4 != group {
+                mtxm[idx] = group << 4;
+            }
+        }
+    }
+
+    /// paints a diamond and re-derives the affected tiles. Returns the
+    /// rectangles the tileset has no tiles for.
+    pub fn brush(&mut self,
+                 terrain: &IsomTerrain,
+                 x: usize,
+                 y: usize,
+                 terrain_type: u16,
+                 mtxm: &mut [u16],
+                 map_width: usize,
+                 map_height: usize)
+                 -> Vec<(usize, usize)> {
+        let mut invalid = Vec::<(usize, usize)>::new();
+        for (rx, ry) in self.set_diamond(x, y, terrain_type) {
+            if !self.update_mtxm(terrain, rx, ry, mtxm, map_width, map_height) {
+                invalid.push((rx, ry));
+            }
+        }
+        invalid
+    }
+
+    /// derives the complete MTXM from the isom data, unresolved tiles are
+    /// null tiles
+    pub fn regenerate_mtxm(&self,
+                           terrain: &IsomTerrain,
+                           map_width: usize,
+                           map_height: usize)
+                           -> Vec<u16> {
+        let mut mtxm = vec![0 as u16; map_width * map_height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.update_mtxm(terrain, x, y, &mut mtxm, map_width, map_height);
+            }
+        }
+        mtxm
+    }
+}
+
+/// number of tiles whose group differs, doodad tiles in the original are
+/// skipped since they are placed on top of the isom terrain
+pub fn count_mismatches(original: &[u16], generated: &[u16]) -> usize {
+    original.iter()
+        .zip(generated.iter())
+        .filter(|&(o, g)| (o >> 4) < 1024 && (o >> 4) != (g >> 4))
+        .count()
+}
@@ -1,6 +1,7 @@
 
 extern crate libc;
 extern crate byteorder;
+#[cfg(feature = "sdl")]
 extern crate sdl2;
 
 #[macro_use]
@@ -22,5 +23,8 @@ pub mod utils;
 pub mod font;
 pub mod unitsdata;
 pub mod terrain;
+pub mod isom;
+pub mod trig;
 pub mod iscript;
+pub mod aiscript;
 pub mod dialog;
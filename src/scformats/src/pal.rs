@@ -1,6 +1,8 @@
 use std::io::Read;
 
+#[cfg(feature = "sdl")]
 use ::sdl2::pixels::Color;
+#[cfg(feature = "sdl")]
 use ::sdl2::render::{Renderer, Texture};
 
 pub struct Palette {
@@ -30,6 +32,7 @@ impl Palette {
         Palette { data: data }
     }
 
+    #[cfg(feature = "sdl")]
     pub fn to_sdl(&self) -> ::sdl2::pixels::Palette {
         let mut cols = [Color::RGB(0, 0, 0); 256];
         for i in 0..256 {
@@ -42,6 +45,7 @@ impl Palette {
     }
 }
 
+#[cfg(feature = "sdl")]
 pub fn palimg_to_texture(renderer: &mut Renderer,
                          width: u32,
                          height: u32,
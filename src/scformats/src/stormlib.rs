@@ -36,6 +36,22 @@ extern "C" {
     //                        moveMethod: u32)
     //                        -> u32;
 
+    fn SFileCreateArchive(mpqname: *const c_char,
+                          create_flags: u32,
+                          max_file_count: u32,
+                          handle: &mut u64)
+                          -> bool;
+    fn SFileCreateFile(handle: u64,
+                       archived_name: *const c_char,
+                       file_time: u64,
+                       file_size: u32,
+                       locale: u32,
+                       flags: u32,
+                       filehandle: &mut u64)
+                       -> bool;
+    fn SFileWriteFile(filehandle: u64, data: *const u8, size: u32, compression: u32) -> bool;
+    fn SFileFinishFile(filehandle: u64) -> bool;
+
     fn SFileExtractFile(handle: u64,
                         in_filename: *const c_char,
                         out_filename: *const c_char,
@@ -47,6 +63,22 @@ use std::io::Cursor;
 // FIXME: use buffered read
 pub type MPQArchiveFile = Cursor<Vec<u8>>;
 
+/// a failed StormLib call, with the name of the archive or file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MPQError {
+    OpenArchive(String),
+    CreateArchive(String),
+    OpenFile(String),
+    ReadFile(String),
+    WriteFile(String),
+    /// names can't contain null bytes
+    InvalidName(String),
+}
+
+fn c_name(name: &str) -> Result<CString, MPQError> {
+    CString::new(name).map_err(|_| MPQError::InvalidName(name.to_string()))
+}
+
 pub struct MPQArchive {
     pub filename: String,
     handle: u64,
@@ -60,50 +92,105 @@ impl Drop for MPQArchive {
 }
 
 impl MPQArchive {
-    pub fn open(filename: &str) -> MPQArchive {
-        let filepath = CString::new(filename).unwrap();
+    pub fn open(filename: &str) -> Result<MPQArchive, MPQError> {
+        let filepath = try!(c_name(filename));
         unsafe {
             let mut handle: u64 = 0;
-            let succ = SFileOpenArchive(filepath.as_ptr(), 0, 0x100, &mut handle);
-            assert!(succ, "opening {} failed!", filename);
-            MPQArchive {
+            if !SFileOpenArchive(filepath.as_ptr(), 0, 0x100, &mut handle) {
+                return Err(MPQError::OpenArchive(filename.to_string()));
+            }
+            Ok(MPQArchive {
                 filename: filename.to_string(),
                 handle: handle,
+            })
+        }
+    }
+    /// creates a new (v1) archive, overwriting existing files
+    pub fn create(filename: &str, max_file_count: u32) -> Result<MPQArchive, MPQError> {
+        let filepath = try!(c_name(filename));
+        unsafe {
+            let mut handle: u64 = 0;
+            if !SFileCreateArchive(filepath.as_ptr(), 0, max_file_count, &mut handle) {
+                return Err(MPQError::CreateArchive(filename.to_string()));
+            }
+            Ok(MPQArchive {
+                filename: filename.to_string(),
+                handle: handle,
+            })
+        }
+    }
+
+    /// adds a file, replacing it if it already exists. The file is imploded,
+    /// StarCraft can't read the other compressions in maps.
+    pub fn add_file(&self, filename: &str, data: &[u8]) -> Result<(), MPQError> {
+        // MPQ_FILE_IMPLODE | MPQ_FILE_REPLACEEXISTING
+        let flags = 0x00000100 | 0x80000000;
+        // MPQ_COMPRESSION_PKWARE, implode ignores it
+        let compression = 0x08;
+        let filepath = try!(c_name(filename));
+        unsafe {
+            let mut filehandle: u64 = 0;
+            if !SFileCreateFile(self.handle,
+                                filepath.as_ptr(),
+                                0,
+                                data.len() as u32,
+                                0,
+                                flags,
+                                &mut filehandle) {
+                return Err(MPQError::WriteFile(filename.to_string()));
+            }
+            let written = SFileWriteFile(filehandle,
+                                         data.as_ptr(),
+                                         data.len() as u32,
+                                         compression);
+            // finish in any case, it frees the handle
+            let finished = SFileFinishFile(filehandle);
+            if !written || !finished {
+                return Err(MPQError::WriteFile(filename.to_string()));
             }
         }
+        Ok(())
     }
+
     pub fn has_file(&self, filename: &str) -> bool {
-        let filepath = CString::new(filename).unwrap();
-        unsafe { SFileHasFile(self.handle, filepath.as_ptr()) }
+        match c_name(filename) {
+            Ok(filepath) => unsafe { SFileHasFile(self.handle, filepath.as_ptr()) },
+            Err(_) => false,
+        }
     }
 
-    pub fn extract(&self, infilename: &str, outfilename: &str) {
-        let in_filepath = CString::new(infilename).unwrap();
-        let out_filepath = CString::new(outfilename).unwrap();
+    pub fn extract(&self, infilename: &str, outfilename: &str) -> Result<(), MPQError> {
+        let in_filepath = try!(c_name(infilename));
+        let out_filepath = try!(c_name(outfilename));
         unsafe {
-            let res = SFileExtractFile(self.handle, in_filepath.as_ptr(), out_filepath.as_ptr(), 0);
-            assert!(res);
+            if !SFileExtractFile(self.handle, in_filepath.as_ptr(), out_filepath.as_ptr(), 0) {
+                return Err(MPQError::ReadFile(infilename.to_string()));
+            }
         }
+        Ok(())
     }
 
-    pub fn open_file(&self, filename: &str) -> MPQArchiveFile {
+    pub fn open_file(&self, filename: &str) -> Result<MPQArchiveFile, MPQError> {
         // XXX: might be more efficient to read the full file at once
-        let filepath = CString::new(filename).unwrap();
+        let filepath = try!(c_name(filename));
         unsafe {
             let mut reshandle: u64 = 0;
-            let succ = SFileOpenFileEx(self.handle, filepath.as_ptr(), 0, &mut reshandle);
-            assert!(succ);
+            if !SFileOpenFileEx(self.handle, filepath.as_ptr(), 0, &mut reshandle) {
+                return Err(MPQError::OpenFile(filename.to_string()));
+            }
 
             let mut fshigh: u32 = 0;
             let fs = SFileGetFileSize(reshandle, &mut fshigh);
 
             let mut read_bytes: u32 = 0;
             let mut buf = vec![0u8; fs as usize];
-            let succ2 = SFileReadFile(reshandle, buf.as_mut_ptr(), fs, &mut read_bytes, 0);
-            assert!(succ2);
+            let succ = SFileReadFile(reshandle, buf.as_mut_ptr(), fs, &mut read_bytes, 0);
             SFileCloseFile(reshandle);
+            if !succ {
+                return Err(MPQError::ReadFile(filename.to_string()));
+            }
 
-            Cursor::new(buf)
+            Ok(Cursor::new(buf))
             // MPQArchiveFile {
             //     handle: reshandle,
             //     //archive: self,
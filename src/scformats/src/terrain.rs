@@ -1,12 +1,16 @@
 use std::io::Cursor;
 
-use std::io::{Read, Seek, SeekFrom};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::fs::File;
 extern crate byteorder;
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::cmp::min;
 
 use ::utils::{read_vec_u8, read_vec_u16};
 use ::pal::Palette;
-use ::stormlib::MPQArchive;
+use ::stormlib::{MPQArchive, MPQError};
+use ::trig::{Trigger, TRIGGER_SIZE};
 
 
 // FIXME: this makes things ugly
@@ -29,22 +33,55 @@ pub enum TileSet {
 
 
 pub struct MapData {
-    pub mpq_archive: MPQArchive,
+    /// None for plain .chk files and generated maps
+    pub mpq_archive: Option<MPQArchive>,
     pub owners: [u8; 12],
+    pub sides: [u8; 12],
     pub tileset: TileSet,
     pub width: u16,
     pub height: u16,
     pub mtxm: Vec<u16>,
+    /// raw ISOM section, see isom::IsomMap
+    pub isom: Vec<u16>,
     pub units: Vec<MapUnit>,
     pub sprites: Vec<MapSprite>,
+    /// MRGN, location n (as used by triggers) is at index n - 1
+    pub locations: Vec<MapLocation>,
     pub strings: Vec<String>,
     pub scenario_name_str_idx: usize,
     pub scenario_desc_str_idx: usize,
+    pub triggers: Vec<Trigger>,
+    pub upgrade_settings: Option<ResearchSettings>,
+    pub tech_settings: Option<ResearchSettings>,
+    pub forces: Forces,
+    /// names of sections the parser doesn't know
+    pub unknown_sections: Vec<String>,
+    /// things that were broken while reading, like truncated sections
+    pub problems: Vec<String>,
 }
+
+/// a map file that can't be opened, broken contents end up in `problems`
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    Mpq(MPQError),
+}
+impl From<io::Error> for MapError {
+    fn from(err: io::Error) -> MapError {
+        MapError::Io(err)
+    }
+}
+impl From<MPQError> for MapError {
+    fn from(err: MPQError) -> MapError {
+        MapError::Mpq(err)
+    }
+}
+
 pub struct Map {
     pub data: MapData,
     pub terrain_info: TerrainInfo,
     pub passable_megatiles: Vec<bool>,
+    pub terrain_grid: TerrainGrid,
 }
 
 // XXX: almost same as in read-units, merge
@@ -59,6 +96,7 @@ macro_rules! def_chk {
         $debug:expr,
         $code_var:ident,
         $data_size:ident,
+        $unknown:ident,
         $( $opcode:pat => ( $( $param:ident : $tpe:ident),*)
            $code:block),*
     )
@@ -84,6 +122,7 @@ macro_rules! def_chk {
                 //_ => panic!("unknown chk section: {:?}", $code_var),
                     _ => {
                         println!("ignoring section: {:?}", $code_var);
+                        $unknown.push($code_var.to_string());
                         $file_var.seek(SeekFrom::Current($data_size as i64)).ok();
                     }
             }
@@ -176,23 +215,409 @@ def_bin_struct! (
 );
 
 
+def_bin_struct! (
+    MapLocation {
+// u32: Left (in pixels)
+        left: u32,
+// u32: Top
+        top: u32,
+// u32: Right
+        right: u32,
+// u32: Bottom
+        bottom: u32,
+// u16: String number of the name of this location
+        string: u16,
+// u16: Location elevation flags. If an elevation is disabled in the location's
+// properties, the corresponding bit value is 1.
+// Bit 0 - Low elevation
+// Bit 1 - Medium elevation
+// Bit 2 - High elevation
+// Bit 3 - Low air
+// Bit 4 - Medium air
+// Bit 5 - High air
+// Bit 6-15 - Unused
+        elevation_flags: u16
+    }
+);
+
+
+/// Upgrade (UPGR, PUPx) or technology (PTEC, PTEx) settings of a map. For
+/// upgrades the values are the maximum and starting levels, for technologies
+/// whether they are available and already researched.
+#[derive(Clone, Debug)]
+pub struct ResearchSettings {
+    /// [player][entry]
+    pub player_max: Vec<Vec<u8>>,
+    pub player_start: Vec<Vec<u8>>,
+    pub global_max: Vec<u8>,
+    pub global_start: Vec<u8>,
+    /// [player][entry]: 1 if the global values apply to the player
+    pub uses_default: Vec<Vec<u8>>,
+}
+impl ResearchSettings {
+    pub fn read(file: &mut Read, count: usize) -> ResearchSettings {
+        let player_max = (0..12).map(|_| read_vec_u8(file, count)).collect();
+        let player_start = (0..12).map(|_| read_vec_u8(file, count)).collect();
+        let global_max = read_vec_u8(file, count);
+        let global_start = read_vec_u8(file, count);
+        let uses_default = (0..12).map(|_| read_vec_u8(file, count)).collect();
+        ResearchSettings {
+            player_max: player_max,
+            player_start: player_start,
+            global_max: global_max,
+            global_start: global_start,
+            uses_default: uses_default,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.global_max.len()
+    }
+
+    /// maximum level (or availability) for a player, None if the section
+    /// doesn't have the entry (e.g. original sections and Brood War upgrades)
+    pub fn max(&self, player: usize, idx: usize) -> Option<u8> {
+        if player >= 12 || idx >= self.len() {
+            None
+        } else if self.uses_default[player][idx] != 0 {
+            Some(self.global_max[idx])
+        } else {
+            Some(self.player_max[player][idx])
+        }
+    }
+
+    /// starting level (or whether it's researched) for a player
+    pub fn start(&self, player: usize, idx: usize) -> Option<u8> {
+        if player >= 12 || idx >= self.len() {
+            None
+        } else if self.uses_default[player][idx] != 0 {
+            Some(self.global_start[idx])
+        } else {
+            Some(self.player_start[player][idx])
+        }
+    }
+}
+
+/// Forces (FORC) of players 1-8, e.g. teams that are allied from the start.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Forces {
+    /// force (0-3) of each player
+    pub player_force: [u8; 8],
+    /// string numbers of the force names, 0 for "Force n"
+    pub names: [u16; 4],
+    /// FORCE_* flags of each force
+    pub flags: [u8; 4],
+}
+pub const FORCE_RANDOM_START: u8 = 0x1;
+pub const FORCE_ALLIES: u8 = 0x2;
+pub const FORCE_ALLIED_VICTORY: u8 = 0x4;
+pub const FORCE_SHARED_VISION: u8 = 0x8;
+const FORC_SIZE: usize = 20;
+
+impl Forces {
+    /// `data` may be shorter than 20 bytes, the rest is 0
+    pub fn read(data: &[u8]) -> Forces {
+        let mut buf = [0 as u8; FORC_SIZE];
+        let len = min(data.len(), FORC_SIZE);
+        buf[..len].copy_from_slice(&data[..len]);
+        let mut forces = Forces::default();
+        forces.player_force.copy_from_slice(&buf[0..8]);
+        for i in 0..4 {
+            forces.names[i] = LittleEndian::read_u16(&buf[8 + i * 2..]);
+        }
+        forces.flags.copy_from_slice(&buf[16..20]);
+        forces
+    }
+
+    pub fn write(&self, out: &mut Write) {
+        out.write_all(&self.player_force).unwrap();
+        for name in self.names.iter() {
+            out.write_u16::<LittleEndian>(*name).unwrap();
+        }
+        out.write_all(&self.flags).unwrap();
+    }
+
+    /// force of a player, None for players 9-12
+    pub fn force(&self, player: usize) -> Option<usize> {
+        self.player_force.get(player).map(|&f| f as usize)
+    }
+}
+
+impl MapUnit {
+    pub fn write(&self, out: &mut Write) {
+        out.write_u32::<LittleEndian>(self.instance_id).unwrap();
+        out.write_u16::<LittleEndian>(self.x).unwrap();
+        out.write_u16::<LittleEndian>(self.y).unwrap();
+        out.write_u16::<LittleEndian>(self.unit_id).unwrap();
+        out.write_u16::<LittleEndian>(self.rel_type).unwrap();
+        out.write_u16::<LittleEndian>(self.special_prop_flags).unwrap();
+        out.write_u16::<LittleEndian>(self.changeable_props).unwrap();
+        out.write_u8(self.player_no).unwrap();
+        out.write_u8(self.hit_points).unwrap();
+        out.write_u8(self.shield_points).unwrap();
+        out.write_u8(self.energy_points).unwrap();
+        out.write_u32::<LittleEndian>(self.resource_amount).unwrap();
+        out.write_u16::<LittleEndian>(self.units_in_hangar).unwrap();
+        out.write_u16::<LittleEndian>(self.state_flags).unwrap();
+        out.write_u32::<LittleEndian>(self._unused).unwrap();
+        out.write_u32::<LittleEndian>(self.rel_instance_id).unwrap();
+    }
+}
+impl MapSprite {
+    pub fn write(&self, out: &mut Write) {
+        out.write_u16::<LittleEndian>(self.sprite_no).unwrap();
+        out.write_u16::<LittleEndian>(self.x).unwrap();
+        out.write_u16::<LittleEndian>(self.y).unwrap();
+        out.write_u8(self.player_no).unwrap();
+        out.write_u8(self._unused).unwrap();
+        out.write_u16::<LittleEndian>(self.flags).unwrap();
+    }
+}
+impl MapLocation {
+    pub fn write(&self, out: &mut Write) {
+        out.write_u32::<LittleEndian>(self.left).unwrap();
+        out.write_u32::<LittleEndian>(self.top).unwrap();
+        out.write_u32::<LittleEndian>(self.right).unwrap();
+        out.write_u32::<LittleEndian>(self.bottom).unwrap();
+        out.write_u16::<LittleEndian>(self.string).unwrap();
+        out.write_u16::<LittleEndian>(self.elevation_flags).unwrap();
+    }
+}
+
+/// the verification code every map carries, the game refuses maps without it
+const VCOD_SEEDS: [u32; 256] = [
+    0x77ca1934, 0x7168dc99, 0xc3bf600a, 0xa775e7a7, 0xa67d291f, 0xbb3ab0d7,
+    0xed2431cc, 0x0b134c17, 0xb7a22065, 0x6b18bd91, 0xdd5dc38d, 0x37d57ae2,
+    0xd46459f6, 0x0f129a63, 0x462e5c43, 0x2af874e3, 0x06376a08, 0x3bd6f637,
+    0x1663940e, 0xec5c6745, 0xb7f77bd7, 0x9ed4fc1a, 0x8c3ffa73, 0x0fe1c02e,
+    0x070974d1, 0xd764e395, 0x74681675, 0xda4fa799, 0x1f1820d5, 0xbea0e6e7,
+    0x1fe3b6a6, 0x70ef0cca, 0x311ad531, 0x3524b84d, 0x7dc7f8e3, 0xde581ae1,
+    0x432705f4, 0x07dbacba, 0x0abe69dc, 0x49ec8fa8, 0x3f1658d7, 0x8ac1dbe5,
+    0x05c0cf41, 0x721cca9d, 0xa55fb1a2, 0x9b7023c4, 0x14e10484, 0xda907b80,
+    0x0669dbfa, 0x400ff3a3, 0xd4cef3be, 0xd7cbc9e3, 0x3401405a, 0xf81468f2,
+    0x1ac58e38, 0x4b3dd6fe, 0xfa050553, 0x8e451034, 0xfe6991dd, 0xf0eee0af,
+    0xdd7e48f3, 0x75dcad9f, 0xe5ac7a62, 0x67621b31, 0x4d36cd20, 0x742198e0,
+    0x717909fb, 0x7fcd6736, 0x3cd65f77, 0xc6a6a2a2, 0x6acee31a, 0x6ca9cd4e,
+    0x3b9dba86, 0xfd76f4b5, 0xbcf044f8, 0x296ee92e, 0x6b2f2523, 0x4427ab08,
+    0x99cc127a, 0x75f2dced, 0x7e383cc5, 0xc51b1cf7, 0x65942dd1, 0xdd48c906,
+    0xac2d32be, 0x8132c9b5, 0x34d84a66, 0xdf153f35, 0xb6ebeeb2, 0x964df604,
+    0x9c944235, 0x61d38a62, 0x6f7ba852, 0xf4fc61dc, 0xfe2d146c, 0x0aa4ea99,
+    0x13fed9e8, 0x594448d0, 0xe3f36680, 0x198dd934, 0xfe63d716, 0x3a7e1830,
+    0xb10f8d9b, 0x8cf5f012, 0xdb58780a, 0x8cb8633e, 0x8ef3aa3a, 0x2e1a8a37,
+    0xeff9315c, 0x7ee36de3, 0x133ebd9b, 0xb9c044c6, 0x90da3abc, 0x74b0ada4,
+    0x892757f8, 0x373fe647, 0x5a7942e4, 0xee8d43df, 0xe8490ab4, 0x1a88c33c,
+    0x766b0188, 0xa3fdc38a, 0x564e7a16, 0xbacb7fa7, 0xec1c5e02, 0x76c9b9b0,
+    0x39b1821e, 0xc557c93e, 0x4c382419, 0xb8542f5d, 0x8e575d6f, 0x520aa130,
+    0x5e71186d, 0x59c30613, 0x623edc1f, 0xebb5dadc, 0xf995911b, 0xdad591a7,
+    0x6bce5333, 0x017000f5, 0xe8eed87f, 0xcef10ac0, 0xd3b6eb63, 0xa5ccef78,
+    0xa4bc5daa, 0xd2f2ab96, 0x9aeaff61, 0xa2ed6aa8, 0x61ed3ebd, 0x9282c139,
+    0xb1233616, 0xe524a0b0, 0xaaa79b05, 0x339b120d, 0xda209283, 0xfcecb025,
+    0x2338d024, 0x74f295fc, 0x19e57380, 0x447d5097, 0xdb449345, 0x691dada2,
+    0xe7ee1444, 0xff877f2c, 0xf1329e38, 0xda29bc4d, 0xfe262742, 0xa92bd2c1,
+    0x0e7a42f6, 0xd17ce8cb, 0x56ec5b0f, 0x3161b769, 0x25f96db4, 0x6d793440,
+    0x0ba753fa, 0xce82a4fa, 0x614945c3, 0x8f2c450d, 0xf7604928, 0x1ec97df3,
+    0xc189d00f, 0xd3f85226, 0x14358f4d, 0x0b5f9dba, 0x004aa907, 0x2f2622f7,
+    0x1ffb673e, 0xc6119ca1, 0x665d4f69, 0x90153458, 0x4654e56c, 0xd6635faf,
+    0xdf950c8a, 0xafe40dbd, 0x4c4040bf, 0x7151f6a3, 0xf826ed29, 0xd5222885,
+    0xfacfbebf, 0x517fc528, 0x076306b8, 0x298fbdec, 0x717e55fa, 0x6632401a,
+    0x9dded4e8, 0x93fc5ed4, 0x3bd53d7a, 0x802e75cd, 0x87744f0a, 0xea8fcc1b,
+    0x7cdba99a, 0xefe55316, 0x6ec178ab, 0x5a8972a4, 0x50702c98, 0x1fdfa1fb,
+    0x44d9b76b, 0x56828007, 0x83c0bffd, 0x5bd0490e, 0x0e6a681e, 0x2f0bc29a,
+    0xe1a0438e, 0xb2f60c99, 0x5e1c7ae0, 0x45a0c82c, 0x88e90b3c, 0xc696b9ac,
+    0x2a83ae74, 0x65fa13bb, 0xa61f4feb, 0xe18a8ab0, 0xb9b8e981, 0x4e1555d5,
+    0x9badf245, 0x7e35c23e, 0x722e925f, 0x23685bb6, 0x0e45c66e, 0xd4873be9,
+    0xe3c041f4, 0xbe4405a8, 0x138a0fe4, 0xf437c41a, 0xef55405a, 0x4b1d799d,
+    0x9c3a794a, 0xcc378576, 0xb60f3d82, 0x7e93a660, 0xc4c25cbd, 0x907fc772,
+    0x10961b4d, 0x68680513, 0xff7bc035, 0x2a438546
+];
+const VCOD_OPCODES: [u8; 16] = [1, 4, 5, 6, 2, 1, 5, 2, 0, 3, 7, 7, 5, 4, 6, 3];
+
+fn write_section(out: &mut Write, name: &str, data: &[u8]) {
+    assert_eq!(name.len(), 4);
+    out.write_all(name.as_bytes()).unwrap();
+    out.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+    out.write_all(data).unwrap();
+}
+
 impl MapData {
+    /// empty map, filled with null tiles
+    pub fn new(tileset: TileSet, width: u16, height: u16) -> MapData {
+        MapData {
+            mpq_archive: None,
+            owners: [0 as u8; 12],
+            sides: [0 as u8; 12],
+            tileset: tileset,
+            width: width,
+            height: height,
+            mtxm: vec![0 as u16; width as usize * height as usize],
+            isom: Vec::<u16>::new(),
+            units: Vec::<MapUnit>::new(),
+            sprites: Vec::<MapSprite>::new(),
+            locations: Vec::<MapLocation>::new(),
+            strings: Vec::<String>::new(),
+            scenario_name_str_idx: 0,
+            scenario_desc_str_idx: 0,
+            triggers: Vec::<Trigger>::new(),
+            upgrade_settings: None,
+            tech_settings: None,
+            forces: Forces::default(),
+            unknown_sections: Vec::<String>::new(),
+            problems: Vec::<String>::new(),
+        }
+    }
+
+    pub fn read_chk<T: Read + Seek>(chk_file: &mut T, mpq_archive: Option<MPQArchive>) -> MapData {
+        let mut mapdata = MapData::new(TileSet::Badlands, 0, 0);
+        mapdata.mpq_archive = mpq_archive;
+        while let Some(_) = mapdata.read_section(chk_file) {
+        }
+        mapdata
+    }
+
+    /// Writes the sections needed by `read_chk` (and the melee sections
+    /// StarEdit expects).
+    pub fn write_chk(&self, out: &mut Write) {
+        let mut buf = Vec::<u8>::new();
+        buf.write_u16::<LittleEndian>(205).unwrap();
+        write_section(out, "VER ", &buf);
+
+        buf.clear();
+        for seed in VCOD_SEEDS.iter() {
+            buf.write_u32::<LittleEndian>(*seed).unwrap();
+        }
+        buf.extend(VCOD_OPCODES.iter());
+        write_section(out, "VCOD", &buf);
+
+        write_section(out, "OWNR", &self.owners);
+        write_section(out, "SIDE", &self.sides);
+
+        buf.clear();
+        buf.write_u16::<LittleEndian>(self.tileset as u16).unwrap();
+        write_section(out, "ERA ", &buf);
+
+        buf.clear();
+        buf.write_u16::<LittleEndian>(self.width).unwrap();
+        buf.write_u16::<LittleEndian>(self.height).unwrap();
+        write_section(out, "DIM ", &buf);
+
+        buf.clear();
+        for tile in &self.mtxm {
+            buf.write_u16::<LittleEndian>(*tile).unwrap();
+        }
+        write_section(out, "MTXM", &buf);
+
+        if !self.isom.is_empty() {
+            buf.clear();
+            for val in &self.isom {
+                buf.write_u16::<LittleEndian>(*val).unwrap();
+            }
+            write_section(out, "ISOM", &buf);
+        }
+
+        buf.clear();
+        for unit in &self.units {
+            unit.write(&mut buf);
+        }
+        write_section(out, "UNIT", &buf);
+
+        buf.clear();
+        for sprite in &self.sprites {
+            sprite.write(&mut buf);
+        }
+        write_section(out, "THG2", &buf);
+
+        // everything covered by fog
+        let mask = vec![0xff as u8; self.width as usize * self.height as usize];
+        write_section(out, "MASK", &mask);
+
+        if !self.locations.is_empty() {
+            buf.clear();
+            for location in &self.locations {
+                location.write(&mut buf);
+            }
+            write_section(out, "MRGN", &buf);
+        }
+
+        // unused strings point to the null byte in front of the first string
+        let string_count = if self.strings.len() > 1024 {
+            self.strings.len()
+        } else {
+            1024
+        };
+        let strings_start = 2 + 2 * string_count;
+        let mut data = vec![0 as u8];
+        buf.clear();
+        buf.write_u16::<LittleEndian>(string_count as u16).unwrap();
+        for i in 0..string_count {
+            if i < self.strings.len() {
+                buf.write_u16::<LittleEndian>((strings_start + data.len()) as u16).unwrap();
+                data.extend(self.strings[i].bytes());
+                data.push(0);
+            } else {
+                buf.write_u16::<LittleEndian>(strings_start as u16).unwrap();
+            }
+        }
+        buf.extend(data);
+        write_section(out, "STR ", &buf);
+
+        buf.clear();
+        buf.write_u16::<LittleEndian>(self.scenario_name_str_idx as u16).unwrap();
+        buf.write_u16::<LittleEndian>(self.scenario_desc_str_idx as u16).unwrap();
+        write_section(out, "SPRP", &buf);
+
+        buf.clear();
+        self.forces.write(&mut buf);
+        write_section(out, "FORC", &buf);
+    }
+
     fn read_section<T: Read + Seek>(&mut self, chk_file: &mut T) -> Option<usize> {
         // read section header
         let mut name_buf = [0 as u8; 4];
-        let read_bytes = chk_file.read(&mut name_buf).unwrap();
+        let read_bytes = match chk_file.read(&mut name_buf) {
+            Ok(read_bytes) => read_bytes,
+            Err(err) => {
+                self.problems.push(format!("can't read the next section: {}", err));
+                return None;
+            }
+        };
         if read_bytes == 0 {
             return None;
         }
-        let name_string = String::from_utf8_lossy(&name_buf);
-        let size = chk_file.read_u32::<LittleEndian>().unwrap();
+        let name_string = String::from_utf8_lossy(&name_buf).to_string();
+        let size = match chk_file.read_u32::<LittleEndian>() {
+            Ok(size) if read_bytes == 4 => size,
+            _ => {
+                self.problems.push(String::from("trailing bytes after the last section"));
+                return None;
+            }
+        };
         println!("name: {}, size: {}", name_string, size);
 
+        // read the whole section first, so broken sizes can't mess up the
+        // following sections
+        let mut data = Vec::<u8>::new();
+        if let Err(err) = chk_file.take(size as u64).read_to_end(&mut data) {
+            self.problems.push(format!("can't read section {:?}: {}", name_string, err));
+        }
+        if data.len() < size as usize {
+            self.problems.push(format!("section {:?} truncated: {} of {} bytes",
+                                       name_string,
+                                       data.len(),
+                                       size));
+        }
+        let size = data.len();
+        // short sections read as zeros
+        data.resize(size + 64, 0);
+        let mut section = Cursor::new(data);
+        let chk_file = &mut section;
+        let mut unknown_sections = Vec::<String>::new();
+
         def_chk!(
             chk_file,
             true,
             name_string,
             size,
+            unknown_sections,
             "TYPE" => (maptype: u32) {
                 let typestring = match maptype {
                     0x53574152 => String::from("StarCraft >= 1.04"),
@@ -262,7 +687,10 @@ impl MapData {
         // 07 - Twilight
         // StarCraft masks the tileset indicator's bit value, so bits after the third place (anything after the value "7") are removed. Thus, 9 (1001 in binary) is interpreted as 1 (0001), 10 (1010) as 2 (0010), etc.
         // Desert, Arctic, and Twilight are Brood War-only tilesets.
-                let ts = match tileset {
+                if tileset > 7 {
+                    self.problems.push(format!("invalid tileset: {}", tileset));
+                }
+                let ts = match tileset & 7 {
                     0 => TileSet::Badlands,
                     1 => TileSet::SpacePlatform,
                     2 => TileSet::Installation,
@@ -270,8 +698,7 @@ impl MapData {
                     4 => TileSet::Jungle,
                     5 => TileSet::Desert,
                     6 => TileSet::Arctic,
-                    7 => TileSet::Twilight,
-                    _ => panic!("invalid tileset: {}", tileset),
+                    _ => TileSet::Twilight,
                 };
                 println!(" tileset: {:?}", ts);
                 self.tileset = ts;
@@ -297,6 +724,9 @@ impl MapData {
         // Italicized settings denote invalid map options. Note Players 9-11 are defaultly Inactive and Player 12 is defaultly Neutral.
                 let species = read_vec_u8(chk_file, 12);
                 println!(" side: {:?}", species);
+                for (i, side) in species.iter().enumerate() {
+                    self.sides[i] = *side;
+                }
             },
             "MTXM" => () {
         // Terrain section that contains a map of the level's
@@ -324,11 +754,13 @@ impl MapData {
         // specified by previous MTXM sections.
         // let terrain = read_u8buf(chk_file, size as usize);
                 let tile_count = self.width as usize * self.height as usize;
-                assert_eq!(tile_count, size as usize / 2);
-                let mut terrain = Vec::<u16>::with_capacity(tile_count);
-                for _ in 0..tile_count {
-                    let val = chk_file.read_u16::<LittleEndian>().unwrap();
-                    terrain.push(val);
+                if size / 2 < tile_count {
+                    self.problems.push(format!("MTXM has {} of {} tiles", size / 2, tile_count));
+                }
+                let mut terrain = self.mtxm.clone();
+                terrain.resize(tile_count, 0);
+                for i in 0..min(tile_count, size / 2) {
+                    terrain[i] = chk_file.read_u16::<LittleEndian>().unwrap();
                 }
                 self.mtxm = terrain;
             },
@@ -337,12 +769,35 @@ impl MapData {
                 chk_file.seek(SeekFrom::Current(size as i64)).ok();
             },
             "UPGR" => () {
-        // upgrade restrictions
-                chk_file.seek(SeekFrom::Current(size as i64)).ok();
+        // upgrade restrictions of the 46 original upgrades
+        // u8[12][46]: maximum level per player
+        // u8[12][46]: starting level per player
+        // u8[46]: global maximum level
+        // u8[46]: global starting level
+        // u8[12][46]: player uses the global values
+        // The Brood War version PUPx takes precedence.
+                if self.upgrade_settings.is_none() {
+                    self.upgrade_settings = Some(ResearchSettings::read(chk_file, 46));
+                }
+            },
+            "PUPx" => () {
+        // Brood War upgrade restrictions, like UPGR with all 61 upgrades
+                self.upgrade_settings = Some(ResearchSettings::read(chk_file, 61));
             },
             "PTEC" => () {
-        // tech restrictions
-                chk_file.seek(SeekFrom::Current(size as i64)).ok();
+        // tech restrictions of the 24 original technologies
+        // u8[12][24]: available per player
+        // u8[12][24]: already researched per player
+        // u8[24]: globally available
+        // u8[24]: globally researched
+        // u8[12][24]: player uses the global values
+                if self.tech_settings.is_none() {
+                    self.tech_settings = Some(ResearchSettings::read(chk_file, 24));
+                }
+            },
+            "PTEx" => () {
+        // Brood War tech restrictions, like PTEC with all 44 technologies
+                self.tech_settings = Some(ResearchSettings::read(chk_file, 44));
             },
             "UNIT" => () {
         // The X/Y coordinates are the center of the sprite of the unit
@@ -352,16 +807,18 @@ impl MapData {
         // count.
         // This section can be split. Additional UNIT sections will add more units.
                 let unit_count = (size as usize) / 36;
-                let mut map_units = Vec::<MapUnit>::with_capacity(unit_count);
+                self.units.reserve(unit_count);
                 for _ in 0..unit_count {
                     let unit = MapUnit::read(chk_file);
-                    map_units.push(unit);
+                    self.units.push(unit);
                 }
-                self.units = map_units;
             },
             "ISOM" => () {
-        // isometric terrain, for staredit?
-                chk_file.seek(SeekFrom::Current(size as i64)).ok();
+        // isometric terrain, only used by staredit to generate TILE
+        // u16[(map width / 2 + 1) * (map height + 1) * 4]: for each
+        // isometric rectangle the values of its left, top, right and bottom
+        // side.
+                self.isom = read_vec_u16(chk_file, size as usize / 2);
             },
             "TILE" => () {
         // staredit terrain
@@ -393,7 +850,7 @@ impl MapData {
         // less will default missing tiles to 0xFF
                 chk_file.seek(SeekFrom::Current(size as i64)).ok();
             },
-            "STR " => (string_count: u16) {
+            "STR " => () {
         // This section contains all the strings in the map.
         // u16: Number of strings in the section (Default: 1024)
         // u16[Number of strings]: 1 integer for each string specifying
@@ -402,30 +859,9 @@ impl MapData {
         // Strings: After the offsets, this is where every string in the
         // map goes, one after another. Each one is terminated by a null
         // character.
-                let mut string_offsets = Vec::with_capacity(string_count as usize);
-                for _ in 0..string_count {
-                    string_offsets.push(chk_file.read_u16::<LittleEndian>().unwrap());
-                }
-                let strings_start = 2 + 2 * (string_count as usize);
-                let data = read_vec_u8(chk_file, (size as usize) - strings_start);
-                let mut strings = Vec::<String>::with_capacity(string_count as usize);
-                let mut inpos;
-                for string_offset in string_offsets {
-                    inpos = string_offset as usize - strings_start;
-                    if inpos == 0 {
-                        continue;
-                    }
-                    let mut res = String::new();
-                    loop {
-                        let val = data[inpos];
-                        if val == 0 {
-                            break;
-                        }
-                        inpos += 1;
-                        res.push(val as char);
-                    }
-                    println!("str: {}", res);
-                    strings.push(res);
+                let strings = read_strings(&chk_file.get_ref()[..size], &mut self.problems);
+                for s in &strings {
+                    println!("str: {}", s);
                 }
                 self.strings = strings;
             },
@@ -438,12 +874,26 @@ impl MapData {
                 chk_file.seek(SeekFrom::Current(size as i64)).ok();
             },
             "MRGN" => () {
-        // locations
-                chk_file.seek(SeekFrom::Current(size as i64)).ok();
+        // locations, 20 bytes each. 64 in original maps, 255 in Brood War
+        // maps. Location 64 is "Anywhere".
+                let location_count = (size as usize) / 20;
+                let mut locations = Vec::<MapLocation>::with_capacity(location_count);
+                for _ in 0..location_count {
+                    locations.push(MapLocation::read(chk_file));
+                }
+                self.locations = locations;
             },
             "TRIG" => () {
-        // triggers
-                chk_file.seek(SeekFrom::Current(size as i64)).ok();
+        // triggers, 2400 bytes each
+                if size % TRIGGER_SIZE != 0 {
+                    self.problems.push(format!("TRIG size {} is no multiple of {}",
+                                               size, TRIGGER_SIZE));
+                }
+                let mut triggers = Vec::<Trigger>::with_capacity(size / TRIGGER_SIZE);
+                for _ in 0..size / TRIGGER_SIZE {
+                    triggers.push(Trigger::read(chk_file));
+                }
+                self.triggers = triggers;
             },
             "MBRF" => () {
         // mission briefings
@@ -476,7 +926,8 @@ impl MapData {
         // bytes are defaulted to 0.
         // Players can be on a force greater than 4, however they
         // will not appear in the game lobby.
-                chk_file.seek(SeekFrom::Current(size as i64)).ok();
+                let data = read_vec_u8(chk_file, size as usize);
+                self.forces = Forces::read(&data);
             },
             "WAV " => () {
         // There are 512 wav entires regardless of how many are actually used.
@@ -502,71 +953,79 @@ impl MapData {
                 chk_file.seek(SeekFrom::Current(size as i64)).ok();
             }
         );
+        self.unknown_sections.extend(unknown_sections);
 
         Some(read_bytes)
     }
 }
+
+/// strings of the STR section, index aligned (string n is at n - 1)
+fn read_strings(data: &[u8], problems: &mut Vec<String>) -> Vec<String> {
+    if data.len() < 2 {
+        problems.push(String::from("STR section too short"));
+        return Vec::new();
+    }
+    let string_count = LittleEndian::read_u16(&data[0..2]) as usize;
+    let mut strings = Vec::<String>::with_capacity(string_count);
+    for i in 0..string_count {
+        let offset_pos = 2 + 2 * i;
+        if offset_pos + 2 > data.len() {
+            problems.push(format!("STR section too short for {} strings", string_count));
+            break;
+        }
+        let mut inpos = LittleEndian::read_u16(&data[offset_pos..offset_pos + 2]) as usize;
+        let mut res = String::new();
+        if inpos >= data.len() {
+            problems.push(format!("string {} starts outside of the STR section", i + 1));
+        }
+        while inpos < data.len() && data[inpos] != 0 {
+            res.push(data[inpos] as char);
+            inpos += 1;
+        }
+        strings.push(res);
+    }
+    strings
+}
 impl Map {
 
-    fn passable_megatiles(mapdata: &MapData, ti: &TerrainInfo) -> Vec<bool> {
-        let mut res = Vec::<bool>::with_capacity(mapdata.mtxm.len());
+    fn passable_megatiles(grid: &TerrainGrid) -> Vec<bool> {
+        let mut res = Vec::<bool>::with_capacity(grid.width * grid.height);
         // check if this megatile is completely passable
-
-        for mtxm_idx in mapdata.mtxm.iter() {
-            let cv5_id = mtxm_idx >> 4;
-            let sub_id = mtxm_idx & 0x000F;
-            let vx4_idx = if cv5_id < 1024u16 {
-                ti.cv5[cv5_id as usize].mega_tiles[sub_id as usize]
-            } else {
-                ti.doodads[(cv5_id as usize) - 1024].mega_tiles[sub_id as usize]
-            } as usize;
-
-            //
-            let mut all_walkable = true;
-            for row in 0..4 {
-                for col in 0..4 {
-                    let vf4_idx = row * 4 + col;
-
-                    let walkable = ti.vf4[vx4_idx].is_walkable(vf4_idx);
-                    all_walkable = all_walkable && walkable;
-                }
+        for ty in 0..grid.height {
+            for tx in 0..grid.width {
+                res.push(grid.is_megatile_walkable(tx, ty));
             }
-            res.push(all_walkable);
         }
-
         res
     }
 
     // XXX scms are just mpq files, so we need to read them from disk
-    pub fn read(gd: &GameDataTrait, filename: &str) -> Map {
+    pub fn read(gd: &GameDataTrait, filename: &str) -> Result<Map, MapError> {
         println!("reading {}", filename);
-        let mpq_archive = MPQArchive::open(filename);
-        let mut chk_file = mpq_archive.open_file("staredit/scenario.chk");
-        let mut mapdata = MapData {
-            mpq_archive: mpq_archive,
-            owners: [0 as u8; 12],
-            tileset: TileSet::Badlands,
-            width: 0,
-            height: 0,
-            mtxm: Vec::<u16>::new(),
-            units: Vec::<MapUnit>::new(),
-            sprites: Vec::<MapSprite>::new(),
-            strings: Vec::<String>::new(),
-            scenario_name_str_idx: 0,
-            scenario_desc_str_idx: 0,
+        let mapdata = if filename.to_lowercase().ends_with(".chk") {
+            let mut chk_file = try!(File::open(filename));
+            MapData::read_chk(&mut chk_file, None)
+        } else {
+            let mpq_archive = try!(MPQArchive::open(filename));
+            let mut chk_file = try!(mpq_archive.open_file("staredit/scenario.chk"));
+            MapData::read_chk(&mut chk_file, Some(mpq_archive))
         };
-        while let Some(_) = mapdata.read_section(&mut chk_file) {
-        }
+        Ok(Map::from_mapdata(gd, mapdata))
+    }
+
+    pub fn from_mapdata(gd: &GameDataTrait, mapdata: MapData) -> Map {
         println!("loading terrain");
         let ti = TerrainInfo::read(gd, mapdata.tileset);
 
         println!("{} units", mapdata.units.len());
 
-        let passable_megatiles = Map::passable_megatiles(&mapdata, &ti);
+        let terrain_grid = TerrainGrid::new(&mapdata, &ti);
+        let passable_megatiles = Map::passable_megatiles(&terrain_grid);
         Map {
             data: mapdata,
             terrain_info: ti,
             passable_megatiles: passable_megatiles,
+            terrain_grid: terrain_grid,
         }
     }
 
@@ -575,13 +1034,7 @@ impl Map {
         for y in 0..self.data.height as usize {
             for x in 0..self.data.width as usize {
                 let mtxm_idx = self.data.mtxm[y * self.data.width as usize + x];
-                let cv5_id = mtxm_idx >> 4;
-                let sub_id = mtxm_idx & 0x000F;
-                let mega_tile_idx = if cv5_id < 1024 {
-                    self.terrain_info.cv5[cv5_id as usize].mega_tiles[sub_id as usize]
-                } else {
-                    self.terrain_info.doodads[(cv5_id as usize) - 1024].mega_tiles[sub_id as usize]
-                };
+                let mega_tile_idx = self.terrain_info.megatile_idx(mtxm_idx);
 
                 let mut col_counts = vec![0 as usize; 256];
                 let vx4 = &self.terrain_info.vx4[mega_tile_idx as usize];
@@ -606,20 +1059,22 @@ impl Map {
         minimap
     }
 
-    pub fn name(&self) -> &str {
-        if self.data.scenario_name_str_idx > 0 {
-            &self.data.strings[self.data.scenario_name_str_idx - 1]
+    /// string n of the STR section (1 based), None for 0 and invalid
+    /// references
+    pub fn string(&self, idx: usize) -> Option<&str> {
+        if idx > 0 {
+            self.data.strings.get(idx - 1).map(|s| s.as_str())
         } else {
-            "unnamed"
+            None
         }
     }
 
+    pub fn name(&self) -> &str {
+        self.string(self.data.scenario_name_str_idx).unwrap_or("unnamed")
+    }
+
     pub fn description(&self) -> &str {
-        if self.data.scenario_desc_str_idx > 0 {
-            &self.data.strings[self.data.scenario_desc_str_idx - 1]
-        } else {
-            ""
-        }
+        self.string(self.data.scenario_desc_str_idx).unwrap_or("")
     }
 
     // XXX
@@ -687,15 +1142,151 @@ impl Map {
     }
 }
 
+/// Exact terrain information of a map, as needed for pathing, vision and
+/// building placement.
+///
+/// Walkability, ground height, "blocks view" and ramp flags come from VF4
+/// and are kept at minitile (8x8 px) resolution, buildability comes from
+/// CV5 and is only available per megatile (32x32 px).
+pub struct TerrainGrid {
+    /// map size in megatiles
+    pub width: usize,
+    pub height: usize,
+    /// VF4 flags, one entry per minitile, (4 * width) x (4 * height)
+    minitile_flags: Vec<u16>,
+    /// CV5 buildability flags, one entry per megatile
+    megatile_flags: Vec<u8>,
+}
+impl TerrainGrid {
+    pub fn new(mapdata: &MapData, ti: &TerrainInfo) -> TerrainGrid {
+        let width = mapdata.width as usize;
+        let height = mapdata.height as usize;
+        let mut minitile_flags = vec![0 as u16; width * height * 16];
+        let mut megatile_flags = vec![CV5_UNBUILDABLE; width * height];
+
+        for ty in 0..height {
+            for tx in 0..width {
+                let tile_idx = ty * width + tx;
+                // missing tiles are null tiles
+                let mtxm_idx = if tile_idx < mapdata.mtxm.len() {
+                    mapdata.mtxm[tile_idx]
+                } else {
+                    0
+                };
+                megatile_flags[tile_idx] = ti.buildability(mtxm_idx);
+                let flags = ti.minitile_flags(mtxm_idx);
+                for row in 0..4 {
+                    for col in 0..4 {
+                        let outpos = (ty * 4 + row) * (width * 4) + (tx * 4 + col);
+                        minitile_flags[outpos] = flags[row * 4 + col];
+                    }
+                }
+            }
+        }
+
+        TerrainGrid {
+            width: width,
+            height: height,
+            minitile_flags: minitile_flags,
+            megatile_flags: megatile_flags,
+        }
+    }
+
+    pub fn minitile_width(&self) -> usize {
+        self.width * 4
+    }
+    pub fn minitile_height(&self) -> usize {
+        self.height * 4
+    }
+
+    /// VF4 flags of a minitile, None if outside of the map
+    pub fn minitile_flags(&self, mx: i32, my: i32) -> Option<u16> {
+        if mx < 0 || my < 0 || mx as usize >= self.minitile_width() ||
+            my as usize >= self.minitile_height() {
+                return None;
+            }
+        Some(self.minitile_flags[my as usize * self.minitile_width() + mx as usize])
+    }
+
+    // minitile resolution
+    pub fn is_walkable(&self, mx: i32, my: i32) -> bool {
+        self.minitile_flags(mx, my).map_or(false, |f| f & VF4_WALKABLE > 0)
+    }
+    pub fn tile_height(&self, mx: i32, my: i32) -> TileHeight {
+        self.minitile_flags(mx, my).map_or(TileHeight::Low, vf4_tile_height)
+    }
+    pub fn blocks_view(&self, mx: i32, my: i32) -> bool {
+        self.minitile_flags(mx, my).map_or(false, |f| f & VF4_BLOCKS_VIEW > 0)
+    }
+    pub fn is_ramp(&self, mx: i32, my: i32) -> bool {
+        self.minitile_flags(mx, my).map_or(false, |f| f & VF4_RAMP > 0)
+    }
+
+    // pixel resolution (the shift also rounds negative coordinates down)
+    pub fn is_walkable_px(&self, x: i32, y: i32) -> bool {
+        self.is_walkable(x >> 3, y >> 3)
+    }
+    pub fn tile_height_px(&self, x: i32, y: i32) -> TileHeight {
+        self.tile_height(x >> 3, y >> 3)
+    }
+    pub fn blocks_view_px(&self, x: i32, y: i32) -> bool {
+        self.blocks_view(x >> 3, y >> 3)
+    }
+    pub fn is_ramp_px(&self, x: i32, y: i32) -> bool {
+        self.is_ramp(x >> 3, y >> 3)
+    }
+
+    // megatile resolution
+    pub fn is_buildable(&self, tx: i32, ty: i32) -> bool {
+        if tx < 0 || ty < 0 || tx as usize >= self.width || ty as usize >= self.height {
+            return false;
+        }
+        let flags = self.megatile_flags[ty as usize * self.width + tx as usize];
+        (flags & CV5_UNBUILDABLE) == 0
+    }
+    pub fn has_creep(&self, tx: i32, ty: i32) -> bool {
+        if tx < 0 || ty < 0 || tx as usize >= self.width || ty as usize >= self.height {
+            return false;
+        }
+        let flags = self.megatile_flags[ty as usize * self.width + tx as usize];
+        (flags & CV5_CREEP) > 0
+    }
+    /// true if all 16 minitiles of a megatile are walkable
+    pub fn is_megatile_walkable(&self, tx: usize, ty: usize) -> bool {
+        let mut all_walkable = true;
+        for row in 0..4 {
+            for col in 0..4 {
+                let walkable = self.is_walkable((tx * 4 + col) as i32, (ty * 4 + row) as i32);
+                all_walkable = all_walkable && walkable;
+            }
+        }
+        all_walkable
+    }
+    /// ground height of a megatile, the highest level of its minitiles
+    pub fn megatile_height(&self, tx: usize, ty: usize) -> TileHeight {
+        let mut res = TileHeight::Low;
+        for row in 0..4 {
+            for col in 0..4 {
+                let h = self.tile_height((tx * 4 + col) as i32, (ty * 4 + row) as i32);
+                if h > res {
+                    res = h;
+                }
+            }
+        }
+        res
+    }
+}
+
 // XXX can't use def_bin_struct with arrays
 pub struct CV5 {
     pub index: u16,
     pub buildability: u8,
     pub ground_height: u8,
-    // left_edge: u16,
-    // top_edge: u16,
-    // right_edge: u16,
-    // bottom_edge: u16,
+    // edge types, tiles fit next to each other if the touching edges match
+    pub left_edge: u16,
+    pub top_edge: u16,
+    pub right_edge: u16,
+    pub bottom_edge: u16,
     // dd_databin_idx: u16,
     // dd_width: u16,
     // dd_height: u16,
@@ -709,12 +1300,20 @@ impl CV5 {
             Ok(index) => {
                 let buildability = infile.read_u8().unwrap();
                 let ground_height = infile.read_u8().unwrap();
-                infile.seek(SeekFrom::Current(16)).ok();
+                let left_edge = infile.read_u16::<LittleEndian>().unwrap();
+                let top_edge = infile.read_u16::<LittleEndian>().unwrap();
+                let right_edge = infile.read_u16::<LittleEndian>().unwrap();
+                let bottom_edge = infile.read_u16::<LittleEndian>().unwrap();
+                infile.seek(SeekFrom::Current(8)).ok();
                 let mega_tiles = read_vec_u16(infile, 16);
                 Some(CV5 {
                     index: index,
                     buildability: buildability,
                     ground_height: ground_height,
+                    left_edge: left_edge,
+                    top_edge: top_edge,
+                    right_edge: right_edge,
+                    bottom_edge: bottom_edge,
                     mega_tiles: mega_tiles,
                 })
             }
@@ -818,11 +1417,39 @@ impl VR4 {
 struct VF4 {
     flags: [u16; 16],
 }
+
+// VF4 minitile flags
+pub const VF4_WALKABLE: u16 = 0x0001;
+pub const VF4_MID: u16 = 0x0002;
+/// both mid and high not set means low ground
+pub const VF4_HIGH: u16 = 0x0004;
+pub const VF4_BLOCKS_VIEW: u16 = 0x0008;
+/// appears in the middle of most ramps and stairs
+pub const VF4_RAMP: u16 = 0x0010;
+
+// CV5 buildability flags (lower byte of the CV5 flags)
+pub const CV5_CREEP: u8 = 0x40;
+pub const CV5_UNBUILDABLE: u8 = 0x80;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TileHeight {
     Low,
     Mid,
     High,
 }
+
+pub fn vf4_tile_height(flags: u16) -> TileHeight {
+    let mid_set = (flags & VF4_MID) > 0;
+    let high_set = (flags & VF4_HIGH) > 0;
+    if mid_set {
+        TileHeight::Mid
+    } else if high_set {
+        TileHeight::High
+    } else {
+        TileHeight::Low
+    }
+}
+
 impl VF4 {
     pub fn read(infile: &mut Read) -> Option<VF4> {
         let mut data = [0 as u16; 16];
@@ -842,18 +1469,10 @@ impl VF4 {
     }
 
     pub fn is_walkable(&self, idx: usize) -> bool {
-        (self.flags[idx] & 0x1) > 0
+        (self.flags[idx] & VF4_WALKABLE) > 0
     }
     pub fn tile_height(&self, idx: usize) -> TileHeight {
-        let mid_set = (self.flags[idx] & 0x2) > 0;
-        let high_set = (self.flags[idx] & 0x4) > 0;
-        if mid_set {
-            TileHeight::Mid
-        } else if high_set {
-            TileHeight::High
-        } else {
-            TileHeight::Low
-        }
+        vf4_tile_height(self.flags[idx])
     }
 }
 
@@ -931,6 +1550,66 @@ impl TerrainInfo {
         }
     }
 
+    /// the (non-doodad) tile groups of the tileset
+    pub fn cv5_groups(&self) -> &[CV5] {
+        &self.cv5
+    }
+
+    pub fn cv5_count(&self) -> usize {
+        self.cv5.len()
+    }
+    pub fn doodad_count(&self) -> usize {
+        self.doodads.len()
+    }
+    pub fn megatile_count(&self) -> usize {
+        self.vx4.len()
+    }
+
+    /// true if the MTXM tile refers to an existing tile group and megatile
+    pub fn is_valid_tile(&self, mtxm_idx: u16) -> bool {
+        let cv5_id = (mtxm_idx >> 4) as usize;
+        let sub_id = (mtxm_idx & 0x000F) as usize;
+        let megatile = if cv5_id < 1024 {
+            self.cv5.get(cv5_id).map(|c| c.mega_tiles[sub_id])
+        } else {
+            self.doodads.get(cv5_id - 1024).map(|d| d.mega_tiles[sub_id])
+        };
+        match megatile {
+            Some(idx) => (idx as usize) < self.vx4.len() && (idx as usize) < self.vf4.len(),
+            None => false,
+        }
+    }
+
+    /// VF4 flags of the 16 minitiles of a MTXM tile
+    pub fn minitile_flags(&self, mtxm_idx: u16) -> [u16; 16] {
+        self.vf4[self.megatile_idx(mtxm_idx)].flags
+    }
+
+    /// vx4/vf4 index of a MTXM tile, invalid tiles are treated as null tiles
+    pub fn megatile_idx(&self, mtxm_idx: u16) -> usize {
+        if !self.is_valid_tile(mtxm_idx) {
+            return 0;
+        }
+        let cv5_id = mtxm_idx >> 4;
+        let sub_id = mtxm_idx & 0x000F;
+        (if cv5_id < 1024 {
+            self.cv5[cv5_id as usize].mega_tiles[sub_id as usize]
+        } else {
+            self.doodads[(cv5_id as usize) - 1024].mega_tiles[sub_id as usize]
+        }) as usize
+    }
+
+    /// CV5 buildability flags of a MTXM tile
+    pub fn buildability(&self, mtxm_idx: u16) -> u8 {
+        let cv5_id = (mtxm_idx >> 4) as usize;
+        let flags = if cv5_id < 1024 {
+            self.cv5.get(cv5_id).map(|c| c.buildability)
+        } else {
+            self.doodads.get(cv5_id - 1024).map(|d| d.buildability)
+        };
+        flags.unwrap_or(CV5_UNBUILDABLE)
+    }
+
     pub fn render_mtxm(&self,
                        mtxm_idx: u16,
                        buffer: &mut [u8],
@@ -938,13 +1617,7 @@ impl TerrainInfo {
                        y: i32,
                        stride: usize,
                        buffer_height: usize) {
-        let cv5_id = mtxm_idx >> 4;
-        let sub_id = mtxm_idx & 0x000F;
-        let mega_tile_idx = if cv5_id < 1024 {
-            self.cv5[cv5_id as usize].mega_tiles[sub_id as usize]
-        } else {
-            self.doodads[(cv5_id as usize) - 1024].mega_tiles[sub_id as usize]
-        };
+        let mega_tile_idx = self.megatile_idx(mtxm_idx);
         //self.render_mega_tile_debug(mega_tile_idx as usize, buffer, x, y, stride, buffer_height);
         self.render_mega_tile(mega_tile_idx as usize, buffer, x, y, stride, buffer_height);
     }
@@ -1046,7 +1719,7 @@ impl TerrainInfo {
                               y: i32,
                               stride: usize,
                               buffer_height: usize) {
-        let walkable = self.vf4[vx4_idx].is_walkable(vf4_idx);
+        let walkable = self.vf4[vx4_idx].flags[vf4_idx] & VF4_WALKABLE > 0;
         for row in 0..8 {
             for col in 0..8 {
                 if (y + row as i32) < 0 || (x + col as i32) < 0 ||
@@ -0,0 +1,115 @@
+use std::io::Read;
+
+use ::byteorder::{LittleEndian, ReadBytesExt};
+
+// triggers, as stored in the TRIG (and MBRF) section of a map
+
+def_bin_struct! (
+    TrigCondition {
+// u32: Location number for the condition (1 based -- 0 refers to No Location)
+        location: u32,
+// u32: Group that the condition applies to
+        group: u32,
+// u32: Qualified number (how many/resource amount)
+        qty: u32,
+// u16: Unit ID condition applies to
+        unit_id: u16,
+// u8: Numeric comparison, switch state
+        comparison: u8,
+// u8: Condition byte, 0 means unused
+        condition: u8,
+// u8: Resource type, score type, Switch number (0-based)
+        resource_type: u8,
+// u8: Flags
+// Bit 0 - Unknown/unused
+// Bit 1 - Enabled flag. If on, the trigger action/condition is disabled.
+// Bit 4 - Unit type is used
+        flags: u8,
+        _unused: u16
+    }
+);
+
+def_bin_struct! (
+    TrigAction {
+// u32: Location - source location in "Order" and "Move Unit", dest location in "Move Location" (1 based -- 0 refers to No Location)
+        location: u32,
+// u32: String number for trigger text (0 means no string)
+        string: u32,
+// u32: WAV string number (0 means no string)
+        wav_string: u32,
+// u32: Seconds/milliseconds of time
+        time: u32,
+// u32: First (or only) Group/Player affected.
+        player: u32,
+// u32: Second group affected, secondary location (1-based), CUWP #, number, AI script (4-byte string), switch (0-based #)
+        param: u32,
+// u16: Unit type, score type, resource type, alliance status
+        unit_type: u16,
+// u8: Action byte, 0 means unused
+        action: u8,
+// u8: Number of units (0 means All Units), action state, unit order, number modifier
+        modifier: u8,
+// u8: Flags
+// Bit 0 - Ignore a wait/transmission once.
+// Bit 1 - Enabled flag. If on, the trigger action/condition is disabled.
+// Bit 2 - Always display text flags.
+// Bit 3 - Unit property is used
+// Bit 4 - Unit type is used
+// Bit 5 - If on, the unit ID is used
+        flags: u8,
+        _padding1: u8,
+        _padding2: u8,
+        _padding3: u8
+    }
+);
+
+pub struct Trigger {
+    /// 16 conditions, unused ones have condition 0
+    pub conditions: Vec<TrigCondition>,
+    /// 64 actions, unused ones have action 0
+    pub actions: Vec<TrigAction>,
+// u32: execution flags
+// Bit 0 - Ignore the following actions: Defeat, Draw.
+// Bit 1 - Preserve trigger. (Can replace Preserve Trigger action)
+// Bit 2 - Ignore execution.
+// Bit 3 - Ignore all of the following actions: Wait, PauseGame, Transmission, PlayWAV, DisplayTextMessage, CenterView, MinimapPing, TalkingPortrait, and MuteUnitSpeech.
+    pub execution_flags: u32,
+    /// u8[27]: 1 byte for each player / group the trigger is executed for
+    /// (0 - not executed, 1 - executed), followed by the current action
+    pub players: Vec<u8>,
+    pub current_action: u8,
+}
+
+/// size of a trigger in bytes
+pub const TRIGGER_SIZE: usize = 2400;
+
+impl Trigger {
+    pub fn read(file: &mut Read) -> Trigger {
+        let mut conditions = Vec::<TrigCondition>::with_capacity(16);
+        for _ in 0..16 {
+            conditions.push(TrigCondition::read(file));
+        }
+        let mut actions = Vec::<TrigAction>::with_capacity(64);
+        for _ in 0..64 {
+            actions.push(TrigAction::read(file));
+        }
+        let execution_flags = file.read_u32::<LittleEndian>().unwrap();
+        let mut players = vec![0 as u8; 27];
+        file.read_exact(&mut players).unwrap();
+        let current_action = file.read_u8().unwrap();
+        Trigger {
+            conditions: conditions,
+            actions: actions,
+            execution_flags: execution_flags,
+            players: players,
+            current_action: current_action,
+        }
+    }
+
+    pub fn used_conditions(&self) -> Vec<&TrigCondition> {
+        self.conditions.iter().filter(|c| c.condition != 0).collect()
+    }
+    pub fn used_actions(&self) -> Vec<&TrigAction> {
+        self.actions.iter().filter(|a| a.action != 0).collect()
+    }
+}
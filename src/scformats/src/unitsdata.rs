@@ -155,6 +155,7 @@ dat_struct! (
 );
 
 enum_from_primitive! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum WeaponsDamageType {
         Independent = 0,
         Explosive,
@@ -164,6 +165,7 @@ enum_from_primitive! {
     }
 }
 enum_from_primitive! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum WeaponsExplosionType {
         None,
         Normal,
@@ -193,6 +195,7 @@ enum_from_primitive! {
     }
 }
 enum_from_primitive! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum WeaponBehavior {
         FlyToTarget,
         FlyToTarget2,
@@ -273,6 +276,57 @@ dat_struct! (
     }
 );
 
+bitflags! {
+    // units.dat special ability flags
+    pub flags UnitFlags: u32 {
+        const UF_BUILDING = 0x00000001,
+        const UF_ADDON = 0x00000002,
+        const UF_FLYER = 0x00000004,
+        const UF_WORKER = 0x00000008,
+        const UF_SUBUNIT = 0x00000010,
+        const UF_FLYING_BUILDING = 0x00000020,
+        const UF_HERO = 0x00000040,
+        const UF_REGENERATE = 0x00000080,
+        const UF_ANIMATED_IDLE = 0x00000100,
+        const UF_CLOAKABLE = 0x00000200,
+        const UF_TWO_UNITS_IN_EGG = 0x00000400,
+        const UF_SINGLE_ENTITY = 0x00000800,
+        const UF_RESOURCE_DEPOT = 0x00001000,
+        const UF_RESOURCE_CONTAINER = 0x00002000,
+        const UF_ROBOTIC = 0x00004000,
+        const UF_DETECTOR = 0x00008000,
+        const UF_ORGANIC = 0x00010000,
+        const UF_REQUIRES_CREEP = 0x00020000,
+        const UF_UNUSED = 0x00040000,
+        const UF_REQUIRES_PSI = 0x00080000,
+        const UF_BURROWABLE = 0x00100000,
+        const UF_SPELLCASTER = 0x00200000,
+        const UF_PERMANENT_CLOAK = 0x00400000,
+        const UF_PICKUP_ITEM = 0x00800000,
+        const UF_IGNORE_SUPPLY_CHECK = 0x01000000,
+        const UF_USE_MEDIUM_OVERLAYS = 0x02000000,
+        const UF_USE_LARGE_OVERLAYS = 0x04000000,
+        const UF_BATTLE_REACTIONS = 0x08000000,
+        const UF_FULL_AUTO_ATTACK = 0x10000000,
+        const UF_INVINCIBLE = 0x20000000,
+        const UF_MECHANICAL = 0x40000000,
+        const UF_PRODUCES_UNITS = 0x80000000
+    }
+}
+
+impl UnitsDat {
+    pub fn flags(&self, unit_id: usize) -> UnitFlags {
+        UnitFlags::from_bits_truncate(self.special_ability_flags[unit_id])
+    }
+}
+
+// unit ids of special units
+pub const UNIT_MINERAL_FIELD_1: u16 = 176;
+pub const UNIT_MINERAL_FIELD_2: u16 = 177;
+pub const UNIT_MINERAL_FIELD_3: u16 = 178;
+pub const UNIT_VESPENE_GEYSER: u16 = 188;
+pub const UNIT_START_LOCATION: u16 = 214;
+
 dat_struct! (
     WeaponsDat
     {
@@ -361,6 +415,50 @@ dat_struct! (
     }
 );
 
+dat_struct! (
+    UpgradesDat
+    {
+// Costs and research time of level n (starting at 0) are
+// base + n * factor.
+        mineral_cost_base: u16; 61,
+        mineral_cost_factor: u16; 61,
+        vespene_cost_base: u16; 61,
+        vespene_cost_factor: u16; 61,
+        research_time_base: u16; 61,
+        research_time_factor: u16; 61,
+        unknown: u16; 61,
+// [pointer to a frame in unit\cmdbtns\cmdicons.grp]
+        icon: u16; 61,
+// [pointer to stat_txt.tbl]
+        label: u16; 61,
+// 0 - Zerg, 1 - Terran, 2 - Protoss, 4 - All
+        race: u8; 61,
+// Maximum number of times the upgrade can be researched.
+        max_repeats: u8; 61,
+        broodwar_only: u8; 61
+    }
+);
+
+dat_struct! (
+    TechDataDat
+    {
+        mineral_cost: u16; 44,
+        vespene_cost: u16; 44,
+        research_time: u16; 44,
+// Energy needed to use the technology.
+        energy_required: u16; 44,
+        unknown: u32; 44,
+// [pointer to a frame in unit\cmdbtns\cmdicons.grp]
+        icon: u16; 44,
+// [pointer to stat_txt.tbl]
+        label: u16; 44,
+// 0 - Zerg, 1 - Terran, 2 - Protoss, 4 - All
+        race: u8; 44,
+        researched: u8; 44,
+        broodwar_only: u8; 44
+    }
+);
+
 dat_struct! (
     OrdersDat
     {
@@ -394,3 +492,223 @@ dat_struct! (
         obscured: u8; 189
     }
 );
+
+enum_from_primitive! {
+    /// entries of orders.dat
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum OrderType {
+        Die = 0,
+        Stop,
+        Guard,
+        PlayerGuard,
+        TurretGuard,
+        BunkerGuard,
+        Move,
+        ReaverStop,
+        Attack1,
+        Attack2,
+        AttackUnit,
+        AttackFixedRange,
+        AttackTile,
+        Hover,
+        AttackMove,
+        InfestedCommandCenter,
+        UnusedNothing,
+        UnusedPowerup,
+        TowerGuard,
+        TowerAttack,
+        VultureMine,
+        StayInRange,
+        TurretAttack,
+        Nothing,
+        Unused24,
+        DroneStartBuild,
+        DroneBuild,
+        CastInfestation,
+        MoveToInfest,
+        InfestingCommandCenter,
+        PlaceBuilding,
+        PlaceProtossBuilding,
+        CreateProtossBuilding,
+        ConstructingBuilding,
+        Repair,
+        MoveToRepair,
+        PlaceAddon,
+        BuildAddon,
+        Train,
+        RallyPointUnit,
+        RallyPointTile,
+        ZergBirth,
+        ZergUnitMorph,
+        ZergBuildingMorph,
+        IncompleteBuilding,
+        IncompleteMorphing,
+        BuildNydusExit,
+        EnterNydusCanal,
+        IncompleteWarping,
+        Follow,
+        Carrier,
+        ReaverCarrierMove,
+        CarrierStop,
+        CarrierAttack,
+        CarrierMoveToAttack,
+        CarrierIgnore2,
+        CarrierFight,
+        CarrierHoldPosition,
+        Reaver,
+        ReaverAttack,
+        ReaverMoveToAttack,
+        ReaverFight,
+        ReaverHoldPosition,
+        TrainFighter,
+        InterceptorAttack,
+        ScarabAttack,
+        RechargeShieldsUnit,
+        RechargeShieldsBattery,
+        ShieldBattery,
+        InterceptorReturn,
+        DroneLand,
+        BuildingLand,
+        BuildingLiftOff,
+        DroneLiftOff,
+        LiftingOff,
+        ResearchTech,
+        Upgrade,
+        Larva,
+        SpawningLarva,
+        Harvest1,
+        Harvest2,
+        MoveToGas,
+        WaitForGas,
+        HarvestGas,
+        ReturnGas,
+        MoveToMinerals,
+        WaitForMinerals,
+        MiningMinerals,
+        Harvest3,
+        Harvest4,
+        ReturnMinerals,
+        Interrupted,
+        EnterTransport,
+        PickupIdle,
+        PickupTransport,
+        PickupBunker,
+        Pickup4,
+        PowerupIdle,
+        Sieging,
+        Unsieging,
+        WatchTarget,
+        InitCreepGrowth,
+        SpreadCreep,
+        StoppingCreepGrowth,
+        GuardianAspect,
+        ArchonWarp,
+        CompletingArchonSummon,
+        HoldPosition,
+        QueenHoldPosition,
+        Cloak,
+        Decloak,
+        Unload,
+        MoveUnload,
+        FireYamatoGun,
+        MoveToFireYamatoGun,
+        CastLockdown,
+        Burrowing,
+        Burrowed,
+        Unburrowing,
+        CastDarkSwarm,
+        CastParasite,
+        CastSpawnBroodlings,
+        CastEMPShockwave,
+        NukeWait,
+        NukeTrain,
+        NukeLaunch,
+        NukePaint,
+        NukeUnit,
+        CastNuclearStrike,
+        NukeTrack,
+        InitializeArbiter,
+        CloakNearbyUnits,
+        PlaceMine,
+        RightClickAction,
+        SuicideUnit,
+        SuicideLocation,
+        SuicideHoldPosition,
+        CastRecall,
+        Teleport,
+        CastScannerSweep,
+        Scanner,
+        CastDefensiveMatrix,
+        CastPsionicStorm,
+        CastIrradiate,
+        CastPlague,
+        CastConsume,
+        CastEnsnare,
+        CastStasisField,
+        CastHallucination,
+        Hallucination2,
+        ResetCollision,
+        ResetHarvestCollision,
+        Patrol,
+        CTFCOPInit,
+        CTFCOPStarted,
+        CTFCOP2,
+        ComputerAI,
+        AtkMoveEP,
+        HarvestMove,
+        AIPatrol,
+        GuardPost,
+        RescuePassive,
+        Neutral,
+        ComputerReturn,
+        InitializePsiProvider,
+        SelfDestructing,
+        Critter,
+        HiddenGun,
+        OpenDoor,
+        CloseDoor,
+        HideTrap,
+        RevealTrap,
+        EnableDoodad,
+        DisableDoodad,
+        WarpIn,
+        Medic,
+        MedicHeal,
+        HealMove,
+        MedicHoldPosition,
+        MedicHealToIdle,
+        CastRestoration,
+        CastDisruptionWeb,
+        CastMindControl,
+        DarkArchonMeld,
+        CastFeedback,
+        CastOpticalFlare,
+        CastMaelstrom,
+        JunkYardDog,
+        Fatal,
+    }
+}
+
+bitflags! {
+    // weapons.dat target flags
+    pub flags WeaponTargetFlags: u16 {
+        const WT_AIR = 0x001,
+        const WT_GROUND = 0x002,
+        const WT_MECHANICAL = 0x004,
+        const WT_ORGANIC = 0x008,
+        const WT_NON_BUILDING = 0x010,
+        const WT_NON_ROBOTIC = 0x020,
+        const WT_TERRAIN = 0x040,
+        const WT_ORGANIC_OR_MECHANICAL = 0x080,
+        const WT_OWN = 0x100
+    }
+}
+
+/// weapon id for "no weapon" in units.dat
+pub const WEAPON_NONE: u8 = 130;
+
+impl WeaponsDat {
+    pub fn target_flags(&self, weapon_id: usize) -> WeaponTargetFlags {
+        WeaponTargetFlags::from_bits_truncate(self.target_flags[weapon_id])
+    }
+}
@@ -5,13 +5,17 @@ use scrust::font::Font;
 use scrust::tbl::read_tbl;
 use scrust::lox::read_lox_overlay_offsets;
 use scrust::spk::SPK;
+use scrust::terrain::{Map, MapData, TileSet, GameDataTrait, ResearchSettings,
+                       FORCE_ALLIES};
+use scrust::isom::{IsomMap, IsomTerrain, count_mismatches};
 
 use std::fs::File;
+use std::io::Cursor;
 
 #[test]
 fn read_grp() {
-    let mpq = MPQArchive::open("/home/dm/code/mysc/data/STARDAT.MPQ");
-    let mut file = mpq.open_file("unit\\cmdbtns\\cmdicons.grp");
+    let mpq = MPQArchive::open("/home/dm/code/mysc/data/STARDAT.MPQ").unwrap();
+    let mut file = mpq.open_file("unit\\cmdbtns\\cmdicons.grp").unwrap();
 
     let grp = GRP::read(&mut file);
     let ref grpheader = grp.header;
@@ -26,8 +30,8 @@ fn read_grp() {
 
 #[test]
 fn read_fnt() {
-    let mpq = MPQArchive::open("/home/dm/code/mysc/data/install.exe");
-    let mut file = mpq.open_file("files\\font\\font14.fnt");
+    let mpq = MPQArchive::open("/home/dm/code/mysc/data/install.exe").unwrap();
+    let mut file = mpq.open_file("files\\font\\font14.fnt").unwrap();
     let fnt = Font::read(&mut file);
     println!("low-id: {}, high-idx: {}, max-width: {}, max-height: {}",
              fnt.header.low_idx, fnt.header.high_idx,
@@ -53,8 +57,8 @@ fn read_tbl_test() {
 
 #[test]
 fn read_lox() {
-    let mpq = MPQArchive::open("/home/dm/code/mysc/data/STARDAT.MPQ");
-    let mut file = mpq.open_file("unit/neutral/geyser.los");
+    let mpq = MPQArchive::open("/home/dm/code/mysc/data/STARDAT.MPQ").unwrap();
+    let mut file = mpq.open_file("unit/neutral/geyser.los").unwrap();
     let lo_offsets = read_lox_overlay_offsets(&mut file);
     println!("frames: {}, overlays per frame: {}", lo_offsets.len(), lo_offsets[0].len());
 
@@ -68,3 +72,133 @@ fn read_lox() {
     assert_eq!(lo_offsets[0][4], (127, 127));
 }
 
+
+struct StarDat {
+    mpq: MPQArchive,
+}
+impl GameDataTrait for StarDat {
+    fn open(&self, filename: &str) -> Option<Cursor<Vec<u8>>> {
+        if self.mpq.has_file(filename) {
+            self.mpq.open_file(filename).ok()
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn regenerate_mtxm() {
+    let gd = StarDat { mpq: MPQArchive::open("/home/dm/code/mysc/data/STARDAT.MPQ").unwrap() };
+    let map = Map::read(&gd, "/home/dm/code/mysc/maps/(2)Challenger.scm").unwrap();
+    let terrain = IsomTerrain::new(&map.terrain_info);
+    let mut isom = IsomMap::from_mapdata(&map.data).unwrap();
+    let w = map.data.width as usize;
+    let h = map.data.height as usize;
+
+    let mut mtxm = isom.regenerate_mtxm(&terrain, w, h);
+    let mismatches = count_mismatches(&map.data.mtxm, &mtxm);
+    println!("{} terrain types, {} of {} tiles differ",
+             terrain.terrain_types.len(), mismatches, w * h);
+    // the editor picks any of the pairs with matching edges and we take the
+    // first one (see the isom module), but the map's tile has to be one of
+    // them wherever the model knows the rectangle
+    let mut unknown = 0;
+    for y in 0..h {
+        for x in 0..w {
+            let group = map.data.mtxm[y * w + x] >> 4;
+            if group >= 1024 {
+                continue;
+            }
+            let candidates = terrain.all_groups_for(isom.rect(x / 2, y).unwrap());
+            if candidates.is_empty() {
+                unknown += 1;
+                continue;
+            }
+            assert!(candidates.iter().any(|&(l, r)| group == if x % 2 == 0 { l } else { r }),
+                    "tile ({}, {}): group {} is not one of {:?}",
+                    x,
+                    y,
+                    group,
+                    candidates);
+        }
+    }
+    println!("{} tiles without isom candidates", unknown);
+
+    // painting a diamond gives it the solid tiles of the terrain type
+    let terrain_type = terrain.terrain_types[0];
+    isom.brush(&terrain, 4, 4, terrain_type, &mut mtxm, w, h);
+    assert_eq!(isom.diamond(4, 4), Some(terrain_type));
+    let (left, right) = terrain.groups_for(isom.rect(4, 4).unwrap()).unwrap();
+    assert_eq!(mtxm[4 * w + 8] >> 4, left);
+    assert_eq!(mtxm[4 * w + 9] >> 4, right);
+}
+
+#[test]
+fn read_research_settings() {
+    // two entries: player maximum, player start, global maximum, global start, defaults
+    let mut data = vec![0 as u8; 12 * 2 * 3 + 2 * 2];
+    data[0] = 1;
+    data[1] = 2;
+    data[24 + 1] = 1;
+    data[48] = 3;
+    data[49] = 3;
+    data[50] = 1;
+    for p in 1..12 {
+        data[52 + p * 2] = 1;
+        data[52 + p * 2 + 1] = 1;
+    }
+    let settings = ResearchSettings::read(&mut Cursor::new(data), 2);
+    assert_eq!(settings.len(), 2);
+    assert_eq!(settings.max(0, 1), Some(2));
+    assert_eq!(settings.start(0, 1), Some(1));
+    assert_eq!(settings.max(1, 0), Some(3));
+    assert_eq!(settings.start(1, 0), Some(1));
+    assert_eq!(settings.start(1, 1), Some(0));
+    assert_eq!(settings.max(0, 2), None);
+}
+
+#[test]
+fn write_chk_round_trip() {
+    let mut map = MapData::new(TileSet::Jungle, 64, 64);
+    map.forces.player_force = [0, 1, 0, 1, 2, 2, 3, 3];
+    map.forces.names[1] = 5;
+    map.forces.flags[0] = FORCE_ALLIES;
+    let mut chk = Vec::<u8>::new();
+    map.write_chk(&mut chk);
+    let read = MapData::read_chk(&mut Cursor::new(chk), None);
+    assert_eq!(read.width, 64);
+    assert_eq!(read.height, 64);
+    assert_eq!(read.forces, map.forces);
+    assert!(read.unknown_sections.is_empty());
+    assert!(read.problems.is_empty());
+}
+
+#[test]
+fn split_unit_sections() {
+    let mut chk = Vec::<u8>::new();
+    MapData::new(TileSet::Jungle, 64, 64).write_chk(&mut chk);
+    // two more UNIT sections with one unit each
+    for unit_id in 0..2 {
+        chk.extend(b"UNIT".iter());
+        chk.extend([36, 0, 0, 0].iter());
+        let mut unit = [0 as u8; 36];
+        unit[8] = unit_id;
+        chk.extend(unit.iter());
+    }
+    let read = MapData::read_chk(&mut Cursor::new(chk), None);
+    assert_eq!(read.units.len(), 2);
+    assert_eq!(read.units[1].unit_id, 1);
+}
+
+struct NoData;
+impl GameDataTrait for NoData {
+    fn open(&self, _: &str) -> Option<Cursor<Vec<u8>>> {
+        None
+    }
+}
+
+#[test]
+fn missing_map() {
+    assert!(Map::read(&NoData, "/nonexistent/map.chk").is_err());
+    assert!(Map::read(&NoData, "/nonexistent/map.scm").is_err());
+}
@@ -0,0 +1,437 @@
+//! Runs the unit simulation without any window, renderer or event pump, e.g.
+//! for regression tests that need to run thousands of ticks.
+
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use ecs::{World, Entity, ModifyData};
+
+use scformats::terrain::{Map, MapError};
+use vision::VisionGrid;
+
+use gamedata::GameData;
+use fixedpoint::fixed_to_px;
+use orders::{Order, issue_order};
+use iscriptsys::IScriptSteppingSys;
+use combat::{launch_weapon, deal_damage, is_dying};
+use construction::{start_construction, spawn_trained_unit};
+use tech::init_research;
+use abilities::cast_spell;
+use transport::{load_unit, unload_all, bunker_fire};
+use morph::{finish_morph, lift_off, land, merge, spawn_larva};
+use subunit::update_subunits;
+use triggers::{Triggers, run_triggers};
+use locations::Locations;
+use ai::{AI, run_ai};
+use unit_ecs::{UnitComponents, UnitSystems, IScriptEntityAction, PlanningMap, DatFiles,
+               PlayerState, MAX_PLAYERS, UnderlayComponent, OverlayComponent, create_scimage,
+               create_scsprite, create_map_unit};
+
+/// new unit world with the iscript system set up
+pub fn create_world(gd: &GameData, map: Option<Rc<PlanningMap>>) -> World<UnitSystems> {
+    let mut world = World::<UnitSystems>::new();
+    world.systems.iscript_stepping_sys.init(IScriptSteppingSys {
+        iscript_copy: gd.iscript.clone(),
+        images_dat: gd.images_dat.clone(),
+        weapons_dat: gd.weapons_dat.clone(),
+        lox_cache: gd.lox_cache.clone(),
+        iscript_entity_actions: Vec::<IScriptEntityAction>::new(),
+        interested: HashMap::default(),
+    });
+    if let Some(ref map) = map {
+        world.data.services.vision = VisionGrid::new(&map.scmap.terrain_grid);
+        world.data.services.triggers = Some(Triggers::new(map.clone()));
+        world.data.services.locations = Locations::new(&map.scmap);
+        world.data.services.ai = Some(AI::new(map));
+    }
+    world.systems.scunit_stepping_sys.map = map;
+    world.data.services.dat = Some(Rc::new(DatFiles::new(gd)));
+    world.data.services.players = vec![PlayerState::new(); MAX_PLAYERS];
+    init_research(&mut world.data.services.players,
+                  &gd.upgrades_dat,
+                  &gd.techdata_dat,
+                  world.systems.scunit_stepping_sys.map.as_ref().map(|m| &m.scmap.data));
+    world
+}
+
+/// runs all systems for one game tick
+pub fn step_world(world: &mut World<UnitSystems>, gd: &GameData) {
+    // interpret iscript for units
+    world.update();
+    world.flush_queue();
+
+    apply_iscript_actions(world, gd);
+    update_subunits(world, gd);
+    run_triggers(world, gd);
+    run_ai(world, gd);
+}
+
+/// removes an entity together with its overlays and underlays
+pub fn remove_entity_rec(world: &mut World<UnitSystems>, entity: Entity) {
+    let children = match world.with_entity_data(&entity, |e, data| {
+        data.iscript_state[e].alive = false;
+        data.iscript_state[e].children.clone()
+    }) {
+        Some(children) => children,
+        // already gone
+        None => return,
+    };
+    world.remove_entity(entity);
+    for c in children {
+        remove_entity_rec(world, c);
+    }
+}
+
+/// creates and removes the entities requested by iscript during the last update
+pub fn apply_iscript_actions(world: &mut World<UnitSystems>, gd: &GameData) {
+    let actions = mem::replace(&mut world.systems
+                                   .iscript_stepping_sys
+                                   .inner
+                                   .as_mut()
+                                   .unwrap()
+                                   .iscript_entity_actions,
+                               Vec::<IScriptEntityAction>::new());
+    let weapon_actions = mem::replace(&mut world.systems.weapon_stepping_sys.actions,
+                                      Vec::<IScriptEntityAction>::new());
+    let unit_actions = mem::replace(&mut world.systems.scunit_stepping_sys.actions,
+                                    Vec::<IScriptEntityAction>::new());
+    for action in actions.into_iter()
+        .chain(weapon_actions.into_iter())
+        .chain(unit_actions.into_iter()) {
+        match action {
+            IScriptEntityAction::RemoveEntity { entity } => {
+                remove_entity_rec(world, entity);
+            }
+            IScriptEntityAction::CreateImageUnderlay { parent, image_id, rel_x, rel_y } => {
+                let (par_mx, par_my, player_id) = world.with_entity_data(&parent, |e, data| {
+                    (data.iscript_state[e].map_pos_x, data.iscript_state[e].map_pos_y,
+                    data.scimage[e].player_id)
+                    }).expect("couldn't get parent map pos");
+                let ent =
+                    create_scimage(world, gd, image_id as usize, par_mx, par_my,
+                                   Some(parent), player_id);
+               world.modify_entity(parent, |e: ModifyData<UnitComponents>,
+                                          data: &mut UnitComponents| {
+                    data.iscript_state[e].children.push(ent);
+                });
+                world.modify_entity(ent, |e: ModifyData<UnitComponents>,
+                                          data: &mut UnitComponents| {
+                    data.iscript_state[e].rel_x = rel_x;
+                    data.iscript_state[e].rel_y = rel_y;
+                    data.underlay.insert(&e, UnderlayComponent {});
+                });
+
+            }
+            IScriptEntityAction::CreateImageOverlay { parent, image_id, rel_x, rel_y } => {
+                let (par_mx, par_my, player_id) =
+                    world.with_entity_data(&parent, |e, data| {
+                        (data.iscript_state[e].map_pos_x, data.iscript_state[e].map_pos_y,
+                        data.scimage[e].player_id)
+                    }).expect("couldn't get parent map pos");
+                let ent =
+                    create_scimage(world, gd, image_id as usize, par_mx, par_my,
+                                   Some(parent), player_id);
+                world.modify_entity(parent, |e: ModifyData<UnitComponents>,
+                                          data: &mut UnitComponents| {
+                    data.iscript_state[e].children.push(ent);
+                });
+                world.modify_entity(ent, |e: ModifyData<UnitComponents>,
+                                          data: &mut UnitComponents| {
+                    data.iscript_state[e].rel_x = rel_x;
+                    data.iscript_state[e].rel_y = rel_y;
+                    data.overlay.insert(&e, OverlayComponent {});
+                });
+            }
+            IScriptEntityAction::CreateSpriteOverlay { sprite_id, x, y } => {
+                let ent = create_scsprite(world, gd, sprite_id as usize, x, y, None, 0);
+                world.modify_entity(ent, |e: ModifyData<UnitComponents>,
+                                          data: &mut UnitComponents| {
+                    data.overlay.insert(&e, OverlayComponent {});
+                });
+            }
+            IScriptEntityAction::CreateSpriteUnderlay { parent,
+                                                        sprite_id,
+                                                        x,
+                                                        y,
+                                                        use_parent_dir } => {
+                let ent =
+                    create_scsprite(world, gd, sprite_id as usize, x, y, parent, 0);
+                let parent_dir = if use_parent_dir {
+                    world.with_entity_data(&parent.unwrap(), |e, data| {
+                        data.iscript_state[e].children.push(ent);
+                        data.iscript_state[e].direction
+                    })
+                } else {
+                    None
+                };
+                world.modify_entity(ent, |e: ModifyData<UnitComponents>,
+                                          data: &mut UnitComponents| {
+                    data.underlay.insert(&e, UnderlayComponent {});
+                    if let Some(initial_dir) = parent_dir {
+                        data.iscript_state[e].direction = initial_dir;
+                    }
+                });
+            }
+            IScriptEntityAction::CreateWeaponsFlingy { weapon_id,
+                                                       source,
+                                                       target,
+                                                       x,
+                                                       y,
+                                                       player_id } => {
+                launch_weapon(world,
+                              gd,
+                              weapon_id as usize,
+                              Some(source),
+                              target,
+                              x,
+                              y,
+                              player_id,
+                              0);
+            }
+            IScriptEntityAction::DealDamage { source } => {
+                deal_damage(world, gd, source);
+            }
+            IScriptEntityAction::StartConstruction { builder, unit_id, x, y } => {
+                // the builder just gives up if the site got blocked or the
+                // player can't pay anymore
+                let _ = start_construction(world, gd, builder, unit_id as usize, x as i32,
+                                           y as i32);
+            }
+            IScriptEntityAction::SpawnUnit { building, unit_id } => {
+                spawn_trained_unit(world, gd, building, unit_id as usize);
+            }
+            IScriptEntityAction::CastSpell { caster } => cast_spell(world, gd, caster),
+            IScriptEntityAction::LoadUnit { transport, unit } => {
+                load_unit(world, transport, unit);
+            }
+            IScriptEntityAction::UnloadAll { transport } => unload_all(world, transport),
+            IScriptEntityAction::BunkerFire { bunker, passenger, target, weapon_id } => {
+                bunker_fire(world, gd, bunker, passenger, target, weapon_id as usize);
+            }
+            IScriptEntityAction::Morph { unit, unit_id } => {
+                finish_morph(world, gd, unit, unit_id as usize)
+            }
+            IScriptEntityAction::LiftOff { building } => lift_off(world, gd, building),
+            IScriptEntityAction::Land { building, x, y } => {
+                land(world, gd, building, x as i32, y as i32);
+            }
+            IScriptEntityAction::Merge { unit, partner, unit_id } => {
+                merge(world, gd, unit, partner, unit_id as usize)
+            }
+            IScriptEntityAction::SpawnLarva { hatchery } => spawn_larva(world, gd, hatchery),
+            // _ => {
+            //     println!("ignoring {:?} iscript create action", action);
+            // }
+        }
+    }
+}
+
+/// an order for a map unit, given at a certain tick
+pub struct ScriptedCommand {
+    pub tick: u64,
+    /// index into the units of the map
+    pub unit: usize,
+    pub order: Order,
+    /// shift-queued
+    pub queued: bool,
+}
+
+/// the simulation relevant state of a unit
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UnitSnapshot {
+    pub unit_id: u16,
+    pub player_id: usize,
+    /// 1/256 pixels
+    pub exact_x: i32,
+    pub exact_y: i32,
+    pub direction: u8,
+    pub movement_direction: u8,
+    pub speed: i32,
+}
+
+pub struct Simulation {
+    pub gd: GameData,
+    pub map: Rc<PlanningMap>,
+    pub world: World<UnitSystems>,
+    /// number of ticks run so far
+    pub tick_count: u64,
+    /// entities of the map units, in map order
+    units: Vec<Entity>,
+    /// pending commands, sorted by tick
+    script: Vec<ScriptedCommand>,
+    /// scheduled commands the units couldn't execute
+    pub rejected_commands: usize,
+}
+impl Simulation {
+    pub fn new(gd: GameData, map: Map) -> Self {
+        let map = Rc::new(PlanningMap::new(map));
+        let mut world = create_world(&gd, Some(map.clone()));
+        let mut units = Vec::<Entity>::with_capacity(map.scmap.data.units.len());
+        for mapunit in &map.scmap.data.units {
+            units.push(create_map_unit(&mut world, &gd, mapunit));
+        }
+        Simulation {
+            gd: gd,
+            map: map,
+            world: world,
+            tick_count: 0,
+            units: units,
+            script: Vec::new(),
+            rejected_commands: 0,
+        }
+    }
+
+    pub fn from_files(data_path: &Path, map_path: &str) -> Result<Self, MapError> {
+        let gd = GameData::init(data_path);
+        let map = try!(Map::read(&gd, map_path));
+        Ok(Simulation::new(gd, map))
+    }
+
+    /// lets the computer play `player` with the melee script of its race
+    pub fn start_ai(&mut self, player: usize) {
+        if let Some(ai) = self.world.data.services.ai.as_mut() {
+            ai.start_melee(player);
+        }
+    }
+
+    pub fn unit_count(&self) -> usize {
+        self.units.len()
+    }
+    pub fn unit_entity(&self, unit: usize) -> Entity {
+        self.units[unit]
+    }
+
+    /// gives `order` to the map unit at the start of `tick`
+    pub fn schedule(&mut self, tick: u64, unit: usize, order: Order, queued: bool) {
+        assert!(tick >= self.tick_count, "can't schedule commands in the past");
+        let pos = self.script.iter().position(|c| c.tick > tick).unwrap_or(self.script.len());
+        self.script.insert(pos,
+                           ScriptedCommand {
+                               tick: tick,
+                               unit: unit,
+                               order: order,
+                               queued: queued,
+                           });
+    }
+
+    fn issue(&mut self, cmd: ScriptedCommand) {
+        let entity = self.units[cmd.unit];
+        if !issue_order(&mut self.world, entity, cmd.order, cmd.queued) {
+            self.rejected_commands += 1;
+        }
+    }
+
+    pub fn step(&mut self) {
+        while !self.script.is_empty() && self.script[0].tick <= self.tick_count {
+            let cmd = self.script.remove(0);
+            self.issue(cmd);
+        }
+        step_world(&mut self.world, &self.gd);
+        self.tick_count += 1;
+    }
+
+    pub fn run(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+    /// all map units that still exist
+    fn snapshots(&self) -> HashMap<Entity, UnitSnapshot> {
+        let dh = &self.world.data;
+        let mut res = HashMap::<Entity, UnitSnapshot>::new();
+        for e in self.world.entities() {
+            if !self.units.contains(&**e) {
+                continue;
+            }
+            res.insert(**e,
+                       UnitSnapshot {
+                           unit_id: dh.scunit[e].unit_id,
+                           player_id: dh.scimage[e].player_id,
+                           exact_x: dh.iscript_state[e].exact_x,
+                           exact_y: dh.iscript_state[e].exact_y,
+                           direction: dh.iscript_state[e].direction,
+                           movement_direction: dh.iscript_state[e].movement_direction,
+                           speed: dh.scflingy[e].speed,
+                       });
+        }
+        res
+    }
+
+    /// living units per player, also those made during the game
+    pub fn player_unit_counts(&self) -> Vec<usize> {
+        let dh = &self.world.data;
+        let mut counts = vec![0; MAX_PLAYERS];
+        for e in self.world
+            .entities()
+            .filter(aspect!(<UnitComponents> all: [scunit] none: [subunit]), &self.world) {
+            if !is_dying(e, dh) {
+                counts[dh.scimage[e].player_id] += 1;
+            }
+        }
+        counts
+    }
+
+    /// None if the unit doesn't exist anymore
+    pub fn unit(&self, unit: usize) -> Option<UnitSnapshot> {
+        self.snapshots().remove(&self.units[unit])
+    }
+
+    /// pixel position of a unit
+    pub fn unit_pos(&self, unit: usize) -> Option<(u16, u16)> {
+        self.unit(unit).map(|u| (fixed_to_px(u.exact_x) as u16, fixed_to_px(u.exact_y) as u16))
+    }
+
+    /// state of all map units, in map order
+    pub fn snapshot(&self) -> Vec<Option<UnitSnapshot>> {
+        let mut snapshots = self.snapshots();
+        self.units.iter().map(|e| snapshots.remove(e)).collect()
+    }
+
+    /// hash over the state of all map units, equal hashes mean equal runs
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.tick_count.hash(&mut hasher);
+        self.snapshot().hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::Simulation;
+    use orders::{Order, OrderTarget};
+    use scformats::unitsdata::OrderType;
+
+    fn run(ticks: u64) -> Simulation {
+        let mut sim = Simulation::from_files(&Path::new(&::scdata_path()), "test.scx").unwrap();
+        let (w, h) = (sim.map.scmap.data.width as i32, sim.map.scmap.data.height as i32);
+        for u in 0..sim.unit_count() {
+            let (x, y) = ((u as i32 * 97) % (w * 32), (u as i32 * 61) % (h * 32));
+            let order_type = if u % 2 == 0 {
+                OrderType::Move
+            } else {
+                OrderType::AttackMove
+            };
+            sim.schedule(u as u64 % 50,
+                         u,
+                         Order::new(order_type, OrderTarget::Position(x, y)),
+                         false);
+        }
+        sim.run(ticks);
+        sim
+    }
+
+    #[test]
+    fn deterministic() {
+        let a = run(2000);
+        let b = run(2000);
+        assert_eq!(a.snapshot(), b.snapshot());
+        assert_eq!(a.state_hash(), b.state_hash());
+    }
+}
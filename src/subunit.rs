@@ -0,0 +1,211 @@
+//! Subunits (units.dat subunit1): the turrets of Goliaths, Siege Tanks and
+//! Missile Turrets. A turret is a unit of its own with its own facing, iscript
+//! and orders, attached to its base at the offset the base image's special
+//! overlay LO file gives for the current frame. It can't be selected or
+//! targeted, damage goes to the base.
+
+use ecs::{World, Entity, EntityData, DataHelper, ModifyData};
+
+use scformats::unitsdata::OrderType;
+
+use gamedata::GameData;
+use fixedpoint::{px_to_fixed, dir256_to_dir32};
+use combat::{is_dying, kill_unit};
+use orders::{Order, OrderTarget};
+use transport::PassengerComponent;
+use unit_ecs::{UnitComponents, UnitServices, UnitSystems, SCUnitStep, SCImageComponent,
+               IScriptStateElement, OverlayComponent, create_scunit};
+
+/// units.dat subunit1 of units without a turret
+const NO_SUBUNIT: usize = 228;
+
+/// the turret part of a unit
+pub struct SubunitComponent {
+    pub base: Entity,
+}
+
+/// creates the turret of a unit, if it has one
+pub fn init_subunit(world: &mut World<UnitSystems>, gd: &GameData, entity: Entity) {
+    let info = world.with_entity_data(&entity, |e, data| {
+        (data.scunit[e].unit_id as usize,
+         data.scimage[e].player_id,
+         data.iscript_state[e].map_pos_x,
+         data.iscript_state[e].map_pos_y,
+         data.iscript_state[e].movement_direction)
+    });
+    let (unit_id, player, x, y, facing) = match info {
+        Some(info) => info,
+        None => return,
+    };
+    let turret_id = gd.units_dat.subunit1[unit_id] as usize;
+    if turret_id == NO_SUBUNIT {
+        return;
+    }
+    let turret = create_scunit(world, gd, turret_id, x, y, player);
+    world.modify_entity(turret,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        data.selectable.remove(&e);
+        // drawn above the base
+        data.overlay.insert(&e, OverlayComponent {});
+        data.subunit.insert(&e, SubunitComponent { base: entity });
+        data.iscript_state[e].parent_entity = Some(entity);
+        data.iscript_state[e].movement_direction = facing;
+        data.iscript_state[e].direction = dir256_to_dir32(facing);
+    });
+    // moves, hides and is removed together with the base
+    world.with_entity_data(&entity, |e, data| {
+        data.scunit[e].subunit = Some(turret);
+        data.iscript_state[e].children.push(turret);
+    });
+}
+
+pub fn is_subunit(e: EntityData<UnitComponents>,
+                  dh: &UnitComponents)
+                  -> bool {
+    dh.subunit.has(&e)
+}
+
+/// offset of the turret from the base for the base's current frame
+fn attach_offset(gd: &GameData,
+                 scimage: &SCImageComponent,
+                 state: &IScriptStateElement)
+                 -> (i32, i32) {
+    let lo_id = gd.images_dat.special_overlay[scimage.image_id as usize];
+    if lo_id == 0 {
+        return (0, 0);
+    }
+    let c = gd.lox_cache.borrow();
+    let lo = c.get_ro(lo_id);
+    let (x, y) = match lo.frames.get(scimage.frame_idx(state)).and_then(|f| f.offsets.get(0)) {
+        Some(&offset) => offset,
+        None => return (0, 0),
+    };
+    if scimage.draw_flipped(state) {
+        (-(x as i32), y as i32)
+    } else {
+        (x as i32, y as i32)
+    }
+}
+
+/// moves all turrets to the attachment point of their base
+pub fn update_subunits(world: &mut World<UnitSystems>, gd: &GameData) {
+    let mut positions = Vec::new();
+    {
+        let dh = &world.data;
+        for e in world.entities().filter(aspect!(<UnitComponents> all: [scunit]), world) {
+            let turret = match dh.scunit[e].subunit {
+                Some(turret) => turret,
+                None => continue,
+            };
+            let state = &dh.iscript_state[e];
+            let (dx, dy) = attach_offset(gd, &dh.scimage[e], state);
+            positions.push((turret,
+                            state.exact_x + px_to_fixed(dx),
+                            state.exact_y + px_to_fixed(dy)));
+        }
+    }
+    for (turret, x, y) in positions {
+        SCUnitStep::move_unit(world, turret, x, y);
+    }
+}
+
+/// the turret shoots at what the base attacks, otherwise it picks its own
+/// targets and faces where the base goes
+pub fn step_subunit(e: &EntityData<UnitComponents>,
+                    dh: &mut DataHelper<UnitComponents, UnitServices>) {
+    let turret = match dh.scunit[*e].subunit {
+        Some(turret) => turret,
+        None => return,
+    };
+    let order = dh.scunit[*e].orders.current;
+    let target = match order.order_type {
+        OrderType::AttackUnit | OrderType::Attack1 | OrderType::Attack2 => order.target,
+        _ => OrderTarget::None,
+    };
+    let facing = dh.iscript_state[*e].movement_direction;
+    dh.with_entity_data(&turret, |te, data| {
+        if is_dying(te, data) {
+            return;
+        }
+        let current = data.scunit[te].orders.current;
+        if let OrderTarget::Unit(_) = target {
+            if current.order_type != OrderType::TurretAttack || current.target != target {
+                data.scunit[te].orders.replace(Order::new(OrderType::TurretAttack, target));
+            }
+            return;
+        }
+        if current.order_type == OrderType::TurretAttack {
+            // the base gave up its target
+            data.scunit[te].orders.finish();
+        }
+        if data.scunit[te].orders.is_idle() {
+            data.iscript_state[te].movement_direction = facing;
+            data.iscript_state[te].direction = dir256_to_dir32(facing);
+        }
+    });
+}
+
+/// the turret dies with its base
+pub fn kill_subunit(e: &EntityData<UnitComponents>,
+                    dh: &mut DataHelper<UnitComponents, UnitServices>) {
+    if let Some(turret) = dh.scunit[*e].subunit.take() {
+        dh.with_entity_data(&turret, |te, data| if !is_dying(te, data) {
+            kill_unit(te, data);
+        });
+    }
+}
+
+/// the turret boards (Some) or leaves (None) a transport together with its base
+pub fn set_subunit_transport(world: &mut World<UnitSystems>,
+                             unit: Entity,
+                             transport: Option<Entity>) {
+    let turret = match world.with_entity_data(&unit, |e, data| data.scunit[e].subunit) {
+        Some(Some(turret)) => turret,
+        _ => return,
+    };
+    world.modify_entity(turret,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        match transport {
+            Some(transport) => {
+                data.passenger.insert(&e, PassengerComponent { transport: transport });
+            }
+            None => {
+                data.passenger.remove(&e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use ecs::Entity;
+    use combat::{is_dying, kill_entity};
+    use simulation::Simulation;
+    use unit_ecs::create_scunit;
+
+    const UNIT_GOLIATH: usize = 3;
+
+    /// removed entities count as dying
+    fn dying(sim: &mut Simulation, ent: Entity) -> bool {
+        sim.world.with_entity_data(&ent, |e, data| is_dying(e, data)).unwrap_or(true)
+    }
+
+    #[test]
+    fn turret_dies_with_base() {
+        let mut sim = Simulation::from_files(&Path::new(&::scdata_path()), "test.scx").unwrap();
+        let base = create_scunit(&mut sim.world, &sim.gd, UNIT_GOLIATH, 200, 200, 0);
+        let turret = sim.world
+            .with_entity_data(&base, |e, data| data.scunit[e].subunit)
+            .and_then(|t| t)
+            .expect("goliath without turret");
+        sim.run(10);
+        kill_entity(&mut sim.world, base);
+        sim.run(1);
+        assert!(dying(&mut sim, base));
+        assert!(dying(&mut sim, turret));
+        // both are gone once the death animation ran
+        sim.run(200);
+        assert!(sim.world.with_entity_data(&turret, |_, _| ()).is_none());
+    }
+}
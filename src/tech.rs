@@ -0,0 +1,382 @@
+//! Research of upgrades (upgrades.dat) and technologies (techdata.dat). Every
+//! player has upgrade levels and researched technologies, limited by the map's
+//! UPGR/PUPx and PTEC/PTEx settings. Buildings research one thing at a time.
+//! Weapon and armor upgrades take effect in combat through the upgrade levels.
+
+use ecs::{World, Entity, EntityData, DataHelper, ModifyData};
+
+use scformats::terrain::MapData;
+use scformats::unitsdata::{UpgradesDat, TechDataDat, UF_BUILDING};
+
+use gamedata::GameData;
+use construction::{BuildError, is_under_construction};
+use morph::{is_lifted, is_morphing};
+use unit_ecs::{UnitComponents, UnitServices, UnitSystems, DatFiles, PlayerState};
+
+pub const TECH_COUNT: usize = 44;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Research {
+    Upgrade(usize),
+    Tech(usize),
+}
+
+/// the research of a building
+pub struct ResearchComponent {
+    pub current: Option<Research>,
+    /// ticks researched so far
+    pub progress: u16,
+    pub research_time: u16,
+}
+
+/// (minerals, gas, time) of the next level of an upgrade at `level`
+pub fn upgrade_cost(upgrades_dat: &UpgradesDat, upgrade: usize, level: u8) -> (u32, u32, u16) {
+    let level = level as u32;
+    (upgrades_dat.mineral_cost_base[upgrade] as u32 +
+     upgrades_dat.mineral_cost_factor[upgrade] as u32 * level,
+     upgrades_dat.vespene_cost_base[upgrade] as u32 +
+     upgrades_dat.vespene_cost_factor[upgrade] as u32 * level,
+     (upgrades_dat.research_time_base[upgrade] as u32 +
+      upgrades_dat.research_time_factor[upgrade] as u32 * level) as u16)
+}
+
+/// (minerals, gas, time) of a technology
+pub fn tech_cost(techdata_dat: &TechDataDat, tech: usize) -> (u32, u32, u16) {
+    (techdata_dat.mineral_cost[tech] as u32,
+     techdata_dat.vespene_cost[tech] as u32,
+     techdata_dat.research_time[tech])
+}
+
+fn research_cost(dat: &DatFiles, player: &PlayerState, research: Research) -> (u32, u32, u16) {
+    match research {
+        Research::Upgrade(upgrade) => {
+            upgrade_cost(&dat.upgrades_dat, upgrade, player.upgrade_levels[upgrade])
+        }
+        Research::Tech(tech) => tech_cost(&dat.techdata_dat, tech),
+    }
+}
+
+/// sets the starting upgrade levels and technologies of all players from the
+/// map settings. Without settings, technologies that can't be researched
+/// (e.g. Scanner Sweep) are available from the start.
+pub fn init_research(players: &mut [PlayerState],
+                     upgrades_dat: &UpgradesDat,
+                     techdata_dat: &TechDataDat,
+                     map: Option<&MapData>) {
+    let upgrade_settings = map.and_then(|m| m.upgrade_settings.as_ref());
+    let tech_settings = map.and_then(|m| m.tech_settings.as_ref());
+    for (p, player) in players.iter_mut().enumerate() {
+        for u in 0..player.upgrade_levels.len() {
+            let max = upgrades_dat.max_repeats[u];
+            player.max_upgrade_levels[u] = upgrade_settings.and_then(|s| s.max(p, u))
+                .map_or(max, |m| m.min(max));
+            player.upgrade_levels[u] = upgrade_settings.and_then(|s| s.start(p, u))
+                .unwrap_or(0)
+                .min(player.max_upgrade_levels[u]);
+        }
+        for t in 0..TECH_COUNT {
+            let free = techdata_dat.mineral_cost[t] == 0 && techdata_dat.vespene_cost[t] == 0;
+            player.tech_available[t] = tech_settings.and_then(|s| s.max(p, t))
+                .map_or(true, |a| a != 0);
+            player.researched[t] = tech_settings.and_then(|s| s.start(p, t))
+                .map_or(free, |r| r != 0);
+        }
+    }
+}
+
+/// adds research to buildings
+pub fn init_unit_research(world: &mut World<UnitSystems>, gd: &GameData, entity: Entity) {
+    let unit_id = match world.with_entity_data(&entity, |e, data| data.scunit[e].unit_id) {
+        Some(unit_id) => unit_id as usize,
+        None => return,
+    };
+    if !gd.units_dat.flags(unit_id).contains(UF_BUILDING) {
+        return;
+    }
+    world.modify_entity(entity,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        data.research.insert(&e,
+                             ResearchComponent {
+                                 current: None,
+                                 progress: 0,
+                                 research_time: 0,
+                             });
+    });
+}
+
+/// is `research` running in any building of `player`
+fn in_progress(world: &World<UnitSystems>, player: usize, research: Research) -> bool {
+    let dh = &world.data;
+    world.entities().any(|e| {
+        dh.research.has(&e) && dh.scimage[e].player_id == player &&
+        dh.research[e].current == Some(research)
+    })
+}
+
+/// the buildings that research an upgrade (units.dat ids), any of them will
+/// do; hardcoded in the original too
+fn upgrade_researched_at(upgrade: usize) -> &'static [usize] {
+    match upgrade {
+        0 | 7 => &[122], // infantry armor and weapons, engineering bay
+        1 | 2 | 8 | 9 => &[123], // vehicle and ship plating and weapons, armory
+        3 | 10 | 11 => &[139], // carapace, melee and missile attacks, evolution chamber
+        4 | 12 => &[141, 137], // flyer carapace and attacks, spire or greater spire
+        5 | 13 | 15 => &[166], // ground armor and weapons, plasma shields, forge
+        6 | 14 | 33 => &[164], // air armor and weapons, singularity charge, cybernetics core
+        16 | 51 => &[112], // u-238 shells, caduceus reactor, academy
+        17 | 54 => &[120], // ion thrusters, charon boosters, machine shop
+        19 => &[116], // titan reactor, science facility
+        20 | 21 => &[117], // ocular implants, moebius reactor, covert ops
+        22 => &[115], // apollo reactor, control tower
+        23 => &[118], // colossus reactor, physics lab
+        24 | 25 | 26 => &[132, 133], // overlord upgrades, lair or hive
+        27 | 28 => &[142], // metabolic boost, adrenal glands, spawning pool
+        29 | 30 => &[135], // muscular augments, grooved spines, hydralisk den
+        31 => &[138], // gamete meiosis, queen's nest
+        32 => &[136], // metasynaptic node, defiler mound
+        34 => &[163], // leg enhancements, citadel of adun
+        35 | 36 | 37 => &[171], // scarab damage, reaver capacity, gravitic drive, support bay
+        38 | 39 => &[159], // sensor array, gravitic boosters, observatory
+        40 | 49 => &[165], // khaydarin amulet, argus talisman, templar archives
+        41 | 42 | 43 | 47 => &[169], // apial sensors, gravitic thrusters, carriers, argus jewel
+        44 => &[170], // khaydarin core, arbiter tribunal
+        52 | 53 => &[140], // anabolic synthesis, chitinous plating, ultralisk cavern
+        _ => &[],
+    }
+}
+
+/// the buildings the player needs for `level` of an upgrade, any of them will do
+fn upgrade_requirements(upgrade: usize, level: u8) -> &'static [usize] {
+    match upgrade {
+        28 => &[133], // adrenal glands, hive
+        54 => &[123], // charon boosters, armory
+        _ if level < 2 => &[],
+        0 | 1 | 2 | 7 | 8 | 9 => &[116], // terran armor and weapons, science facility
+        3 | 4 | 10 | 11 | 12 if level == 2 => &[132, 133], // zerg armor and attacks, lair
+        3 | 4 | 10 | 11 | 12 => &[133], // hive
+        5 | 13 => &[165], // ground armor and weapons, templar archives
+        6 | 14 => &[169], // air armor and weapons, fleet beacon
+        15 => &[164], // plasma shields, cybernetics core
+        _ => &[],
+    }
+}
+
+/// the buildings that research a technology, none for those that can't be
+/// researched
+fn tech_researched_at(tech: usize) -> &'static [usize] {
+    match tech {
+        0 | 24 | 30 => &[112], // stim packs, restoration, optical flare, academy
+        1 | 10 => &[117], // lockdown, personnel cloaking, covert ops
+        2 | 7 => &[116], // emp shockwave, irradiate, science facility
+        3 | 5 => &[120], // spider mines, siege mode, machine shop
+        8 => &[118], // yamato gun, physics lab
+        9 => &[115], // cloaking field, control tower
+        11 => &[131, 132, 133], // burrowing, hatchery, lair or hive
+        13 | 17 => &[138], // spawn broodlings, ensnare, queen's nest
+        15 | 16 => &[136], // plague, consume, defiler mound
+        19 | 20 | 27 | 31 => &[165], // storm, hallucination, mind control, maelstrom
+        21 | 22 => &[170], // recall, stasis field, arbiter tribunal
+        25 => &[169], // disruption web, fleet beacon
+        32 => &[135], // lurker aspect, hydralisk den
+        _ => &[],
+    }
+}
+
+fn tech_requirements(tech: usize) -> &'static [usize] {
+    match tech {
+        32 => &[132, 133], // lurker aspect, lair or hive
+        _ => &[],
+    }
+}
+
+/// can a `building_id` research `level` of an upgrade (ignored for
+/// technologies); `owns` tells if the player has a finished building
+pub fn check_research_requirements<F>(research: Research,
+                                      level: u8,
+                                      building_id: usize,
+                                      owns: F)
+                                      -> Result<(), BuildError>
+    where F: Fn(usize) -> bool
+{
+    let (researched_at, required) = match research {
+        Research::Upgrade(upgrade) => {
+            (upgrade_researched_at(upgrade), upgrade_requirements(upgrade, level))
+        }
+        Research::Tech(tech) => (tech_researched_at(tech), tech_requirements(tech)),
+    };
+    if !researched_at.contains(&building_id) {
+        return Err(BuildError::NotAllowed);
+    }
+    if !required.is_empty() && !required.iter().any(|&b| owns(b)) {
+        return Err(BuildError::MissingRequirement);
+    }
+    Ok(())
+}
+
+/// does `player` have a finished `unit_id`
+fn owns_finished(world: &World<UnitSystems>, player: usize, unit_id: usize) -> bool {
+    let dh = &world.data;
+    world.entities().any(|e| {
+        dh.scunit.has(&e) && dh.scimage[e].player_id == player &&
+        dh.scunit[e].unit_id as usize == unit_id && !is_under_construction(e, dh)
+    })
+}
+
+/// starts researching in a building and pays for it
+pub fn start_research(world: &mut World<UnitSystems>,
+                      building: Entity,
+                      research: Research)
+                      -> Result<(), BuildError> {
+    let dat = world.data.services.dat();
+    let info = world.with_entity_data(&building, |e, data| {
+        if !data.research.has(&e) || is_under_construction(e, data) || is_lifted(e, data) ||
+           is_morphing(e, data) {
+            None
+        } else {
+            Some((data.scunit[e].unit_id as usize,
+                  data.scimage[e].player_id,
+                  data.research[e].current.is_some() ||
+                  data.train_queue.has(&e) && !data.train_queue[e].queue.is_empty()))
+        }
+    });
+    let (building_id, player, busy) = match info {
+        Some(Some(info)) => info,
+        _ => return Err(BuildError::NotAllowed),
+    };
+    if busy {
+        return Err(BuildError::Busy);
+    }
+    let level = match research {
+        Research::Upgrade(upgrade) if upgrade < dat.upgrades_dat.race.len() => {
+            world.data.services.upgrade_level(player, upgrade) + 1
+        }
+        Research::Tech(tech) if tech < TECH_COUNT => 0,
+        _ => return Err(BuildError::NotAllowed),
+    };
+    try!(check_research_requirements(research,
+                                     level,
+                                     building_id,
+                                     |b| owns_finished(world, player, b)));
+    {
+        let state = &world.data.services.players[player];
+        match research {
+            Research::Upgrade(upgrade) => {
+                if state.max_upgrade_levels[upgrade] == 0 {
+                    return Err(BuildError::Unavailable);
+                }
+                if state.upgrade_levels[upgrade] >= state.max_upgrade_levels[upgrade] {
+                    return Err(BuildError::AlreadyResearched);
+                }
+            }
+            Research::Tech(tech) => {
+                if !state.tech_available[tech] {
+                    return Err(BuildError::Unavailable);
+                }
+                if state.researched[tech] {
+                    return Err(BuildError::AlreadyResearched);
+                }
+            }
+        }
+    }
+    if in_progress(world, player, research) {
+        return Err(BuildError::Busy);
+    }
+
+    let (minerals, gas, time) =
+        research_cost(&dat, &world.data.services.players[player], research);
+    {
+        let state = &mut world.data.services.players[player];
+        if state.minerals < minerals {
+            return Err(BuildError::NotEnoughMinerals);
+        }
+        if state.gas < gas {
+            return Err(BuildError::NotEnoughGas);
+        }
+        state.minerals -= minerals;
+        state.gas -= gas;
+    }
+    world.with_entity_data(&building, |e, data| {
+        data.research[e].current = Some(research);
+        data.research[e].progress = 0;
+        data.research[e].research_time = time.max(1);
+    });
+    Ok(())
+}
+
+/// stops the research of a building and refunds it
+pub fn cancel_research(world: &mut World<UnitSystems>, building: Entity) -> bool {
+    let dat = world.data.services.dat();
+    let cancelled = world.with_entity_data(&building, |e, data| {
+        if !data.research.has(&e) {
+            return None;
+        }
+        let player = data.scimage[e].player_id;
+        data.research[e].progress = 0;
+        data.research[e].current.take().map(|r| (r, player))
+    });
+    match cancelled {
+        Some(Some((research, player))) => {
+            let (minerals, gas, _) =
+                research_cost(&dat, &world.data.services.players[player], research);
+            let state = &mut world.data.services.players[player];
+            state.minerals += minerals;
+            state.gas += gas;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// advances the research of a building by one tick
+pub fn step_research(e: &EntityData<UnitComponents>,
+                     dh: &mut DataHelper<UnitComponents, UnitServices>) {
+    if !dh.research.has(e) || dh.research[*e].current.is_none() {
+        return;
+    }
+    dh.research[*e].progress += 1;
+    if dh.research[*e].progress < dh.research[*e].research_time {
+        return;
+    }
+    let research = dh.research[*e].current.take().unwrap();
+    dh.research[*e].progress = 0;
+    let player_id = dh.scimage[*e].player_id;
+    let player = &mut dh.services.players[player_id];
+    match research {
+        Research::Upgrade(upgrade) => player.upgrade_levels[upgrade] += 1,
+        Research::Tech(tech) => player.researched[tech] = true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use construction::BuildError;
+    use super::{Research, check_research_requirements};
+
+    #[test]
+    fn research_requirements() {
+        let nothing = |_| false;
+        // stim packs at an academy, not at a barracks
+        assert_eq!(check_research_requirements(Research::Tech(0), 0, 112, nothing), Ok(()));
+        assert_eq!(check_research_requirements(Research::Tech(0), 0, 111, nothing),
+                   Err(BuildError::NotAllowed));
+        // scanner sweep isn't researched anywhere
+        assert_eq!(check_research_requirements(Research::Tech(4), 0, 107, nothing),
+                   Err(BuildError::NotAllowed));
+        // lurker aspect needs a lair or hive
+        assert_eq!(check_research_requirements(Research::Tech(32), 0, 135, nothing),
+                   Err(BuildError::MissingRequirement));
+        assert_eq!(check_research_requirements(Research::Tech(32), 0, 135, |b| b == 133),
+                   Ok(()));
+        // infantry weapons 2 and 3 need a science facility
+        let upgrade = Research::Upgrade(7);
+        assert_eq!(check_research_requirements(upgrade, 1, 122, nothing), Ok(()));
+        assert_eq!(check_research_requirements(upgrade, 2, 122, nothing),
+                   Err(BuildError::MissingRequirement));
+        assert_eq!(check_research_requirements(upgrade, 3, 122, |b| b == 116), Ok(()));
+        // zerg carapace 3 needs a hive, a lair isn't enough
+        let carapace = Research::Upgrade(3);
+        assert_eq!(check_research_requirements(carapace, 2, 139, |b| b == 132), Ok(()));
+        assert_eq!(check_research_requirements(carapace, 3, 139, |b| b == 132),
+                   Err(BuildError::MissingRequirement));
+    }
+}
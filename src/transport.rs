@@ -0,0 +1,465 @@
+//! Transports and bunkers. Units board with units.dat "space required" up to
+//! the transport's "space provided" and leave the world until they are
+//! unloaded: they keep all their state, but don't run orders, can't be hit and
+//! aren't drawn. Units in a bunker shoot from it with a bit more range.
+
+use ecs::{World, Entity, EntityData, DataHelper, ModifyData};
+
+use scformats::unitsdata::{OrderType, UnitsDat, WEAPON_NONE, UF_BUILDING, UF_FLYER, UF_ORGANIC};
+
+use gamedata::GameData;
+use fixedpoint::{px_to_fixed, direction_from_delta, dir256_to_dir32};
+use collision::{Footprint, collides};
+use abilities::attack_cooldown;
+use combat::{is_dying, kill_unit, record_death, launch_weapon};
+use economy::{Race, race};
+use subunit::set_subunit_transport;
+use orders::{Order, OrderTarget, UnitInfo, find_unit, unit_distance, chase, attack_weapon,
+             is_enemy};
+use unit_ecs::{UnitComponents, UnitServices, UnitSystems, DatFiles, PlanningMap, SCUnitStep,
+               IScriptEntityAction, OverlayComponent, create_scimage};
+
+// units.dat
+const UNIT_BUNKER: usize = 125;
+/// images.dat: muzzle flash of units shooting from a bunker
+const IMAGE_BUNKER_OVERLAY: usize = 377;
+
+/// units board when they are this close
+const LOAD_RANGE: i32 = 8;
+/// extra weapon range of units in a bunker
+const BUNKER_RANGE_BONUS: i32 = 32;
+/// grid spacing when looking for a spot to unload
+const UNLOAD_SPACING: i32 = 8;
+const UNLOAD_RINGS: i32 = 12;
+
+pub struct TransportComponent {
+    pub cargo: Vec<Entity>,
+    /// sum of the space required by the cargo
+    pub used: u8,
+    pub space: u8,
+}
+
+/// a unit inside a transport
+pub struct PassengerComponent {
+    pub transport: Entity,
+}
+
+/// adds cargo space to units that provide space
+pub fn init_unit_transport(world: &mut World<UnitSystems>, gd: &GameData, entity: Entity) {
+    let unit_id = match world.with_entity_data(&entity, |e, data| data.scunit[e].unit_id) {
+        Some(unit_id) => unit_id as usize,
+        None => return,
+    };
+    let space = gd.units_dat.space_provided[unit_id];
+    if space == 0 {
+        return;
+    }
+    world.modify_entity(entity,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        data.transport.insert(&e,
+                              TransportComponent {
+                                  cargo: Vec::new(),
+                                  used: 0,
+                                  space: space,
+                              });
+    });
+}
+
+/// can the unit type ever ride in the transport type
+pub fn fits(units_dat: &UnitsDat, transport_id: usize, unit_id: usize) -> bool {
+    let flags = units_dat.flags(unit_id);
+    if flags.contains(UF_BUILDING) || flags.contains(UF_FLYER) ||
+       units_dat.space_required[unit_id] > units_dat.space_provided[transport_id] ||
+       race(units_dat, unit_id) != race(units_dat, transport_id) {
+        return false;
+    }
+    // bunkers only take infantry
+    transport_id != UNIT_BUNKER || flags.contains(UF_ORGANIC)
+}
+
+/// would right-clicking `transport` with `unit` make it board
+pub fn can_enter(world: &mut World<UnitSystems>, unit: Entity, transport: Entity) -> bool {
+    let dat = world.data.services.dat();
+    let transport_info = world.with_entity_data(&transport, |e, data| {
+        if !data.transport.has(&e) || is_dying(e, data) {
+            None
+        } else {
+            Some((data.scunit[e].unit_id as usize,
+                  data.scimage[e].player_id,
+                  data.transport[e].space - data.transport[e].used))
+        }
+    });
+    let (transport_id, player, free) = match transport_info {
+        Some(Some(info)) => info,
+        _ => return false,
+    };
+    world.with_entity_data(&unit, |e, data| {
+            let unit_id = data.scunit[e].unit_id as usize;
+            data.scimage[e].player_id == player && fits(&dat.units_dat, transport_id, unit_id) &&
+            dat.units_dat.space_required[unit_id] <= free
+        })
+        .unwrap_or(false)
+}
+
+/// sets the visibility of a unit and its overlays
+fn set_visible(world: &mut World<UnitSystems>, unit: Entity, visible: bool) {
+    let children = world.with_entity_data(&unit, |e, data| {
+            data.iscript_state[e].visible = visible;
+            data.iscript_state[e].children.clone()
+        })
+        .unwrap_or_else(Vec::new);
+    for c in children {
+        world.with_entity_data(&c, |e, data| data.iscript_state[e].visible = visible);
+    }
+}
+
+/// puts a unit into a transport, returns false if it doesn't fit
+pub fn load_unit(world: &mut World<UnitSystems>, transport: Entity, unit: Entity) -> bool {
+    if unit == transport || !can_enter(world, unit, transport) {
+        return false;
+    }
+    let dat = world.data.services.dat();
+    let space = match world.with_entity_data(&unit, |e, data| {
+        if is_dying(e, data) || data.passenger.has(&e) {
+            return None;
+        }
+        data.scunit[e].path = None;
+        data.scflingy[e].speed = 0;
+        let idle = data.scunit[e].orders.idle_order;
+        data.scunit[e].orders.queue.clear();
+        data.scunit[e].orders.replace(Order::new(idle, OrderTarget::None));
+        Some(dat.units_dat.space_required[data.scunit[e].unit_id as usize])
+    }) {
+        Some(Some(space)) => space,
+        _ => return false,
+    };
+    world.with_entity_data(&transport, |e, data| {
+        data.transport[e].cargo.push(unit);
+        data.transport[e].used += space;
+    });
+    world.modify_entity(unit,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        data.passenger.insert(&e, PassengerComponent { transport: transport });
+    });
+    set_subunit_transport(world, unit, Some(transport));
+    set_visible(world, unit, false);
+    true
+}
+
+/// nearest spot around (x, y) where a unit with footprint `size` (at 0, 0)
+/// stands on walkable ground without overlapping `occupied`
+pub fn free_spot(x: i32,
+                 y: i32,
+                 size: Footprint,
+                 occupied: &[Footprint],
+                 walkable: &Fn(i32, i32) -> bool)
+                 -> Option<(i32, i32)> {
+    for ring in 0..UNLOAD_RINGS {
+        for dy in -ring..ring + 1 {
+            for dx in -ring..ring + 1 {
+                if dx.abs() != ring && dy.abs() != ring {
+                    continue;
+                }
+                let (sx, sy) = (x + dx * UNLOAD_SPACING, y + dy * UNLOAD_SPACING);
+                let fp = Footprint {
+                    left: sx + size.left,
+                    top: sy + size.top,
+                    right: sx + size.right,
+                    bottom: sy + size.bottom,
+                };
+                if !walkable(fp.left, fp.top) || !walkable(fp.right, fp.top) ||
+                   !walkable(fp.left, fp.bottom) || !walkable(fp.right, fp.bottom) {
+                    continue;
+                }
+                if occupied.iter().all(|o| {
+                    let (ox, oy) = o.overlap(&fp);
+                    ox <= 0 || oy <= 0
+                }) {
+                    return Some((sx, sy));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// lets all units out around the transport, those without space stay inside
+pub fn unload_all(world: &mut World<UnitSystems>, transport: Entity) {
+    let dat = world.data.services.dat();
+    let map = match world.systems.scunit_stepping_sys.map {
+        Some(ref map) => map.clone(),
+        None => return,
+    };
+    let info = world.with_entity_data(&transport, |e, data| {
+        if !data.transport.has(&e) {
+            return None;
+        }
+        Some((data.transport[e].cargo.clone(),
+              data.iscript_state[e].map_pos_x as i32,
+              data.iscript_state[e].map_pos_y as i32))
+    });
+    let (cargo, x, y) = match info {
+        Some(Some(info)) => info,
+        _ => return,
+    };
+    let mut occupied = Vec::new();
+    {
+        let dh = &world.data;
+        for e in world.entities().filter(aspect!(<UnitComponents> all: [scunit]
+                                                 none: [passenger]),
+                                         world) {
+            let unit_id = dh.scunit[e].unit_id as usize;
+            if **e != transport && collides(&dat.units_dat, unit_id) && !is_dying(e, dh) {
+                occupied.push(Footprint::of_unit(&dat.units_dat,
+                                                 unit_id,
+                                                 dh.iscript_state[e].map_pos_x as i32,
+                                                 dh.iscript_state[e].map_pos_y as i32));
+            }
+        }
+    }
+    for unit in cargo {
+        let unit_id = match world.with_entity_data(&unit, |e, data| data.scunit[e].unit_id) {
+            Some(unit_id) => unit_id as usize,
+            None => continue,
+        };
+        let spot = free_spot(x,
+                             y,
+                             Footprint::of_unit(&dat.units_dat, unit_id, 0, 0),
+                             &occupied,
+                             &|px, py| map.is_walkable(px, py));
+        let (ux, uy) = match spot {
+            Some(spot) => spot,
+            None => continue,
+        };
+        occupied.push(Footprint::of_unit(&dat.units_dat, unit_id, ux, uy));
+        world.modify_entity(unit,
+                            |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+            data.passenger.remove(&e);
+        });
+        set_subunit_transport(world, unit, None);
+        SCUnitStep::move_unit(world, unit, px_to_fixed(ux), px_to_fixed(uy));
+        set_visible(world, unit, true);
+        world.with_entity_data(&transport, |e, data| {
+            data.transport[e].cargo.retain(|c| *c != unit);
+            data.transport[e].used -= dat.units_dat.space_required[unit_id];
+        });
+    }
+}
+
+/// EnterTransport, PickupTransport, Unload and MoveUnload
+pub fn is_transport_order(order_type: OrderType) -> bool {
+    match order_type {
+        OrderType::EnterTransport | OrderType::PickupTransport | OrderType::Unload |
+        OrderType::MoveUnload => true,
+        _ => false,
+    }
+}
+
+pub fn order_transport(e: &EntityData<UnitComponents>,
+                       dh: &mut DataHelper<UnitComponents, UnitServices>,
+                       map: &PlanningMap,
+                       dat: &DatFiles,
+                       units: &[UnitInfo],
+                       me: &UnitInfo,
+                       order: Order,
+                       actions: &mut Vec<IScriptEntityAction>) {
+    match (order.order_type, order.target) {
+        (OrderType::EnterTransport, OrderTarget::Unit(target)) |
+        (OrderType::PickupTransport, OrderTarget::Unit(target)) => {
+            let target = match find_unit(units, target) {
+                Some(target) => target,
+                None => {
+                    SCUnitStep::halt(e, dh);
+                    dh.scunit[*e].orders.finish();
+                    return;
+                }
+            };
+            if unit_distance(&dat.units_dat, me, &target) > LOAD_RANGE {
+                dh.scunit[*e].orders.state = 1;
+                // bunkers don't come to pick anyone up
+                if !dat.units_dat.flags(me.unit_id).contains(UF_BUILDING) {
+                    chase(e, dh, map, target.x, target.y);
+                }
+                return;
+            }
+            SCUnitStep::halt(e, dh);
+            let (transport, unit) = if order.order_type == OrderType::EnterTransport {
+                (target.entity, me.entity)
+            } else {
+                (me.entity, target.entity)
+            };
+            actions.push(IScriptEntityAction::LoadUnit {
+                transport: transport,
+                unit: unit,
+            });
+            dh.scunit[*e].orders.finish();
+        }
+        (OrderType::Unload, _) => {
+            SCUnitStep::halt(e, dh);
+            actions.push(IScriptEntityAction::UnloadAll { transport: me.entity });
+            dh.scunit[*e].orders.finish();
+        }
+        (OrderType::MoveUnload, OrderTarget::Position(x, y)) => {
+            if dh.scunit[*e].orders.state == 0 {
+                SCUnitStep::move_to(e, dh, map, x, y);
+                dh.scunit[*e].orders.state = 1;
+            }
+            if !SCUnitStep::follow_path(e, dh) {
+                actions.push(IScriptEntityAction::UnloadAll { transport: me.entity });
+                dh.scunit[*e].orders.finish();
+            }
+        }
+        _ => dh.scunit[*e].orders.finish(),
+    }
+}
+
+/// the cargo dies with its transport
+pub fn kill_cargo(e: &EntityData<UnitComponents>,
+                  dh: &mut DataHelper<UnitComponents, UnitServices>) {
+    if !dh.transport.has(e) {
+        return;
+    }
+    let cargo: Vec<Entity> = dh.transport[*e].cargo.drain(..).collect();
+    dh.transport[*e].used = 0;
+    for unit in cargo {
+        let death = dh.with_entity_data(&unit, |ue, data| if is_dying(ue, data) {
+            None
+        } else {
+            kill_unit(ue, data)
+        });
+        record_death(&mut dh.services, death.and_then(|d| d));
+    }
+}
+
+/// supply of the units in a transport, they don't count as units otherwise
+pub fn cargo_supply(e: EntityData<UnitComponents>,
+                    dh: &mut DataHelper<UnitComponents, UnitServices>,
+                    units_dat: &UnitsDat)
+                    -> Vec<(Race, u32)> {
+    if !dh.transport.has(&e) {
+        return Vec::new();
+    }
+    let cargo = dh.transport[e].cargo.clone();
+    cargo.iter()
+        .filter_map(|c| dh.with_entity_data(c, |ce, data| data.scunit[ce].unit_id as usize))
+        .filter_map(|unit_id| {
+            race(units_dat, unit_id).map(|r| (r, units_dat.supply_required[unit_id] as u32))
+        })
+        .collect()
+}
+
+/// units in a bunker shoot at enemies in range
+pub fn step_bunker(e: &EntityData<UnitComponents>,
+                   dh: &mut DataHelper<UnitComponents, UnitServices>,
+                   dat: &DatFiles,
+                   units: &[UnitInfo],
+                   actions: &mut Vec<IScriptEntityAction>) {
+    if !dh.transport.has(e) || dh.scunit[*e].unit_id as usize != UNIT_BUNKER {
+        return;
+    }
+    let me = match find_unit(units, ***e) {
+        Some(me) => me,
+        None => return,
+    };
+    let cargo = dh.transport[*e].cargo.clone();
+    for passenger in cargo {
+        let ready = dh.with_entity_data(&passenger, |pe, data| {
+            if data.scunit[pe].weapon_cooldown > 0 {
+                data.scunit[pe].weapon_cooldown -= 1;
+                None
+            } else {
+                Some(data.scunit[pe].unit_id as usize)
+            }
+        });
+        let unit_id = match ready {
+            Some(Some(unit_id)) => unit_id,
+            _ => continue,
+        };
+        // the passenger aims, but from the bunker's position
+        let shooter = UnitInfo { unit_id: unit_id, ..me };
+        let mut best = None;
+        for other in units {
+            if !is_enemy(&dat.units_dat, &shooter, other) {
+                continue;
+            }
+            let weapon = match attack_weapon(&dat.units_dat,
+                                             &dat.weapons_dat,
+                                             unit_id,
+                                             other.unit_id) {
+                Some(weapon) => weapon,
+                None => continue,
+            };
+            let dist = unit_distance(&dat.units_dat, &me, other);
+            if dist <= dat.weapons_dat.maximum_range[weapon] as i32 + BUNKER_RANGE_BONUS &&
+               best.map_or(true, |(_, _, d)| dist < d) {
+                best = Some((other.entity, weapon, dist));
+            }
+        }
+        if let Some((target, weapon, _)) = best {
+            dh.with_entity_data(&passenger, |pe, data| {
+                data.scunit[pe].weapon_cooldown =
+                    attack_cooldown(pe, data, dat.weapons_dat.cooldown[weapon]);
+            });
+            actions.push(IScriptEntityAction::BunkerFire {
+                bunker: ***e,
+                passenger: passenger,
+                target: target,
+                weapon_id: weapon as u16,
+            });
+        }
+    }
+}
+
+/// launches the weapon of a unit in a bunker and shows the flash
+pub fn bunker_fire(world: &mut World<UnitSystems>,
+                   gd: &GameData,
+                   bunker: Entity,
+                   passenger: Entity,
+                   target: Entity,
+                   weapon_id: usize) {
+    if weapon_id >= WEAPON_NONE as usize {
+        return;
+    }
+    let bunker_info = world.with_entity_data(&bunker, |e, data| {
+        (data.iscript_state[e].map_pos_x,
+         data.iscript_state[e].map_pos_y,
+         data.scimage[e].player_id)
+    });
+    let target_pos = world.with_entity_data(&target, |e, data| {
+        (data.iscript_state[e].map_pos_x as i32, data.iscript_state[e].map_pos_y as i32)
+    });
+    let ((x, y, player), (tx, ty)) = match (bunker_info, target_pos) {
+        (Some(b), Some(t)) => (b, t),
+        _ => return,
+    };
+    launch_weapon(world, gd, weapon_id, Some(passenger), Some(target), x, y, player, 0);
+
+    let direction = direction_from_delta(tx - x as i32, ty - y as i32);
+    let flash = create_scimage(world, gd, IMAGE_BUNKER_OVERLAY, x, y, Some(bunker), player);
+    world.modify_entity(flash,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        data.iscript_state[e].movement_direction = direction;
+        data.iscript_state[e].direction = dir256_to_dir32(direction);
+        data.overlay.insert(&e, OverlayComponent {});
+    });
+    world.with_entity_data(&bunker, |e, data| data.iscript_state[e].children.push(flash));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::free_spot;
+    use collision::Footprint;
+
+    #[test]
+    fn unload_spots() {
+        let size = Footprint { left: -4, top: -4, right: 4, bottom: 4 };
+        let walkable = |x: i32, _: i32| x >= 0;
+
+        assert_eq!(free_spot(50, 50, size, &[], &walkable), Some((50, 50)));
+        let taken = Footprint { left: 40, top: 40, right: 60, bottom: 60 };
+        let spot = free_spot(50, 50, size, &[taken], &walkable).unwrap();
+        assert!(spot != (50, 50) && spot != (58, 58));
+        // only the right half of the map is walkable
+        assert_eq!(free_spot(0, 0, size, &[], &walkable), Some((8, -8)));
+    }
+}
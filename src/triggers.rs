@@ -0,0 +1,1005 @@
+//! Map triggers (TRIG). Every 2 game seconds the triggers of each playing
+//! player are checked in map order; a trigger whose conditions all hold runs
+//! its actions and, unless preserved, never runs again for that player. A Wait
+//! or Transmission action pauses the trigger and blocks all other triggers of
+//! the player until it is over ("wait blocks"), then the rest of the list runs
+//! right away.
+
+use std::rc::Rc;
+
+use ecs::{World, Entity};
+
+use scformats::terrain::{Map, TileHeight, Forces, FORCE_ALLIES, FORCE_ALLIED_VICTORY};
+use scformats::trig::{Trigger, TrigCondition, TrigAction};
+use scformats::unitsdata::{OrderType, UnitsDat, UF_BUILDING};
+
+use gamedata::GameData;
+use fixedpoint::px_to_fixed;
+use combat::{is_dying, kill_entity};
+use construction::is_under_construction;
+use locations::{Locations, unit_elevation};
+use orders::{Order, OrderTarget, issue_order};
+use simulation::remove_entity_rec;
+use unit_ecs::{UnitComponents, UnitSystems, PlanningMap, PlayerState, SCUnitStep,
+               UNIT_TYPE_COUNT, create_scunit};
+
+/// game ticks between two trigger checks
+const TRIGGER_INTERVAL: u64 = 30;
+/// game ticks per game second
+const TICKS_PER_SECOND: u64 = 15;
+/// waits count in ms of the fastest game speed, like in the original
+const WAIT_MS_PER_TICK: u32 = 42;
+const SWITCH_COUNT: usize = 256;
+/// players 1-8, the others don't run triggers
+const TRIGGER_PLAYERS: usize = 8;
+
+// MapData::owners of players in the game
+const OWNER_COMPUTER_GAME: u8 = 1;
+const OWNER_HUMAN: u8 = 2;
+const OWNER_COMPUTER: u8 = 5;
+const OWNER_OPEN: u8 = 6;
+
+// Trigger::execution_flags
+const EXEC_PRESERVE: u32 = 0x2;
+const EXEC_IGNORE: u32 = 0x4;
+// TrigCondition::flags and TrigAction::flags
+const FLAG_DISABLED: u8 = 0x2;
+
+// player groups
+const GROUP_CURRENT_PLAYER: u32 = 13;
+const GROUP_FOES: u32 = 14;
+const GROUP_ALLIES: u32 = 15;
+const GROUP_NEUTRAL_PLAYERS: u32 = 16;
+const GROUP_ALL_PLAYERS: u32 = 17;
+const GROUP_FORCE_1: u32 = 18;
+const GROUP_FORCE_4: u32 = 21;
+const GROUP_NON_ALLIED_VICTORY_PLAYERS: u32 = 26;
+
+// unit types that stand for several
+const ANY_UNIT: usize = 229;
+const MEN: usize = 230;
+const BUILDINGS: usize = 231;
+const FACTORIES: usize = 232;
+/// buildings that produce units
+const FACTORY_UNITS: [usize; 11] = [106, 111, 113, 114, 131, 132, 133, 154, 155, 160, 167];
+
+// numeric comparisons
+const AT_LEAST: u8 = 0;
+const AT_MOST: u8 = 1;
+const EXACTLY: u8 = 10;
+// switch states
+const SWITCH_IS_SET: u8 = 2;
+// modifiers
+const SET: u8 = 4;
+const CLEAR: u8 = 5;
+const TOGGLE: u8 = 6;
+const SET_TO: u8 = 7;
+const ADD: u8 = 8;
+const SUBTRACT: u8 = 9;
+const RANDOMIZE: u8 = 11;
+
+// resource types
+const ORE: u16 = 0;
+const GAS: u16 = 1;
+const ORE_AND_GAS: u16 = 2;
+
+// score types
+const SCORE_TOTAL: u8 = 0;
+const SCORE_UNITS: u8 = 1;
+const SCORE_BUILDINGS: u8 = 2;
+const SCORE_UNITS_AND_BUILDINGS: u8 = 3;
+const SCORE_KILLS: u8 = 4;
+const SCORE_RAZINGS: u8 = 5;
+const SCORE_KILLS_AND_RAZINGS: u8 = 6;
+const SCORE_CUSTOM: u8 = 7;
+
+// conditions
+const COND_COUNTDOWN_TIMER: u8 = 1;
+const COND_COMMAND: u8 = 2;
+const COND_BRING: u8 = 3;
+const COND_ACCUMULATE: u8 = 4;
+const COND_KILL: u8 = 5;
+const COND_COMMAND_THE_MOST: u8 = 6;
+const COND_COMMAND_THE_MOST_AT: u8 = 7;
+const COND_MOST_KILLS: u8 = 8;
+const COND_HIGHEST_SCORE: u8 = 9;
+const COND_MOST_RESOURCES: u8 = 10;
+const COND_SWITCH: u8 = 11;
+const COND_ELAPSED_TIME: u8 = 12;
+const COND_OPPONENTS: u8 = 14;
+const COND_DEATHS: u8 = 15;
+const COND_COMMAND_THE_LEAST: u8 = 16;
+const COND_COMMAND_THE_LEAST_AT: u8 = 17;
+const COND_LEAST_KILLS: u8 = 18;
+const COND_LOWEST_SCORE: u8 = 19;
+const COND_LEAST_RESOURCES: u8 = 20;
+const COND_SCORE: u8 = 21;
+const COND_ALWAYS: u8 = 22;
+
+// actions
+const ACT_VICTORY: u8 = 1;
+const ACT_DEFEAT: u8 = 2;
+const ACT_PRESERVE_TRIGGER: u8 = 3;
+const ACT_WAIT: u8 = 4;
+const ACT_PAUSE_GAME: u8 = 5;
+const ACT_UNPAUSE_GAME: u8 = 6;
+const ACT_TRANSMISSION: u8 = 7;
+const ACT_DISPLAY_TEXT: u8 = 9;
+const ACT_CREATE_UNIT_WITH_PROPERTIES: u8 = 11;
+const ACT_SET_SWITCH: u8 = 13;
+const ACT_SET_COUNTDOWN_TIMER: u8 = 14;
+const ACT_RUN_AI_SCRIPT: u8 = 15;
+const ACT_RUN_AI_SCRIPT_AT: u8 = 16;
+const ACT_KILL_UNIT: u8 = 22;
+const ACT_KILL_UNIT_AT: u8 = 23;
+const ACT_REMOVE_UNIT: u8 = 24;
+const ACT_REMOVE_UNIT_AT: u8 = 25;
+const ACT_SET_RESOURCES: u8 = 26;
+const ACT_SET_SCORE: u8 = 27;
+const ACT_MOVE_LOCATION: u8 = 38;
+const ACT_MOVE_UNIT: u8 = 39;
+const ACT_SET_INVINCIBILITY: u8 = 43;
+const ACT_CREATE_UNIT: u8 = 44;
+const ACT_SET_DEATHS: u8 = 45;
+const ACT_ORDER: u8 = 46;
+const ACT_GIVE_UNITS: u8 = 48;
+const ACT_MODIFY_HIT_POINTS: u8 = 49;
+const ACT_MODIFY_ENERGY: u8 = 50;
+const ACT_MODIFY_SHIELDS: u8 = 51;
+const ACT_MODIFY_RESOURCES: u8 = 52;
+const ACT_PAUSE_TIMER: u8 = 54;
+const ACT_UNPAUSE_TIMER: u8 = 55;
+const ACT_DRAW: u8 = 56;
+const ACT_SET_ALLIANCE: u8 = 57;
+
+// alliance status, as in the Set Alliance Status action
+const ENEMY: u8 = 0;
+const ALLY: u8 = 1;
+const ALLIED_VICTORY: u8 = 2;
+
+// orders of the Order action
+const ORDER_MOVE: u8 = 0;
+const ORDER_PATROL: u8 = 1;
+const ORDER_ATTACK: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Victory,
+    Defeat,
+    Draw,
+}
+
+/// a "Run AI Script" action, for the AI to pick up
+#[derive(Debug, Clone, Copy)]
+pub struct AIScriptRequest {
+    pub player: usize,
+    /// four characters, e.g. "TMCx"
+    pub script: u32,
+    /// 0 if the script doesn't need one
+    pub location: u32,
+}
+
+/// what the trigger engine needs to know about a unit
+#[derive(Debug, Clone, Copy)]
+struct TriggerUnit {
+    entity: Entity,
+    unit_id: usize,
+    player: usize,
+    x: i32,
+    y: i32,
+    height: TileHeight,
+    flying: bool,
+}
+
+/// how a trigger continues after an action
+enum Flow {
+    Next,
+    /// waits for the given ms
+    Wait(u32),
+}
+
+pub struct Triggers {
+    map: Rc<PlanningMap>,
+    /// players in the game
+    playing: Vec<usize>,
+    /// per trigger and player: ran and isn't preserved
+    done: Vec<[bool; TRIGGER_PLAYERS]>,
+    /// per trigger and player: action to continue with after a wait, 0 if
+    /// the trigger isn't running
+    resume: Vec<[usize; TRIGGER_PLAYERS]>,
+    /// per player: waiting trigger and ms left
+    waits: [Option<(usize, u32)>; TRIGGER_PLAYERS],
+    /// per player: alliance status towards players 1-12
+    alliances: [[u8; 12]; TRIGGER_PLAYERS],
+    /// game ticks since the start
+    pub ticks: u64,
+    pub switches: Vec<bool>,
+    /// seconds left
+    pub countdown: u32,
+    pub countdown_paused: bool,
+    /// the game should stop until a trigger unpauses it
+    pub game_paused: bool,
+    pub outcomes: [Option<Outcome>; TRIGGER_PLAYERS],
+    /// (player, text) for the UI to show
+    pub messages: Vec<(usize, String)>,
+    pub ai_requests: Vec<AIScriptRequest>,
+}
+impl Triggers {
+    pub fn new(map: Rc<PlanningMap>) -> Self {
+        let count = map.scmap.data.triggers.len();
+        let playing: Vec<usize> = (0..TRIGGER_PLAYERS)
+            .filter(|&p| {
+                let owner = map.scmap.data.owners[p];
+                owner == OWNER_COMPUTER_GAME || owner == OWNER_HUMAN || owner == OWNER_COMPUTER ||
+                owner == OWNER_OPEN
+            })
+            .collect();
+        let alliances = initial_alliances(&map.scmap.data.forces, &playing);
+        Triggers {
+            map: map,
+            playing: playing,
+            done: vec![[false; TRIGGER_PLAYERS]; count],
+            resume: vec![[0; TRIGGER_PLAYERS]; count],
+            waits: [None; TRIGGER_PLAYERS],
+            alliances: alliances,
+            ticks: 0,
+            switches: vec![false; SWITCH_COUNT],
+            countdown: 0,
+            countdown_paused: false,
+            game_paused: false,
+            outcomes: [None; TRIGGER_PLAYERS],
+            messages: Vec::new(),
+            ai_requests: Vec::new(),
+        }
+    }
+
+    /// one game tick
+    fn step(&mut self, world: &mut World<UnitSystems>, gd: &GameData) {
+        self.ticks += 1;
+        if self.ticks % TICKS_PER_SECOND == 0 && !self.countdown_paused && self.countdown > 0 {
+            self.countdown -= 1;
+        }
+        let check = self.ticks % TRIGGER_INTERVAL == 0;
+        for i in 0..self.playing.len() {
+            let player = self.playing[i];
+            match self.waits[player] {
+                Some((trigger, ms)) if ms > WAIT_MS_PER_TICK => {
+                    self.waits[player] = Some((trigger, ms - WAIT_MS_PER_TICK));
+                }
+                Some((trigger, _)) => {
+                    self.waits[player] = None;
+                    self.run_from(world, gd, player, trigger);
+                }
+                None if check => self.run_from(world, gd, player, 0),
+                None => {}
+            }
+        }
+    }
+
+    /// runs the triggers of `player` from `first` on, until one waits
+    fn run_from(&mut self,
+                world: &mut World<UnitSystems>,
+                gd: &GameData,
+                player: usize,
+                first: usize) {
+        let map = self.map.clone();
+        for (t, trigger) in map.scmap.data.triggers.iter().enumerate().skip(first) {
+            if self.run_trigger(world, gd, trigger, t, player) {
+                return;
+            }
+        }
+    }
+
+    /// returns true if the trigger waits
+    fn run_trigger(&mut self,
+                   world: &mut World<UnitSystems>,
+                   gd: &GameData,
+                   trigger: &Trigger,
+                   t: usize,
+                   player: usize)
+                   -> bool {
+        if trigger.execution_flags & EXEC_IGNORE != 0 ||
+           !runs_for(&trigger.players, &self.map.scmap.data.forces, player) {
+            return false;
+        }
+        let mut next = self.resume[t][player];
+        if next == 0 {
+            if self.done[t][player] {
+                return false;
+            }
+            let units = trigger_units(world, &self.map.scmap);
+            if !trigger.conditions
+                .iter()
+                .filter(|c| c.condition != 0 && c.flags & FLAG_DISABLED == 0)
+                .all(|c| self.check(world, &gd.units_dat, &units, c, player)) {
+                return false;
+            }
+        }
+        while next < trigger.actions.len() {
+            let action = &trigger.actions[next];
+            next += 1;
+            if action.action == 0 || action.flags & FLAG_DISABLED != 0 {
+                continue;
+            }
+            if let Flow::Wait(ms) = self.execute(world, gd, action, player) {
+                self.resume[t][player] = next;
+                self.waits[player] = Some((t, ms));
+                return true;
+            }
+        }
+        self.resume[t][player] = 0;
+        let preserved = trigger.execution_flags & EXEC_PRESERVE != 0 ||
+                        trigger.actions.iter().any(|a| {
+            a.action == ACT_PRESERVE_TRIGGER && a.flags & FLAG_DISABLED == 0
+        });
+        self.done[t][player] = !preserved;
+        false
+    }
+
+    /// the players a player group stands for, seen from `current`
+    fn group_players(&self, group: u32, current: usize) -> Vec<usize> {
+        match group {
+            g if g < 12 => vec![g as usize],
+            GROUP_CURRENT_PLAYER => vec![current],
+            GROUP_FOES => self.others(current, |status| status == ENEMY),
+            GROUP_ALLIES => self.others(current, |status| status != ENEMY),
+            GROUP_NON_ALLIED_VICTORY_PLAYERS => {
+                self.others(current, |status| status != ALLIED_VICTORY)
+            }
+            GROUP_NEUTRAL_PLAYERS => (8..12).collect(),
+            GROUP_ALL_PLAYERS => self.playing.clone(),
+            g if g >= GROUP_FORCE_1 && g <= GROUP_FORCE_4 => {
+                let force = (g - GROUP_FORCE_1) as usize;
+                let forces = &self.map.scmap.data.forces;
+                self.playing.iter().cloned().filter(|&p| forces.force(p) == Some(force)).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// the other players in the game whose alliance status (as seen by
+    /// `current`) matches
+    fn others<F>(&self, current: usize, status: F) -> Vec<usize>
+        where F: Fn(u8) -> bool
+    {
+        self.playing
+            .iter()
+            .cloned()
+            .filter(|&p| p != current && status(self.alliances[current][p]))
+            .collect()
+    }
+
+    /// units of `players` and `unit_type`, in `location` if given; `count` 0
+    /// means all of them
+    fn select(&self,
+              units_dat: &UnitsDat,
+              locations: &Locations,
+              units: &[TriggerUnit],
+              players: &[usize],
+              unit_type: usize,
+              location: Option<u32>,
+              count: u8)
+              -> Vec<Entity> {
+        let selected = units.iter()
+            .filter(|u| {
+                players.contains(&u.player) && matches_type(units_dat, unit_type, u.unit_id) &&
+                location.map_or(true, |l| locations.contains(l, u.x, u.y, u.height, u.flying))
+            })
+            .map(|u| u.entity);
+        if count == 0 {
+            selected.collect()
+        } else {
+            selected.take(count as usize).collect()
+        }
+    }
+
+    fn unit_count(&self,
+                  units_dat: &UnitsDat,
+                  locations: &Locations,
+                  units: &[TriggerUnit],
+                  players: &[usize],
+                  unit_type: usize,
+                  location: Option<u32>)
+                  -> u32 {
+        self.select(units_dat, locations, units, players, unit_type, location, 0).len() as u32
+    }
+
+    /// does `player` have more (or less) than every other player in the game
+    fn is_extreme(&self, player: usize, least: bool, value: &Fn(usize) -> u32) -> bool {
+        let mine = value(player);
+        self.playing.iter().filter(|&&p| p != player).all(|&p| if least {
+            mine < value(p)
+        } else {
+            mine > value(p)
+        })
+    }
+
+    fn check(&self,
+             world: &World<UnitSystems>,
+             units_dat: &UnitsDat,
+             units: &[TriggerUnit],
+             c: &TrigCondition,
+             player: usize)
+             -> bool {
+        let states = &world.data.services.players;
+        let locations = &world.data.services.locations;
+        let players = self.group_players(c.group, player);
+        let unit_type = c.unit_id as usize;
+        let at = Some(c.location);
+        match c.condition {
+            COND_COUNTDOWN_TIMER => compare(self.countdown, c.comparison, c.qty),
+            COND_COMMAND => {
+                compare(self.unit_count(units_dat, locations, units, &players, unit_type, None),
+                        c.comparison,
+                        c.qty)
+            }
+            COND_BRING => {
+                compare(self.unit_count(units_dat, locations, units, &players, unit_type, at),
+                        c.comparison,
+                        c.qty)
+            }
+            COND_ACCUMULATE => {
+                let amount: u32 = players.iter()
+                    .map(|&p| resources(states[p].minerals, states[p].gas, c.resource_type as u16))
+                    .sum();
+                compare(amount, c.comparison, c.qty)
+            }
+            COND_KILL => {
+                compare(players.iter()
+                            .map(|&p| counted(units_dat, &states[p].kills, unit_type))
+                            .sum(),
+                        c.comparison,
+                        c.qty)
+            }
+            COND_DEATHS => {
+                compare(players.iter()
+                            .map(|&p| counted(units_dat, &states[p].deaths, unit_type))
+                            .sum(),
+                        c.comparison,
+                        c.qty)
+            }
+            COND_COMMAND_THE_MOST | COND_COMMAND_THE_LEAST => {
+                self.is_extreme(player,
+                                c.condition == COND_COMMAND_THE_LEAST,
+                                &|p| {
+                                    self.unit_count(units_dat,
+                                                    locations,
+                                                    units,
+                                                    &[p],
+                                                    unit_type,
+                                                    None)
+                                })
+            }
+            COND_COMMAND_THE_MOST_AT | COND_COMMAND_THE_LEAST_AT => {
+                self.is_extreme(player,
+                                c.condition == COND_COMMAND_THE_LEAST_AT,
+                                &|p| {
+                                    self.unit_count(units_dat,
+                                                    locations,
+                                                    units,
+                                                    &[p],
+                                                    unit_type,
+                                                    at)
+                                })
+            }
+            COND_MOST_KILLS | COND_LEAST_KILLS => {
+                self.is_extreme(player,
+                                c.condition == COND_LEAST_KILLS,
+                                &|p| counted(units_dat, &states[p].kills, unit_type))
+            }
+            COND_HIGHEST_SCORE | COND_LOWEST_SCORE => {
+                self.is_extreme(player,
+                                c.condition == COND_LOWEST_SCORE,
+                                &|p| score(&states[p], c.resource_type))
+            }
+            COND_MOST_RESOURCES | COND_LEAST_RESOURCES => {
+                self.is_extreme(player, c.condition == COND_LEAST_RESOURCES, &|p| {
+                    resources(states[p].minerals, states[p].gas, c.resource_type as u16)
+                })
+            }
+            COND_SCORE => {
+                compare(players.iter()
+                            .map(|&p| score(&states[p], c.resource_type))
+                            .sum(),
+                        c.comparison,
+                        c.qty)
+            }
+            COND_SWITCH => {
+                self.switches[c.resource_type as usize] == (c.comparison == SWITCH_IS_SET)
+            }
+            COND_ELAPSED_TIME => {
+                compare((self.ticks / TICKS_PER_SECOND) as u32, c.comparison, c.qty)
+            }
+            COND_OPPONENTS => {
+                let opponents = self.playing
+                    .iter()
+                    .filter(|&&p| {
+                        p != player && self.alliances[player][p] == ENEMY &&
+                        self.outcomes[p].is_none() &&
+                        units.iter().any(|u| u.player == p)
+                    })
+                    .count();
+                compare(opponents as u32, c.comparison, c.qty)
+            }
+            COND_ALWAYS => true,
+            // never, mission briefing
+            _ => false,
+        }
+    }
+
+    fn execute(&mut self,
+               world: &mut World<UnitSystems>,
+               gd: &GameData,
+               a: &TrigAction,
+               player: usize)
+               -> Flow {
+        let players = self.group_players(a.player, player);
+        let unit_type = a.unit_type as usize;
+        let units = trigger_units(world, &self.map.scmap);
+        match a.action {
+            ACT_VICTORY => self.outcomes[player] = Some(Outcome::Victory),
+            ACT_DEFEAT => self.outcomes[player] = Some(Outcome::Defeat),
+            ACT_DRAW => {
+                for &p in &self.playing {
+                    self.outcomes[p] = Some(Outcome::Draw);
+                }
+            }
+            // checked when the trigger is done
+            ACT_PRESERVE_TRIGGER => {}
+            ACT_WAIT => return Flow::Wait(a.time),
+            ACT_PAUSE_GAME => self.game_paused = true,
+            ACT_UNPAUSE_GAME => self.game_paused = false,
+            ACT_DISPLAY_TEXT => self.show_text(player, a.string),
+            ACT_TRANSMISSION => {
+                self.show_text(player, a.string);
+                return Flow::Wait(a.time);
+            }
+            ACT_SET_SWITCH => {
+                let switch = a.param as usize % SWITCH_COUNT;
+                self.switches[switch] = match a.modifier {
+                    SET => true,
+                    CLEAR => false,
+                    TOGGLE => !self.switches[switch],
+                    RANDOMIZE => world.data.services.rng.rand() & 1 != 0,
+                    _ => self.switches[switch],
+                };
+            }
+            ACT_SET_COUNTDOWN_TIMER => self.countdown = modify(self.countdown, a.modifier, a.time),
+            ACT_PAUSE_TIMER => self.countdown_paused = true,
+            ACT_UNPAUSE_TIMER => self.countdown_paused = false,
+            ACT_RUN_AI_SCRIPT | ACT_RUN_AI_SCRIPT_AT => {
+                self.ai_requests.push(AIScriptRequest {
+                    player: player,
+                    script: a.param,
+                    location: if a.action == ACT_RUN_AI_SCRIPT_AT {
+                        a.location
+                    } else {
+                        0
+                    },
+                });
+            }
+            ACT_CREATE_UNIT | ACT_CREATE_UNIT_WITH_PROPERTIES => {
+                // FIXME: unit properties (UPRP) aren't read
+                if let Some((x, y)) = world.data.services.locations.center(a.location) {
+                    if unit_type < UNIT_TYPE_COUNT {
+                        for &p in &players {
+                            for _ in 0..a.modifier.max(1) {
+                                create_scunit(world, gd, unit_type, x as u16, y as u16, p);
+                            }
+                        }
+                    }
+                }
+            }
+            ACT_KILL_UNIT | ACT_KILL_UNIT_AT => {
+                let (location, count) = if a.action == ACT_KILL_UNIT_AT {
+                    (Some(a.location), a.modifier)
+                } else {
+                    (None, 0)
+                };
+                let selected = self.select(&gd.units_dat,
+                                           &world.data.services.locations,
+                                           &units,
+                                           &players,
+                                           unit_type,
+                                           location,
+                                           count);
+                for e in selected {
+                    kill_entity(world, e);
+                }
+            }
+            ACT_REMOVE_UNIT | ACT_REMOVE_UNIT_AT => {
+                let (location, count) = if a.action == ACT_REMOVE_UNIT_AT {
+                    (Some(a.location), a.modifier)
+                } else {
+                    (None, 0)
+                };
+                let selected = self.select(&gd.units_dat,
+                                           &world.data.services.locations,
+                                           &units,
+                                           &players,
+                                           unit_type,
+                                           location,
+                                           count);
+                for e in selected {
+                    remove_entity_rec(world, e);
+                }
+            }
+            ACT_SET_RESOURCES => {
+                for &p in &players {
+                    let state = &mut world.data.services.players[p];
+                    if a.unit_type == ORE || a.unit_type == ORE_AND_GAS {
+                        state.minerals = modify(state.minerals, a.modifier, a.param);
+                    }
+                    if a.unit_type == GAS || a.unit_type == ORE_AND_GAS {
+                        state.gas = modify(state.gas, a.modifier, a.param);
+                    }
+                }
+            }
+            ACT_SET_SCORE => {
+                for &p in &players {
+                    let state = &mut world.data.services.players[p];
+                    match a.unit_type as u8 {
+                        SCORE_UNITS => {
+                            state.unit_score = modify(state.unit_score, a.modifier, a.param)
+                        }
+                        SCORE_BUILDINGS => {
+                            state.building_score =
+                                modify(state.building_score, a.modifier, a.param)
+                        }
+                        SCORE_KILLS => {
+                            state.kill_score = modify(state.kill_score, a.modifier, a.param)
+                        }
+                        SCORE_RAZINGS => {
+                            state.razing_score = modify(state.razing_score, a.modifier, a.param)
+                        }
+                        SCORE_CUSTOM => {
+                            state.custom_score = modify(state.custom_score, a.modifier, a.param)
+                        }
+                        // total, units and buildings, kills and razings are sums of
+                        // the others, there's no single score to modify
+                        _ => {}
+                    }
+                }
+            }
+            ACT_SET_DEATHS => {
+                if unit_type < UNIT_TYPE_COUNT {
+                    for &p in &players {
+                        let deaths = &mut world.data.services.players[p].deaths[unit_type];
+                        *deaths = modify(*deaths, a.modifier, a.param);
+                    }
+                }
+            }
+            ACT_MOVE_UNIT => {
+                if let Some((x, y)) = world.data.services.locations.center(a.param) {
+                    let selected = self.select(&gd.units_dat,
+                                               &world.data.services.locations,
+                                               &units,
+                                               &players,
+                                               unit_type,
+                                               Some(a.location),
+                                               a.modifier);
+                    for e in selected {
+                        world.with_entity_data(&e, |e, data| SCUnitStep::halt(&e, data));
+                        SCUnitStep::move_unit(world, e, px_to_fixed(x), px_to_fixed(y));
+                    }
+                }
+            }
+            ACT_ORDER => {
+                let order_type = match a.modifier {
+                    ORDER_MOVE => OrderType::Move,
+                    ORDER_PATROL => OrderType::Patrol,
+                    ORDER_ATTACK => OrderType::AttackMove,
+                    _ => return Flow::Next,
+                };
+                if let Some((x, y)) = world.data.services.locations.center(a.param) {
+                    let selected = self.select(&gd.units_dat,
+                                               &world.data.services.locations,
+                                               &units,
+                                               &players,
+                                               unit_type,
+                                               Some(a.location),
+                                               0);
+                    for e in selected {
+                        issue_order(world,
+                                    e,
+                                    Order::new(order_type, OrderTarget::Position(x, y)),
+                                    false);
+                    }
+                }
+            }
+            ACT_GIVE_UNITS => {
+                let new_owner = a.param as usize;
+                if new_owner < 12 {
+                    let selected = self.select(&gd.units_dat,
+                                               &world.data.services.locations,
+                                               &units,
+                                               &players,
+                                               unit_type,
+                                               Some(a.location),
+                                               a.modifier);
+                    for e in selected {
+                        give_unit(world, e, new_owner);
+                    }
+                }
+            }
+            ACT_SET_INVINCIBILITY => {
+                let selected = self.select(&gd.units_dat,
+                                           &world.data.services.locations,
+                                           &units,
+                                           &players,
+                                           unit_type,
+                                           Some(a.location),
+                                           0);
+                for e in selected {
+                    world.with_entity_data(&e, |e, data| {
+                        let invincible = data.status[e].invincible;
+                        data.status[e].invincible = match a.modifier {
+                            SET => true,
+                            CLEAR => false,
+                            TOGGLE => !invincible,
+                            _ => invincible,
+                        };
+                    });
+                }
+            }
+            ACT_MODIFY_HIT_POINTS | ACT_MODIFY_ENERGY | ACT_MODIFY_SHIELDS => {
+                let percent = a.param.min(100) as i32;
+                let selected = self.select(&gd.units_dat,
+                                           &world.data.services.locations,
+                                           &units,
+                                           &players,
+                                           unit_type,
+                                           Some(a.location),
+                                           a.modifier);
+                for e in selected {
+                    world.with_entity_data(&e, |e, data| {
+                        let health = &mut data.health[e];
+                        match a.action {
+                            ACT_MODIFY_HIT_POINTS => {
+                                health.hit_points = (health.max_hit_points * percent / 100).max(1);
+                            }
+                            ACT_MODIFY_ENERGY => health.energy = health.max_energy * percent / 100,
+                            _ => health.shields = health.max_shields * percent / 100,
+                        }
+                    });
+                }
+            }
+            ACT_MODIFY_RESOURCES => {
+                let selected = self.select(&gd.units_dat,
+                                           &world.data.services.locations,
+                                           &units,
+                                           &players,
+                                           ANY_UNIT,
+                                           Some(a.location),
+                                           a.modifier);
+                for e in selected {
+                    world.with_entity_data(&e, |e, data| if data.resource.has(&e) {
+                        data.resource[e].amount = a.param;
+                    });
+                }
+            }
+            ACT_MOVE_LOCATION => {
+                // centers location `param` on the first matching unit
+                let selected = self.select(&gd.units_dat,
+                                           &world.data.services.locations,
+                                           &units,
+                                           &players,
+                                           unit_type,
+                                           Some(a.location),
+                                           1);
+                let target = selected.first().and_then(|e| {
+                    world.with_entity_data(e, |e, data| {
+                        (data.iscript_state[e].map_pos_x as i32,
+                         data.iscript_state[e].map_pos_y as i32)
+                    })
+                });
+                if let Some((x, y)) = target {
+                    world.data.services.locations.center_on(a.param, x, y);
+                }
+            }
+            ACT_SET_ALLIANCE => {
+                let status = a.unit_type as u8;
+                if status <= ALLIED_VICTORY {
+                    for &p in players.iter().filter(|&&p| p != player) {
+                        self.alliances[player][p] = status;
+                    }
+                }
+            }
+            // sounds, view, leaderboards, portraits, mission objectives,
+            // comments, doodads, hangars: nothing the simulation needs
+            _ => {}
+        }
+        Flow::Next
+    }
+
+    fn show_text(&mut self, player: usize, string: u32) {
+        let text = self.map.scmap.string(string as usize).map(String::from);
+        if let Some(text) = text {
+            self.messages.push((player, text));
+        }
+    }
+}
+
+/// runs the triggers for one game tick
+pub fn run_triggers(world: &mut World<UnitSystems>, gd: &GameData) {
+    let mut triggers = match world.data.services.triggers.take() {
+        Some(triggers) => triggers,
+        None => return,
+    };
+    triggers.step(world, gd);
+    world.data.services.triggers = Some(triggers);
+}
+
+/// all units the triggers see: finished, alive and not a turret
+fn trigger_units(world: &World<UnitSystems>, map: &Map) -> Vec<TriggerUnit> {
+    let dh = &world.data;
+    world.entities()
+        .filter(aspect!(<UnitComponents> all: [scunit] none: [subunit]), world)
+        .filter(|e| !is_dying(*e, dh) && !is_under_construction(*e, dh))
+        .map(|e| {
+            let (height, flying) = unit_elevation(e, dh, map);
+            TriggerUnit {
+                entity: **e,
+                unit_id: dh.scunit[e].unit_id as usize,
+                player: dh.scimage[e].player_id,
+                x: dh.iscript_state[e].map_pos_x as i32,
+                y: dh.iscript_state[e].map_pos_y as i32,
+                height: height,
+                flying: flying,
+            }
+        })
+        .collect()
+}
+
+/// changes the owner of a unit and its overlays
+fn give_unit(world: &mut World<UnitSystems>, unit: Entity, player: usize) {
+    let children = world.with_entity_data(&unit, |e, data| {
+            data.scimage[e].player_id = player;
+            data.iscript_state[e].children.clone()
+        })
+        .unwrap_or_else(Vec::new);
+    for c in children {
+        give_unit(world, c, player);
+    }
+}
+
+fn matches_type(units_dat: &UnitsDat, unit_type: usize, unit_id: usize) -> bool {
+    let building = units_dat.flags(unit_id).contains(UF_BUILDING);
+    match unit_type {
+        ANY_UNIT => true,
+        MEN => !building,
+        BUILDINGS => building,
+        FACTORIES => FACTORY_UNITS.contains(&unit_id),
+        _ => unit_type == unit_id,
+    }
+}
+
+/// kills or deaths of a unit type
+fn counted(units_dat: &UnitsDat, per_type: &[u32], unit_type: usize) -> u32 {
+    per_type.iter()
+        .enumerate()
+        .filter(|&(unit_id, _)| matches_type(units_dat, unit_type, unit_id))
+        .map(|(_, n)| *n)
+        .sum()
+}
+
+fn resources(minerals: u32, gas: u32, resource_type: u16) -> u32 {
+    match resource_type {
+        ORE => minerals,
+        GAS => gas,
+        _ => minerals + gas,
+    }
+}
+
+/// does a trigger run for `player`, either directly, for all players or for
+/// the player's force
+fn runs_for(trigger_players: &[u8], forces: &Forces, player: usize) -> bool {
+    let force_group = forces.force(player)
+        .map(|f| GROUP_FORCE_1 as usize + f)
+        .filter(|&g| g <= GROUP_FORCE_4 as usize);
+    trigger_players[player] != 0 || trigger_players[GROUP_ALL_PLAYERS as usize] != 0 ||
+    force_group.map_or(false, |g| trigger_players[g] != 0)
+}
+
+/// players of the same force are allies if the force says so
+fn initial_alliances(forces: &Forces, playing: &[usize]) -> [[u8; 12]; TRIGGER_PLAYERS] {
+    let mut alliances = [[ENEMY; 12]; TRIGGER_PLAYERS];
+    for &p in playing {
+        for &q in playing.iter().filter(|&&q| q != p) {
+            let force = forces.player_force[p] as usize;
+            if force != forces.player_force[q] as usize {
+                continue;
+            }
+            let flags = forces.flags.get(force).cloned().unwrap_or(0);
+            if flags & FORCE_ALLIED_VICTORY != 0 && flags & FORCE_ALLIES != 0 {
+                alliances[p][q] = ALLIED_VICTORY;
+            } else if flags & FORCE_ALLIES != 0 {
+                alliances[p][q] = ALLY;
+            }
+        }
+    }
+    alliances
+}
+
+fn score(state: &PlayerState, score_type: u8) -> u32 {
+    match score_type {
+        SCORE_TOTAL => {
+            state.unit_score + state.building_score + state.kill_score + state.razing_score +
+            state.custom_score
+        }
+        SCORE_UNITS => state.unit_score,
+        SCORE_BUILDINGS => state.building_score,
+        SCORE_UNITS_AND_BUILDINGS => state.unit_score + state.building_score,
+        SCORE_KILLS => state.kill_score,
+        SCORE_RAZINGS => state.razing_score,
+        SCORE_KILLS_AND_RAZINGS => state.kill_score + state.razing_score,
+        SCORE_CUSTOM => state.custom_score,
+        _ => 0,
+    }
+}
+
+fn compare(value: u32, comparison: u8, qty: u32) -> bool {
+    match comparison {
+        AT_LEAST => value >= qty,
+        AT_MOST => value <= qty,
+        EXACTLY => value == qty,
+        _ => false,
+    }
+}
+
+fn modify(value: u32, modifier: u8, amount: u32) -> u32 {
+    match modifier {
+        SET_TO => amount,
+        ADD => value.saturating_add(amount),
+        SUBTRACT => value.saturating_sub(amount),
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scformats::terrain::{Forces, FORCE_ALLIES};
+    use super::{compare, modify, runs_for, initial_alliances, AT_LEAST, AT_MOST, EXACTLY,
+                SET_TO, ADD, SUBTRACT, ENEMY, ALLY};
+
+    #[test]
+    fn comparisons() {
+        assert!(compare(5, AT_LEAST, 5));
+        assert!(!compare(4, AT_LEAST, 5));
+        assert!(compare(0, AT_MOST, 3));
+        assert!(!compare(4, AT_MOST, 3));
+        assert!(compare(7, EXACTLY, 7));
+        assert!(!compare(8, EXACTLY, 7));
+    }
+
+    #[test]
+    fn modifiers() {
+        assert_eq!(modify(10, SET_TO, 3), 3);
+        assert_eq!(modify(10, ADD, 3), 13);
+        assert_eq!(modify(2, SUBTRACT, 3), 0);
+        assert_eq!(modify(u32::max_value(), ADD, 1), u32::max_value());
+    }
+
+    #[test]
+    fn forces() {
+        let mut forces = Forces::default();
+        // players 1 and 2 in an allied force 1, player 3 alone in force 2
+        forces.player_force = [0, 0, 1, 0, 0, 0, 0, 0];
+        forces.flags[0] = FORCE_ALLIES;
+        let alliances = initial_alliances(&forces, &[0, 1, 2]);
+        assert_eq!(alliances[0][1], ALLY);
+        assert_eq!(alliances[1][0], ALLY);
+        assert_eq!(alliances[0][2], ENEMY);
+        assert_eq!(alliances[2][0], ENEMY);
+        assert_eq!(alliances[0][0], ENEMY);
+
+        let mut players = [0 as u8; 28];
+        // force 2
+        players[19] = 1;
+        assert!(runs_for(&players, &forces, 2));
+        assert!(!runs_for(&players, &forces, 0));
+        players[19] = 0;
+        players[17] = 1;
+        assert!(runs_for(&players, &forces, 0));
+    }
+}
@@ -13,6 +13,7 @@ use scformats::terrain::GameDataTrait;
 use ::{GameContext, GameState, LayerTrait, GameEvents, MousePointerType};
 
 use ::gamedata::GameData;
+use ::vision::VisionGrid;
 
 use std::cmp::{min, max};
 
@@ -130,6 +131,9 @@ impl MousePointer {
 
 struct MiniMap {
     minimap: Texture,
+    /// unfogged minimap and its palette, to redraw the fog
+    bitmap: Vec<u8>,
+    pal: Palette,
     mmapwratio: f32,
     mmaphratio: f32,
     mmap_cur_rect: Rect,
@@ -158,6 +162,8 @@ impl MiniMap {
 
         MiniMap {
             minimap: mmap,
+            bitmap: mmap_bmp,
+            pal: Palette::from_buffer(&map.terrain_info.pal.data),
             mmap_rect: Rect::new(6, 348, 128, 128),
             mmap_cur_rect: mmap_cur_rect,
             mmapwratio: mapw2mmapw_ratio,
@@ -191,6 +197,20 @@ impl MiniMap {
         self.mmap_cur_rect.set_y(new_y);
     }
 
+    fn update_fog(&mut self,
+                  renderer: &mut Renderer,
+                  vision: &VisionGrid,
+                  player: usize,
+                  darken: &[u8]) {
+        let mut bitmap = self.bitmap.clone();
+        vision.render_minimap_fog(player, darken, &mut bitmap);
+        self.minimap = palimg_to_texture(renderer,
+                                         self.map_size.x() as u32,
+                                         self.map_size.y() as u32,
+                                         &bitmap,
+                                         &self.pal);
+    }
+
     fn render(&self, renderer: &mut Renderer) {
         let _ = renderer.copy(&self.minimap, None, Some(self.mmap_rect));
 
@@ -292,6 +312,76 @@ impl SelectionPanel {
     }
 }
 
+/// minerals, gas and supply in the top right corner
+struct ResourceBar {
+    /// (minerals, gas, supply used, supply provided) currently shown
+    shown: Option<(u32, u32, u32, u32)>,
+    buffer: Vec<u8>,
+    text: Texture,
+    pos_rect: Rect,
+}
+const RESOURCE_BAR_W: u32 = 240;
+const RESOURCE_BAR_H: u32 = 14;
+impl ResourceBar {
+    pub fn new(gd: &GameData, ctx: &mut GameContext) -> Self {
+        let buffer = vec![0; (RESOURCE_BAR_W * RESOURCE_BAR_H) as usize];
+        let text = palimg_to_texture(&mut ctx.renderer,
+                                     RESOURCE_BAR_W,
+                                     RESOURCE_BAR_H,
+                                     &buffer,
+                                     &gd.font_reindexing_store.get_game_reindex().palette);
+        ResourceBar {
+            shown: None,
+            buffer: buffer,
+            text: text,
+            pos_rect: Rect::new(640 - RESOURCE_BAR_W as i32, 2, RESOURCE_BAR_W, RESOURCE_BAR_H),
+        }
+    }
+
+    /// supply in half units, like units.dat
+    pub fn update(&mut self,
+                  gd: &GameData,
+                  ctx: &mut GameContext,
+                  minerals: u32,
+                  gas: u32,
+                  supply: (u32, u32)) {
+        let values = (minerals, gas, supply.0, supply.1);
+        if self.shown == Some(values) {
+            return;
+        }
+        self.shown = Some(values);
+
+        for b in self.buffer.iter_mut() {
+            *b = 0;
+        }
+        let text = format!("Minerals {}   Gas {}   Supply {}/{}",
+                           minerals,
+                           gas,
+                           (supply.0 + 1) / 2,
+                           supply.1 / 2);
+        gd.font(FontSize::Font10).render_textbox(&text,
+                                                 0,
+                                                 &gd.font_reindexing_store
+                                                     .get_game_reindex()
+                                                     .data,
+                                                 &mut self.buffer,
+                                                 RESOURCE_BAR_W,
+                                                 &Rect::new(0,
+                                                            0,
+                                                            RESOURCE_BAR_W,
+                                                            RESOURCE_BAR_H));
+        self.text = palimg_to_texture(&mut ctx.renderer,
+                                      RESOURCE_BAR_W,
+                                      RESOURCE_BAR_H,
+                                      &self.buffer,
+                                      &gd.font_reindexing_store.get_game_reindex().palette);
+    }
+
+    pub fn render(&self, renderer: &mut Renderer) {
+        let _ = renderer.copy(&self.text, None, Some(self.pos_rect));
+    }
+}
+
 pub struct UiLayer {
     pub mp: MousePointer,
     ticks: u16,
@@ -302,6 +392,7 @@ pub struct UiLayer {
     dragging_rect: Option<Rect>,
 
     selection_panel: SelectionPanel,
+    resource_bar: ResourceBar,
 }
 impl UiLayer {
     pub fn new(gd: &GameData, context: &mut GameContext, map: &Map) -> Self {
@@ -322,9 +413,29 @@ impl UiLayer {
             is_scrolling: false,
             dragging_rect: None,
             selection_panel: SelectionPanel::new(gd, context),
+            resource_bar: ResourceBar::new(gd, context),
         }
     }
 
+    /// redraws the fog on the minimap
+    pub fn update_fog(&mut self,
+                      context: &mut GameContext,
+                      vision: &VisionGrid,
+                      player: usize,
+                      darken: &[u8]) {
+        self.minimap.update_fog(&mut context.renderer, vision, player, darken);
+    }
+
+    /// supply in half units
+    pub fn update_resources(&mut self,
+                            gd: &GameData,
+                            context: &mut GameContext,
+                            minerals: u32,
+                            gas: u32,
+                            supply: (u32, u32)) {
+        self.resource_bar.update(gd, context, minerals, gas, supply);
+    }
+
     pub fn is_over_hud(&self, x: i32, y: i32) -> bool {
         // TODO: use masked hud_texture?
         (y > 367) ||
@@ -518,5 +629,6 @@ impl LayerTrait for UiLayer {
         self.mp.render(renderer);
 
         self.selection_panel.render(renderer);
+        self.resource_bar.render(renderer);
     }
 }
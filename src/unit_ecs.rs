@@ -9,33 +9,78 @@ use byteorder::{LittleEndian, ByteOrder};
 
 use gamedata::GameData;
 use render::{render_buffer_with_solid_reindexing, render_buffer_with_transparency_reindexing,
-             render_buffer_solid};
+             render_buffer_solid, render_buffer_distortion};
 use gamedata::GRPCache;
 use iscriptsys::IScriptSteppingSys;
-use scformats::unitsdata::WeaponBehavior;
+use scformats::unitsdata::{WeaponBehavior, OrderType, UnitsDat, FlingyDat, WeaponsDat, OrdersDat,
+                           UpgradesDat, TechDataDat, UF_BUILDING};
+use enum_primitive::FromPrimitive;
+use orders::{UnitOrders, UnitInfo, process_orders};
+use combat::{WeaponStep, WeaponState, is_dying};
+use health::HealthComponent;
+use collision::{separation, building_tiles};
+use vision::VisionGrid;
+use economy::{ResourceComponent, WorkerComponent, Supply, STARTING_MINERALS, init_unit_economy,
+              set_map_resources, remove_depleted};
+use construction::{ConstructionComponent, TrainQueueComponent, init_unit_production,
+                   is_under_construction, step_construction, step_production, training_supply};
+use tech::{ResearchComponent, TECH_COUNT, init_unit_research, step_research};
+use abilities::{StatusComponent, step_status, is_disabled, speed_percent};
+use cloak::{CloakComponent, hidden_from, step_cloak, set_map_cloak};
+use transport::{TransportComponent, PassengerComponent, init_unit_transport, kill_cargo,
+                step_bunker, cargo_supply};
+use morph::{MorphComponent, LiftComponent, LarvaComponent, init_unit_morph, is_lifted,
+            step_morph, step_larva, morphing_supply};
+use subunit::{SubunitComponent, init_subunit, step_subunit, kill_subunit};
+use triggers::Triggers;
+use locations::Locations;
+use ai::AI;
 use scformats::iscript::{IScript, AnimationType};
 
-use std::f32;
+use fixedpoint::{Fixed, GameRng, DIRECTION_TABLE, direction_offset, direction_from_delta,
+                 direction_diff, turn_towards, dir256_to_dir32, px_to_fixed, fixed_to_px,
+                 approx_distance};
 
 use bresenham::Bresenham;
 
-use scformats::terrain::Map;
+use scformats::terrain::{Map, MapUnit};
 use std::rc::Rc;
+use std::cell::RefCell;
 
 use pathplanning::jps::{jps_a_star, PlanningMapTrait};
 
 pub struct PlanningMap{
     pub scmap: Map,
+    /// megatiles covered by buildings
+    pub blocked: RefCell<Vec<bool>>,
 }
 impl PlanningMap {
     pub fn new(map: Map) -> Self {
-        PlanningMap{scmap: map}
+        let tiles = map.passable_megatiles.len();
+        PlanningMap{scmap: map, blocked: RefCell::new(vec![false; tiles])}
     }
 
+    pub fn set_blocked(&self, tiles: &[usize]) {
+        let mut blocked = self.blocked.borrow_mut();
+        for b in blocked.iter_mut() {
+            *b = false;
+        }
+        for &idx in tiles {
+            blocked[idx] = true;
+        }
+    }
+
+    /// terrain only, ignoring buildings
+    pub fn is_walkable(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x / 32 >= self.width() as i32 || y / 32 >= self.height() as i32 {
+            return false;
+        }
+        self.scmap.passable_megatiles[self.xy2idx(x / 32, y / 32)]
+    }
 }
 impl PlanningMapTrait for PlanningMap {
     fn is_passable(&self, idx: usize) -> bool {
-        self.scmap.passable_megatiles[idx]
+        self.scmap.passable_megatiles[idx] && !self.blocked.borrow()[idx]
     }
     fn width(&self) -> usize {
         self.scmap.data.width as usize
@@ -45,17 +90,6 @@ impl PlanningMapTrait for PlanningMap {
     }
 }
 
-pub fn angle2discrete(angle: f32) -> u8 {
-    let pi = f32::consts::PI;
-    // x+24 % 32 ~ x + 90°
-    (((angle+pi)*32. / (2.*pi)).round() + 24.) as u8 % 32
-}
-
-pub fn discrete2angle(discrete_angle: u8) -> f32 {
-    let pi = f32::consts::PI;
-    (((discrete_angle + 8) % 32) as f32 / 32.) * (2.*pi) - pi
-}
-
 #[derive(Debug)]
 pub enum IScriptEntityAction {
     CreateImageUnderlay {
@@ -81,8 +115,44 @@ pub enum IScriptEntityAction {
 
     CreateWeaponsFlingy {
         weapon_id: u16,
-        rel_x: isize,
-        rel_y: isize,
+        source: Entity,
+        target: Option<Entity>,
+        x: u16,
+        y: u16,
+        player_id: usize,
+    },
+    /// damage of a weapon flingy or melee unit
+    DealDamage { source: Entity },
+    /// a worker arrived at the site of a building
+    StartConstruction {
+        builder: Entity,
+        unit_id: u16,
+        x: u16,
+        y: u16,
+    },
+    /// a building finished training a unit
+    SpawnUnit { building: Entity, unit_id: u16 },
+    /// iscript's castspell opcode ran
+    CastSpell { caster: Entity },
+    LoadUnit { transport: Entity, unit: Entity },
+    UnloadAll { transport: Entity },
+    /// a unit turns into another unit type
+    Morph { unit: Entity, unit_id: u16 },
+    LiftOff { building: Entity },
+    Land { building: Entity, x: u16, y: u16 },
+    /// two templar become an archon
+    Merge {
+        unit: Entity,
+        partner: Entity,
+        unit_id: u16,
+    },
+    SpawnLarva { hatchery: Entity },
+    /// a unit in a bunker shoots
+    BunkerFire {
+        bunker: Entity,
+        passenger: Entity,
+        target: Entity,
+        weapon_id: u16,
     },
     RemoveEntity { entity: Entity },
 }
@@ -109,11 +179,17 @@ pub struct IScriptStateElement {
     pub visible: bool,
     pub alive: bool,
     // pub current_state: IScriptCurrentUnitState,
-    /// for move opcode
-    pub movement_angle: f32,
+    /// for move opcode, 256 directions
+    pub movement_direction: u8,
 
+    /// pixel part of exact_x/exact_y, use set_exact_pos/set_map_pos to move
     pub map_pos_x: u16,
     pub map_pos_y: u16,
+    pub exact_x: Fixed,
+    pub exact_y: Fixed,
+    /// position at the start of the current tick, for interpolation
+    pub last_exact_x: Fixed,
+    pub last_exact_y: Fixed,
     pub parent_entity: Option<Entity>,
     pub children: Vec<Entity>,
     /// stops iscript interpretation (for opcode IgnoreRest)
@@ -147,11 +223,15 @@ impl IScriptStateElement {
             rel_y: 0,
             frameset: 0,
             direction: 0,
-            movement_angle: 0f32,
+            movement_direction: 0,
             alive: true,
             // current_state: IScriptCurrentUnitState::Idle,
             map_pos_x: map_x,
             map_pos_y: map_y,
+            exact_x: px_to_fixed(map_x as i32),
+            exact_y: px_to_fixed(map_y as i32),
+            last_exact_x: px_to_fixed(map_x as i32),
+            last_exact_y: px_to_fixed(map_y as i32),
             parent_entity: parent_entity,
             children: Vec::new(),
             paused: false,
@@ -160,11 +240,36 @@ impl IScriptStateElement {
     }
 
     pub fn move_forward(&mut self, dist: u8) {
-        let fdist = dist as f32;
-        let (dx, dy) = (self.movement_angle.cos() * fdist ,
-                        self.movement_angle.sin() * fdist);
-        self.map_pos_x = (self.map_pos_x as i32 + dx.round() as i32) as u16;
-        self.map_pos_y = (self.map_pos_y as i32 + dy.round() as i32) as u16;
+        let (dx, dy) = direction_offset(self.movement_direction, dist as i32);
+        let (x, y) = (self.exact_x + dx, self.exact_y + dy);
+        self.set_exact_pos(x, y);
+    }
+
+    /// positions are clamped to the pixel range of u16
+    pub fn set_exact_pos(&mut self, x: Fixed, y: Fixed) {
+        let max = px_to_fixed(u16::max_value() as i32);
+        self.exact_x = x.max(0).min(max);
+        self.exact_y = y.max(0).min(max);
+        self.map_pos_x = fixed_to_px(self.exact_x) as u16;
+        self.map_pos_y = fixed_to_px(self.exact_y) as u16;
+    }
+    /// jumps to the position, without interpolating
+    pub fn set_map_pos(&mut self, x: u16, y: u16) {
+        self.set_exact_pos(px_to_fixed(x as i32), px_to_fixed(y as i32));
+        self.store_last_pos();
+    }
+
+    pub fn store_last_pos(&mut self) {
+        self.last_exact_x = self.exact_x;
+        self.last_exact_y = self.exact_y;
+    }
+    /// pixel position between the last and the current tick, alpha in 0..256
+    pub fn interpolated_pos(&self, alpha: u32) -> (i32, i32) {
+        let alpha = alpha as i64;
+        let dx = ((self.exact_x - self.last_exact_x) as i64 * alpha) >> 8;
+        let dy = ((self.exact_y - self.last_exact_y) as i64 * alpha) >> 8;
+        (fixed_to_px(self.last_exact_x + dx as Fixed),
+         fixed_to_px(self.last_exact_y + dy as Fixed))
     }
 
     /// reference to iscript animation offsets
@@ -221,10 +326,134 @@ impl IScriptStateElement {
 
 /// unit service definition
 #[derive(Default)]
-pub struct UnitServices {}
-impl UnitServices {}
+pub struct UnitServices {
+    /// all randomness in the simulation has to come from here
+    pub rng: GameRng,
+    pub dat: Option<Rc<DatFiles>>,
+    pub players: Vec<PlayerState>,
+    pub vision: VisionGrid,
+    /// None without a map
+    pub triggers: Option<Triggers>,
+    /// moved by triggers
+    pub locations: Locations,
+    /// None without a map
+    pub ai: Option<AI>,
+}
+impl UnitServices {
+    pub fn dat(&self) -> Rc<DatFiles> {
+        self.dat.as_ref().expect("dat files not set!").clone()
+    }
+
+    pub fn upgrade_level(&self, player: usize, upgrade: usize) -> u8 {
+        self.players
+            .get(player)
+            .and_then(|p| p.upgrade_levels.get(upgrade))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    pub fn has_tech(&self, player: usize, tech: usize) -> bool {
+        self.players
+            .get(player)
+            .and_then(|p| p.researched.get(tech))
+            .cloned()
+            .unwrap_or(false)
+    }
+}
+
+pub const MAX_PLAYERS: usize = 12;
+pub const UPGRADE_COUNT: usize = 61;
+/// entries in units.dat
+pub const UNIT_TYPE_COUNT: usize = 228;
+
+#[derive(Clone)]
+pub struct PlayerState {
+    pub upgrade_levels: Vec<u8>,
+    pub max_upgrade_levels: Vec<u8>,
+    pub researched: Vec<bool>,
+    /// techs the map allows to research
+    pub tech_available: Vec<bool>,
+    pub minerals: u32,
+    pub gas: u32,
+    /// recounted every tick
+    pub supply: Supply,
+    /// lost units per unit type
+    pub deaths: Vec<u32>,
+    /// killed enemy units per unit type
+    pub kills: Vec<u32>,
+    /// build score of the units and buildings made during the game
+    pub unit_score: u32,
+    pub building_score: u32,
+    pub kill_score: u32,
+    pub razing_score: u32,
+    /// set by triggers
+    pub custom_score: u32,
+}
+impl PlayerState {
+    pub fn new() -> Self {
+        PlayerState {
+            upgrade_levels: vec![0; UPGRADE_COUNT],
+            max_upgrade_levels: vec![0; UPGRADE_COUNT],
+            researched: vec![false; TECH_COUNT],
+            tech_available: vec![false; TECH_COUNT],
+            minerals: STARTING_MINERALS,
+            gas: 0,
+            supply: Supply::default(),
+            deaths: vec![0; UNIT_TYPE_COUNT],
+            kills: vec![0; UNIT_TYPE_COUNT],
+            unit_score: 0,
+            building_score: 0,
+            kill_score: 0,
+            razing_score: 0,
+            custom_score: 0,
+        }
+    }
+
+    /// scores a unit or building this player finished
+    pub fn record_built(&mut self, units_dat: &UnitsDat, unit_id: usize) {
+        let score = units_dat.build_score[unit_id] as u32;
+        if units_dat.flags(unit_id).contains(UF_BUILDING) {
+            self.building_score += score;
+        } else {
+            self.unit_score += score;
+        }
+    }
+
+    /// counts a unit of type `unit_id` killed by this player
+    pub fn record_kill(&mut self, units_dat: &UnitsDat, unit_id: usize) {
+        self.kills[unit_id] += 1;
+        let score = units_dat.destroy_score[unit_id] as u32;
+        if units_dat.flags(unit_id).contains(UF_BUILDING) {
+            self.razing_score += score;
+        } else {
+            self.kill_score += score;
+        }
+    }
+}
 impl ServiceManager for UnitServices {}
 
+/// the dat files the systems need to look up unit properties
+pub struct DatFiles {
+    pub units_dat: UnitsDat,
+    pub flingy_dat: FlingyDat,
+    pub weapons_dat: WeaponsDat,
+    pub orders_dat: OrdersDat,
+    pub upgrades_dat: UpgradesDat,
+    pub techdata_dat: TechDataDat,
+}
+impl DatFiles {
+    pub fn new(gd: &GameData) -> Self {
+        DatFiles {
+            units_dat: gd.units_dat.clone(),
+            flingy_dat: gd.flingy_dat.clone(),
+            weapons_dat: gd.weapons_dat.clone(),
+            orders_dat: gd.orders_dat.clone(),
+            upgrades_dat: gd.upgrades_dat.clone(),
+            techdata_dat: gd.techdata_dat.clone(),
+        }
+    }
+}
+
 // component definitions
 
 pub enum SCImageRemapping {
@@ -241,6 +470,8 @@ pub struct SCImageComponent {
     pub player_id: usize,
     can_turn: bool,
     remapping: SCImageRemapping,
+    /// false for e.g. shadows, which disappear when the unit cloaks
+    pub draw_if_cloaked: bool,
 }
 impl SCImageComponent {
     pub fn new(gd: &GameData, image_id: u16) -> Self {
@@ -273,6 +504,7 @@ impl SCImageComponent {
             player_id: 0,
             can_turn: can_turn,
             remapping: remapping,
+            draw_if_cloaked: gd.images_dat.draw_if_cloaked[image_id as usize] != 0,
         }
     }
 
@@ -332,6 +564,19 @@ impl SCImageComponent {
         }
     }
 
+    /// the distortion of cloaked units that the player can see
+    pub fn draw_cloaked(&self,
+                        inbuf: &[u8],
+                        w: u32,
+                        h: u32,
+                        flipped: bool,
+                        cx: i32,
+                        cy: i32,
+                        outbuf: &mut [u8],
+                        outbuf_pitch: u32) {
+        render_buffer_distortion(inbuf, w, h, flipped, cx, cy, outbuf, outbuf_pitch);
+    }
+
     // TODO: it might make sense to join scimage & iscriptstate
     pub fn frame_idx(&self, iscript_state: &IScriptStateElement) -> usize {
         if !self.can_turn {
@@ -351,6 +596,16 @@ impl SCImageComponent {
 pub struct SCSpriteComponent {
     pub sprite_id: u16,
 }
+// palette indices of the status bars
+const BAR_HEIGHT: u32 = 5;
+const BAR_BORDER: u8 = 0;
+const BAR_EMPTY: u8 = 0;
+const BAR_GREEN: u8 = 185;
+const BAR_YELLOW: u8 = 135;
+const BAR_RED: u8 = 111;
+const BAR_SHIELDS: u8 = 165;
+const BAR_ENERGY: u8 = 164;
+
 pub struct SelectableComponent {
     /// from sprites.dat: length of health bar in pixels
     pub health_bar: u8,
@@ -361,6 +616,28 @@ pub struct SelectableComponent {
     pub sel_height: u16,
 }
 impl SelectableComponent {
+    /// not all sprites are selectable
+    pub fn for_sprite(gd: &GameData, sprite_id: usize) -> Option<Self> {
+        if sprite_id < 130 {
+            return None;
+        }
+        let circle_img = gd.sprites_dat.selection_circle_image[sprite_id - 130];
+        let circle_grp_id = gd.images_dat.grp_id[561 + circle_img as usize];
+
+        let (sel_width, sel_height) = {
+            let mut grp_cache = gd.grp_cache.borrow_mut();
+            let grp = grp_cache.get(gd, circle_grp_id);
+            (grp.header.width, grp.header.height)
+        };
+        Some(SelectableComponent {
+            health_bar: gd.sprites_dat.health_bar[sprite_id - 130],
+            circle_offset: gd.sprites_dat.selection_circle_offset[sprite_id - 130],
+            circle_grp_id: circle_grp_id,
+            sel_width: sel_width,
+            sel_height: sel_height,
+        })
+    }
+
     pub fn draw_selection_circle(&self,
                                  grp_cache: &GRPCache,
                                  cx: i32,
@@ -379,32 +656,85 @@ impl SelectableComponent {
     }
 
 
-    // FIXME: clipping
-    pub fn draw_healthbar(&self, cx: u32, cy: u32, buffer: &mut [u8], buffer_pitch: u32) {
+    /// hit point bar with shield bar above and energy bar below, if the
+    /// unit has them
+    pub fn draw_healthbar(&self,
+                          health: &HealthComponent,
+                          cx: i32,
+                          cy: i32,
+                          buffer: &mut [u8],
+                          buffer_pitch: u32) {
         let boxes = self.health_bar as u32 / 3;
-        let box_width = 3;
-        if self.health_bar == 0 {
+        if boxes == 0 {
             return;
         }
-        let width = 2 + (box_width * boxes) + (boxes - 1);
-        let height = 8;
+        let mut y = cy + self.circle_offset as i32 - BAR_HEIGHT as i32 / 2;
+        if health.max_shields > 0 {
+            SelectableComponent::draw_bar(boxes,
+                                          health.shield_boxes(boxes),
+                                          BAR_SHIELDS,
+                                          cx,
+                                          y - BAR_HEIGHT as i32 + 1,
+                                          buffer,
+                                          buffer_pitch);
+        }
+        let hp_color = match health.hp_percent() {
+            p if p > 66 => BAR_GREEN,
+            p if p > 33 => BAR_YELLOW,
+            _ => BAR_RED,
+        };
+        SelectableComponent::draw_bar(boxes,
+                                      health.hp_boxes(boxes),
+                                      hp_color,
+                                      cx,
+                                      y,
+                                      buffer,
+                                      buffer_pitch);
+        y += BAR_HEIGHT as i32 - 1;
+        if health.max_energy > 0 {
+            SelectableComponent::draw_bar(boxes,
+                                          health.energy_boxes(boxes),
+                                          BAR_ENERGY,
+                                          cx,
+                                          y,
+                                          buffer,
+                                          buffer_pitch);
+        }
+    }
 
-        let mut outpos = ((cy + self.circle_offset as u32) - height / 2) * buffer_pitch +
-                         (cx - width / 2);
-        for y in 0..height {
+    /// bar of `boxes` boxes, `filled` of them in `color`, centered at cx
+    fn draw_bar(boxes: u32,
+                filled: u32,
+                color: u8,
+                cx: i32,
+                top: i32,
+                buffer: &mut [u8],
+                buffer_pitch: u32) {
+        let box_width = 3;
+        let width = 2 + (box_width * boxes) + (boxes - 1);
+        let left = cx - width as i32 / 2;
+        let buffer_height = (buffer.len() / buffer_pitch as usize) as i32;
+        for y in 0..BAR_HEIGHT {
+            let sy = top + y as i32;
+            if sy < 0 || sy >= buffer_height {
+                continue;
+            }
             for x in 0..width {
-                let outer_border = y == 0 || y == height - 1 || x == 0 || x == (width - 1);
+                let sx = left + x as i32;
+                if sx < 0 || sx >= buffer_pitch as i32 {
+                    continue;
+                }
+                let outer_border = y == 0 || y == BAR_HEIGHT - 1 || x == 0 || x == (width - 1);
                 let inner_border = x % (box_width + 1) == 0;
-                if inner_border || outer_border {
-                    // black
-                    buffer[outpos as usize] = 0;
+                let color = if inner_border || outer_border {
+                    BAR_BORDER
+                } else if x / (box_width + 1) < filled {
+                    color
                 } else {
-                    // green
-                    buffer[outpos as usize] = 185;
-                }
-                outpos += 1;
+                    BAR_EMPTY
+                };
+                buffer[(sy * buffer_pitch as i32 + sx) as usize] = color;
             }
-            outpos += buffer_pitch - width;
         }
     }
 }
@@ -418,24 +748,41 @@ pub struct SCFlingyComponent {
     pub flingy_id: u16,
     pub move_control: FlingyMoveControl,
 
-    /// only used when FlingyDat move control, measured in pixels per frame
-    pub speed: f32,
-    // FIXME: ignoring turn_radius for now
+    /// only used when FlingyDat move control, measured in 1/256 pixels per frame
+    pub speed: Fixed,
+    /// directions (of 256) per frame
+    pub turn_rate: u8,
 
     // FIXME: use global FlingyDat
     top_speed: u32,
     acceleration: u16,
     halt_distance: u32,
 }
-#[derive(Debug)]
-pub enum UnitCommand {
-    Move(i32, i32),
-    Attack(u32),
-}
-#[derive(Debug,Clone)]
-pub enum UnitState {
-    FollowingPath,
-    Idle,
+impl SCFlingyComponent {
+    pub fn new(gd: &GameData, flingy_id: usize) -> Self {
+        let move_control = match gd.flingy_dat.move_control[flingy_id] {
+            0 => FlingyMoveControl::FlingyDat,
+            1 => FlingyMoveControl::PartiallyMobile,
+            2 => FlingyMoveControl::IScriptBin,
+            _ => unimplemented!(),
+        };
+        SCFlingyComponent {
+            flingy_id: flingy_id as u16,
+            move_control: move_control,
+            speed: 0,
+            turn_rate: gd.flingy_dat.turn_radius[flingy_id],
+            acceleration: gd.flingy_dat.acceleration[flingy_id],
+            top_speed: gd.flingy_dat.top_speed[flingy_id],
+            halt_distance: gd.flingy_dat.halt_distance[flingy_id],
+        }
+    }
+
+    pub fn is_iscript_controlled(&self) -> bool {
+        match self.move_control {
+            FlingyMoveControl::IScriptBin => true,
+            _ => false,
+        }
+    }
 }
 pub struct SCUnitComponent {
     pub unit_id: u16,
@@ -450,10 +797,18 @@ pub struct SCUnitComponent {
     pub weapon_shift_proj: u8,
     pub accepts_player_orders: bool,
 
-    /// command stack
-    pub commands: Vec<UnitCommand>,
-    pub state: UnitState,
+    pub orders: UnitOrders,
+    /// ticks until the weapon can fire again
+    pub weapon_cooldown: u8,
+    /// unit the weapons are fired at
+    pub attack_target: Option<Entity>,
     pub path: Option<Path>,
+    /// path handed over by a group move, used by the next move_to to its goal
+    pub planned_path: Option<Path>,
+    /// was pushed by another unit this tick
+    pub collided: bool,
+    /// turret, e.g. of a goliath
+    pub subunit: Option<Entity>,
 }
 
 pub struct Path {
@@ -479,6 +834,10 @@ impl Path {
         }
     }
 
+    pub fn goal(&self) -> Option<::Point> {
+        self.path.first().cloned()
+    }
+
     pub fn mark_tiles(&self,
                    map: &Map, map_x: isize, map_y: isize,
                    buffer: &mut [u8],
@@ -515,13 +874,13 @@ impl Path {
     }
 
     /// give the rest length of the path in pixels
-    pub fn path_dist(&self, mx: isize, my: isize) -> f32 {
-        let mut dist = 0f32;
+    pub fn path_dist(&self, mx: isize, my: isize) -> i32 {
+        let mut dist = 0;
 
         let mut mx = mx as i32;
         let mut my = my as i32;
         for p in self.path.iter().rev() {
-            dist += ((p.x() - mx) as f32).hypot((p.y() - my) as f32);
+            dist += approx_distance(p.x() - mx, p.y() - my);
 
             mx = p.x();
             my = p.y();
@@ -530,22 +889,22 @@ impl Path {
         dist
     }
 
-    /// returns: -180 to +180 (in rad)
-    fn follow(&mut self, mx: i32, my: i32) -> Option<f32> {
+    /// returns the direction (of 256) to the next waypoint
+    fn follow(&mut self, mx: i32, my: i32) -> Option<u8> {
         if self.path.len() == 0 {
             return None;
         }
         let (xdiff, ydiff) = {
             let next_tile = &self.path.last().unwrap();
-            ((next_tile.x() - mx as i32) as f32,
-             (next_tile.y() - my as i32) as f32)
+            (next_tile.x() - mx as i32,
+             next_tile.y() - my as i32)
         };
 
-        let goal_dist = xdiff.hypot(ydiff);
+        let goal_dist = approx_distance(xdiff, ydiff);
         // println!("following path len: {}, dist: {}",
         //          self.tile_path.len(),
         //          goal_dist);
-        if goal_dist < 4. {
+        if goal_dist < 4 {
             if self.path.len() >= 1 {
                 self.path.pop();
                 return self.follow(mx, my);
@@ -553,13 +912,17 @@ impl Path {
                 return None;
             }
         }
-        let angle = ydiff.atan2(xdiff);
-        return Some(angle);
+        return Some(direction_from_delta(xdiff, ydiff));
     }
 }
 
+/// distance (in pixels) to the goal at which pushed units stop
+const COLLISION_GOAL_RANGE: i32 = 32;
+
 pub struct SCUnitStep {
     pub map: Option<Rc<PlanningMap>>,
+    /// entities to create or remove after the update
+    pub actions: Vec<IScriptEntityAction>,
 }
 use ecs::system::{EntityProcess, EntitySystem};
 use ecs::{EntityIter, System};
@@ -581,19 +944,36 @@ impl SCUnitStep {
     // so weapons will continue accelerating to their top speed and ram
     // the target rather than slowing down to gently "land" at it. That is
     // the only difference as I can tell between it and "Flingy Control."
-    fn follow_path(e: &EntityData<UnitComponents>,
-                   dh: &mut DataHelper<UnitComponents, UnitServices>) {
+    /// returns false once the goal is reached
+    pub fn follow_path(e: &EntityData<UnitComponents>,
+                       dh: &mut DataHelper<UnitComponents, UnitServices>) -> bool {
         let mx = dh.iscript_state[*e].map_pos_x;
         let my = dh.iscript_state[*e].map_pos_y;
 
-        let angle = dh.scunit[*e].path.as_mut()
-            .expect("following path, but no path set!")
-            .follow(mx as i32, my as i32);
-        match angle {
-            Some(angle) => {
-                let disc_angle = angle2discrete(angle);
-                dh.iscript_state[*e].direction = disc_angle;
-                dh.iscript_state[*e].movement_angle = angle;
+        // in a group not everyone fits on the goal, close enough is fine
+        let crowded_at_goal = match dh.scunit[*e].path.as_ref() {
+            Some(path) => {
+                path.path.len() == 1 &&
+                path.path_dist(mx as isize, my as isize) < COLLISION_GOAL_RANGE
+            }
+            None => false,
+        };
+        if crowded_at_goal && dh.scunit[*e].collided {
+            SCUnitStep::halt(e, dh);
+            return false;
+        }
+
+        let target_dir = match dh.scunit[*e].path.as_mut() {
+            Some(path) => path.follow(mx as i32, my as i32),
+            None => None,
+        };
+        match target_dir {
+            Some(target_dir) => {
+                let facing = turn_towards(dh.iscript_state[*e].movement_direction,
+                                          target_dir,
+                                          dh.scflingy[*e].turn_rate);
+                dh.iscript_state[*e].direction = dir256_to_dir32(facing);
+                dh.iscript_state[*e].movement_direction = facing;
 
                 let mc = dh.scflingy[*e].move_control.clone();
                 match mc {
@@ -603,48 +983,155 @@ impl SCUnitStep {
                     FlingyMoveControl::FlingyDat => {
                         // FIXME: consider turn radius
                         // use flingy data
-                        let top_speed = (dh.scflingy[*e].top_speed as f32) / 256f32;
-                        let acceleration = (dh.scflingy[*e].acceleration as f32) / 256f32;
-                        let halt_distance = (dh.scflingy[*e].halt_distance as f32) / 256f32;
-
-                        let dist = dh.scunit[*e].path.as_ref().unwrap().path_dist(mx as isize,
-                                                                                  my as isize);
+                        // all of these are in 1/256 pixels
+                        let top_speed = dh.scflingy[*e].top_speed as Fixed *
+                                        speed_percent(*e, dh) / 100;
+                        let acceleration = dh.scflingy[*e].acceleration as Fixed;
+                        let halt_distance = dh.scflingy[*e].halt_distance as Fixed;
+
+                        let dist = px_to_fixed(dh.scunit[*e]
+                            .path
+                            .as_ref()
+                            .unwrap()
+                            .path_dist(mx as isize, my as isize));
                         let speed = dh.scflingy[*e].speed;
-                        if (speed < top_speed) && (dist > halt_distance) {
-                            let newspeed = speed + acceleration;
-                            dh.scflingy[*e].speed = newspeed.min(top_speed);
+                        let speed = if (speed < top_speed) && (dist > halt_distance) {
+                            (speed + acceleration).min(top_speed)
                         } else if dist <= halt_distance {
-                            dh.scflingy[*e].speed -= acceleration;
-                        }
-
-                        let (dx, dy) = (angle.cos() * speed,
-                                        angle.sin() * speed);
-                        dh.iscript_state[*e].map_pos_x = (mx as i32 + dx.round() as i32) as u16;
-                        dh.iscript_state[*e].map_pos_y = (my as i32 + dy.round() as i32) as u16;
-
-                        // FIXME: duplicated from OpCode::Move
-                        let children = dh.iscript_state[*e].children.clone();
-                        let (mx, my) = (dh.iscript_state[*e].map_pos_x, dh.iscript_state[*e].map_pos_y);
-                        for c in children {
-                            dh.with_entity_data(&c, |ent, data| {
-                                data.iscript_state[ent].map_pos_x = mx;
-                                data.iscript_state[ent].map_pos_y = my;
-                            });
+                            // keep crawling, otherwise the goal might never be reached
+                            (speed - acceleration).max(acceleration.min(top_speed))
+                        } else {
+                            speed
+                        };
+                        dh.scflingy[*e].speed = speed;
+
+                        // turn on the spot first if the waypoint is behind us
+                        if direction_diff(facing, target_dir).abs() <= 32 {
+                            let (vx, vy) = DIRECTION_TABLE[facing as usize];
+                            let x = dh.iscript_state[*e].exact_x + ((vx * speed) >> 8);
+                            let y = dh.iscript_state[*e].exact_y + ((vy * speed) >> 8);
+                            SCUnitStep::set_unit_pos(e, dh, x, y);
                         }
                     },
                     _ => {
                         println!("partiallyMobile move control niy!");
                     },
                 }
-
+                true
             },
             None => {
-                println!("goal reached!");
-                dh.iscript_state[*e].next_animation = Some(AnimationType::WalkingToIdle);
-                dh.scunit[*e].state = UnitState::Idle;
+                SCUnitStep::halt(e, dh);
+                false
             }
         }
     }
+
+    /// plans a path to (tx, ty) and starts walking
+    pub fn move_to(e: &EntityData<UnitComponents>,
+                   dh: &mut DataHelper<UnitComponents, UnitServices>,
+                   map: &PlanningMap,
+                   tx: i32,
+                   ty: i32) {
+        let mx = dh.iscript_state[*e].map_pos_x as i32;
+        let my = dh.iscript_state[*e].map_pos_y as i32;
+        let was_moving = dh.scunit[*e].path.is_some();
+        let path = match dh.scunit[*e].planned_path.take() {
+            Some(path) => {
+                if path.goal() == Some(::Point::new(tx, ty)) {
+                    path
+                } else {
+                    Path::plan(mx, my, tx, ty, map)
+                }
+            }
+            None => Path::plan(mx, my, tx, ty, map),
+        };
+        dh.scunit[*e].path = Some(path);
+        if !was_moving {
+            dh.iscript_state[*e].next_animation = Some(AnimationType::Walking);
+        }
+    }
+
+    /// moves the unit and its children
+    pub fn set_unit_pos(e: &EntityData<UnitComponents>,
+                        dh: &mut DataHelper<UnitComponents, UnitServices>,
+                        x: Fixed,
+                        y: Fixed) {
+        SCUnitStep::move_unit(dh, ***e, x, y);
+    }
+
+    /// set_unit_pos by entity, e.g. outside of the systems
+    pub fn move_unit(dh: &mut DataHelper<UnitComponents, UnitServices>,
+                     unit: Entity,
+                     x: Fixed,
+                     y: Fixed) {
+        // FIXME: duplicated from OpCode::Move
+        let pos = dh.with_entity_data(&unit, |e, data| {
+            data.iscript_state[e].set_exact_pos(x, y);
+            (data.iscript_state[e].exact_x,
+             data.iscript_state[e].exact_y,
+             data.iscript_state[e].children.clone())
+        });
+        let (ex, ey, children) = match pos {
+            Some(pos) => pos,
+            None => return,
+        };
+        for c in children {
+            dh.with_entity_data(&c, |ent, data| {
+                data.iscript_state[ent].set_exact_pos(ex, ey);
+            });
+        }
+    }
+
+    fn unit_infos(entities: &[EntityData<UnitComponents>],
+                  dh: &DataHelper<UnitComponents, UnitServices>)
+                  -> Vec<UnitInfo> {
+        entities.iter()
+            .filter(|e| !is_dying(**e, dh))
+            .map(|e| {
+                UnitInfo {
+                    entity: ***e,
+                    unit_id: dh.scunit[*e].unit_id as usize,
+                    player: dh.scimage[*e].player_id,
+                    x: dh.iscript_state[*e].map_pos_x as i32,
+                    y: dh.iscript_state[*e].map_pos_y as i32,
+                    hidden_from: hidden_from(*e, dh),
+                    lifted: is_lifted(*e, dh),
+                }
+            })
+            .collect()
+    }
+
+    /// pushes overlapping ground units apart
+    fn separate_units(entities: &[EntityData<UnitComponents>],
+                      dh: &mut DataHelper<UnitComponents, UnitServices>,
+                      map: &PlanningMap,
+                      dat: &DatFiles) {
+        let alive: Vec<&EntityData<UnitComponents>> = entities.iter()
+            .filter(|e| !is_dying(**e, dh))
+            .collect();
+        let units = SCUnitStep::unit_infos(entities, dh);
+        let moving: Vec<bool> = alive.iter().map(|e| dh.scunit[**e].path.is_some()).collect();
+        let pushes = separation(&dat.units_dat, &units, &moving);
+        for (e, &(px, py)) in alive.iter().zip(pushes.iter()) {
+            dh.scunit[**e].collided = px != 0 || py != 0;
+            if px == 0 && py == 0 {
+                continue;
+            }
+            let x = dh.iscript_state[**e].exact_x + px;
+            let y = dh.iscript_state[**e].exact_y + py;
+            if map.is_walkable(fixed_to_px(x), fixed_to_px(y)) {
+                SCUnitStep::set_unit_pos(e, dh, x, y);
+            }
+        }
+    }
+
+    /// stops moving
+    pub fn halt(e: &EntityData<UnitComponents>, dh: &mut UnitComponents) {
+        if dh.scunit[*e].path.take().is_some() {
+            dh.iscript_state[*e].next_animation = Some(AnimationType::WalkingToIdle);
+        }
+        dh.scflingy[*e].speed = 0;
+    }
 }
 
 impl EntityProcess for SCUnitStep {
@@ -654,36 +1141,64 @@ impl EntityProcess for SCUnitStep {
         if self.map.is_none() {
             return;
         }
-        let mapref = &self.map.as_ref();
-        let map = mapref.cloned().unwrap();
-        for e in en {
-            let mx = dh.iscript_state[e].map_pos_x;
-            let my = dh.iscript_state[e].map_pos_y;
-
-            // take in & apply new commands
-            if let Some(cmd) = dh.scunit[e].commands.pop() {
-                match cmd {
-                    UnitCommand::Move(tx, ty) => {
-                        println!("move {}, {}", tx, ty);
-                        // let tile_path = self.plan_path(mx as i32, my as i32, tx, ty, &map);
-                        let tile_path = Path::plan(mx as i32, my as i32, tx, ty, &map);
-
-                        dh.scunit[e].state = UnitState::FollowingPath;
-                        dh.scunit[e].path = Some(tile_path);
-                        dh.iscript_state[e].next_animation = Some(AnimationType::Walking);
-                    },
-                    UnitCommand::Attack(u) => {
-                        println!("attacking {}", u);
-                    },
-                }
+        let map = self.map.as_ref().cloned().unwrap();
+        let dat = dh.services.dat();
+
+        // orders only see the state of the other units at the start of the tick
+        let entities: Vec<EntityData<UnitComponents>> = en.collect();
+        let units = SCUnitStep::unit_infos(&entities, dh);
+        map.set_blocked(&building_tiles(&dat.units_dat, &units, map.width(), map.height()));
+        for e in &entities {
+            if is_dying(*e, dh) {
+                kill_cargo(e, dh);
+                kill_subunit(e, dh);
+                continue;
             }
-
-            let state = dh.scunit[e].state.clone();
-            match state {
-                UnitState::FollowingPath => {
-                    SCUnitStep::follow_path(&e, dh);
-                },
-                UnitState::Idle => {},
+            dh.health[*e].regenerate();
+            step_status(e, dh, &dat, &units, &mut self.actions);
+            step_cloak(e, dh);
+            if is_disabled(*e, dh) {
+                SCUnitStep::halt(e, dh);
+            } else {
+                process_orders(e, dh, &map, &dat, &units, &mut self.actions);
+            }
+            step_construction(e, dh, &mut self.actions);
+            step_production(e, dh, &dat, &mut self.actions);
+            step_research(e, dh);
+            step_morph(e, dh, &mut self.actions);
+            step_larva(e, dh, &mut self.actions);
+            step_bunker(e, dh, &dat, &units, &mut self.actions);
+            step_subunit(e, dh);
+            remove_depleted(e, dh);
+        }
+        SCUnitStep::separate_units(&entities, dh, &map, &dat);
+        let units = SCUnitStep::unit_infos(&entities, dh);
+        dh.services.vision.update(&dat.units_dat, &units);
+
+        // unfinished buildings don't provide supply, units in production need it
+        let building: Vec<Entity> = entities.iter()
+            .filter(|e| is_under_construction(**e, dh))
+            .map(|e| ***e)
+            .collect();
+        let finished: Vec<UnitInfo> = units.iter()
+            .filter(|u| !building.contains(&u.entity))
+            .cloned()
+            .collect();
+        for (player, state) in dh.services.players.iter_mut().enumerate() {
+            state.supply = Supply::count(&dat.units_dat, &finished, player);
+        }
+        for e in &entities {
+            if let Some((race, supply)) = training_supply(*e, dh, &dat.units_dat) {
+                let player = dh.scimage[*e].player_id;
+                dh.services.players[player].supply.used[race as usize] += supply;
+            }
+            if let Some((race, supply)) = morphing_supply(*e, dh, &dat.units_dat) {
+                let player = dh.scimage[*e].player_id;
+                dh.services.players[player].supply.used[race as usize] += supply;
+            }
+            let player = dh.scimage[*e].player_id;
+            for (race, supply) in cargo_supply(*e, dh, &dat.units_dat) {
+                dh.services.players[player].supply.used[race as usize] += supply;
             }
         }
     }
@@ -697,8 +1212,18 @@ pub struct OverlayComponent {}
 pub struct SCWeaponComponent {
     pub weapon_id: u16,
     pub age: usize,
-    // FIXME: implement behaviors
     pub behavior: WeaponBehavior,
+    /// unit that fired the weapon
+    pub source: Option<Entity>,
+    pub player_id: usize,
+    pub target: Option<Entity>,
+    pub target_pos: (i32, i32),
+    pub state: WeaponState,
+    pub bounces_left: u8,
+    /// bounces deal less damage
+    pub damage_divisor: i32,
+    /// units that were already hit by a weapon going to max range
+    pub hit_units: Vec<Entity>,
 }
 
 use ecs::system::LazySystem;
@@ -712,6 +1237,20 @@ components! {
         #[hot] scflingy: SCFlingyComponent,
         #[hot] scunit: SCUnitComponent,
         #[hot] scweapon: SCWeaponComponent,
+        #[hot] health: HealthComponent,
+        #[hot] resource: ResourceComponent,
+        #[hot] worker: WorkerComponent,
+        #[hot] construction: ConstructionComponent,
+        #[hot] train_queue: TrainQueueComponent,
+        #[hot] research: ResearchComponent,
+        #[hot] status: StatusComponent,
+        #[hot] cloak: CloakComponent,
+        #[hot] transport: TransportComponent,
+        #[hot] passenger: PassengerComponent,
+        #[hot] morph: MorphComponent,
+        #[hot] lift: LiftComponent,
+        #[hot] larva: LarvaComponent,
+        #[hot] subunit: SubunitComponent,
 
         #[hot] underlay: UnderlayComponent,
         #[hot] overlay: OverlayComponent,
@@ -727,9 +1266,17 @@ systems! {
             scunit_stepping_sys: EntitySystem<SCUnitStep> =
                 EntitySystem::new(SCUnitStep {
                     map: None,
+                    actions: Vec::new(),
                 },
                                   aspect!(<UnitComponents>
-                                          all: [scunit])),
+                                          all: [scunit]
+                                          none: [passenger])),
+            weapon_stepping_sys: EntitySystem<WeaponStep> =
+                EntitySystem::new(WeaponStep {
+                    actions: Vec::new(),
+                },
+                                  aspect!(<UnitComponents>
+                                          all: [scweapon])),
         },
         passive: {
         }
@@ -773,28 +1320,8 @@ pub fn create_scsprite(world: &mut World<UnitSystems>,
     world.modify_entity(entity,
                         |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
         data.scsprite.insert(&e, SCSpriteComponent { sprite_id: sprite_id as u16 });
-
-        // not all sprites are selectable
-        if sprite_id >= 130 {
-            let circle_img = gd.sprites_dat.selection_circle_image[(sprite_id - 130) as usize];
-            let circle_grp_id = gd.images_dat.grp_id[561 + circle_img as usize];
-
-            let (sel_width, sel_height) = {
-                let mut grp_cache = gd.grp_cache.borrow_mut();
-                let grp = grp_cache.get(gd, circle_grp_id);
-                (grp.header.width, grp.header.height)
-            };
-
-            data.selectable
-                .insert(&e,
-                        SelectableComponent {
-                            health_bar: gd.sprites_dat.health_bar[(sprite_id - 130) as usize],
-                            circle_offset:
-                                gd.sprites_dat.selection_circle_offset[(sprite_id - 130) as usize],
-                            circle_grp_id: circle_grp_id,
-                            sel_width: sel_width,
-                            sel_height: sel_height,
-                        });
+        if let Some(selectable) = SelectableComponent::for_sprite(gd, sprite_id) {
+            data.selectable.insert(&e, selectable);
         }
     });
     entity
@@ -808,25 +1335,11 @@ pub fn create_scflingy(world: &mut World<UnitSystems>,
                        player_id: usize)
                        -> Entity {
     let sprite_id = gd.flingy_dat.sprite_id[flingy_id as usize];
-    let move_control = match gd.flingy_dat.move_control[flingy_id as usize] {
-        0 => FlingyMoveControl::FlingyDat,
-        1 => FlingyMoveControl::PartiallyMobile,
-        2 => FlingyMoveControl::IScriptBin,
-        _ => unimplemented!(),
-    };
     let entity = create_scsprite(world, gd, sprite_id as usize, map_x, map_y, None, player_id);
 
     world.modify_entity(entity,
                         |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
-        data.scflingy.insert(&e,
-                             SCFlingyComponent {
-                                 flingy_id: flingy_id as u16,
-                                 move_control: move_control,
-                                 speed: 0f32,
-                                 acceleration: gd.flingy_dat.acceleration[flingy_id],
-                                 top_speed: gd.flingy_dat.top_speed[flingy_id],
-                                 halt_distance: gd.flingy_dat.halt_distance[flingy_id],
-                             });
+        data.scflingy.insert(&e, SCFlingyComponent::new(gd, flingy_id));
     });
 
     entity
@@ -853,6 +1366,8 @@ pub fn create_scunit(world: &mut World<UnitSystems>,
     // }
 
     let flingy_id = gd.units_dat.flingy_id[unit_id as usize];
+    let idle_order = OrderType::from_u8(gd.units_dat.human_ai_idle[unit_id])
+        .unwrap_or(OrderType::Nothing);
 
     let entity = create_scflingy(world, gd, flingy_id as usize, map_x, map_y, player_id);
     world.modify_entity(entity,
@@ -866,12 +1381,46 @@ pub fn create_scunit(world: &mut World<UnitSystems>,
                                used_weapon: gd_weapon,
                                accepts_player_orders: true,
                                weapon_shift_proj: 0,
-                               commands: Vec::<UnitCommand>::new(),
-                               state: UnitState::Idle,
+                               orders: UnitOrders::new(idle_order),
+                               weapon_cooldown: 0,
+                               attack_target: None,
                                path: None,
+                               planned_path: None,
+                               collided: false,
+                               subunit: None,
                            });
+        data.health.insert(&e, HealthComponent::new(&gd.units_dat, unit_id));
+        data.status.insert(&e, StatusComponent::new());
+        data.cloak.insert(&e, CloakComponent::new(&gd.units_dat, unit_id));
     });
+    init_unit_economy(world, gd, entity);
+    init_unit_production(world, gd, entity);
+    init_unit_research(world, gd, entity);
+    init_unit_transport(world, gd, entity);
+    init_unit_morph(world, gd, entity);
+    init_subunit(world, gd, entity);
+
+    entity
+}
 
+/// creates a unit placed in a map, using the map's hit point, shield and energy
+/// percentages
+pub fn create_map_unit(world: &mut World<UnitSystems>, gd: &GameData, mapunit: &MapUnit) -> Entity {
+    let entity = create_scunit(world,
+                               gd,
+                               mapunit.unit_id as usize,
+                               mapunit.x,
+                               mapunit.y,
+                               mapunit.player_no as usize);
+    world.modify_entity(entity,
+                        |e: ModifyData<UnitComponents>, data: &mut UnitComponents| {
+        data.health[e].set_map_percentages(mapunit.changeable_props,
+                                           mapunit.hit_points,
+                                           mapunit.shield_points,
+                                           mapunit.energy_points);
+    });
+    set_map_resources(world, entity, mapunit);
+    set_map_cloak(world, entity, mapunit);
     entity
 }
 
@@ -0,0 +1,221 @@
+//! Fog of war. Every player sees the tiles within the sight range of their
+//! units, tiles once seen stay explored. Ground units can't look up onto
+//! higher ground, air units see everything in range. Detectors additionally
+//! mark the tiles in their range as detected, which reveals cloaked units.
+
+use scformats::terrain::{TerrainGrid, TileHeight};
+use scformats::unitsdata::{UnitsDat, UF_FLYER, UF_DETECTOR};
+
+use orders::UnitInfo;
+
+/// players 8 and up (neutral, rescuable, ...) don't give vision
+const VISION_PLAYERS: usize = 8;
+
+#[derive(Default)]
+pub struct VisionGrid {
+    /// map size in tiles
+    pub width: usize,
+    pub height: usize,
+    /// one bit per player for every tile
+    visible: Vec<u16>,
+    explored: Vec<u16>,
+    detected: Vec<u16>,
+    tile_heights: Vec<TileHeight>,
+}
+impl VisionGrid {
+    pub fn new(terrain: &TerrainGrid) -> Self {
+        let mut heights = Vec::with_capacity(terrain.width * terrain.height);
+        for ty in 0..terrain.height {
+            for tx in 0..terrain.width {
+                heights.push(terrain.megatile_height(tx, ty));
+            }
+        }
+        VisionGrid::with_heights(terrain.width, terrain.height, heights)
+    }
+
+    pub fn with_heights(width: usize, height: usize, tile_heights: Vec<TileHeight>) -> Self {
+        assert_eq!(tile_heights.len(), width * height);
+        VisionGrid {
+            width: width,
+            height: height,
+            visible: vec![0; width * height],
+            explored: vec![0; width * height],
+            detected: vec![0; width * height],
+            tile_heights: tile_heights,
+        }
+    }
+
+    fn tile_idx(&self, tx: i32, ty: i32) -> Option<usize> {
+        if tx < 0 || ty < 0 || tx as usize >= self.width || ty as usize >= self.height {
+            None
+        } else {
+            Some(ty as usize * self.width + tx as usize)
+        }
+    }
+
+    fn has_bit(grid: &[u16], idx: Option<usize>, player: usize) -> bool {
+        idx.map_or(false, |idx| grid[idx] & (1 << player) != 0)
+    }
+
+    // tile resolution
+    pub fn is_visible(&self, player: usize, tx: i32, ty: i32) -> bool {
+        VisionGrid::has_bit(&self.visible, self.tile_idx(tx, ty), player)
+    }
+    pub fn is_explored(&self, player: usize, tx: i32, ty: i32) -> bool {
+        VisionGrid::has_bit(&self.explored, self.tile_idx(tx, ty), player)
+    }
+    pub fn is_detected(&self, player: usize, tx: i32, ty: i32) -> bool {
+        VisionGrid::has_bit(&self.detected, self.tile_idx(tx, ty), player)
+    }
+
+    // pixel resolution
+    pub fn is_visible_px(&self, player: usize, x: i32, y: i32) -> bool {
+        self.is_visible(player, x >> 5, y >> 5)
+    }
+    pub fn is_explored_px(&self, player: usize, x: i32, y: i32) -> bool {
+        self.is_explored(player, x >> 5, y >> 5)
+    }
+    pub fn is_detected_px(&self, player: usize, x: i32, y: i32) -> bool {
+        self.is_detected(player, x >> 5, y >> 5)
+    }
+
+    /// marks the tiles within `range` tiles around (x, y) (in pixels)
+    pub fn reveal(&mut self, player: usize, x: i32, y: i32, range: i32, air: bool, detector: bool) {
+        let (cx, cy) = (x >> 5, y >> 5);
+        let own_height = match self.tile_idx(cx, cy) {
+            Some(idx) => self.tile_heights[idx],
+            None => return,
+        };
+        let bit = 1 << player;
+        for ty in cy - range..cy + range + 1 {
+            for tx in cx - range..cx + range + 1 {
+                let (dx, dy) = (tx - cx, ty - cy);
+                if dx * dx + dy * dy > range * range + range {
+                    continue;
+                }
+                let idx = match self.tile_idx(tx, ty) {
+                    Some(idx) => idx,
+                    None => continue,
+                };
+                // low ground can't see high ground
+                if !air && self.tile_heights[idx] > own_height {
+                    continue;
+                }
+                self.visible[idx] |= bit;
+                self.explored[idx] |= bit;
+                if detector {
+                    self.detected[idx] |= bit;
+                }
+            }
+        }
+    }
+
+    /// forgets what is currently seen, explored tiles stay explored
+    pub fn clear_visible(&mut self) {
+        for v in self.visible.iter_mut() {
+            *v = 0;
+        }
+        for d in self.detected.iter_mut() {
+            *d = 0;
+        }
+    }
+
+    /// recomputes what every player sees, called once per tick
+    pub fn update(&mut self, units_dat: &UnitsDat, units: &[UnitInfo]) {
+        self.clear_visible();
+        for u in units {
+            if u.player >= VISION_PLAYERS {
+                continue;
+            }
+            let flags = units_dat.flags(u.unit_id);
+            self.reveal(u.player,
+                        u.x,
+                        u.y,
+                        units_dat.sight_range[u.unit_id] as i32,
+                        flags.contains(UF_FLYER),
+                        flags.contains(UF_DETECTOR));
+        }
+    }
+
+    /// darkens the fogged parts of the rendered map, unexplored tiles turn
+    /// black. `darken` maps a palette index to a darker one.
+    pub fn render_fog(&self,
+                      player: usize,
+                      map_x: u16,
+                      map_y: u16,
+                      darken: &[u8],
+                      buffer: &mut [u8],
+                      buffer_pitch: u32) {
+        let pitch = buffer_pitch as usize;
+        let rows = buffer.len() / pitch;
+        for row in 0..rows {
+            let ty = (map_y as i32 + row as i32) >> 5;
+            for col in 0..pitch {
+                let tx = (map_x as i32 + col as i32) >> 5;
+                if self.is_visible(player, tx, ty) {
+                    continue;
+                }
+                let pos = row * pitch + col;
+                buffer[pos] = if self.is_explored(player, tx, ty) {
+                    darken[buffer[pos] as usize]
+                } else {
+                    0
+                };
+            }
+        }
+    }
+
+    /// applies the fog to a minimap bitmap (one pixel per tile)
+    pub fn render_minimap_fog(&self, player: usize, darken: &[u8], minimap: &mut [u8]) {
+        for ty in 0..self.height {
+            for tx in 0..self.width {
+                let pos = ty * self.width + tx;
+                if self.is_visible(player, tx as i32, ty as i32) {
+                    continue;
+                }
+                minimap[pos] = if self.is_explored(player, tx as i32, ty as i32) {
+                    darken[minimap[pos] as usize]
+                } else {
+                    0
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VisionGrid;
+    use scformats::terrain::TileHeight;
+
+    #[test]
+    fn reveal_and_fog() {
+        // left half low ground, right half high ground
+        let heights = (0..100)
+            .map(|i| if i % 10 < 5 {
+                TileHeight::Low
+            } else {
+                TileHeight::High
+            })
+            .collect();
+        let mut vision = VisionGrid::with_heights(10, 10, heights);
+        vision.reveal(0, 2 * 32 + 16, 5 * 32 + 16, 4, false, false);
+        assert!(vision.is_visible(0, 4, 5));
+        assert!(!vision.is_visible(0, 5, 5));
+        assert!(!vision.is_visible(1, 2, 5));
+        assert!(!vision.is_detected(0, 2, 5));
+
+        vision.reveal(1, 7 * 32 + 16, 5 * 32 + 16, 4, false, true);
+        assert!(vision.is_visible(1, 4, 5));
+        assert!(vision.is_detected(1, 7, 5));
+
+        vision.reveal(0, 2 * 32 + 16, 5 * 32 + 16, 4, true, false);
+        assert!(vision.is_visible(0, 5, 5));
+        assert!(!vision.is_visible(0, 7, 5));
+
+        vision.clear_visible();
+        assert!(!vision.is_visible(0, 2, 5));
+        assert!(vision.is_explored(0, 2, 5));
+        assert!(!vision.is_detected(1, 7, 5));
+    }
+}